@@ -2,6 +2,13 @@ const loginLink = document.getElementById("login-link");
 const logoutLink = document.getElementById("logout-link");
 const protectImg = document.getElementById("protected-img");
 
+// Set by auth-service next to the auth cookie, and echoed in the `X-CSRF-Token` header of
+// requests acting on the session
+function csrfToken() {
+    const cookie = document.cookie.split("; ").find(cookie => cookie.startsWith("csrf_token="));
+    return cookie ? decodeURIComponent(cookie.substring("csrf_token=".length)) : "";
+}
+
 logoutLink.addEventListener("click", (e) => {
     e.preventDefault();
 
@@ -10,6 +17,9 @@ logoutLink.addEventListener("click", (e) => {
     fetch(url, {
         method: 'POST',
         credentials: 'include', // This will include cookies in the request
+        headers: {
+            'X-CSRF-Token': csrfToken(),
+        },
     }).then(response => {
         if (response.ok) {
             loginLink.style.display = "block";
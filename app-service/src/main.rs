@@ -1,27 +1,131 @@
-use std::env;
+use std::{collections::BTreeMap, env, future::IntoFuture, time::Duration};
 
 use askama::Template;
+use auth_client::{AuthClient, Claims, KeySource};
 use axum::{
+    extract::{FromRef, State},
     http::StatusCode,
     response::{Html, IntoResponse},
     routing::get,
     Json, Router,
 };
-use axum_extra::extract::CookieJar;
 use serde::Serialize;
-use tower_http::services::ServeDir;
+use tokio::net::TcpListener;
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    services::ServeDir,
+    trace::TraceLayer,
+};
+
+mod shutdown;
+mod telemetry;
+
+use shutdown::Shutdown;
+
+// How long `/health/ready` waits on auth-service
+const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
+// On SIGTERM, how long to keep serving with `/health/ready` failing, in seconds
+const SHUTDOWN_DELAY_ENV_VAR: &str = "SHUTDOWN_DELAY_SECS";
+// Then how long in-flight requests get to complete, in seconds
+const DRAIN_TIMEOUT_ENV_VAR: &str = "DRAIN_TIMEOUT_SECS";
+
+#[derive(Clone)]
+struct AppState {
+    auth_client: AuthClient,
+    // Triggered when the service starts shutting down, which fails `/health/ready`
+    shutdown: Shutdown,
+}
+
+impl FromRef<AppState> for AuthClient {
+    fn from_ref(state: &AppState) -> Self {
+        state.auth_client.clone()
+    }
+}
 
 #[tokio::main]
 async fn main() {
-    let app = Router::new()
+    let tracer_provider = telemetry::init_tracing();
+    let shutdown_delay = duration_from_env(SHUTDOWN_DELAY_ENV_VAR, Duration::ZERO);
+    let drain_timeout = duration_from_env(DRAIN_TIMEOUT_ENV_VAR, Duration::from_secs(30));
+    let shutdown = Shutdown::default();
+    tokio::spawn(shutdown.clone().trigger_on_signal());
+    let state = AppState {
+        auth_client: auth_client(),
+        shutdown: shutdown.clone(),
+    };
+
+    let listener = TcpListener::bind("0.0.0.0:8000").await.unwrap();
+    serve(
+        listener,
+        app(state),
+        shutdown,
+        shutdown_delay,
+        drain_timeout,
+    )
+    .await
+    .unwrap();
+    let _ = tracer_provider.shutdown();
+}
+
+fn app(state: AppState) -> Router {
+    Router::new()
         .nest_service("/assets", ServeDir::new("assets"))
         .route("/", get(root))
-        .route("/protected", get(protected));
+        .route("/protected", get(protected))
+        .route("/health/live", get(health_live))
+        .route("/health/ready", get(health_ready))
+        .with_state(state)
+        .layer(TraceLayer::new_for_http().make_span_with(telemetry::make_span))
+        // Keeps the caller's `x-request-id` or assigns one, and echoes it in the response
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+}
+
+// Serves until `shutdown` is triggered, then keeps serving for `shutdown_delay` while
+// `/health/ready` fails. New connections are refused after that, and in-flight requests get
+// `drain_timeout` to complete before they are dropped, as in auth-service's `Application::run`.
+async fn serve(
+    listener: TcpListener,
+    app: Router,
+    shutdown: Shutdown,
+    shutdown_delay: Duration,
+    drain_timeout: Duration,
+) -> Result<(), std::io::Error> {
+    let stop_accepting = || {
+        let triggered = shutdown.wait();
+        async move {
+            triggered.await;
+            tokio::time::sleep(shutdown_delay).await;
+        }
+    };
+    let drain_deadline = {
+        let stopped_accepting = stop_accepting();
+        async move {
+            stopped_accepting.await;
+            tracing::info!("Refusing new connections, draining in-flight requests");
+            tokio::time::sleep(drain_timeout).await;
+        }
+    };
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8000").await.unwrap();
+    tracing::info!("listening on {}", listener.local_addr()?);
+    let server = axum::serve(listener, app).with_graceful_shutdown(stop_accepting());
+    tokio::select! {
+        result = server.into_future() => result,
+        _ = drain_deadline => {
+            tracing::warn!("Requests still in flight after the drain timeout are dropped");
+            Ok(())
+        }
+    }
+}
 
-    println!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+fn duration_from_env(name: &str, default: Duration) -> Duration {
+    match env::var(name) {
+        Ok(secs) if !secs.is_empty() => Duration::from_secs(
+            secs.parse()
+                .unwrap_or_else(|_| panic!("{} must be a number of seconds", name)),
+        ),
+        _ => default,
+    }
 }
 
 #[derive(Template)]
@@ -46,43 +150,233 @@ async fn root() -> impl IntoResponse {
     Html(template.render().unwrap())
 }
 
-async fn protected(jar: CookieJar) -> impl IntoResponse {
-    let jwt_cookie = match jar.get("jwt") {
-        Some(cookie) => cookie,
-        None => {
-            return StatusCode::UNAUTHORIZED.into_response();
-        }
+// Tokens are verified with the secret auth-service signs them with, or with the keys published
+// at `AUTH_SERVICE_JWKS_URL` when it is set. Only revocation is checked with auth-service.
+// The token is read from the cookie named `AUTH_COOKIE_NAME`, `jwt` by default.
+fn auth_client() -> AuthClient {
+    let auth_hostname = env::var("AUTH_SERVICE_HOST_NAME").unwrap_or("0.0.0.0".to_owned());
+    let key_source = match env::var("AUTH_SERVICE_JWKS_URL") {
+        Ok(url) if !url.is_empty() => KeySource::Jwks {
+            url,
+            refresh_interval: Duration::from_secs(300),
+        },
+        _ => KeySource::SharedSecret(
+            env::var("JWT_SECRET")
+                .expect("JWT_SECRET or AUTH_SERVICE_JWKS_URL must be set")
+                .into(),
+        ),
     };
 
-    let api_client = reqwest::Client::builder().build().unwrap();
+    let auth_client = AuthClient::new(&format!("http://{}:3000", auth_hostname), key_source);
+    // Follows auth-service's `auth.cookie.name` when it isn't the default
+    match env::var("AUTH_COOKIE_NAME") {
+        Ok(cookie_name) if !cookie_name.is_empty() => auth_client.with_cookie_name(&cookie_name),
+        _ => auth_client,
+    }
+}
 
-    let verify_token_body = serde_json::json!({
-        "token": &jwt_cookie.value(),
-    });
+async fn protected(_claims: Claims) -> Json<ProtectedRouteResponse> {
+    Json(ProtectedRouteResponse {
+        img_url: "https://i.ibb.co/YP90j68/Light-Live-Bootcamp-Certificate.png".to_owned(),
+    })
+}
 
-    let auth_hostname = env::var("AUTH_SERVICE_HOST_NAME").unwrap_or("0.0.0.0".to_owned());
-    let url = format!("http://{}:3000/verify-token", auth_hostname);
+#[derive(Serialize)]
+pub struct ProtectedRouteResponse {
+    pub img_url: String,
+}
+
+async fn health_live() -> impl IntoResponse {
+    Json(LivenessResponse { status: "live" })
+}
 
-    let response = match api_client.post(&url).json(&verify_token_body).send().await {
-        Ok(response) => response,
-        Err(_) => {
-            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+// Ready once auth-service answers, as every protected request asks it about revocation,
+// and until shutdown begins
+async fn health_ready(State(state): State<AppState>) -> impl IntoResponse {
+    if state.shutdown.is_triggered() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ReadinessResponse {
+                status: "shutting_down",
+                checks: BTreeMap::new(),
+            }),
+        );
+    }
+
+    let auth_client = state.auth_client;
+    let error = match tokio::time::timeout(READINESS_TIMEOUT, auth_client.check_reachable()).await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => {
+            tracing::warn!("auth-service health check failed: {}", e);
+            Some("unavailable")
         }
+        Err(_) => Some("timed out"),
     };
 
-    match response.status() {
-        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::BAD_REQUEST => {
-            StatusCode::UNAUTHORIZED.into_response()
-        }
-        reqwest::StatusCode::OK => Json(ProtectedRouteResponse {
-            img_url: "https://i.ibb.co/YP90j68/Light-Live-Bootcamp-Certificate.png".to_owned(),
-        })
-        .into_response(),
-        _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-    }
+    let (status, readiness, dependency) = match error {
+        None => (StatusCode::OK, "ready", "up"),
+        Some(_) => (StatusCode::SERVICE_UNAVAILABLE, "not_ready", "down"),
+    };
+    let auth_service = DependencyReport {
+        status: dependency,
+        error,
+    };
+    (
+        status,
+        Json(ReadinessResponse {
+            status: readiness,
+            checks: BTreeMap::from([("auth-service", auth_service)]),
+        }),
+    )
 }
 
 #[derive(Serialize)]
-pub struct ProtectedRouteResponse {
-    pub img_url: String,
+struct LivenessResponse {
+    status: &'static str,
+}
+
+// Same shape as auth-service's readiness report
+#[derive(Serialize)]
+struct ReadinessResponse {
+    status: &'static str,
+    checks: BTreeMap<&'static str, DependencyReport>,
+}
+
+#[derive(Serialize)]
+struct DependencyReport {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{task::JoinHandle, time::Instant};
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    const SHUTDOWN_DELAY: Duration = Duration::from_millis(200);
+    const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+    struct TestApp {
+        address: String,
+        shutdown: Shutdown,
+        server: JoinHandle<Result<(), std::io::Error>>,
+        // Stands in for auth-service, and is live
+        _auth_service: MockServer,
+    }
+
+    impl TestApp {
+        // Also serves `/slow`, which takes `delay` to respond
+        async fn start(delay: Duration) -> Self {
+            let auth_service = MockServer::start().await;
+            Mock::given(method("GET"))
+                .and(path("/health/live"))
+                .respond_with(ResponseTemplate::new(200))
+                .mount(&auth_service)
+                .await;
+            let shutdown = Shutdown::default();
+            let state = AppState {
+                auth_client: AuthClient::new(
+                    &auth_service.uri(),
+                    KeySource::SharedSecret("secret".into()),
+                ),
+                shutdown: shutdown.clone(),
+            };
+            let app = app(state).route(
+                "/slow",
+                get(move || async move { tokio::time::sleep(delay).await }),
+            );
+
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = format!("http://{}", listener.local_addr().unwrap());
+            let server = tokio::spawn(serve(
+                listener,
+                app,
+                shutdown.clone(),
+                SHUTDOWN_DELAY,
+                DRAIN_TIMEOUT,
+            ));
+
+            Self {
+                address,
+                shutdown,
+                server,
+                _auth_service: auth_service,
+            }
+        }
+
+        async fn get(&self, path: &str) -> reqwest::Response {
+            reqwest::get(format!("{}{}", &self.address, path))
+                .await
+                .expect("Failed to execute request")
+        }
+
+        // Starts a request to `/slow` and gives it time to reach the server
+        async fn start_slow_request(&self) -> JoinHandle<reqwest::Result<reqwest::Response>> {
+            let request = tokio::spawn(reqwest::get(format!("{}/slow", &self.address)));
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            request
+        }
+
+        async fn shut_down(self) {
+            self.shutdown.trigger();
+            self.server.await.unwrap().unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn ready_fails_as_soon_as_shutdown_begins() {
+        let app = TestApp::start(Duration::ZERO).await;
+        assert_eq!(app.get("/health/ready").await.status().as_u16(), 200);
+
+        app.shutdown.trigger();
+
+        // Still served during the shutdown delay, for load balancers to take the instance out
+        let response = app.get("/health/ready").await;
+        assert_eq!(response.status().as_u16(), 503);
+        let report = response.json::<serde_json::Value>().await.unwrap();
+        assert_eq!(report["status"], "shutting_down");
+        assert_eq!(report["checks"], serde_json::json!({}));
+        assert_eq!(app.get("/health/live").await.status().as_u16(), 200);
+
+        app.shut_down().await;
+    }
+
+    #[tokio::test]
+    async fn in_flight_requests_complete_before_the_server_stops() {
+        // Outlasts the shutdown delay but not the drain timeout
+        let app = TestApp::start(Duration::from_millis(500)).await;
+        let request = app.start_slow_request().await;
+        let address = app.address.clone();
+
+        let started = Instant::now();
+        app.shut_down().await;
+
+        let response = request.await.unwrap().expect("The request was dropped");
+        assert_eq!(response.status().as_u16(), 200);
+        assert!(started.elapsed() >= SHUTDOWN_DELAY);
+        // New connections are refused once the server has stopped
+        let error = reqwest::get(format!("{}/health/live", address))
+            .await
+            .expect_err("The server still accepts connections");
+        assert!(error.is_connect(), "{:?}", error);
+    }
+
+    #[tokio::test]
+    async fn shutdown_gives_up_on_requests_after_the_drain_timeout() {
+        let app = TestApp::start(Duration::from_secs(30)).await;
+        let _request = app.start_slow_request().await;
+
+        let started = Instant::now();
+        app.shut_down().await;
+
+        let elapsed = started.elapsed();
+        assert!(elapsed >= SHUTDOWN_DELAY + DRAIN_TIMEOUT, "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+    }
 }
@@ -0,0 +1,81 @@
+use std::{future::Future, sync::Arc};
+
+use tokio::sync::watch;
+
+// Tells everything holding a clone that the service is shutting down, as in auth-service
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            sender: Arc::new(watch::Sender::new(false)),
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    // Resolves once the shutdown is triggered, immediately if it already was
+    pub fn wait(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut receiver = self.sender.subscribe();
+        async move {
+            // Can't fail, `self` keeps the sender alive
+            let _ = receiver.wait_for(|triggered| *triggered).await;
+        }
+    }
+
+    // Triggers the shutdown on SIGTERM, as sent by Docker and Kubernetes, or on Ctrl+C
+    pub async fn trigger_on_signal(self) {
+        let ctrl_c = async {
+            tokio::signal::ctrl_c()
+                .await
+                .expect("Failed to listen for Ctrl+C");
+        };
+        #[cfg(unix)]
+        let terminate = async {
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                .expect("Failed to listen for SIGTERM")
+                .recv()
+                .await;
+        };
+        #[cfg(not(unix))]
+        let terminate = std::future::pending::<()>();
+
+        tokio::select! {
+            _ = ctrl_c => {}
+            _ = terminate => {}
+        }
+        tracing::info!("Shutdown signal received");
+        self.trigger();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn wait_resolves_once_triggered() {
+        let shutdown = Shutdown::default();
+        let waiting = tokio::spawn(shutdown.wait());
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        shutdown.trigger();
+
+        waiting.await.unwrap();
+        assert!(shutdown.is_triggered());
+        // Later waits resolve right away
+        shutdown.wait().await;
+    }
+}
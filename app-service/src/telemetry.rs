@@ -0,0 +1,63 @@
+use std::env;
+
+use axum::{body::Body, http::Request};
+use opentelemetry::{
+    propagation::TextMapPropagator,
+    trace::{TraceContextExt, TracerProvider as _},
+};
+use opentelemetry_http::HeaderExtractor;
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{prelude::*, EnvFilter};
+
+// Spans are exported to this OTLP/HTTP collector when it is set, e.g. "http://localhost:4318".
+// `OTEL_EXPORTER_OTLP_PROTOCOL` and the other standard variables are honored as well.
+const OTLP_ENDPOINT_ENV_VAR: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Logs to stdout and, with a collector configured, exports spans. Spans get trace ids either
+// way, so a caller's trace continues through to auth-service.
+pub fn init_tracing() -> SdkTracerProvider {
+    let resource = Resource::builder().with_service_name("app-service").build();
+    let mut provider = SdkTracerProvider::builder().with_resource(resource);
+    if env::var(OTLP_ENDPOINT_ENV_VAR).is_ok_and(|endpoint| !endpoint.is_empty()) {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .build()
+            .expect("Failed to build the OTLP span exporter");
+        provider = provider.with_batch_exporter(exporter);
+    }
+    let provider = provider.build();
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().compact())
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("app-service")))
+        .init();
+
+    provider
+}
+
+// One span per request, a child of the caller's span when the request carries a `traceparent`
+pub fn make_span(request: &Request<Body>) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let span = tracing::info_span!(
+        "[REQUEST]",
+        method = %request.method(),
+        uri = %request.uri(),
+        request_id = %request_id,
+    );
+
+    let parent = TraceContextPropagator::new().extract(&HeaderExtractor(request.headers()));
+    if parent.span().span_context().is_valid() {
+        let _ = span.set_parent(parent);
+    }
+    span
+}
@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+// The claims auth-service puts in every token it issues
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims {
+    // Email of the user the token was issued to
+    pub sub: String,
+    pub exp: usize,
+    pub iat: usize,
+    // Identifies the token when it is banned on logout
+    pub jti: String,
+}
@@ -0,0 +1,132 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::StatusCode;
+use secrecy::{ExposeSecret, SecretString};
+use serde_json::json;
+
+use crate::{
+    keys::{KeySource, Keys},
+    revocation::RevocationCache,
+    trace_context::trace_context_headers,
+    AuthError, Claims, JWT_COOKIE_NAME,
+};
+
+// How long auth-service's answer that a token is not revoked is trusted
+const DEFAULT_REVOCATION_CACHE_TTL: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Verifies tokens issued by auth-service. Cheap to clone, clones share their keys and cache.
+#[derive(Clone)]
+pub struct AuthClient {
+    verify_token_url: String,
+    health_url: String,
+    http: reqwest::Client,
+    keys: Arc<Keys>,
+    revocations: Arc<RevocationCache>,
+    cookie_name: String,
+}
+
+impl AuthClient {
+    // `auth_service_url` is the base URL of auth-service, e.g. `http://auth-service:3000`
+    pub fn new(auth_service_url: &str, key_source: KeySource) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .expect("Failed to build HTTP client");
+
+        let auth_service_url = auth_service_url.trim_end_matches('/');
+
+        Self {
+            verify_token_url: format!("{}/verify-token", auth_service_url),
+            health_url: format!("{}/health/live", auth_service_url),
+            keys: Arc::new(Keys::new(key_source, http.clone())),
+            http,
+            revocations: Arc::new(RevocationCache::new(DEFAULT_REVOCATION_CACHE_TTL)),
+            cookie_name: JWT_COOKIE_NAME.to_owned(),
+        }
+    }
+
+    // A zero TTL asks auth-service about every request
+    pub fn with_revocation_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.revocations = Arc::new(RevocationCache::new(ttl));
+        self
+    }
+
+    pub fn with_cookie_name(mut self, cookie_name: &str) -> Self {
+        cookie_name.clone_into(&mut self.cookie_name);
+        self
+    }
+
+    pub fn cookie_name(&self) -> &str {
+        &self.cookie_name
+    }
+
+    pub async fn verify(&self, token: &SecretString) -> Result<Claims, AuthError> {
+        let claims = self.keys.decode(token.expose_secret()).await?;
+
+        let revoked = match self.revocations.get(&claims.jti) {
+            Some(revoked) => revoked,
+            None => {
+                let revoked = self.introspect(token).await?;
+                self.revocations
+                    .insert(&claims.jti, revoked, expires_in(&claims));
+                revoked
+            }
+        };
+
+        if revoked {
+            return Err(AuthError::InvalidToken);
+        }
+        Ok(claims)
+    }
+
+    // Whether auth-service is up, for the readiness checks of the services that rely on it
+    pub async fn check_reachable(&self) -> Result<(), AuthError> {
+        let response = self
+            .http
+            .get(&self.health_url)
+            .send()
+            .await
+            .map_err(|e| AuthError::Unavailable(e.to_string()))?;
+
+        match response.status() {
+            StatusCode::OK => Ok(()),
+            status => Err(AuthError::Unavailable(format!(
+                "unexpected response from {}: {}",
+                self.health_url, status
+            ))),
+        }
+    }
+
+    // Asks auth-service whether it still accepts the token, i.e. it wasn't banned on logout
+    async fn introspect(&self, token: &SecretString) -> Result<bool, AuthError> {
+        let response = self
+            .http
+            .post(&self.verify_token_url)
+            .headers(trace_context_headers())
+            .json(&json!({ "token": token.expose_secret() }))
+            .send()
+            .await
+            .map_err(|e| AuthError::Unavailable(e.to_string()))?;
+
+        match response.status() {
+            StatusCode::OK => Ok(false),
+            StatusCode::UNAUTHORIZED => Ok(true),
+            status => Err(AuthError::Unavailable(format!(
+                "unexpected response from {}: {}",
+                self.verify_token_url, status
+            ))),
+        }
+    }
+}
+
+fn expires_in(claims: &Claims) -> Duration {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Duration::from_secs((claims.exp as u64).saturating_sub(now))
+}
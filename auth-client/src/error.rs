@@ -0,0 +1,31 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("Missing auth token")]
+    MissingToken,
+    #[error("Invalid auth token")]
+    InvalidToken,
+    // Keys or revocation status couldn't be fetched, so the token can't be trusted either way
+    #[error("Auth service unavailable: {0}")]
+    Unavailable(String),
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        let (status, error_message) = match self {
+            AuthError::MissingToken => (StatusCode::UNAUTHORIZED, "Missing auth token"),
+            AuthError::InvalidToken => (StatusCode::UNAUTHORIZED, "Invalid auth token"),
+            AuthError::Unavailable(_) => {
+                (StatusCode::SERVICE_UNAVAILABLE, "Auth service unavailable")
+            }
+        };
+        (status, Json(json!({ "error": error_message }))).into_response()
+    }
+}
@@ -0,0 +1,39 @@
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap},
+};
+use axum_extra::extract::CookieJar;
+use secrecy::SecretString;
+
+use crate::{AuthClient, AuthError, Claims};
+
+// Handlers take `Claims` to require a valid token, read from an `Authorization: Bearer` header
+// or else from the auth cookie. The state only needs to provide an `AuthClient`.
+impl<S> FromRequestParts<S> for Claims
+where
+    AuthClient: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let client = AuthClient::from_ref(state);
+        let token = bearer_token(&parts.headers)
+            .or_else(|| {
+                CookieJar::from_headers(&parts.headers)
+                    .get(client.cookie_name())
+                    .map(|cookie| SecretString::from(cookie.value().to_owned()))
+            })
+            .ok_or(AuthError::MissingToken)?;
+
+        client.verify(&token).await
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<SecretString> {
+    let (scheme, token) = headers.get(AUTHORIZATION)?.to_str().ok()?.split_once(' ')?;
+
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then(|| SecretString::from(token.trim().to_owned()))
+}
@@ -0,0 +1,226 @@
+use std::time::Duration;
+
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use secrecy::{ExposeSecret, SecretString};
+use tokio::{sync::RwLock, time::Instant};
+
+use crate::{trace_context::trace_context_headers, AuthError, Claims};
+
+// A token with a key id missing from the cached set refetches it, but no more often than this
+const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(30);
+
+// Where the keys that verify token signatures come from
+pub enum KeySource {
+    // The `auth.jwt_secret` auth-service signs its tokens with (HS256)
+    SharedSecret(SecretString),
+    // A JWKS document, fetched when first needed and again once `refresh_interval` has passed
+    Jwks {
+        url: String,
+        refresh_interval: Duration,
+    },
+}
+
+pub(crate) enum Keys {
+    Shared(DecodingKey),
+    Jwks(JwksCache),
+}
+
+impl Keys {
+    pub(crate) fn new(source: KeySource, http: reqwest::Client) -> Self {
+        match source {
+            KeySource::SharedSecret(secret) => {
+                Keys::Shared(DecodingKey::from_secret(secret.expose_secret().as_bytes()))
+            }
+            KeySource::Jwks {
+                url,
+                refresh_interval,
+            } => Keys::Jwks(JwksCache {
+                url,
+                refresh_interval,
+                http,
+                fetched: RwLock::new(None),
+            }),
+        }
+    }
+
+    // Checks the token's signature and expiry, without asking whether it was revoked
+    pub(crate) async fn decode(&self, token: &str) -> Result<Claims, AuthError> {
+        let header = decode_header(token).map_err(|_| AuthError::InvalidToken)?;
+        let (key, algorithm) = match self {
+            Keys::Shared(key) => (key.clone(), Algorithm::HS256),
+            // The key's family must match the algorithm, so a token can't pick a weaker one
+            Keys::Jwks(cache) => (cache.key(header.kid.as_deref()).await?, header.alg),
+        };
+
+        let mut validation = Validation::new(algorithm);
+        // auth-service rejects a token as soon as it expires, so does the client
+        validation.leeway = 0;
+        validation.set_required_spec_claims(&["exp", "sub"]);
+
+        decode::<Claims>(token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|_| AuthError::InvalidToken)
+    }
+}
+
+pub(crate) struct JwksCache {
+    url: String,
+    refresh_interval: Duration,
+    http: reqwest::Client,
+    fetched: RwLock<Option<FetchedKeys>>,
+}
+
+struct FetchedKeys {
+    at: Instant,
+    keys: Vec<(Option<String>, DecodingKey)>,
+}
+
+impl FetchedKeys {
+    // Tokens without a key id are checked against the first key
+    fn find(&self, kid: Option<&str>) -> Option<DecodingKey> {
+        self.keys
+            .iter()
+            .find(|(key_id, _)| kid.is_none() || key_id.as_deref() == kid)
+            .map(|(_, key)| key.clone())
+    }
+}
+
+impl JwksCache {
+    async fn key(&self, kid: Option<&str>) -> Result<DecodingKey, AuthError> {
+        if let Some(fetched) = self.fetched.read().await.as_ref() {
+            if fetched.at.elapsed() < self.refresh_interval {
+                if let Some(key) = fetched.find(kid) {
+                    return Ok(key);
+                }
+                if fetched.at.elapsed() < MIN_REFETCH_INTERVAL {
+                    return Err(AuthError::InvalidToken);
+                }
+            }
+        }
+
+        let mut fetched = self.fetched.write().await;
+        // Another request may have refetched the keys while this one waited for the lock
+        if let Some(recent) = fetched
+            .as_ref()
+            .filter(|fetched| fetched.at.elapsed() < MIN_REFETCH_INTERVAL)
+        {
+            return recent.find(kid).ok_or(AuthError::InvalidToken);
+        }
+        let keys = self.fetch().await?;
+        let key = keys.find(kid);
+        *fetched = Some(keys);
+        key.ok_or(AuthError::InvalidToken)
+    }
+
+    async fn fetch(&self) -> Result<FetchedKeys, AuthError> {
+        let unavailable = |e: reqwest::Error| AuthError::Unavailable(e.to_string());
+        let jwks: JwkSet = self
+            .http
+            .get(&self.url)
+            .headers(trace_context_headers())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(unavailable)?
+            .json()
+            .await
+            .map_err(unavailable)?;
+
+        // Keys this crate can't use are skipped rather than failing the whole set
+        let keys = jwks
+            .keys
+            .iter()
+            .filter_map(|jwk| {
+                DecodingKey::from_jwk(jwk)
+                    .ok()
+                    .map(|key| (jwk.common.key_id.clone(), key))
+            })
+            .collect();
+
+        Ok(FetchedKeys {
+            at: Instant::now(),
+            keys,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    use super::*;
+
+    const SECRET: &str = "secret";
+
+    fn claims(exp_in_secs: i64) -> Claims {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        Claims {
+            sub: "test@example.com".to_owned(),
+            exp: (now + exp_in_secs) as usize,
+            iat: now as usize,
+            jti: "test_jti".to_owned(),
+        }
+    }
+
+    fn token(header: &Header, claims: &Claims, secret: &str) -> String {
+        encode(header, claims, &EncodingKey::from_secret(secret.as_bytes())).unwrap()
+    }
+
+    fn shared_keys() -> Keys {
+        Keys::new(
+            KeySource::SharedSecret(SecretString::from(SECRET)),
+            reqwest::Client::new(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_decode_valid_token() {
+        let claims = claims(600);
+        let token = token(&Header::default(), &claims, SECRET);
+
+        assert_eq!(shared_keys().decode(&token).await.unwrap(), claims);
+    }
+
+    #[tokio::test]
+    async fn test_decode_token_signed_with_another_secret() {
+        let token = token(&Header::default(), &claims(600), "other-secret");
+
+        assert!(matches!(
+            shared_keys().decode(&token).await,
+            Err(AuthError::InvalidToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_decode_expired_token() {
+        let token = token(&Header::default(), &claims(-1), SECRET);
+
+        assert!(matches!(
+            shared_keys().decode(&token).await,
+            Err(AuthError::InvalidToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_decode_token_with_another_algorithm() {
+        let token = token(&Header::new(Algorithm::HS512), &claims(600), SECRET);
+
+        assert!(matches!(
+            shared_keys().decode(&token).await,
+            Err(AuthError::InvalidToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_decode_malformed_token() {
+        assert!(matches!(
+            shared_keys().decode("not-a-token").await,
+            Err(AuthError::InvalidToken)
+        ));
+    }
+}
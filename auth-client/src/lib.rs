@@ -0,0 +1,17 @@
+// Verifies auth-service tokens inside downstream services. Signatures and expiry are checked
+// locally, and only revocation is asked of auth-service, with the answers cached for a short while.
+mod claims;
+mod client;
+mod error;
+mod extract;
+mod keys;
+mod revocation;
+mod trace_context;
+
+pub use claims::Claims;
+pub use client::AuthClient;
+pub use error::AuthError;
+pub use keys::KeySource;
+
+// Name of the cookie auth-service stores the token in
+pub const JWT_COOKIE_NAME: &str = "jwt";
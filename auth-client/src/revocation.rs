@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use dashmap::DashMap;
+use tokio::time::Instant;
+
+// Answers auth-service gave about whether tokens were revoked, keyed by their `jti`
+pub(crate) struct RevocationCache {
+    ttl: Duration,
+    checks: DashMap<String, Check>,
+}
+
+struct Check {
+    revoked: bool,
+    valid_until: Instant,
+}
+
+impl RevocationCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            checks: DashMap::new(),
+        }
+    }
+
+    // Whether the token was revoked, if auth-service was asked recently enough
+    pub(crate) fn get(&self, jti: &str) -> Option<bool> {
+        self.checks
+            .get(jti)
+            .filter(|check| check.valid_until > Instant::now())
+            .map(|check| check.revoked)
+    }
+
+    // A token that is still active may be revoked at any time, so that answer is kept for the
+    // cache TTL. A revoked token stays revoked, so that answer is kept until the token expires.
+    pub(crate) fn insert(&self, jti: &str, revoked: bool, expires_in: Duration) {
+        let now = Instant::now();
+        self.checks.retain(|_, check| check.valid_until > now);
+        if self.ttl.is_zero() && !revoked {
+            return;
+        }
+
+        let valid_for = if revoked { expires_in } else { self.ttl };
+        self.checks.insert(
+            jti.to_owned(),
+            Check {
+                revoked,
+                valid_until: now + valid_for,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN_EXPIRES_IN: Duration = Duration::from_secs(600);
+
+    #[test]
+    fn test_answers_are_cached() {
+        let cache = RevocationCache::new(Duration::from_secs(5));
+
+        assert_eq!(cache.get("active_jti"), None);
+
+        cache.insert("active_jti", false, TOKEN_EXPIRES_IN);
+        cache.insert("revoked_jti", true, TOKEN_EXPIRES_IN);
+
+        assert_eq!(cache.get("active_jti"), Some(false));
+        assert_eq!(cache.get("revoked_jti"), Some(true));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_active_tokens_are_checked_again_after_ttl() {
+        let cache = RevocationCache::new(Duration::from_secs(5));
+        cache.insert("active_jti", false, TOKEN_EXPIRES_IN);
+        cache.insert("revoked_jti", true, TOKEN_EXPIRES_IN);
+
+        tokio::time::advance(Duration::from_secs(5)).await;
+
+        assert_eq!(cache.get("active_jti"), None);
+        assert_eq!(cache.get("revoked_jti"), Some(true));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_revocations_are_dropped_when_the_token_expires() {
+        let cache = RevocationCache::new(Duration::from_secs(5));
+        cache.insert("revoked_jti", true, TOKEN_EXPIRES_IN);
+
+        tokio::time::advance(TOKEN_EXPIRES_IN).await;
+        cache.insert("other_jti", true, TOKEN_EXPIRES_IN);
+
+        assert_eq!(cache.get("revoked_jti"), None);
+        assert_eq!(cache.checks.len(), 1);
+    }
+
+    #[test]
+    fn test_zero_ttl_disables_caching_active_tokens() {
+        let cache = RevocationCache::new(Duration::ZERO);
+
+        cache.insert("active_jti", false, TOKEN_EXPIRES_IN);
+
+        assert_eq!(cache.get("active_jti"), None);
+    }
+}
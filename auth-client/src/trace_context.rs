@@ -0,0 +1,17 @@
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry_http::HeaderInjector;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use reqwest::header::HeaderMap;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+// W3C `traceparent` and `tracestate` headers continuing the current span's trace, so calls to
+// auth-service show up under the request that made them. Empty unless the service installs a
+// `tracing-opentelemetry` layer.
+pub(crate) fn trace_context_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    TraceContextPropagator::new().inject_context(
+        &tracing::Span::current().context(),
+        &mut HeaderInjector(&mut headers),
+    );
+    headers
+}
@@ -0,0 +1,105 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use auth_client::{AuthClient, AuthError, Claims, KeySource};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use opentelemetry::trace::{TraceContextExt, TracerProvider as _};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use secrecy::SecretString;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::prelude::*;
+use wiremock::{
+    matchers::{header_exists, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+fn client(auth_service: &MockServer) -> AuthClient {
+    AuthClient::new(
+        &auth_service.uri(),
+        KeySource::SharedSecret(SecretString::from("secret")),
+    )
+}
+
+#[tokio::test]
+async fn auth_service_is_reachable_when_it_is_live() {
+    let auth_service = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/health/live"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&auth_service)
+        .await;
+
+    assert!(client(&auth_service).check_reachable().await.is_ok());
+}
+
+#[tokio::test]
+async fn auth_service_is_unreachable_when_it_errors_or_is_gone() {
+    let auth_service = MockServer::start().await;
+    Mock::given(path("/health/live"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&auth_service)
+        .await;
+    let failing = client(&auth_service);
+
+    assert!(matches!(
+        failing.check_reachable().await,
+        Err(AuthError::Unavailable(_))
+    ));
+
+    let gone = client(&auth_service);
+    drop(auth_service);
+    assert!(matches!(
+        gone.check_reachable().await,
+        Err(AuthError::Unavailable(_))
+    ));
+}
+
+#[tokio::test]
+async fn revocation_checks_continue_the_callers_trace() {
+    let auth_service = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/verify-token"))
+        .and(header_exists("traceparent"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&auth_service)
+        .await;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as usize;
+    let claims = Claims {
+        sub: "test@example.com".to_owned(),
+        exp: now + 600,
+        iat: now,
+        jti: "jti".to_owned(),
+    };
+    let token = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(b"secret"),
+    )
+    .unwrap();
+    // Spans only get OpenTelemetry ids with a `tracing-opentelemetry` layer, as in the services
+    let provider = SdkTracerProvider::builder().build();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+    let _default = tracing::subscriber::set_default(subscriber);
+    let span = tracing::info_span!("request");
+    let trace_id = span.context().span().span_context().trace_id();
+
+    client(&auth_service)
+        .verify(&SecretString::from(token))
+        .instrument(span)
+        .await
+        .unwrap();
+
+    let requests = auth_service.received_requests().await.unwrap();
+    let traceparent = requests[0].headers["traceparent"].to_str().unwrap();
+    assert!(
+        traceparent.starts_with(&format!("00-{}-", trace_id)),
+        "{}",
+        traceparent
+    );
+}
@@ -0,0 +1,235 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use auth_client::{AuthClient, Claims, KeySource};
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, Request, StatusCode},
+    routing::get,
+    Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use secrecy::SecretString;
+use serde_json::json;
+use tower::ServiceExt;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{body_json, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+const SECRET: &str = "secret";
+const EMAIL: &str = "test@example.com";
+
+fn token(header: &Header) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as usize;
+    let claims = Claims {
+        sub: EMAIL.to_owned(),
+        exp: now + 600,
+        iat: now,
+        jti: Uuid::new_v4().to_string(),
+    };
+    encode(
+        header,
+        &claims,
+        &EncodingKey::from_secret(SECRET.as_bytes()),
+    )
+    .unwrap()
+}
+
+fn shared_secret() -> KeySource {
+    KeySource::SharedSecret(SecretString::from(SECRET))
+}
+
+// Stands in for auth-service's `/verify-token`, answering `status` for `token`
+async fn mock_verify_token(server: &MockServer, token: &str, status: u16, calls: u64) {
+    Mock::given(method("POST"))
+        .and(path("/verify-token"))
+        .and(body_json(json!({ "token": token })))
+        .respond_with(ResponseTemplate::new(status))
+        .expect(calls)
+        .mount(server)
+        .await;
+}
+
+fn app(client: AuthClient) -> Router {
+    Router::new()
+        .route(
+            "/protected",
+            get(|claims: Claims| async move { claims.sub }),
+        )
+        .with_state(client)
+}
+
+async fn get_protected(
+    app: &Router,
+    header: Option<(header::HeaderName, String)>,
+) -> (StatusCode, String) {
+    let mut request = Request::get("/protected");
+    if let Some((name, value)) = header {
+        request = request.header(name, value);
+    }
+    let response = app
+        .clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+fn cookie(token: &str) -> Option<(header::HeaderName, String)> {
+    Some((header::COOKIE, format!("jwt={}", token)))
+}
+
+fn bearer(token: &str) -> Option<(header::HeaderName, String)> {
+    Some((header::AUTHORIZATION, format!("Bearer {}", token)))
+}
+
+#[tokio::test]
+async fn claims_are_read_from_the_jwt_cookie() {
+    let auth_service = MockServer::start().await;
+    let token = token(&Header::default());
+    mock_verify_token(&auth_service, &token, 200, 1).await;
+    let app = app(AuthClient::new(&auth_service.uri(), shared_secret()));
+
+    assert_eq!(
+        get_protected(&app, cookie(&token)).await,
+        (StatusCode::OK, EMAIL.to_owned())
+    );
+}
+
+#[tokio::test]
+async fn claims_are_read_from_a_bearer_token() {
+    let auth_service = MockServer::start().await;
+    let token = token(&Header::default());
+    mock_verify_token(&auth_service, &token, 200, 1).await;
+    let app = app(AuthClient::new(&auth_service.uri(), shared_secret()));
+
+    assert_eq!(
+        get_protected(&app, bearer(&token)).await,
+        (StatusCode::OK, EMAIL.to_owned())
+    );
+}
+
+#[tokio::test]
+async fn requests_without_a_token_are_rejected() {
+    let auth_service = MockServer::start().await;
+    let app = app(AuthClient::new(&auth_service.uri(), shared_secret()));
+
+    let (status, _) = get_protected(&app, None).await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn invalid_tokens_are_rejected_without_asking_auth_service() {
+    let auth_service = MockServer::start().await;
+    let token = token(&Header::default());
+    mock_verify_token(&auth_service, &token, 200, 0).await;
+    let app = app(AuthClient::new(
+        &auth_service.uri(),
+        KeySource::SharedSecret(SecretString::from("other-secret")),
+    ));
+
+    let (status, _) = get_protected(&app, cookie(&token)).await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn revoked_tokens_are_rejected() {
+    let auth_service = MockServer::start().await;
+    let token = token(&Header::default());
+    mock_verify_token(&auth_service, &token, 401, 1).await;
+    let app = app(AuthClient::new(&auth_service.uri(), shared_secret()));
+
+    // The revocation is cached, auth-service is only asked once
+    for _ in 0..2 {
+        let (status, _) = get_protected(&app, cookie(&token)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
+
+#[tokio::test]
+async fn revocation_checks_are_cached() {
+    let auth_service = MockServer::start().await;
+    let token = token(&Header::default());
+    mock_verify_token(&auth_service, &token, 200, 1).await;
+    let app = app(AuthClient::new(&auth_service.uri(), shared_secret()));
+
+    for _ in 0..3 {
+        let (status, _) = get_protected(&app, cookie(&token)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn revocation_is_checked_on_every_request_without_a_cache() {
+    let auth_service = MockServer::start().await;
+    let token = token(&Header::default());
+    mock_verify_token(&auth_service, &token, 200, 3).await;
+    let app = app(AuthClient::new(&auth_service.uri(), shared_secret())
+        .with_revocation_cache_ttl(Duration::ZERO));
+
+    for _ in 0..3 {
+        let (status, _) = get_protected(&app, cookie(&token)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn requests_fail_when_auth_service_is_unavailable() {
+    let auth_service = MockServer::start().await;
+    let token = token(&Header::default());
+    Mock::given(path("/verify-token"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&auth_service)
+        .await;
+    let app = app(AuthClient::new(&auth_service.uri(), shared_secret()));
+
+    let (status, _) = get_protected(&app, cookie(&token)).await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn keys_are_fetched_once_from_a_jwks() {
+    let auth_service = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/.well-known/jwks.json"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "keys": [
+                { "kty": "oct", "kid": "old", "k": URL_SAFE_NO_PAD.encode("old-secret") },
+                { "kty": "oct", "kid": "current", "k": URL_SAFE_NO_PAD.encode(SECRET) },
+            ]
+        })))
+        .expect(1)
+        .mount(&auth_service)
+        .await;
+    let header = Header {
+        kid: Some("current".to_owned()),
+        ..Header::default()
+    };
+    let (first, second) = (token(&header), token(&header));
+    mock_verify_token(&auth_service, &first, 200, 1).await;
+    mock_verify_token(&auth_service, &second, 200, 1).await;
+    let app = app(AuthClient::new(
+        &auth_service.uri(),
+        KeySource::Jwks {
+            url: format!("{}/.well-known/jwks.json", auth_service.uri()),
+            refresh_interval: Duration::from_secs(300),
+        },
+    ));
+
+    for token in [first, second] {
+        assert_eq!(
+            get_protected(&app, bearer(&token)).await,
+            (StatusCode::OK, EMAIL.to_owned())
+        );
+    }
+}
@@ -0,0 +1,27 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS webhook_deliveries(
+   id UUID NOT NULL PRIMARY KEY,
+   subscription_id TEXT NOT NULL,
+   event_type TEXT NOT NULL,
+   payload TEXT NOT NULL,
+   status TEXT NOT NULL DEFAULT 'pending',
+   attempts INTEGER NOT NULL DEFAULT 0,
+   next_attempt_at TIMESTAMPTZ NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_due_idx
+   ON webhook_deliveries (next_attempt_at)
+   WHERE status = 'pending';
+
+CREATE TABLE IF NOT EXISTS webhook_delivery_log(
+   id BIGSERIAL PRIMARY KEY,
+   delivery_id UUID NOT NULL REFERENCES webhook_deliveries(id) ON DELETE CASCADE,
+   attempt INTEGER NOT NULL,
+   attempted_at TIMESTAMPTZ NOT NULL,
+   response_status INTEGER,
+   error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS webhook_delivery_log_delivery_id_idx
+   ON webhook_delivery_log (delivery_id);
@@ -0,0 +1,5 @@
+-- Add up migration script here
+ALTER TABLE email_outbox RENAME COLUMN content TO text_body;
+ALTER TABLE email_outbox ADD COLUMN html_body TEXT;
+UPDATE email_outbox SET html_body = text_body;
+ALTER TABLE email_outbox ALTER COLUMN html_body SET NOT NULL;
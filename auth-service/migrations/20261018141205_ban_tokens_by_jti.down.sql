@@ -0,0 +1,2 @@
+DELETE FROM banned_tokens;
+ALTER TABLE banned_tokens RENAME COLUMN jti TO token_hash;
@@ -0,0 +1,4 @@
+-- Tokens are banned by their `jti` claim. Tokens issued before that have no `jti` and are
+-- rejected anyway, so their bans are dropped.
+DELETE FROM banned_tokens;
+ALTER TABLE banned_tokens RENAME COLUMN token_hash TO jti;
@@ -0,0 +1 @@
+-- The blanked bodies can't be restored
@@ -0,0 +1,2 @@
+-- Dead-lettered emails no longer keep their bodies, which may hold 2FA codes
+UPDATE email_outbox SET html_body = '', text_body = '' WHERE status = 'dead_lettered';
@@ -0,0 +1,7 @@
+-- Same schema as the PostgreSQL users table, including its later migrations
+CREATE TABLE IF NOT EXISTS users(
+   email TEXT NOT NULL PRIMARY KEY,
+   password_hash TEXT NOT NULL,
+   requires_2fa BOOLEAN NOT NULL DEFAULT FALSE,
+   locale TEXT
+);
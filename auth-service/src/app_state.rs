@@ -1,20 +1,54 @@
 use std::sync::Arc;
-use tokio::sync::RwLock;
 
-use crate::domain::{BannedTokenStore, EmailClient, TwoFACodeStore, UserStore};
+use crate::{
+    domain::{
+        BannedTokenStore, Clock, EmailClient, EmailOutboxStore, HealthCheck, IdGenerator,
+        TwoFACodeStore, UserStore, WebhookStore,
+    },
+    services::{
+        capturing_email_client::CapturingEmailClient, email_outbox::EmailOutbox,
+        email_templates::EmailTemplates, health_checks::HealthChecks,
+        random_id_generator::RandomIdGenerator, system_clock::SystemClock,
+        webhooks::WebhookPublisher,
+    },
+    settings::AuthSettings,
+    utils::shutdown::Shutdown,
+};
 
 // Using a type alias to improve readability!
-pub type UserStoreType = Arc<RwLock<dyn UserStore + Send + Sync>>;
-pub type BannedTokenStoreType = Arc<RwLock<dyn BannedTokenStore + Send + Sync>>;
-pub type TwoFACodeStoreType = Arc<RwLock<dyn TwoFACodeStore + Send + Sync>>;
+pub type UserStoreType = Arc<dyn UserStore + Send + Sync>;
+pub type BannedTokenStoreType = Arc<dyn BannedTokenStore + Send + Sync>;
+pub type TwoFACodeStoreType = Arc<dyn TwoFACodeStore + Send + Sync>;
 pub type EmailClientType = Arc<dyn EmailClient + Send + Sync>;
+pub type EmailOutboxStoreType = Arc<dyn EmailOutboxStore + Send + Sync>;
+pub type EmailOutboxType = Arc<EmailOutbox>;
+pub type EmailTemplatesType = Arc<EmailTemplates>;
+pub type WebhookStoreType = Arc<dyn WebhookStore + Send + Sync>;
+pub type WebhookPublisherType = Arc<WebhookPublisher>;
+pub type AuthSettingsType = Arc<AuthSettings>;
+pub type ClockType = Arc<dyn Clock + Send + Sync>;
+pub type IdGeneratorType = Arc<dyn IdGenerator + Send + Sync>;
+pub type MailboxType = Arc<CapturingEmailClient>;
+pub type HealthCheckType = Arc<dyn HealthCheck + Send + Sync>;
+pub type HealthChecksType = Arc<HealthChecks>;
 
 #[derive(Clone)]
 pub struct AppState {
     pub user_store: UserStoreType,
     pub banned_token_store: BannedTokenStoreType,
     pub two_fa_code_store: TwoFACodeStoreType,
-    pub email_client: EmailClientType,
+    pub email_outbox: EmailOutboxType,
+    pub email_templates: EmailTemplatesType,
+    pub webhook_publisher: WebhookPublisherType,
+    pub auth_settings: AuthSettingsType,
+    pub clock: ClockType,
+    pub id_generator: IdGeneratorType,
+    // Emails shown at `/dev/mailbox`, only with the `capture` email client
+    pub mailbox: Option<MailboxType>,
+    // Dependencies probed by `/health/ready`, none by default
+    pub health_checks: HealthChecksType,
+    // Triggered when the service starts shutting down, which fails `/health/ready`
+    pub shutdown: Shutdown,
 }
 
 impl AppState {
@@ -22,13 +56,49 @@ impl AppState {
         user_store: UserStoreType,
         banned_token_store: BannedTokenStoreType,
         two_fa_code_store: TwoFACodeStoreType,
-        email_client: EmailClientType,
+        email_outbox: EmailOutboxType,
+        email_templates: EmailTemplatesType,
+        webhook_publisher: WebhookPublisherType,
+        auth_settings: AuthSettingsType,
     ) -> Self {
         Self {
             user_store,
             banned_token_store,
             two_fa_code_store,
-            email_client,
+            email_outbox,
+            email_templates,
+            webhook_publisher,
+            auth_settings,
+            clock: Arc::new(SystemClock),
+            id_generator: Arc::new(RandomIdGenerator),
+            mailbox: None,
+            health_checks: Arc::new(HealthChecks::default()),
+            shutdown: Shutdown::default(),
         }
     }
+
+    pub fn with_clock(mut self, clock: ClockType) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_id_generator(mut self, id_generator: IdGeneratorType) -> Self {
+        self.id_generator = id_generator;
+        self
+    }
+
+    pub fn with_mailbox(mut self, mailbox: MailboxType) -> Self {
+        self.mailbox = Some(mailbox);
+        self
+    }
+
+    pub fn with_health_checks(mut self, health_checks: HealthChecksType) -> Self {
+        self.health_checks = health_checks;
+        self
+    }
+
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
 }
@@ -0,0 +1,6 @@
+use chrono::{DateTime, Utc};
+
+// Source of the current time, so expiry can be tested without sleeping
+pub trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
@@ -1,3 +1,6 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
 use color_eyre::eyre::{eyre, Context, Report, Result};
 use rand::Rng;
 use secrecy::{ExposeSecret, SecretString};
@@ -6,11 +9,22 @@ use uuid::Uuid;
 
 use crate::domain::Email;
 
-use super::User;
+use super::{OutboxEmail, User, WebhookDelivery, WebhookDeliveryAttempt};
 
 #[async_trait::async_trait]
 pub trait UserStore {
-    async fn add_user(&mut self, user: User) -> Result<(), UserStoreError>;
+    async fn add_user(&self, user: User) -> Result<(), UserStoreError>;
+    // Adds the user along with the webhook deliveries its signup triggers. A store sharing its
+    // database with the webhook queue writes both in one transaction and returns no deliveries,
+    // the others only add the user and hand the deliveries back for the caller to queue.
+    async fn add_user_with_deliveries(
+        &self,
+        user: User,
+        deliveries: Vec<WebhookDelivery>,
+    ) -> Result<Vec<WebhookDelivery>, UserStoreError> {
+        self.add_user(user).await?;
+        Ok(deliveries)
+    }
     async fn get_user(&self, email: &Email) -> Result<User, UserStoreError>;
     async fn validate_user(
         &self,
@@ -45,8 +59,13 @@ impl PartialEq for UserStoreError {
 
 #[async_trait::async_trait]
 pub trait BannedTokenStore {
-    async fn add_token(&mut self, token: &SecretString) -> Result<(), BannedTokenStoreError>;
-    async fn contains_token(&self, token: &SecretString) -> Result<bool, BannedTokenStoreError>;
+    // Tokens are banned by their `jti` claim, until `expires_at` when the token itself expires
+    async fn add_token(
+        &self,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), BannedTokenStoreError>;
+    async fn contains_token(&self, jti: &str) -> Result<bool, BannedTokenStoreError>;
 }
 
 #[derive(Debug, Error)]
@@ -59,12 +78,12 @@ pub enum BannedTokenStoreError {
 #[async_trait::async_trait]
 pub trait TwoFACodeStore {
     async fn add_code(
-        &mut self,
+        &self,
         email: Email,
         login_attempt_id: LoginAttemptId,
         code: TwoFACode,
     ) -> Result<(), TwoFACodeStoreError>;
-    async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError>;
+    async fn remove_code(&self, email: &Email) -> Result<(), TwoFACodeStoreError>;
     async fn get_code(
         &self,
         email: &Email,
@@ -89,6 +108,94 @@ impl PartialEq for TwoFACodeStoreError {
     }
 }
 
+// This trait represents the persistent queue and delivery log behind outbound webhooks
+#[async_trait::async_trait]
+pub trait WebhookStore {
+    async fn enqueue_delivery(&self, delivery: WebhookDelivery) -> Result<(), WebhookStoreError>;
+    // Returns pending deliveries due at `now` and hides them from other workers until `lease_until`
+    async fn claim_due_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<WebhookDelivery>, WebhookStoreError>;
+    // Persists the new state of `delivery` and appends `attempt` to its delivery log
+    async fn record_attempt(
+        &self,
+        delivery: &WebhookDelivery,
+        attempt: WebhookDeliveryAttempt,
+    ) -> Result<(), WebhookStoreError>;
+    async fn get_delivery(&self, id: &Uuid) -> Result<WebhookDelivery, WebhookStoreError>;
+    async fn get_delivery_log(
+        &self,
+        id: &Uuid,
+    ) -> Result<Vec<WebhookDeliveryAttempt>, WebhookStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum WebhookStoreError {
+    #[error("Webhook delivery not found")]
+    DeliveryNotFound,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for WebhookStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::DeliveryNotFound, Self::DeliveryNotFound)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+// This trait represents the transactional outbox emails are queued in before delivery
+#[async_trait::async_trait]
+pub trait EmailOutboxStore {
+    async fn enqueue_email(&self, email: OutboxEmail) -> Result<(), EmailOutboxStoreError>;
+    // Returns pending emails due at `now` and hides them from other workers until `lease_until`
+    async fn claim_due_emails(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<OutboxEmail>, EmailOutboxStoreError>;
+    // Removes a delivered email, so message contents (e.g. 2FA codes) are not kept around
+    async fn mark_sent(&self, id: &Uuid) -> Result<(), EmailOutboxStoreError>;
+    // Persists attempts, schedule, status, last error and bodies after a failed delivery
+    async fn record_failure(&self, email: &OutboxEmail) -> Result<(), EmailOutboxStoreError>;
+    async fn get_email(&self, id: &Uuid) -> Result<OutboxEmail, EmailOutboxStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum EmailOutboxStoreError {
+    #[error("Outbox email not found")]
+    EmailNotFound,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for EmailOutboxStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::EmailNotFound, Self::EmailNotFound)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+// How long a 2FA code can be used after the login attempt that sent it
+pub const TWO_FA_CODE_TTL: Duration = Duration::from_secs(600);
+
+// Implemented by stores whose backend doesn't drop expired entries by itself
+#[async_trait::async_trait]
+pub trait ExpiringStore {
+    // Deletes the entries that expired at `now` and returns how many were removed
+    async fn purge_expired(&self, now: DateTime<Utc>) -> Result<u64>;
+}
+
 #[derive(Debug, Clone)]
 pub struct LoginAttemptId(SecretString);
 
@@ -106,8 +213,7 @@ impl AsRef<SecretString> for LoginAttemptId {
 
 impl LoginAttemptId {
     pub fn parse(id: SecretString) -> Result<Self> {
-        let parsed_id =
-            Uuid::parse_str(&id.expose_secret()).wrap_err("Invalid login attempt id")?;
+        let parsed_id = Uuid::parse_str(id.expose_secret()).wrap_err("Invalid login attempt id")?;
         Ok(Self(SecretString::new(
             parsed_id.to_string().into_boxed_str(),
         )))
@@ -8,6 +8,7 @@ pub trait EmailClient {
         &self,
         recipient: &Email,
         subject: &str,
-        content: &str,
+        html_body: &str,
+        text_body: &str,
     ) -> Result<()>;
 }
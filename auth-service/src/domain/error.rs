@@ -13,6 +13,8 @@ pub enum AuthAPIError {
     MissingToken,
     #[error("Invalid token")]
     InvalidToken,
+    #[error("Missing or invalid CSRF token")]
+    InvalidCsrfToken,
     #[error("Unexpected error")]
     UnexpectedError(#[source] Report),
 }
@@ -0,0 +1,10 @@
+use color_eyre::eyre::Result;
+
+// A dependency `/health/ready` probes, e.g. the database behind the stores
+#[async_trait::async_trait]
+pub trait HealthCheck {
+    // Key of the dependency in the readiness report
+    fn name(&self) -> &'static str;
+
+    async fn check(&self) -> Result<()>;
+}
@@ -0,0 +1,11 @@
+use uuid::Uuid;
+
+use super::{LoginAttemptId, TwoFACode};
+
+// Source of the login attempt ids and 2FA codes handed out at login, and of the jti of issued
+// tokens and the ids of queued emails and webhooks, so tests can predict them
+pub trait IdGenerator {
+    fn login_attempt_id(&self) -> LoginAttemptId;
+    fn two_fa_code(&self) -> TwoFACode;
+    fn uuid(&self) -> Uuid;
+}
@@ -0,0 +1,97 @@
+use std::str::FromStr;
+
+use color_eyre::eyre::{eyre, Report, Result};
+
+// Languages we have email templates for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Locale {
+    #[default]
+    En,
+    Es,
+    Fr,
+}
+
+impl Locale {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::Es => "es",
+            Self::Fr => "fr",
+        }
+    }
+
+    // Picks the supported language the client ranks highest in an `Accept-Language` header,
+    // e.g. "fr-CH, fr;q=0.9, en;q=0.8". Region subtags are ignored.
+    pub fn from_accept_language(header: &str) -> Option<Self> {
+        let mut candidates: Vec<(f32, Self)> = header
+            .split(',')
+            .filter_map(|entry| {
+                let mut parts = entry.trim().split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+                let language = tag.split('-').next()?.to_ascii_lowercase();
+                let locale = language.parse().ok()?;
+                (quality > 0.0).then_some((quality, locale))
+            })
+            .collect();
+
+        // Stable sort keeps the header order for equal weights
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+        candidates.first().map(|(_, locale)| *locale)
+    }
+}
+
+impl FromStr for Locale {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "en" => Ok(Self::En),
+            "es" => Ok(Self::Es),
+            "fr" => Ok(Self::Fr),
+            _ => Err(eyre!("{} is not a supported locale", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locale_is_parsed_from_language_code() {
+        assert_eq!("en".parse::<Locale>().unwrap(), Locale::En);
+        assert_eq!("es".parse::<Locale>().unwrap(), Locale::Es);
+        assert_eq!("fr".parse::<Locale>().unwrap(), Locale::Fr);
+        assert!("de".parse::<Locale>().is_err());
+    }
+
+    #[test]
+    fn accept_language_picks_the_highest_weighted_supported_locale() {
+        assert_eq!(Locale::from_accept_language("es"), Some(Locale::Es));
+        assert_eq!(
+            Locale::from_accept_language("fr-CH, fr;q=0.9, en;q=0.8"),
+            Some(Locale::Fr)
+        );
+        assert_eq!(
+            Locale::from_accept_language("en;q=0.5, es;q=0.7"),
+            Some(Locale::Es)
+        );
+        assert_eq!(
+            Locale::from_accept_language("de-DE, es;q=0.3"),
+            Some(Locale::Es)
+        );
+        assert_eq!(Locale::from_accept_language("ES-mx"), Some(Locale::Es));
+    }
+
+    #[test]
+    fn accept_language_without_supported_locale_yields_none() {
+        assert_eq!(Locale::from_accept_language(""), None);
+        assert_eq!(Locale::from_accept_language("de, it;q=0.8"), None);
+        assert_eq!(Locale::from_accept_language("*"), None);
+        assert_eq!(Locale::from_accept_language("fr;q=0"), None);
+        assert_eq!(Locale::from_accept_language("fr;q=abc"), None);
+    }
+}
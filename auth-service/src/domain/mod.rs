@@ -1,13 +1,25 @@
+mod clock;
 mod data_stores;
 mod email;
 mod email_client;
 mod error;
+mod health_check;
+mod id_generator;
+mod locale;
+mod outbox_email;
 mod password;
 mod user;
+mod webhook;
 
+pub use clock::*;
 pub use data_stores::*;
 pub use email::*;
 pub use email_client::*;
 pub use error::*;
+pub use health_check::*;
+pub use id_generator::*;
+pub use locale::*;
+pub use outbox_email::*;
 pub use password::*;
 pub use user::*;
+pub use webhook::*;
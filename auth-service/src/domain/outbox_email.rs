@@ -0,0 +1,82 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Report, Result};
+use uuid::Uuid;
+
+use super::{Clock, Email, IdGenerator};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutboxEmailStatus {
+    Pending,
+    // Delivery was given up on after the retry policy was exhausted
+    DeadLettered,
+}
+
+impl OutboxEmailStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::DeadLettered => "dead_lettered",
+        }
+    }
+}
+
+impl FromStr for OutboxEmailStatus {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pending" => Ok(Self::Pending),
+            "dead_lettered" => Ok(Self::DeadLettered),
+            _ => Err(eyre!("{} is not a known outbox email status", s)),
+        }
+    }
+}
+
+// An email waiting in the transactional outbox to be handed to the `EmailClient`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutboxEmail {
+    pub id: Uuid,
+    pub recipient: Email,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+    pub status: OutboxEmailStatus,
+    pub attempts: u32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl OutboxEmail {
+    pub fn new(
+        recipient: Email,
+        subject: String,
+        html_body: String,
+        text_body: String,
+        clock: &dyn Clock,
+        id_generator: &dyn IdGenerator,
+    ) -> Self {
+        let now = clock.now();
+        Self {
+            id: id_generator.uuid(),
+            recipient,
+            subject,
+            html_body,
+            text_body,
+            status: OutboxEmailStatus::Pending,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+            created_at: now,
+        }
+    }
+    // Gives up on the email. The bodies are dropped since they may hold a 2FA code, while the
+    // recipient, subject and last error are kept for inspection
+    pub fn dead_letter(&mut self) {
+        self.status = OutboxEmailStatus::DeadLettered;
+        self.html_body.clear();
+        self.text_body.clear();
+    }
+}
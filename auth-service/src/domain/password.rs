@@ -2,10 +2,12 @@ use argon2::{
     password_hash::{rand_core::OsRng, SaltString},
     Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
 };
-use color_eyre::eyre::{eyre, Context, Result};
+use std::time::Instant;
+
+use color_eyre::eyre::{eyre, Result};
 use secrecy::{ExposeSecret, SecretString};
 
-use crate::domain::UserStoreError;
+use crate::{domain::UserStoreError, utils::metrics::record_password_hash};
 
 #[derive(Debug, Clone)]
 pub struct Password(SecretString); // updated!
@@ -24,18 +26,18 @@ impl Password {
         }
         let result = compute_password_hash(&s)
             .await
-            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+            .map_err(UserStoreError::UnexpectedError)?;
 
         Ok(Self(result))
     }
 
     #[tracing::instrument(name = "HashedPassword Parse password hash", skip_all)]
     pub fn parse_password_hash(hash: SecretString) -> Result<Self> {
-        match PasswordHash::new(&hash.expose_secret()) {
+        match PasswordHash::new(hash.expose_secret()) {
             Ok(hashed_password) => Ok(Self(SecretString::new(
                 hashed_password.to_string().into_boxed_str(),
             ))),
-            Err(_) => return Err(eyre!("Failed to parse string to a HashedPassword type")),
+            Err(_) => Err(eyre!("Failed to parse string to a HashedPassword type")),
         }
     }
 
@@ -50,9 +52,11 @@ impl Password {
             current_span.in_scope(|| {
                 let expected_password_hash = PasswordHash::new(&password_hash)?;
 
-                Argon2::default()
-                    .verify_password(password_candidate.as_bytes(), &expected_password_hash)
-                    .map_err(|e| e.into())
+                let start = Instant::now();
+                let verified = Argon2::default()
+                    .verify_password(password_candidate.as_bytes(), &expected_password_hash);
+                record_password_hash("verify", start.elapsed());
+                verified.map_err(|e| e.into())
             })
         })
         .await?
@@ -70,6 +74,7 @@ async fn compute_password_hash(password: &SecretString) -> Result<SecretString>
     let password_hash_res = tokio::task::spawn_blocking(move || {
         current_span.in_scope(|| {
             let salt: SaltString = SaltString::generate(&mut OsRng);
+            let start = Instant::now();
             let password_hash = Argon2::new(
                 Algorithm::Argon2id,
                 Version::V0x13,
@@ -77,6 +82,7 @@ async fn compute_password_hash(password: &SecretString) -> Result<SecretString>
             )
             .hash_password(password.as_bytes(), &salt)?
             .to_string();
+            record_password_hash("hash", start.elapsed());
 
             Ok(SecretString::new(password_hash.into_boxed_str()))
         })
@@ -1,4 +1,4 @@
-use crate::domain::{Email, Password};
+use crate::domain::{Email, Locale, Password};
 
 // The User struct should contain 3 fields. email, which is a String;
 // password, which is also a String; and requires_2fa, which is a boolean.
@@ -7,6 +7,8 @@ pub struct User {
     email: Email,
     password: Password,
     requires_2fa: bool,
+    // Language the user wants their emails in, if they picked one
+    locale: Option<Locale>,
 }
 
 impl User {
@@ -15,9 +17,15 @@ impl User {
             email,
             password,
             requires_2fa,
+            locale: None,
         }
     }
 
+    pub fn with_locale(mut self, locale: Option<Locale>) -> Self {
+        self.locale = locale;
+        self
+    }
+
     pub fn email(&self) -> &Email {
         &self.email
     }
@@ -29,4 +37,8 @@ impl User {
     pub fn requires_2fa(&self) -> bool {
         self.requires_2fa
     }
+
+    pub fn locale(&self) -> Option<Locale> {
+        self.locale
+    }
 }
@@ -0,0 +1,177 @@
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Report, Result};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{Clock, Email, IdGenerator};
+
+// Account lifecycle events downstream systems can subscribe to. The service has no password
+// change or account deletion yet, so only the signup events are emitted for now
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum WebhookEventType {
+    #[serde(rename = "user.signed_up")]
+    UserSignedUp,
+    #[serde(rename = "user.2fa_enabled")]
+    TwoFactorEnabled,
+    #[serde(rename = "user.password_changed")]
+    PasswordChanged,
+    #[serde(rename = "user.deleted")]
+    UserDeleted,
+}
+
+impl WebhookEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UserSignedUp => "user.signed_up",
+            Self::TwoFactorEnabled => "user.2fa_enabled",
+            Self::PasswordChanged => "user.password_changed",
+            Self::UserDeleted => "user.deleted",
+        }
+    }
+}
+
+impl fmt::Display for WebhookEventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for WebhookEventType {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "user.signed_up" => Ok(Self::UserSignedUp),
+            "user.2fa_enabled" => Ok(Self::TwoFactorEnabled),
+            "user.password_changed" => Ok(Self::PasswordChanged),
+            "user.deleted" => Ok(Self::UserDeleted),
+            _ => Err(eyre!("{} is not a known webhook event type", s)),
+        }
+    }
+}
+
+// The JSON document POSTed to every subscriber of an event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookEvent {
+    pub id: Uuid,
+    #[serde(rename = "type")]
+    pub event_type: WebhookEventType,
+    pub occurred_at: DateTime<Utc>,
+    pub data: WebhookEventData,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookEventData {
+    pub email: String,
+}
+
+impl WebhookEvent {
+    pub fn new(
+        event_type: WebhookEventType,
+        email: &Email,
+        clock: &dyn Clock,
+        id_generator: &dyn IdGenerator,
+    ) -> Self {
+        Self {
+            id: id_generator.uuid(),
+            event_type,
+            occurred_at: clock.now(),
+            data: WebhookEventData {
+                email: email.as_ref().expose_secret().to_owned(),
+            },
+        }
+    }
+}
+
+// A receiver endpoint, configured under `webhooks.subscriptions` in settings
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookSubscription {
+    pub id: String,
+    pub url: String,
+    pub secret: SecretString,
+    pub events: Vec<WebhookEventType>,
+}
+
+impl WebhookSubscription {
+    pub fn is_subscribed_to(&self, event_type: WebhookEventType) -> bool {
+        self.events.contains(&event_type)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookDeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+impl WebhookDeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Delivered => "delivered",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+impl FromStr for WebhookDeliveryStatus {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pending" => Ok(Self::Pending),
+            "delivered" => Ok(Self::Delivered),
+            "failed" => Ok(Self::Failed),
+            _ => Err(eyre!("{} is not a known webhook delivery status", s)),
+        }
+    }
+}
+
+// One queued event for one subscription
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub subscription_id: String,
+    pub event_type: WebhookEventType,
+    pub payload: String,
+    pub status: WebhookDeliveryStatus,
+    pub attempts: u32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl WebhookDelivery {
+    pub fn new(
+        subscription_id: String,
+        event_type: WebhookEventType,
+        payload: String,
+        clock: &dyn Clock,
+        id_generator: &dyn IdGenerator,
+    ) -> Self {
+        let now = clock.now();
+        Self {
+            id: id_generator.uuid(),
+            subscription_id,
+            event_type,
+            payload,
+            status: WebhookDeliveryStatus::Pending,
+            attempts: 0,
+            next_attempt_at: now,
+            created_at: now,
+        }
+    }
+}
+
+// A single entry of the delivery log
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookDeliveryAttempt {
+    pub delivery_id: Uuid,
+    pub attempt: u32,
+    pub attempted_at: DateTime<Utc>,
+    pub response_status: Option<u16>,
+    pub error: Option<String>,
+}
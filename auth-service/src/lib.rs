@@ -1,20 +1,37 @@
 use std::error::Error;
 use std::fmt::Write;
+use std::future::IntoFuture;
+use std::net::SocketAddr;
+use std::time::Duration;
 
 use axum::{
-    http::{Method, StatusCode},
+    extract::connect_info::{ConnectInfo, IntoMakeServiceWithConnectInfo},
+    http::StatusCode,
+    middleware::{self, AddExtension},
     response::{IntoResponse, Response},
-    routing::post,
+    routing::{get, post},
     serve::Serve,
     Json, Router,
 };
-use redis::{Client, RedisResult};
+#[cfg(feature = "redis")]
+use redis::RedisResult;
+#[cfg(any(feature = "postgres", feature = "sqlite"))]
 use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "redis")]
+use services::data_stores::RedisConnection;
+#[cfg(feature = "redis")]
+use settings::RedisSettings;
+#[cfg(feature = "postgres")]
 use sqlx::{postgres::PgPoolOptions, PgPool};
+#[cfg(feature = "sqlite")]
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions},
+    SqlitePool,
+};
 use tokio::net::TcpListener;
 use tower_http::{
-    cors::CorsLayer,
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
     services::{ServeDir, ServeFile},
     trace::TraceLayer,
 };
@@ -22,63 +39,172 @@ use tower_http::{
 use crate::{
     app_state::AppState,
     domain::AuthAPIError,
-    routes::{login, logout, signup, verify_2fa, verify_token},
-    utils::tracing::{make_span_with_request_id, on_request, on_response},
+    routes::{
+        dev_mailbox, health_live, health_ready, login, logout, signup, verify_2fa, verify_token,
+    },
+    settings::ApplicationSettings,
+    utils::{
+        cors::cors_layer,
+        csrf::require_csrf_token,
+        metrics::{prometheus_handle, render_metrics, track_http_requests},
+        shutdown::Shutdown,
+        tracing::{make_span_with_request_id, on_request, on_response, scope_request_span},
+    },
 };
 
 pub mod app_state;
 pub mod domain;
 pub mod routes;
 pub mod services;
+pub mod settings;
 pub mod utils;
 
 // This struct encapsulates our application-related logic.
 pub struct Application {
-    server: Serve<TcpListener, Router, Router>,
+    // Handlers see the peer address, e.g. to keep `/dev/mailbox` local
+    server: Serve<
+        TcpListener,
+        IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+        AddExtension<Router, ConnectInfo<SocketAddr>>,
+    >,
     // address is exposed as a public field
     // so we have access to it in tests.
     pub address: String,
+    // Serves `/metrics` when it has its own address
+    metrics_server: Option<Serve<TcpListener, Router, Router>>,
+    pub metrics_address: Option<String>,
+    // When to stop serving, see `run`
+    shutdown: Shutdown,
+    shutdown_delay: Duration,
+    drain_timeout: Duration,
 }
 
 impl Application {
-    pub async fn build(app_state: AppState, address: &str) -> Result<Self, Box<dyn Error>> {
-        let allowed_origins = [
-            "http://localhost:8000".parse()?,
-            "http://165.227.197.168:8000".parse()?,
-        ];
-
-        let cors = CorsLayer::new()
-            .allow_methods([Method::GET, Method::POST])
-            .allow_credentials(true)
-            .allow_origin(allowed_origins);
+    pub async fn build(
+        app_state: AppState,
+        settings: &ApplicationSettings,
+    ) -> Result<Self, Box<dyn Error>> {
+        let cors = cors_layer(&settings.allowed_origins)?;
 
+        let shutdown = app_state.shutdown.clone();
         let assets_dir =
             ServeDir::new("assets").not_found_service(ServeFile::new("assets/index.html"));
-        let router = Router::new()
+        let mut router = Router::new()
             .fallback_service(assets_dir)
             .route("/signup", post(signup))
             .route("/login", post(login))
             .route("/verify-2fa", post(verify_2fa))
-            .route("/logout", post(logout))
             .route("/verify-token", post(verify_token))
+            .route("/health/live", get(health_live))
+            .route("/health/ready", get(health_ready));
+        if app_state.mailbox.is_some() {
+            router = router.route("/dev/mailbox", get(dev_mailbox));
+        }
+        // Routes acting on the caller's session, which need a CSRF token alongside the auth cookie
+        let session_routes = Router::new().route("/logout", post(logout)).route_layer(
+            middleware::from_fn_with_state(app_state.clone(), require_csrf_token),
+        );
+        let router = router.merge(session_routes);
+        let metrics = get(render_metrics);
+        let (router, metrics_server, metrics_address) = match &settings.metrics_address {
+            Some(metrics_address) => {
+                let listener = tokio::net::TcpListener::bind(metrics_address).await?;
+                let address = listener.local_addr()?.to_string();
+                let metrics_router = Router::new()
+                    .route("/metrics", metrics)
+                    .with_state(prometheus_handle());
+                (
+                    router,
+                    Some(axum::serve(listener, metrics_router)),
+                    Some(address),
+                )
+            }
+            None => {
+                let router = router.route("/metrics", metrics.with_state(prometheus_handle()));
+                (router, None, None)
+            }
+        };
+        let router = router
             .with_state(app_state)
+            .layer(middleware::from_fn(track_http_requests))
+            .layer(middleware::from_fn(scope_request_span))
             .layer(cors)
             .layer(
                 TraceLayer::new_for_http()
                     .make_span_with(make_span_with_request_id)
                     .on_request(on_request)
                     .on_response(on_response),
-            );
-        let listener = tokio::net::TcpListener::bind(address).await?;
+            )
+            // Keeps the caller's `x-request-id` or assigns one, and echoes it in the response
+            .layer(PropagateRequestIdLayer::x_request_id())
+            .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid));
+        let listener = tokio::net::TcpListener::bind(&settings.address).await?;
         let address = listener.local_addr()?.to_string();
-        let server = axum::serve(listener, router);
+        let server = axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        );
 
-        Ok(Application { server, address })
+        Ok(Application {
+            server,
+            address,
+            metrics_server,
+            metrics_address,
+            shutdown,
+            shutdown_delay: settings.shutdown_delay,
+            drain_timeout: settings.drain_timeout,
+        })
     }
 
+    // Serves until the app state's `Shutdown` is triggered, then keeps serving for `shutdown_delay`
+    // while `/health/ready` fails. New connections are refused after that, and in-flight requests
+    // get `drain_timeout` to complete before they are dropped.
     pub async fn run(self) -> Result<(), std::io::Error> {
-        tracing::info!("listening on {}", &self.address);
-        self.server.await
+        let Self {
+            server,
+            address,
+            metrics_server,
+            metrics_address,
+            shutdown,
+            shutdown_delay,
+            drain_timeout,
+        } = self;
+        let stop_accepting = || {
+            let triggered = shutdown.wait();
+            async move {
+                triggered.await;
+                tokio::time::sleep(shutdown_delay).await;
+            }
+        };
+        let drain_deadline = {
+            let stopped_accepting = stop_accepting();
+            async move {
+                stopped_accepting.await;
+                tracing::info!("Refusing new connections, draining in-flight requests");
+                tokio::time::sleep(drain_timeout).await;
+            }
+        };
+
+        tracing::info!("listening on {}", &address);
+        let server = server.with_graceful_shutdown(stop_accepting());
+        let serving = async move {
+            match (metrics_server, metrics_address) {
+                (Some(metrics_server), Some(metrics_address)) => {
+                    tracing::info!("serving metrics on {}", metrics_address);
+                    let metrics_server = metrics_server.with_graceful_shutdown(stop_accepting());
+                    tokio::try_join!(server.into_future(), metrics_server.into_future())?;
+                    Ok(())
+                }
+                _ => server.await,
+            }
+        };
+        tokio::select! {
+            result = serving => result,
+            _ = drain_deadline => {
+                tracing::warn!("Requests still in flight after the drain timeout are dropped");
+                Ok(())
+            }
+        }
     }
 }
 
@@ -98,6 +224,9 @@ impl IntoResponse for AuthAPIError {
             }
             AuthAPIError::MissingToken => (StatusCode::BAD_REQUEST, "Missing auth token"),
             AuthAPIError::InvalidToken => (StatusCode::UNAUTHORIZED, "Invalid auth token"),
+            AuthAPIError::InvalidCsrfToken => {
+                (StatusCode::FORBIDDEN, "Missing or invalid CSRF token")
+            }
             AuthAPIError::UnexpectedError(_) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, "Unexpected error")
             }
@@ -124,6 +253,7 @@ fn log_error_chain(e: &(dyn Error + 'static)) {
     tracing::error!("{}", report);
 }
 
+#[cfg(feature = "postgres")]
 pub async fn get_postgres_pool(url: &SecretString) -> Result<PgPool, sqlx::Error> {
     // Create a new PostgreSQL connection pool
     PgPoolOptions::new()
@@ -132,7 +262,23 @@ pub async fn get_postgres_pool(url: &SecretString) -> Result<PgPool, sqlx::Error
         .await
 }
 
-pub fn get_redis_client(redis_hostname: String) -> RedisResult<Client> {
-    let redis_url = format!("redis://{}/", redis_hostname);
-    redis::Client::open(redis_url)
+#[cfg(feature = "sqlite")]
+pub async fn get_sqlite_pool(url: &SecretString) -> Result<SqlitePool, sqlx::Error> {
+    // Create the database file on first start. WAL lets reads proceed while a write is in progress
+    let options = url
+        .expose_secret()
+        .parse::<SqliteConnectOptions>()?
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal);
+
+    SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect_with(options)
+        .await
+}
+
+// Connects in the mode `redis.mode` selects, see `RedisConnection`
+#[cfg(feature = "redis")]
+pub async fn get_redis_connection(settings: &RedisSettings) -> RedisResult<RedisConnection> {
+    RedisConnection::connect(settings).await
 }
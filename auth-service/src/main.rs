@@ -1,56 +1,377 @@
 use std::sync::Arc;
 
+#[cfg(feature = "postgres")]
+use auth_service::get_postgres_pool;
+#[cfg(feature = "redis")]
+use auth_service::get_redis_connection;
+#[cfg(feature = "postmark")]
+use auth_service::services::postmark_email_client::PostmarkEmailClient;
+#[cfg(feature = "smtp")]
+use auth_service::services::smtp_email_client::SmtpEmailClient;
+#[cfg(feature = "postgres")]
+use auth_service::services::{
+    data_stores::{
+        PostgresBannedTokenStore, PostgresEmailOutboxStore, PostgresTwoFACodeStore,
+        PostgresUserStore, PostgresWebhookStore,
+    },
+    health_checks::PostgresHealthCheck,
+};
+#[cfg(any(feature = "postgres", feature = "sqlite"))]
+use auth_service::settings::DatabaseSettings;
 use auth_service::{
-    app_state::AppState,
-    domain::Email,
-    get_postgres_pool, get_redis_client,
+    app_state::{
+        AppState, BannedTokenStoreType, ClockType, EmailClientType, EmailOutboxStoreType,
+        HealthCheckType, IdGeneratorType, MailboxType, TwoFACodeStoreType, UserStoreType,
+        WebhookStoreType,
+    },
     services::{
-        data_stores::{PostgresUserStore, RedisBannedTokenStore, RedisTwoFACodeStore},
-        postmark_email_client::PostmarkEmailClient,
+        capturing_email_client::CapturingEmailClient,
+        data_stores::{
+            HashMapEmailOutboxStore, HashMapTwoFACodeStore, HashMapUserStore, HashMapWebhookStore,
+            HashSetBannedTokenStore, Instrumented,
+        },
+        email_outbox::EmailOutbox,
+        email_templates::EmailTemplates,
+        expiry_purge::{ExpiringStoreType, ExpiryPurgeWorker},
+        health_checks::HealthChecks,
+        mock_email_client::MockEmailClient,
+        random_id_generator::RandomIdGenerator,
+        system_clock::SystemClock,
+        webhooks::{WebhookPublisher, WebhookWorker},
     },
+    settings::{EmailClientKind, EmailSettings, Settings, StorageBackend, WebhookSettings},
     utils::{
-        constants::{prod, DATABASE_URL, POSTMARK_AUTH_TOKEN, REDIS_HOST_NAME},
+        shutdown::{BackgroundWorkers, Shutdown},
         tracing::init_tracing,
     },
     Application,
 };
+#[cfg(feature = "sqlite")]
+use auth_service::{
+    get_sqlite_pool,
+    services::{data_stores::SqliteUserStore, health_checks::SqliteHealthCheck},
+};
+#[cfg(feature = "redis")]
+use auth_service::{
+    services::{
+        data_stores::{RedisBannedTokenStore, RedisConnection, RedisTwoFACodeStore},
+        health_checks::RedisHealthCheck,
+    },
+    settings::RedisSettings,
+};
 use reqwest::Client;
-use secrecy::SecretString;
+#[cfg(feature = "postgres")]
 use sqlx::PgPool;
-use tokio::sync::RwLock;
+#[cfg(feature = "sqlite")]
+use sqlx::SqlitePool;
 
 #[tokio::main]
 async fn main() {
     color_eyre::install().expect("Failed to install color_eyre!");
-    init_tracing().expect("Failed to initialize tracing");
-    // We will use this PostgreSQL pool in the next task!
-    let pg_pool = configure_postgresql().await;
-    let redis_connection = Arc::new(RwLock::new(configure_redis()));
-    let user_store = Arc::new(RwLock::new(PostgresUserStore::new(pg_pool)));
-    let banned_token_store = Arc::new(RwLock::new(RedisBannedTokenStore::new(
-        redis_connection.clone(),
-    )));
-    let two_fa_code_store = Arc::new(RwLock::new(RedisTwoFACodeStore::new(
-        redis_connection.clone(),
-    )));
-    let email_client = Arc::new(configure_postmark_email_client());
+    let settings = match Settings::load() {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // Flushes the remaining spans to the collector on the way out
+    let _tracing_guard = init_tracing(&settings.tracing).expect("Failed to initialize tracing");
+
+    let shutdown = Shutdown::default();
+    tokio::spawn(shutdown.clone().trigger_on_signal());
+
+    let clock: ClockType = Arc::new(SystemClock);
+    let id_generator: IdGeneratorType = Arc::new(RandomIdGenerator);
+    let stores = Stores::connect(&settings, clock.clone()).await;
+    let webhook_store = stores.webhook_store();
+    let user_store = stores.user_store();
+    let banned_token_store = stores.banned_token_store();
+    let two_fa_code_store = stores.two_fa_code_store();
+    let mailbox: MailboxType = Arc::new(CapturingEmailClient::default().with_clock(clock.clone()));
+    let (email_client, email_health_check) = configure_email_client(&settings.email, &mailbox);
+    let email_outbox_store = stores.email_outbox_store();
+    let email_outbox = Arc::new(
+        EmailOutbox::new(email_outbox_store)
+            .with_clock(clock.clone())
+            .with_id_generator(id_generator.clone()),
+    );
+    // Stopped in this order once the app has drained, emails first as they hold 2FA codes
+    let mut workers = BackgroundWorkers::default();
+    let email_outbox_worker = email_outbox.worker(
+        email_client,
+        settings.email_outbox.retry,
+        settings.email_outbox.poll_interval,
+    );
+    workers.spawn("email_outbox", |shutdown| email_outbox_worker.run(shutdown));
+    let email_templates = Arc::new(EmailTemplates::new(settings.email.brand.clone()));
+    let webhook_publisher = Arc::new(
+        WebhookPublisher::new(
+            settings.webhooks.subscriptions.clone(),
+            webhook_store.clone(),
+        )
+        .with_clock(clock.clone())
+        .with_id_generator(id_generator.clone()),
+    );
+    let webhook_worker =
+        configure_webhook_worker(&settings.webhooks, webhook_store).with_clock(clock.clone());
+    workers.spawn("webhooks", |shutdown| webhook_worker.run(shutdown));
+    if let Some(worker) = stores.expiry_purge_worker() {
+        workers.spawn("expiry_purge", |shutdown| worker.run(shutdown));
+    }
+
+    let mut health_checks = stores.health_checks();
+    if settings.health.check_email_provider {
+        health_checks.extend(email_health_check);
+    }
+    let health_checks = Arc::new(HealthChecks::new(health_checks, settings.health.timeout));
+
     let app_state = AppState::new(
         user_store,
         banned_token_store,
         two_fa_code_store,
-        email_client,
-    );
+        email_outbox,
+        email_templates,
+        webhook_publisher,
+        Arc::new(settings.auth.clone()),
+    )
+    .with_clock(clock)
+    .with_id_generator(id_generator)
+    .with_health_checks(health_checks)
+    .with_shutdown(shutdown.clone());
+    let app_state = match settings.email.client {
+        EmailClientKind::Capture => app_state.with_mailbox(mailbox),
+        _ => app_state,
+    };
 
-    let app = Application::build(app_state, prod::APP_ADDRESS)
+    let app = Application::build(app_state, &settings.application)
         .await
         .expect("Failed to build app");
 
     app.run().await.expect("Failed to run app");
+    workers.stop(settings.application.drain_timeout).await;
+    tracing::info!("Shut down");
+}
+
+// Builds each store on the backend picked in the settings, connecting to Postgres, SQLite and
+// Redis only when a store uses them. Backends a store doesn't support, or that aren't compiled in,
+// are rejected when loading the settings.
+struct Stores<'a> {
+    settings: &'a Settings,
+    // What the expiring stores take as the current time
+    clock: ClockType,
+    // Shared by the app and the expiry purge worker, whichever backend is picked
+    memory_banned_tokens: HashSetBannedTokenStore,
+    memory_two_fa_codes: HashMapTwoFACodeStore,
+    #[cfg(feature = "postgres")]
+    pg_pool: Option<PgPool>,
+    #[cfg(feature = "sqlite")]
+    sqlite_pool: Option<SqlitePool>,
+    #[cfg(feature = "redis")]
+    redis_connection: Option<RedisConnection>,
+}
+
+impl<'a> Stores<'a> {
+    async fn connect(settings: &'a Settings, clock: ClockType) -> Self {
+        Self {
+            settings,
+            memory_banned_tokens: HashSetBannedTokenStore::default().with_clock(clock.clone()),
+            memory_two_fa_codes: HashMapTwoFACodeStore::default().with_clock(clock.clone()),
+            clock,
+            #[cfg(feature = "postgres")]
+            pg_pool: match settings.storage.uses(StorageBackend::Postgres) {
+                true => Some(configure_postgresql(&settings.database).await),
+                false => None,
+            },
+            #[cfg(feature = "sqlite")]
+            sqlite_pool: match settings.storage.uses(StorageBackend::Sqlite) {
+                true => Some(configure_sqlite(&settings.database).await),
+                false => None,
+            },
+            #[cfg(feature = "redis")]
+            redis_connection: match settings.storage.uses(StorageBackend::Redis) {
+                true => Some(configure_redis(&settings.redis).await),
+                false => None,
+            },
+        }
+    }
+
+    // Each store is wrapped to record its operation latencies, labelled with the backend.
+    // Postgres queues webhook deliveries in its own transaction, settings make sure the webhook
+    // store is on Postgres too
+    fn user_store(&self) -> UserStoreType {
+        let backend = self.settings.storage.users;
+        match backend {
+            StorageBackend::Memory => {
+                Arc::new(Instrumented::new(HashMapUserStore::default(), backend))
+            }
+            #[cfg(feature = "postgres")]
+            StorageBackend::Postgres => Arc::new(Instrumented::new(
+                PostgresUserStore::new(self.pg_pool()),
+                backend,
+            )),
+            #[cfg(feature = "sqlite")]
+            StorageBackend::Sqlite => Arc::new(Instrumented::new(
+                SqliteUserStore::new(self.sqlite_pool()),
+                backend,
+            )),
+            backend => unsupported_backend("user", backend),
+        }
+    }
+
+    fn banned_token_store(&self) -> BannedTokenStoreType {
+        let backend = self.settings.storage.banned_tokens;
+        match backend {
+            StorageBackend::Memory => Arc::new(Instrumented::new(
+                self.memory_banned_tokens.clone(),
+                backend,
+            )),
+            #[cfg(feature = "postgres")]
+            StorageBackend::Postgres => Arc::new(Instrumented::new(
+                PostgresBannedTokenStore::new(self.pg_pool()).with_clock(self.clock.clone()),
+                backend,
+            )),
+            #[cfg(feature = "redis")]
+            StorageBackend::Redis => Arc::new(Instrumented::new(
+                RedisBannedTokenStore::new(self.redis_connection()),
+                backend,
+            )),
+            // Only reachable when a backend's feature is disabled
+            #[allow(unreachable_patterns)]
+            backend => unsupported_backend("banned token", backend),
+        }
+    }
+
+    fn two_fa_code_store(&self) -> TwoFACodeStoreType {
+        let backend = self.settings.storage.two_fa_codes;
+        match backend {
+            StorageBackend::Memory => {
+                Arc::new(Instrumented::new(self.memory_two_fa_codes.clone(), backend))
+            }
+            #[cfg(feature = "postgres")]
+            StorageBackend::Postgres => Arc::new(Instrumented::new(
+                PostgresTwoFACodeStore::new(self.pg_pool()).with_clock(self.clock.clone()),
+                backend,
+            )),
+            #[cfg(feature = "redis")]
+            StorageBackend::Redis => Arc::new(Instrumented::new(
+                RedisTwoFACodeStore::new(self.redis_connection()).with_clock(self.clock.clone()),
+                backend,
+            )),
+            // Only reachable when a backend's feature is disabled
+            #[allow(unreachable_patterns)]
+            backend => unsupported_backend("2FA code", backend),
+        }
+    }
+
+    fn email_outbox_store(&self) -> EmailOutboxStoreType {
+        let backend = self.settings.storage.email_outbox;
+        match backend {
+            StorageBackend::Memory => Arc::new(Instrumented::new(
+                HashMapEmailOutboxStore::default(),
+                backend,
+            )),
+            #[cfg(feature = "postgres")]
+            StorageBackend::Postgres => Arc::new(Instrumented::new(
+                PostgresEmailOutboxStore::new(self.pg_pool()),
+                backend,
+            )),
+            backend => unsupported_backend("email outbox", backend),
+        }
+    }
+
+    fn webhook_store(&self) -> WebhookStoreType {
+        let backend = self.settings.storage.webhooks;
+        match backend {
+            StorageBackend::Memory => {
+                Arc::new(Instrumented::new(HashMapWebhookStore::default(), backend))
+            }
+            #[cfg(feature = "postgres")]
+            StorageBackend::Postgres => Arc::new(Instrumented::new(
+                PostgresWebhookStore::new(self.pg_pool()),
+                backend,
+            )),
+            backend => unsupported_backend("webhook", backend),
+        }
+    }
+
+    // Every external service a store uses, probed by `/health/ready`
+    fn health_checks(&self) -> Vec<HealthCheckType> {
+        // Stays empty when the service is built without any external backend
+        #[allow(unused_mut)]
+        let mut checks: Vec<HealthCheckType> = Vec::new();
+        #[cfg(feature = "postgres")]
+        if let Some(pg_pool) = &self.pg_pool {
+            checks.push(Arc::new(PostgresHealthCheck::new(pg_pool.clone())));
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(sqlite_pool) = &self.sqlite_pool {
+            checks.push(Arc::new(SqliteHealthCheck::new(sqlite_pool.clone())));
+        }
+        #[cfg(feature = "redis")]
+        if let Some(redis_connection) = &self.redis_connection {
+            checks.push(Arc::new(RedisHealthCheck::new(redis_connection.clone())));
+        }
+        checks
+    }
+
+    // Postgres and the in-memory stores keep expired banned tokens and 2FA codes until they are
+    // purged, Redis drops them by itself
+    fn expiry_purge_worker(&self) -> Option<ExpiryPurgeWorker> {
+        let storage = &self.settings.storage;
+        let mut stores: Vec<ExpiringStoreType> = Vec::new();
+        match storage.banned_tokens {
+            StorageBackend::Memory => stores.push(Arc::new(self.memory_banned_tokens.clone())),
+            #[cfg(feature = "postgres")]
+            StorageBackend::Postgres => {
+                stores.push(Arc::new(PostgresBannedTokenStore::new(self.pg_pool())))
+            }
+            _ => {}
+        }
+        match storage.two_fa_codes {
+            StorageBackend::Memory => stores.push(Arc::new(self.memory_two_fa_codes.clone())),
+            #[cfg(feature = "postgres")]
+            StorageBackend::Postgres => {
+                stores.push(Arc::new(PostgresTwoFACodeStore::new(self.pg_pool())))
+            }
+            _ => {}
+        }
+
+        (!stores.is_empty()).then(|| {
+            ExpiryPurgeWorker::new(stores, storage.purge_interval).with_clock(self.clock.clone())
+        })
+    }
+
+    #[cfg(feature = "postgres")]
+    fn pg_pool(&self) -> PgPool {
+        self.pg_pool
+            .clone()
+            .expect("Postgres is connected when a store uses it")
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn sqlite_pool(&self) -> SqlitePool {
+        self.sqlite_pool
+            .clone()
+            .expect("SQLite is connected when a store uses it")
+    }
+
+    #[cfg(feature = "redis")]
+    fn redis_connection(&self) -> RedisConnection {
+        self.redis_connection
+            .clone()
+            .expect("Redis is connected when a store uses it")
+    }
+}
+
+fn unsupported_backend(store: &str, backend: StorageBackend) -> ! {
+    unreachable!("{} store can't use the {} backend", store, backend.as_str())
 }
 
-async fn configure_postgresql() -> PgPool {
+#[cfg(feature = "postgres")]
+async fn configure_postgresql(settings: &DatabaseSettings) -> PgPool {
     // Create a new database connection pool
-    let pg_pool = get_postgres_pool(&DATABASE_URL)
+    let pg_pool = get_postgres_pool(&settings.url)
         .await
         .expect("Failed to create Postgres connection pool!");
 
@@ -63,27 +384,107 @@ async fn configure_postgresql() -> PgPool {
     pg_pool
 }
 
+#[cfg(feature = "sqlite")]
+async fn configure_sqlite(settings: &DatabaseSettings) -> SqlitePool {
+    let sqlite_pool = get_sqlite_pool(&settings.url)
+        .await
+        .expect("Failed to create SQLite connection pool!");
+
+    // SQLite has its own migrations, the PostgreSQL ones use Postgres-only SQL
+    sqlx::migrate!("./sqlite_migrations")
+        .run(&sqlite_pool)
+        .await
+        .expect("Failed to run SQLite migrations");
+
+    sqlite_pool
+}
+
+// The client, and a health check of its provider when it talks to one
+fn configure_email_client(
+    settings: &EmailSettings,
+    mailbox: &MailboxType,
+) -> (EmailClientType, Option<HealthCheckType>) {
+    match settings.client {
+        #[cfg(feature = "postmark")]
+        EmailClientKind::Postmark => {
+            let client = Arc::new(configure_postmark_email_client(settings));
+            (client.clone(), Some(client))
+        }
+        #[cfg(not(feature = "postmark"))]
+        EmailClientKind::Postmark => unreachable!("Postmark is rejected when loading the settings"),
+        #[cfg(feature = "smtp")]
+        EmailClientKind::Smtp => {
+            let client = Arc::new(configure_smtp_email_client(settings));
+            (client.clone(), Some(client))
+        }
+        #[cfg(not(feature = "smtp"))]
+        EmailClientKind::Smtp => unreachable!("SMTP is rejected when loading the settings"),
+        EmailClientKind::Mock => (Arc::new(MockEmailClient), None),
+        EmailClientKind::Capture => (mailbox.clone(), None),
+    }
+}
+
 // New!
-fn configure_postmark_email_client() -> PostmarkEmailClient {
+#[cfg(feature = "postmark")]
+fn configure_postmark_email_client(settings: &EmailSettings) -> PostmarkEmailClient {
     let http_client = Client::builder()
-        .timeout(prod::email_client::TIMEOUT)
+        .timeout(settings.timeout)
         .build()
         .expect("Failed to build HTTP client");
 
     PostmarkEmailClient::new(
-        prod::email_client::BASE_URL.to_owned(),
-        Email::parse(SecretString::new(
-            prod::email_client::SENDER.to_owned().into_boxed_str(),
-        ))
-        .unwrap(),
-        POSTMARK_AUTH_TOKEN.to_owned(),
+        settings.postmark.base_url.clone(),
+        settings
+            .sender()
+            .expect("Sender is validated with the settings"),
+        settings.postmark.auth_token.clone(),
+        http_client,
+    )
+}
+
+#[cfg(feature = "smtp")]
+fn configure_smtp_email_client(settings: &EmailSettings) -> SmtpEmailClient {
+    let ca_certificate = settings
+        .smtp
+        .ca_certificate
+        .as_ref()
+        .map(|path| std::fs::read(path).expect("Failed to read email.smtp.ca_certificate"));
+
+    SmtpEmailClient::new(
+        &settings.smtp.host,
+        settings.smtp.port(),
+        settings.smtp.tls,
+        ca_certificate.as_deref(),
+        settings.smtp.credentials(),
+        settings
+            .sender()
+            .expect("Sender is validated with the settings"),
+        settings.timeout,
+    )
+    .expect("Failed to build SMTP email client")
+}
+
+fn configure_webhook_worker(
+    settings: &WebhookSettings,
+    webhook_store: WebhookStoreType,
+) -> WebhookWorker {
+    let http_client = Client::builder()
+        .timeout(settings.timeout)
+        .build()
+        .expect("Failed to build HTTP client");
+
+    WebhookWorker::new(
+        settings.subscriptions.clone(),
+        webhook_store,
         http_client,
+        settings.retry,
+        settings.poll_interval,
     )
 }
 
-fn configure_redis() -> redis::Connection {
-    get_redis_client(REDIS_HOST_NAME.to_owned())
-        .expect("Failed to get Redis client")
-        .get_connection()
-        .expect("Failed to get Redis connection")
+#[cfg(feature = "redis")]
+async fn configure_redis(settings: &RedisSettings) -> RedisConnection {
+    get_redis_connection(settings)
+        .await
+        .expect("Failed to connect to Redis")
 }
@@ -0,0 +1,45 @@
+use std::net::SocketAddr;
+
+use askama::Template;
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+
+use crate::{
+    app_state::AppState, domain::AuthAPIError, services::capturing_email_client::SentEmail,
+};
+
+#[derive(Template)]
+#[template(path = "dev/mailbox.html")]
+struct MailboxTemplate {
+    emails: Vec<SentEmail>,
+}
+
+// Headers a reverse proxy adds to the requests it passes on
+const PROXY_HEADERS: [&str; 3] = ["forwarded", "x-forwarded-for", "x-real-ip"];
+
+// Shows the emails kept by the `capture` email client. They hold 2FA codes, so the page is only
+// served to requests made from the machine the service runs on. A proxy on that machine would
+// make every request look local, so proxied requests are turned away too.
+#[tracing::instrument(name = "Dev mailbox", skip_all)]
+pub async fn dev_mailbox(
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, AuthAPIError> {
+    let is_local = peer.ip().to_canonical().is_loopback()
+        && !PROXY_HEADERS.iter().any(|name| headers.contains_key(*name));
+    let Some(mailbox) = state.mailbox.as_ref().filter(|_| is_local) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let mut emails = mailbox.sent();
+    emails.reverse();
+    let page = MailboxTemplate { emails }
+        .render()
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    Ok(Html(page).into_response())
+}
@@ -0,0 +1,31 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde_json::json;
+
+use crate::{
+    app_state::AppState,
+    services::health_checks::{ReadinessReport, ReadinessStatus},
+};
+
+// The process is up and serving requests, whatever the state of its dependencies
+pub async fn health_live() -> impl IntoResponse {
+    Json(json!({ "status": "live" }))
+}
+
+// Whether every dependency answered in time, with a report per dependency.
+// `503` tells load balancers and orchestrators to hold traffic until it recovers,
+// or for good once the service is shutting down.
+#[tracing::instrument(name = "Readiness check", skip_all)]
+pub async fn health_ready(State(state): State<AppState>) -> impl IntoResponse {
+    let report = match state.shutdown.is_triggered() {
+        true => ReadinessReport::shutting_down(),
+        false => state.health_checks.run().await,
+    };
+    let status = match report.status {
+        ReadinessStatus::Ready => StatusCode::OK,
+        ReadinessStatus::NotReady | ReadinessStatus::ShuttingDown => {
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    };
+
+    (status, Json(report))
+}
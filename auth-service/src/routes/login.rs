@@ -1,30 +1,61 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    extract::State,
+    http::{header::ACCEPT_LANGUAGE, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
 use axum_extra::extract::CookieJar;
+use color_eyre::eyre;
 use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     app_state::AppState,
-    domain::{AuthAPIError, Email, LoginAttemptId, Password, TwoFACode, UserStoreError},
-    utils::auth::generate_auth_cookie,
+    domain::{AuthAPIError, Email, Locale, Password, User, UserStoreError},
+    utils::{
+        auth::{generate_auth_cookies, generate_auth_token},
+        metrics::{record_2fa_code_issued, record_login, LoginOutcome},
+        tracing::{record_outcome, record_user},
+    },
 };
 
+type LoginResult = Result<(StatusCode, Json<LoginResponse>), AuthAPIError>;
+
 #[tracing::instrument(name = "Login", skip_all)] // New!
 pub async fn login(
     State(state): State<AppState>,
     jar: CookieJar,
+    headers: HeaderMap,
     Json(request): Json<LoginRequest>,
 ) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
+    let (jar, result) = authenticate(&state, jar, &headers, request).await;
+    let outcome = login_outcome(&result);
+    record_login(outcome);
+    record_outcome(outcome.as_str());
+
+    (jar, result)
+}
+
+async fn authenticate(
+    state: &AppState,
+    jar: CookieJar,
+    headers: &HeaderMap,
+    request: LoginRequest,
+) -> (CookieJar, LoginResult) {
     let email = match Email::parse(request.email) {
         Ok(email) => email,
         Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
     };
+    record_user(
+        email.as_ref().expose_secret(),
+        &state.auth_settings.jwt_secret,
+    );
     let password = request.password;
     if Password::parse(password.clone()).await.is_err() {
         return (jar, Err(AuthAPIError::InvalidCredentials));
     };
 
-    let user_store = state.user_store.read().await;
+    let user_store = &state.user_store;
 
     match user_store.validate_user(&email, &password).await {
         Ok(_) => (),
@@ -39,48 +70,61 @@ pub async fn login(
     };
 
     if user.requires_2fa() {
-        handle_2fa(user.email(), &state, jar).await
+        let locale = email_locale(&user, headers);
+        handle_2fa(user.email(), locale, state, jar).await
     } else {
-        handle_no_2fa(&user.email(), jar).await
+        handle_no_2fa(user.email(), request.token_delivery, state, jar).await
+    }
+}
+
+fn login_outcome(result: &LoginResult) -> LoginOutcome {
+    match result {
+        Ok((_, Json(LoginResponse::RegularAuth | LoginResponse::Token(_)))) => {
+            LoginOutcome::Success
+        }
+        Ok((_, Json(LoginResponse::TwoFactorAuth(_)))) => LoginOutcome::TwoFactorRequired,
+        Err(AuthAPIError::InvalidCredentials) => LoginOutcome::InvalidInput,
+        Err(AuthAPIError::IncorrectCredentials) => LoginOutcome::IncorrectCredentials,
+        Err(_) => LoginOutcome::Error,
     }
 }
 
 #[tracing::instrument(name = "Login::handle_2fa", skip_all)] // New!
 async fn handle_2fa(
     email: &Email,
+    locale: Locale,
     state: &AppState,
     jar: CookieJar,
-) -> (
-    CookieJar,
-    Result<(StatusCode, Json<LoginResponse>), AuthAPIError>,
-) {
+) -> (CookieJar, LoginResult) {
     // First, we must generate a new random login attempt ID and 2FA code
-    let two_fa_code = TwoFACode::default();
-    let login_attempt_id = LoginAttemptId::default();
+    let two_fa_code = state.id_generator.two_fa_code();
+    let login_attempt_id = state.id_generator.login_attempt_id();
     let login_attampt_id_str = login_attempt_id.as_ref().expose_secret().to_owned();
 
-    if let Err(e) = state
-        .email_client
-        .send_email(
-            email,
-            "Your 2FA Code",
-            &format!("Your 2FA code is: {}", two_fa_code.as_ref().expose_secret()),
-        )
-        .await
-    {
-        return (jar, Err(AuthAPIError::UnexpectedError(e)));
+    let email_content = match state.email_templates.two_fa_code(locale, &two_fa_code) {
+        Ok(content) => content,
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
     };
 
+    // The code is stored before its email is queued, so no email goes out with a code that can't
+    // be used. If the email can't be queued the code is dropped again, as the user never gets it.
     if let Err(e) = state
         .two_fa_code_store
-        .write()
-        .await
         .add_code(email.clone(), login_attempt_id, two_fa_code)
         .await
     {
         return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
     }
 
+    // The email is sent in the background, once it is queued the code can be delivered
+    if let Err(e) = state.email_outbox.enqueue(email, email_content).await {
+        if let Err(e) = state.two_fa_code_store.remove_code(email).await {
+            tracing::error!("Failed to drop the 2FA code of an unsent email: {:?}", e);
+        }
+        return (jar, Err(AuthAPIError::UnexpectedError(e)));
+    };
+    record_2fa_code_issued();
+
     let two_factor_auth_res = Json(LoginResponse::TwoFactorAuth(TwoFactorAuthResponse {
         message: "2FA required".to_owned(),
         login_attempt_id: login_attampt_id_str,
@@ -88,20 +132,43 @@ async fn handle_2fa(
     (jar, Ok((StatusCode::PARTIAL_CONTENT, two_factor_auth_res)))
 }
 
+// The user's saved preference wins over whatever the browser asks for
+fn email_locale(user: &User, headers: &HeaderMap) -> Locale {
+    user.locale()
+        .or_else(|| {
+            headers
+                .get(ACCEPT_LANGUAGE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(Locale::from_accept_language)
+        })
+        .unwrap_or_default()
+}
+
 #[tracing::instrument(name = "Login::handle_no_2fa", skip_all)] // New!
 async fn handle_no_2fa(
     email: &Email,
+    token_delivery: TokenDelivery,
+    state: &AppState,
     jar: CookieJar,
-) -> (
-    CookieJar,
-    Result<(StatusCode, Json<LoginResponse>), AuthAPIError>,
-) {
-    let auth_cookie = match generate_auth_cookie(email) {
-        Ok(cookie) => cookie,
+) -> (CookieJar, LoginResult) {
+    if token_delivery == TokenDelivery::Body {
+        return match TokenResponse::generate(email, state) {
+            Ok(token) => (jar, Ok((StatusCode::OK, Json(LoginResponse::Token(token))))),
+            Err(e) => (jar, Err(AuthAPIError::UnexpectedError(e))),
+        };
+    }
+
+    let (auth_cookie, csrf_cookie) = match generate_auth_cookies(
+        email,
+        &state.auth_settings,
+        &*state.clock,
+        &*state.id_generator,
+    ) {
+        Ok(cookies) => cookies,
         Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
     };
 
-    let updated_jar = jar.add(auth_cookie);
+    let updated_jar = jar.add(auth_cookie).add(csrf_cookie);
 
     let regular_auth_res = Json(LoginResponse::RegularAuth);
     (updated_jar, Ok((StatusCode::OK, regular_auth_res)))
@@ -111,15 +178,28 @@ async fn handle_no_2fa(
 pub struct LoginRequest {
     pub email: SecretString,
     pub password: SecretString,
+    #[serde(default, rename = "tokenDelivery")]
+    pub token_delivery: TokenDelivery,
+}
+
+// How a client wants its token once logged in: set in the auth cookie for browsers, or returned
+// in the response body for clients sending it as a Bearer token. `/verify-2fa` takes it too.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenDelivery {
+    #[default]
+    Cookie,
+    Body,
 }
 
-// The login route can return 2 possible success responses.
+// The login route can return 3 possible success responses.
 // This enum models each response!
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 #[serde(untagged)]
 pub enum LoginResponse {
     RegularAuth,
     TwoFactorAuth(TwoFactorAuthResponse),
+    Token(TokenResponse),
 }
 
 // If a user requires 2FA, this JSON body should be returned!
@@ -129,3 +209,32 @@ pub struct TwoFactorAuthResponse {
     #[serde(rename = "loginAttemptId")]
     pub login_attempt_id: String,
 }
+
+// The token itself, for clients that asked for `"tokenDelivery": "body"`
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct TokenResponse {
+    #[serde(rename = "accessToken")]
+    pub access_token: String,
+    #[serde(rename = "tokenType")]
+    pub token_type: String,
+    // Seconds until the token expires
+    #[serde(rename = "expiresIn")]
+    pub expires_in: u64,
+}
+
+impl TokenResponse {
+    pub(crate) fn generate(email: &Email, state: &AppState) -> eyre::Result<Self> {
+        let access_token = generate_auth_token(
+            email,
+            &state.auth_settings,
+            &*state.clock,
+            &*state.id_generator,
+        )?;
+
+        Ok(Self {
+            access_token,
+            token_type: "Bearer".to_owned(),
+            expires_in: state.auth_settings.token_ttl.as_secs(),
+        })
+    }
+}
@@ -1,42 +1,60 @@
 use axum::{extract::State, http::StatusCode, response::IntoResponse};
-use axum_extra::extract::{cookie, CookieJar};
-use secrecy::SecretString;
+use axum_extra::extract::CookieJar;
 
 use crate::{
     app_state::AppState,
     domain::AuthAPIError,
-    utils::{auth::validate_token, constants::JWT_COOKIE_NAME},
+    utils::{
+        auth::{auth_cookie, validate_token},
+        csrf::csrf_cookie,
+        extract::{AuthToken, TokenSource},
+        metrics::record_token_banned,
+        tracing::{record_outcome, record_user},
+    },
 };
 
 #[tracing::instrument(name = "Logout", skip_all)] // New!
 pub async fn logout(
     State(state): State<AppState>,
     jar: CookieJar,
+    token: AuthToken,
 ) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
-    let cookie = match jar.get(JWT_COOKIE_NAME) {
-        Some(cookie) => cookie,
-        None => return (jar, Err(AuthAPIError::MissingToken)),
-    };
-
-    let token = SecretString::new(cookie.value().to_owned().into_boxed_str());
-    if validate_token(&token, state.banned_token_store.clone())
-        .await
-        .is_err()
+    let claims = match validate_token(
+        &token.token,
+        state.banned_token_store.clone(),
+        &state.auth_settings,
+        &*state.clock,
+    )
+    .await
     {
-        return (jar, Err(AuthAPIError::InvalidToken));
-    }
+        Ok(claims) => claims,
+        Err(_) => return (jar, Err(AuthAPIError::InvalidToken)),
+    };
+    record_user(&claims.sub, &state.auth_settings.jwt_secret);
 
+    // The ban only has to last until the token expires
+    let expires_at = match claims.expires_at() {
+        Ok(expires_at) => expires_at,
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
+    };
     if let Err(e) = state
         .banned_token_store
-        .write()
-        .await
-        .add_token(&token)
+        .add_token(&claims.jti, expires_at)
         .await
     {
         return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
     };
+    record_token_banned();
+    record_outcome("logged_out");
 
-    let jar = jar.remove(cookie::Cookie::from(JWT_COOKIE_NAME));
+    // Clearing it takes the same path and domain it was set with. Bearer clients hold their token
+    // themselves, and a cookie they may also have is left alone.
+    let jar = match token.source {
+        TokenSource::Cookie => jar
+            .remove(auth_cookie(&state.auth_settings))
+            .remove(csrf_cookie(&state.auth_settings)),
+        TokenSource::Bearer => jar,
+    };
 
     (jar, Ok(StatusCode::OK))
 }
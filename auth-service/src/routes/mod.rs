@@ -1,3 +1,5 @@
+mod dev_mailbox;
+mod health;
 mod login;
 mod logout;
 mod signup;
@@ -5,6 +7,8 @@ mod verify_2fa;
 mod verify_token;
 
 // re-export items from sub-modules
+pub use dev_mailbox::*;
+pub use health::*;
 pub use login::*;
 pub use logout::*;
 pub use signup::*;
@@ -1,10 +1,16 @@
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
-use secrecy::SecretString;
+use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     app_state::AppState,
-    domain::{AuthAPIError, Email, Password, User, UserStoreError},
+    domain::{
+        AuthAPIError, Email, Locale, Password, User, UserStoreError, WebhookEvent, WebhookEventType,
+    },
+    utils::{
+        metrics::record_signup,
+        tracing::{record_outcome, record_user},
+    },
 };
 
 #[tracing::instrument(name = "Signup", skip_all)] // New!
@@ -13,18 +19,45 @@ pub async fn signup(
     Json(request): Json<SignupRequest>,
 ) -> Result<impl IntoResponse, AuthAPIError> {
     let email = Email::parse(request.email).map_err(|_| AuthAPIError::InvalidCredentials)?;
+    record_user(
+        email.as_ref().expose_secret(),
+        &state.auth_settings.jwt_secret,
+    );
     let password = Password::parse(request.password)
         .await
         .map_err(|_| AuthAPIError::InvalidCredentials)?;
 
-    let user = User::new(email, password, request.requires_2fa);
+    // Unsupported languages are ignored, emails then follow the login's Accept-Language
+    let locale = request
+        .locale
+        .as_deref()
+        .and_then(Locale::from_accept_language);
+    let user = User::new(email.clone(), password, request.requires_2fa).with_locale(locale);
 
-    let mut user_store = state.user_store.write().await;
+    // The events are only queued once the user is added, so there are none for a signup that fails
+    let event =
+        |event_type| WebhookEvent::new(event_type, &email, &*state.clock, &*state.id_generator);
+    let mut events = vec![event(WebhookEventType::UserSignedUp)];
+    if request.requires_2fa {
+        events.push(event(WebhookEventType::TwoFactorEnabled));
+    }
+    let deliveries = state
+        .webhook_publisher
+        .deliveries(&events)
+        .map_err(AuthAPIError::UnexpectedError)?;
 
-    user_store.add_user(user).await.map_err(|e| match e {
-        UserStoreError::UserAlreadyExists => AuthAPIError::UserAlreadyExists,
-        e => AuthAPIError::UnexpectedError(e.into()),
-    })?;
+    // Only the deliveries the store couldn't queue in the user's transaction are handed back
+    let deliveries = state
+        .user_store
+        .add_user_with_deliveries(user, deliveries)
+        .await
+        .map_err(|e| match e {
+            UserStoreError::UserAlreadyExists => AuthAPIError::UserAlreadyExists,
+            e => AuthAPIError::UnexpectedError(e.into()),
+        })?;
+    state.webhook_publisher.enqueue(deliveries).await;
+    record_signup();
+    record_outcome("signed_up");
 
     let response = Json(SignupResponse {
         message: "User created successfully!".to_string(),
@@ -32,12 +65,15 @@ pub async fn signup(
 
     Ok((StatusCode::CREATED, response))
 }
+
 #[derive(Deserialize)]
 pub struct SignupRequest {
     pub email: SecretString,
     pub password: SecretString,
     #[serde(rename = "requires2FA")]
     pub requires_2fa: bool,
+    #[serde(default)]
+    pub locale: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug, PartialEq)]
@@ -1,12 +1,21 @@
-use axum::{extract::State, response::IntoResponse, Json};
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
 use axum_extra::extract::CookieJar;
-use secrecy::SecretString;
+use secrecy::{ExposeSecret, SecretString};
 use serde::Deserialize;
 
 use crate::{
     app_state::AppState,
-    domain::{AuthAPIError, Email, LoginAttemptId, TwoFACode},
-    utils::auth::generate_auth_cookie,
+    domain::{AuthAPIError, Email, LoginAttemptId, TwoFACode, TwoFACodeStoreError},
+    routes::{TokenDelivery, TokenResponse},
+    utils::{
+        auth::generate_auth_cookies,
+        metrics::record_2fa_verification,
+        tracing::{record_outcome, record_user},
+    },
 };
 
 #[tracing::instrument(name = "Verify 2FA", skip_all)] // New!
@@ -14,11 +23,15 @@ pub async fn verify_2fa(
     State(state): State<AppState>,
     jar: CookieJar,
     Json(request): Json<Verify2FARequest>,
-) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
+) -> (CookieJar, Result<Response, AuthAPIError>) {
     let email = match Email::parse(request.email) {
         Ok(v) => v,
         Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
     };
+    record_user(
+        email.as_ref().expose_secret(),
+        &state.auth_settings.jwt_secret,
+    );
 
     let login_attempt_id = match LoginAttemptId::parse(request.login_attemp_id) {
         Ok(v) => v,
@@ -30,29 +43,56 @@ pub async fn verify_2fa(
         Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
     };
 
-    let mut two_fa_code_store = state.two_fa_code_store.write().await;
+    let two_fa_code_store = &state.two_fa_code_store;
 
     let code_tuple = match two_fa_code_store.get_code(&email).await {
         Ok(v) => v,
-        Err(_) => return (jar, Err(AuthAPIError::IncorrectCredentials)),
+        Err(_) => {
+            record_2fa_verification(false);
+            record_outcome("failed");
+            return (jar, Err(AuthAPIError::IncorrectCredentials));
+        }
     };
 
     if code_tuple.0 != login_attempt_id || code_tuple.1 != two_fa_code {
+        record_2fa_verification(false);
+        record_outcome("failed");
         return (jar, Err(AuthAPIError::IncorrectCredentials));
     }
 
-    if let Err(e) = two_fa_code_store.remove_code(&email).await {
-        return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
+    // Removing the code is what consumes it, a concurrent request that got here first wins
+    match two_fa_code_store.remove_code(&email).await {
+        Ok(_) => (),
+        Err(TwoFACodeStoreError::LoginAttemptIdNotFound) => {
+            record_2fa_verification(false);
+            record_outcome("failed");
+            return (jar, Err(AuthAPIError::IncorrectCredentials));
+        }
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
+    }
+    record_2fa_verification(true);
+    record_outcome("verified");
+
+    if request.token_delivery == TokenDelivery::Body {
+        return match TokenResponse::generate(&email, &state) {
+            Ok(token) => (jar, Ok(Json(token).into_response())),
+            Err(e) => (jar, Err(AuthAPIError::UnexpectedError(e))),
+        };
     }
 
-    let cookie = match generate_auth_cookie(&email) {
-        Ok(cookie) => cookie,
+    let (auth_cookie, csrf_cookie) = match generate_auth_cookies(
+        &email,
+        &state.auth_settings,
+        &*state.clock,
+        &*state.id_generator,
+    ) {
+        Ok(cookies) => cookies,
         Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
     };
 
-    let updated_jar = jar.add(cookie);
+    let updated_jar = jar.add(auth_cookie).add(csrf_cookie);
 
-    (updated_jar, Ok(()))
+    (updated_jar, Ok(().into_response()))
 }
 
 #[derive(Deserialize)]
@@ -62,4 +102,6 @@ pub struct Verify2FARequest {
     pub login_attemp_id: SecretString,
     #[serde(rename = "2FACode")]
     pub two_fa_code: SecretString,
+    #[serde(default, rename = "tokenDelivery")]
+    pub token_delivery: TokenDelivery,
 }
@@ -1,17 +1,53 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    extract::{rejection::JsonRejection, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
 use secrecy::SecretString;
 use serde::Deserialize;
 
-use crate::{app_state::AppState, domain::AuthAPIError, utils::auth::validate_token};
+use crate::{
+    app_state::AppState,
+    domain::AuthAPIError,
+    utils::{
+        auth::validate_token,
+        extract::AuthToken,
+        tracing::{record_outcome, record_user},
+    },
+};
 
 #[tracing::instrument(name = "Verify Token", skip_all)] // New!
 pub async fn verify_token(
     State(state): State<AppState>,
-    Json(request): Json<VerifyTokenRequest>,
-) -> Result<impl IntoResponse, AuthAPIError> {
-    match validate_token(&request.token, state.banned_token_store.clone()).await {
-        Ok(_) => Ok(StatusCode::OK),
-        Err(_) => Err(AuthAPIError::InvalidToken),
+    auth_token: Option<AuthToken>,
+    request: Result<Json<VerifyTokenRequest>, JsonRejection>,
+) -> Result<impl IntoResponse, Response> {
+    // Services verifying a user's token send it in the body, clients checking their own may send
+    // it like any other request instead
+    let token = match (request, auth_token) {
+        (Ok(Json(request)), _) => request.token,
+        (Err(_), Some(auth_token)) => auth_token.token,
+        (Err(rejection), None) => return Err(rejection.into_response()),
+    };
+
+    match validate_token(
+        &token,
+        state.banned_token_store.clone(),
+        &state.auth_settings,
+        &*state.clock,
+    )
+    .await
+    {
+        Ok(claims) => {
+            record_user(&claims.sub, &state.auth_settings.jwt_secret);
+            record_outcome("valid");
+            Ok(StatusCode::OK)
+        }
+        Err(_) => {
+            record_outcome("invalid");
+            Err(AuthAPIError::InvalidToken.into_response())
+        }
     }
 }
 
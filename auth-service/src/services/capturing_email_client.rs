@@ -0,0 +1,196 @@
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::Result;
+use secrecy::ExposeSecret;
+
+use crate::{
+    app_state::ClockType,
+    domain::{Email, EmailClient},
+    services::system_clock::SystemClock,
+};
+
+// An email as the app handed it to the email client
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentEmail {
+    pub recipient: String,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+    pub sent_at: DateTime<Utc>,
+}
+
+impl SentEmail {
+    // The first 6-digit code in the text body, e.g. a 2FA code
+    pub fn code(&self) -> Option<String> {
+        self.text_body
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .find(|word| word.len() == 6 && word.bytes().all(|b| b.is_ascii_digit()))
+            .map(str::to_owned)
+    }
+
+    // Every http(s) link in the text body, in order
+    pub fn links(&self) -> Vec<String> {
+        self.text_body
+            .split_whitespace()
+            .filter(|word| word.starts_with("http://") || word.starts_with("https://"))
+            .map(|link| link.trim_end_matches(['.', ',', ')', '>']).to_owned())
+            .collect()
+    }
+}
+
+// Keeps every email instead of sending it, so tests, or a developer through `/dev/mailbox`,
+// can read what a user would have received
+pub struct CapturingEmailClient {
+    sent: RwLock<Vec<SentEmail>>,
+    clock: ClockType,
+}
+
+impl Default for CapturingEmailClient {
+    fn default() -> Self {
+        Self {
+            sent: RwLock::new(Vec::new()),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl CapturingEmailClient {
+    pub fn with_clock(mut self, clock: ClockType) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // Every email sent so far, oldest first
+    pub fn sent(&self) -> Vec<SentEmail> {
+        self.sent.read().unwrap().clone()
+    }
+
+    pub fn emails_to(&self, recipient: &str) -> Vec<SentEmail> {
+        self.sent
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|email| email.recipient == recipient)
+            .cloned()
+            .collect()
+    }
+
+    pub fn last_email_to(&self, recipient: &str) -> Option<SentEmail> {
+        self.sent
+            .read()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|email| email.recipient == recipient)
+            .cloned()
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailClient for CapturingEmailClient {
+    async fn send_email(
+        &self,
+        recipient: &Email,
+        subject: &str,
+        html_body: &str,
+        text_body: &str,
+    ) -> Result<()> {
+        let email = SentEmail {
+            recipient: recipient.as_ref().expose_secret().to_owned(),
+            subject: subject.to_owned(),
+            html_body: html_body.to_owned(),
+            text_body: text_body.to_owned(),
+            sent_at: self.clock.now(),
+        };
+        self.sent.write().unwrap().push(email);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+    use secrecy::SecretString;
+
+    use super::*;
+    use crate::services::fake_clock::FakeClock;
+
+    fn email(address: &str) -> Email {
+        Email::parse(SecretString::from(address)).unwrap()
+    }
+
+    fn sent_email(text_body: &str) -> SentEmail {
+        SentEmail {
+            recipient: "test@example.com".to_owned(),
+            subject: "Subject".to_owned(),
+            html_body: String::new(),
+            text_body: text_body.to_owned(),
+            sent_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sent_emails_are_kept_in_order() {
+        let clock = Arc::new(FakeClock::default());
+        let client = CapturingEmailClient::default().with_clock(clock.clone());
+        let recipient = email("test@example.com");
+
+        client
+            .send_email(&recipient, "First", "<p>1</p>", "1")
+            .await
+            .unwrap();
+        clock.advance(TimeDelta::seconds(1));
+        client
+            .send_email(&recipient, "Second", "<p>2</p>", "2")
+            .await
+            .unwrap();
+
+        let sent = client.sent();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].recipient, "test@example.com");
+        assert_eq!(sent[0].subject, "First");
+        assert_eq!(sent[1].text_body, "2");
+        assert_eq!(sent[1].sent_at - sent[0].sent_at, TimeDelta::seconds(1));
+    }
+
+    #[tokio::test]
+    async fn test_emails_are_found_by_recipient() {
+        let client = CapturingEmailClient::default();
+        let (alice, bob) = (email("alice@example.com"), email("bob@example.com"));
+
+        client.send_email(&alice, "First", "", "").await.unwrap();
+        client.send_email(&bob, "Other", "", "").await.unwrap();
+        client.send_email(&alice, "Second", "", "").await.unwrap();
+
+        assert_eq!(client.emails_to("alice@example.com").len(), 2);
+        assert_eq!(
+            client.last_email_to("alice@example.com").unwrap().subject,
+            "Second"
+        );
+        assert!(client.last_email_to("carol@example.com").is_none());
+    }
+
+    #[test]
+    fn test_code_is_extracted_from_text_body() {
+        let email = sent_email("Use the following code to sign in to Brand 2:\n\n012345\n\nBye");
+
+        assert_eq!(email.code().as_deref(), Some("012345"));
+        assert_eq!(sent_email("No code, only 1234567 and abc123").code(), None);
+    }
+
+    #[test]
+    fn test_links_are_extracted_from_text_body() {
+        let email =
+            sent_email("Reset it at https://example.com/reset?token=abc. Brand - http://localhost");
+
+        assert_eq!(
+            email.links(),
+            vec![
+                "https://example.com/reset?token=abc".to_owned(),
+                "http://localhost".to_owned()
+            ]
+        );
+    }
+}
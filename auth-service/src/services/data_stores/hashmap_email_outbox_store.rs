@@ -0,0 +1,162 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::domain::{EmailOutboxStore, EmailOutboxStoreError, OutboxEmail, OutboxEmailStatus};
+
+// Behind one lock rather than a `DashMap`, so a claim picks and leases the due emails atomically
+#[derive(Default)]
+pub struct HashMapEmailOutboxStore {
+    emails: Mutex<HashMap<Uuid, OutboxEmail>>,
+}
+
+#[async_trait::async_trait]
+impl EmailOutboxStore for HashMapEmailOutboxStore {
+    async fn enqueue_email(&self, email: OutboxEmail) -> Result<(), EmailOutboxStoreError> {
+        self.emails.lock().unwrap().insert(email.id, email);
+        Ok(())
+    }
+
+    async fn claim_due_emails(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<OutboxEmail>, EmailOutboxStoreError> {
+        let mut emails = self.emails.lock().unwrap();
+        let mut due: Vec<&mut OutboxEmail> = emails
+            .values_mut()
+            .filter(|e| e.status == OutboxEmailStatus::Pending && e.next_attempt_at <= now)
+            .collect();
+        due.sort_by_key(|e| e.next_attempt_at);
+
+        Ok(due
+            .into_iter()
+            .take(limit)
+            .map(|e| {
+                e.next_attempt_at = lease_until;
+                e.clone()
+            })
+            .collect())
+    }
+
+    async fn mark_sent(&self, id: &Uuid) -> Result<(), EmailOutboxStoreError> {
+        self.emails
+            .lock()
+            .unwrap()
+            .remove(id)
+            .map(|_| ())
+            .ok_or(EmailOutboxStoreError::EmailNotFound)
+    }
+
+    async fn record_failure(&self, email: &OutboxEmail) -> Result<(), EmailOutboxStoreError> {
+        let mut emails = self.emails.lock().unwrap();
+        let stored = emails
+            .get_mut(&email.id)
+            .ok_or(EmailOutboxStoreError::EmailNotFound)?;
+        *stored = email.clone();
+        Ok(())
+    }
+
+    async fn get_email(&self, id: &Uuid) -> Result<OutboxEmail, EmailOutboxStoreError> {
+        self.emails
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .ok_or(EmailOutboxStoreError::EmailNotFound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::SecretString;
+
+    use super::*;
+    use crate::{
+        domain::Email,
+        services::{random_id_generator::RandomIdGenerator, system_clock::SystemClock},
+    };
+
+    fn test_email() -> OutboxEmail {
+        let recipient = Email::parse(SecretString::new(
+            "test@example.com".to_owned().into_boxed_str(),
+        ))
+        .unwrap();
+        OutboxEmail::new(
+            recipient,
+            "Subject".to_owned(),
+            "<p>Content</p>".to_owned(),
+            "Content".to_owned(),
+            &SystemClock,
+            &RandomIdGenerator,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_enqueue_and_get_email() {
+        let store = HashMapEmailOutboxStore::default();
+        let email = test_email();
+
+        store.enqueue_email(email.clone()).await.unwrap();
+
+        assert_eq!(store.get_email(&email.id).await.unwrap(), email);
+    }
+
+    #[tokio::test]
+    async fn test_claim_due_emails_leases_claimed_emails() {
+        let store = HashMapEmailOutboxStore::default();
+        let email = test_email();
+        store.enqueue_email(email.clone()).await.unwrap();
+
+        let now = Utc::now();
+        let lease_until = now + chrono::Duration::seconds(30);
+
+        let claimed = store.claim_due_emails(now, lease_until, 10).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].id, email.id);
+
+        let claimed = store.claim_due_emails(now, lease_until, 10).await.unwrap();
+        assert!(claimed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_claim_due_emails_skips_dead_letters() {
+        let store = HashMapEmailOutboxStore::default();
+        let mut email = test_email();
+        email.status = OutboxEmailStatus::DeadLettered;
+        store.enqueue_email(email).await.unwrap();
+
+        let now = Utc::now();
+        let claimed = store.claim_due_emails(now, now, 10).await.unwrap();
+        assert!(claimed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mark_sent_removes_email() {
+        let store = HashMapEmailOutboxStore::default();
+        let email = test_email();
+        store.enqueue_email(email.clone()).await.unwrap();
+
+        store.mark_sent(&email.id).await.unwrap();
+
+        let err = store.get_email(&email.id).await.unwrap_err();
+        assert_eq!(err, EmailOutboxStoreError::EmailNotFound);
+        let err = store.mark_sent(&email.id).await.unwrap_err();
+        assert_eq!(err, EmailOutboxStoreError::EmailNotFound);
+    }
+
+    #[tokio::test]
+    async fn test_record_failure_updates_email() {
+        let store = HashMapEmailOutboxStore::default();
+        let mut email = test_email();
+        store.enqueue_email(email.clone()).await.unwrap();
+
+        email.attempts = 1;
+        email.last_error = Some("timed out".to_owned());
+        store.record_failure(&email).await.unwrap();
+
+        assert_eq!(store.get_email(&email.id).await.unwrap(), email);
+    }
+}
@@ -1,28 +1,72 @@
-use std::collections::HashMap;
-
-use crate::domain::{
-    Email, {LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError},
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::Result;
+use dashmap::DashMap;
+
+use crate::{
+    app_state::ClockType,
+    domain::{
+        Email, ExpiringStore,
+        {LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError, TWO_FA_CODE_TTL},
+    },
+    services::system_clock::SystemClock,
 };
 
-#[derive(Default)]
+// Clones share the same codes, so the expiry purge worker can hold one
+#[derive(Clone)]
 pub struct HashMapTwoFACodeStore {
-    codes: HashMap<Email, (LoginAttemptId, TwoFACode)>,
+    codes: Arc<DashMap<Email, TwoFAEntry>>,
+    clock: ClockType,
+}
+
+struct TwoFAEntry {
+    login_attempt_id: LoginAttemptId,
+    code: TwoFACode,
+    expires_at: DateTime<Utc>,
+}
+
+impl HashMapTwoFACodeStore {
+    pub fn with_clock(mut self, clock: ClockType) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+impl Default for HashMapTwoFACodeStore {
+    fn default() -> Self {
+        Self {
+            codes: Arc::new(DashMap::new()),
+            clock: Arc::new(SystemClock),
+        }
+    }
 }
 
 #[async_trait::async_trait]
 impl TwoFACodeStore for HashMapTwoFACodeStore {
     async fn add_code(
-        &mut self,
+        &self,
         email: Email,
         login_attempt_id: LoginAttemptId,
         code: TwoFACode,
     ) -> Result<(), TwoFACodeStoreError> {
-        self.codes.insert(email, (login_attempt_id, code));
+        let expires_at = self.clock.now() + TWO_FA_CODE_TTL;
+        self.codes.insert(
+            email,
+            TwoFAEntry {
+                login_attempt_id,
+                code,
+                expires_at,
+            },
+        );
         Ok(())
     }
-    async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError> {
+    async fn remove_code(&self, email: &Email) -> Result<(), TwoFACodeStoreError> {
+        // An expired code is dropped too, but can't be consumed
+        let now = self.clock.now();
         self.codes
             .remove(email)
+            .filter(|(_, entry)| entry.expires_at > now)
             .map(|_| ())
             .ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)
     }
@@ -30,18 +74,39 @@ impl TwoFACodeStore for HashMapTwoFACodeStore {
         &self,
         email: &Email,
     ) -> Result<(LoginAttemptId, TwoFACode), TwoFACodeStoreError> {
+        let now = self.clock.now();
         self.codes
             .get(email)
-            .cloned()
+            .filter(|entry| entry.expires_at > now)
+            .map(|entry| (entry.login_attempt_id.clone(), entry.code.clone()))
             .ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)
     }
 }
+
+#[async_trait::async_trait]
+impl ExpiringStore for HashMapTwoFACodeStore {
+    async fn purge_expired(&self, now: DateTime<Utc>) -> Result<u64> {
+        // Counted as they are removed, codes added meanwhile would skew a difference in length
+        let mut purged = 0;
+        self.codes.retain(|_, entry| {
+            let live = entry.expires_at > now;
+            purged += u64::from(!live);
+            live
+        });
+
+        Ok(purged)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use secrecy::SecretString;
 
     use super::*;
-    use crate::domain::{Email, LoginAttemptId, TwoFACode};
+    use crate::{
+        domain::{Clock, Email, LoginAttemptId, TwoFACode},
+        services::fake_clock::FakeClock,
+    };
 
     fn test_email() -> Email {
         Email::parse(SecretString::new(
@@ -60,7 +125,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_add_and_get_code() {
-        let mut store = HashMapTwoFACodeStore::default();
+        let store = HashMapTwoFACodeStore::default();
         let email = test_email();
         let login_attempt_id = test_login_attempt_id();
         let code = test_code();
@@ -77,7 +142,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_remove_code() {
-        let mut store = HashMapTwoFACodeStore::default();
+        let store = HashMapTwoFACodeStore::default();
         let email = test_email();
         let login_attempt_id = test_login_attempt_id();
         let code = test_code();
@@ -92,9 +157,51 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[tokio::test]
+    async fn test_code_expires_after_ten_minutes() {
+        let clock = Arc::new(FakeClock::default());
+        let store = HashMapTwoFACodeStore::default().with_clock(clock.clone());
+        let email = test_email();
+
+        store
+            .add_code(email.clone(), test_login_attempt_id(), test_code())
+            .await
+            .unwrap();
+        clock.advance(chrono::Duration::minutes(10));
+
+        assert!(store.get_code(&email).await.is_err());
+        assert!(store.remove_code(&email).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_purge_drops_expired_codes() {
+        let clock = Arc::new(FakeClock::default());
+        let store = HashMapTwoFACodeStore::default().with_clock(clock.clone());
+        let expired_email = test_email();
+        let live_email = Email::parse(SecretString::new(
+            "live@example.com".to_owned().into_boxed_str(),
+        ))
+        .unwrap();
+
+        store
+            .add_code(expired_email, test_login_attempt_id(), test_code())
+            .await
+            .unwrap();
+        clock.advance(chrono::Duration::minutes(5));
+        store
+            .add_code(live_email.clone(), test_login_attempt_id(), test_code())
+            .await
+            .unwrap();
+        clock.advance(chrono::Duration::minutes(5));
+
+        assert_eq!(store.purge_expired(clock.now()).await.unwrap(), 1);
+        assert_eq!(store.codes.len(), 1);
+        assert!(store.get_code(&live_email).await.is_ok());
+    }
+
     #[tokio::test]
     async fn test_remove_code_not_found() {
-        let mut store = HashMapTwoFACodeStore::default();
+        let store = HashMapTwoFACodeStore::default();
         let email = test_email();
 
         let result = store.remove_code(&email).await;
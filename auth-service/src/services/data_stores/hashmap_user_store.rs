@@ -1,31 +1,30 @@
-use std::collections::HashMap;
-
+use dashmap::{mapref::entry::Entry, DashMap};
 use secrecy::SecretString;
 
 use crate::domain::{Email, User, UserStore, UserStoreError};
 
+// Users live in a sharded map, so requests for different users don't contend on one lock
 #[derive(Default)]
 pub struct HashMapUserStore {
-    users: HashMap<Email, User>,
+    users: DashMap<Email, User>,
 }
 
 #[async_trait::async_trait]
 impl UserStore for HashMapUserStore {
-    async fn add_user(&mut self, user: User) -> Result<(), UserStoreError> {
-        let email = user.email();
-
-        if self.users.contains_key(&email) {
-            Err(UserStoreError::UserAlreadyExists)
-        } else {
-            self.users.insert(email.clone(), user);
-            Ok(())
+    async fn add_user(&self, user: User) -> Result<(), UserStoreError> {
+        match self.users.entry(user.email().clone()) {
+            Entry::Occupied(_) => Err(UserStoreError::UserAlreadyExists),
+            Entry::Vacant(entry) => {
+                entry.insert(user);
+                Ok(())
+            }
         }
     }
 
     async fn get_user(&self, email: &Email) -> Result<User, UserStoreError> {
         self.users
             .get(email)
-            .cloned()
+            .map(|user| user.clone())
             .ok_or(UserStoreError::UserNotFound)
     }
 
@@ -34,7 +33,8 @@ impl UserStore for HashMapUserStore {
         email: &Email,
         raw_password: &SecretString,
     ) -> Result<(), UserStoreError> {
-        let user = self.users.get(email).ok_or(UserStoreError::UserNotFound)?;
+        // Clone the user so the shard isn't locked while the password hash is verified
+        let user = self.get_user(email).await?;
 
         user.password()
             .verify_raw_password(raw_password)
@@ -46,7 +46,7 @@ impl UserStore for HashMapUserStore {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::domain::{Email, Password};
+    use crate::domain::{Email, Locale, Password};
 
     fn secret_str(s: &str) -> SecretString {
         // new
@@ -75,7 +75,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_add_user() {
-        let mut store = HashMapUserStore::default();
+        let store = HashMapUserStore::default();
 
         let u1 = make_user("a@test.com", "password1").await;
         assert!(store.add_user(u1).await.is_ok());
@@ -87,7 +87,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_user() {
-        let mut store = HashMapUserStore::default();
+        let store = HashMapUserStore::default();
         store
             .add_user(make_user("a@test.com", "password").await)
             .await
@@ -102,9 +102,21 @@ mod tests {
         assert_eq!(err, UserStoreError::UserNotFound);
     }
 
+    #[tokio::test]
+    async fn test_get_user_keeps_locale() {
+        let store = HashMapUserStore::default();
+        let user = make_user("a@test.com", "password")
+            .await
+            .with_locale(Some(Locale::Es));
+        store.add_user(user).await.unwrap();
+
+        let u = store.get_user(&create_email("a@test.com")).await.unwrap();
+        assert_eq!(u.locale(), Some(Locale::Es));
+    }
+
     #[tokio::test]
     async fn test_validate_user() {
-        let mut store = HashMapUserStore::default();
+        let store = HashMapUserStore::default();
         store
             .add_user(make_user("a@test.com", "password").await)
             .await
@@ -0,0 +1,209 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::domain::{
+    WebhookDelivery, WebhookDeliveryAttempt, WebhookDeliveryStatus, WebhookStore, WebhookStoreError,
+};
+
+// Behind one lock rather than `DashMap`s, so a claim picks and leases the due deliveries
+// atomically, and a delivery and its log are updated together
+#[derive(Default)]
+pub struct HashMapWebhookStore {
+    state: Mutex<Deliveries>,
+}
+
+#[derive(Default)]
+struct Deliveries {
+    deliveries: HashMap<Uuid, WebhookDelivery>,
+    delivery_log: HashMap<Uuid, Vec<WebhookDeliveryAttempt>>,
+}
+
+#[async_trait::async_trait]
+impl WebhookStore for HashMapWebhookStore {
+    async fn enqueue_delivery(&self, delivery: WebhookDelivery) -> Result<(), WebhookStoreError> {
+        self.state
+            .lock()
+            .unwrap()
+            .deliveries
+            .insert(delivery.id, delivery);
+        Ok(())
+    }
+
+    async fn claim_due_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<WebhookDelivery>, WebhookStoreError> {
+        let mut state = self.state.lock().unwrap();
+        let mut due: Vec<&mut WebhookDelivery> = state
+            .deliveries
+            .values_mut()
+            .filter(|d| d.status == WebhookDeliveryStatus::Pending && d.next_attempt_at <= now)
+            .collect();
+        due.sort_by_key(|d| d.next_attempt_at);
+
+        Ok(due
+            .into_iter()
+            .take(limit)
+            .map(|d| {
+                d.next_attempt_at = lease_until;
+                d.clone()
+            })
+            .collect())
+    }
+
+    async fn record_attempt(
+        &self,
+        delivery: &WebhookDelivery,
+        attempt: WebhookDeliveryAttempt,
+    ) -> Result<(), WebhookStoreError> {
+        let mut state = self.state.lock().unwrap();
+        let stored = state
+            .deliveries
+            .get_mut(&delivery.id)
+            .ok_or(WebhookStoreError::DeliveryNotFound)?;
+        *stored = delivery.clone();
+
+        state
+            .delivery_log
+            .entry(delivery.id)
+            .or_default()
+            .push(attempt);
+        Ok(())
+    }
+
+    async fn get_delivery(&self, id: &Uuid) -> Result<WebhookDelivery, WebhookStoreError> {
+        self.state
+            .lock()
+            .unwrap()
+            .deliveries
+            .get(id)
+            .cloned()
+            .ok_or(WebhookStoreError::DeliveryNotFound)
+    }
+
+    async fn get_delivery_log(
+        &self,
+        id: &Uuid,
+    ) -> Result<Vec<WebhookDeliveryAttempt>, WebhookStoreError> {
+        let state = self.state.lock().unwrap();
+        if !state.deliveries.contains_key(id) {
+            return Err(WebhookStoreError::DeliveryNotFound);
+        }
+        Ok(state.delivery_log.get(id).cloned().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::WebhookEventType,
+        services::{random_id_generator::RandomIdGenerator, system_clock::SystemClock},
+    };
+
+    fn test_delivery() -> WebhookDelivery {
+        WebhookDelivery::new(
+            "test-subscription".to_owned(),
+            WebhookEventType::UserSignedUp,
+            "{}".to_owned(),
+            &SystemClock,
+            &RandomIdGenerator,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_enqueue_and_get_delivery() {
+        let store = HashMapWebhookStore::default();
+        let delivery = test_delivery();
+
+        store.enqueue_delivery(delivery.clone()).await.unwrap();
+
+        assert_eq!(store.get_delivery(&delivery.id).await.unwrap(), delivery);
+        assert!(store
+            .get_delivery_log(&delivery.id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_delivery_not_found() {
+        let store = HashMapWebhookStore::default();
+
+        let err = store.get_delivery(&Uuid::new_v4()).await.unwrap_err();
+        assert_eq!(err, WebhookStoreError::DeliveryNotFound);
+    }
+
+    #[tokio::test]
+    async fn test_claim_due_deliveries_leases_claimed_deliveries() {
+        let store = HashMapWebhookStore::default();
+        let delivery = test_delivery();
+        store.enqueue_delivery(delivery.clone()).await.unwrap();
+
+        let now = Utc::now();
+        let lease_until = now + chrono::Duration::seconds(30);
+
+        let claimed = store
+            .claim_due_deliveries(now, lease_until, 10)
+            .await
+            .unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].id, delivery.id);
+
+        // A leased delivery must not be handed out twice
+        let claimed = store
+            .claim_due_deliveries(now, lease_until, 10)
+            .await
+            .unwrap();
+        assert!(claimed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_claim_due_deliveries_skips_future_and_finished_deliveries() {
+        let store = HashMapWebhookStore::default();
+        let now = Utc::now();
+
+        let mut scheduled = test_delivery();
+        scheduled.next_attempt_at = now + chrono::Duration::seconds(60);
+        let mut delivered = test_delivery();
+        delivered.status = WebhookDeliveryStatus::Delivered;
+
+        store.enqueue_delivery(scheduled).await.unwrap();
+        store.enqueue_delivery(delivered).await.unwrap();
+
+        let claimed = store.claim_due_deliveries(now, now, 10).await.unwrap();
+        assert!(claimed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_record_attempt_updates_delivery_and_log() {
+        let store = HashMapWebhookStore::default();
+        let mut delivery = test_delivery();
+        store.enqueue_delivery(delivery.clone()).await.unwrap();
+
+        delivery.attempts = 1;
+        delivery.status = WebhookDeliveryStatus::Delivered;
+        let attempt = WebhookDeliveryAttempt {
+            delivery_id: delivery.id,
+            attempt: 1,
+            attempted_at: Utc::now(),
+            response_status: Some(200),
+            error: None,
+        };
+
+        store
+            .record_attempt(&delivery, attempt.clone())
+            .await
+            .unwrap();
+
+        assert_eq!(store.get_delivery(&delivery.id).await.unwrap(), delivery);
+        assert_eq!(
+            store.get_delivery_log(&delivery.id).await.unwrap(),
+            vec![attempt]
+        );
+    }
+}
@@ -1,50 +1,138 @@
-use std::collections::HashSet;
+use std::sync::Arc;
 
-use secrecy::{ExposeSecret, SecretString};
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::Result;
+use dashmap::DashMap;
 
-use crate::domain::{BannedTokenStore, BannedTokenStoreError};
+use crate::{
+    app_state::ClockType,
+    domain::{BannedTokenStore, BannedTokenStoreError, ExpiringStore},
+    services::system_clock::SystemClock,
+};
 
-#[derive(Default)]
+// Clones share the same bans, so the expiry purge worker can hold one
+#[derive(Clone)]
 pub struct HashSetBannedTokenStore {
-    users: HashSet<String>,
+    // The `jti` of each banned token with the time the token expires
+    tokens: Arc<DashMap<String, DateTime<Utc>>>,
+    clock: ClockType,
+}
+
+impl HashSetBannedTokenStore {
+    pub fn with_clock(mut self, clock: ClockType) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+impl Default for HashSetBannedTokenStore {
+    fn default() -> Self {
+        Self {
+            tokens: Arc::new(DashMap::new()),
+            clock: Arc::new(SystemClock),
+        }
+    }
 }
 
 #[async_trait::async_trait]
 impl BannedTokenStore for HashSetBannedTokenStore {
-    async fn add_token(&mut self, token: &SecretString) -> Result<(), BannedTokenStoreError> {
-        self.users.insert(token.expose_secret().to_owned());
+    async fn add_token(
+        &self,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), BannedTokenStoreError> {
+        self.tokens.insert(jti.to_owned(), expires_at);
         Ok(())
     }
-    async fn contains_token(&self, token: &SecretString) -> Result<bool, BannedTokenStoreError> {
-        Ok(self.users.contains(token.expose_secret()))
+    async fn contains_token(&self, jti: &str) -> Result<bool, BannedTokenStoreError> {
+        let now = self.clock.now();
+        Ok(self
+            .tokens
+            .get(jti)
+            .is_some_and(|expires_at| *expires_at > now))
+    }
+}
+
+#[async_trait::async_trait]
+impl ExpiringStore for HashSetBannedTokenStore {
+    async fn purge_expired(&self, now: DateTime<Utc>) -> Result<u64> {
+        // Counted as they are removed, bans added meanwhile would skew a difference in length
+        let mut purged = 0;
+        self.tokens.retain(|_, expires_at| {
+            let live = *expires_at > now;
+            purged += u64::from(!live);
+            live
+        });
+
+        Ok(purged)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use chrono::Duration;
+
     use super::*;
-    use crate::domain::BannedTokenStore;
+    use crate::{
+        domain::{BannedTokenStore, Clock, ExpiringStore},
+        services::fake_clock::FakeClock,
+    };
 
     #[tokio::test]
     async fn test_ban_and_check_token() {
-        let mut store = HashSetBannedTokenStore::default();
-        let token = SecretString::new("test_token".to_owned().into_boxed_str());
+        let store = HashSetBannedTokenStore::default();
+        let expires_at = Utc::now() + Duration::minutes(10);
+
+        assert!(!store.contains_token("test_jti").await.unwrap());
+
+        store.add_token("test_jti", expires_at).await.unwrap();
 
-        assert_eq!(store.contains_token(&token).await.unwrap(), false);
+        assert!(store.contains_token("test_jti").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_ban_expires_with_the_token() {
+        let clock = Arc::new(FakeClock::default());
+        let store = HashSetBannedTokenStore::default().with_clock(clock.clone());
+
+        store
+            .add_token("test_jti", clock.now() + Duration::minutes(10))
+            .await
+            .unwrap();
+        clock.advance(Duration::minutes(9));
+        assert!(store.contains_token("test_jti").await.unwrap());
+
+        clock.advance(Duration::minutes(1));
+        assert!(!store.contains_token("test_jti").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_purge_drops_expired_bans() {
+        let clock = Arc::new(FakeClock::default());
+        let store = HashSetBannedTokenStore::default().with_clock(clock.clone());
 
-        store.add_token(&token).await.unwrap();
+        store
+            .add_token("expired_jti", clock.now() + Duration::minutes(1))
+            .await
+            .unwrap();
+        store
+            .add_token("live_jti", clock.now() + Duration::minutes(10))
+            .await
+            .unwrap();
+        clock.advance(Duration::minutes(1));
 
-        assert_eq!(store.contains_token(&token).await.unwrap(), true);
+        assert_eq!(store.purge_expired(clock.now()).await.unwrap(), 1);
+        assert_eq!(store.tokens.len(), 1);
+        assert!(store.contains_token("live_jti").await.unwrap());
     }
 
     #[tokio::test]
     async fn test_multiple_tokens() {
-        let mut store = HashSetBannedTokenStore::default();
-        let token1 = SecretString::new("token1".to_owned().into_boxed_str());
-        let token2 = SecretString::new("token2".to_owned().into_boxed_str());
+        let store = HashSetBannedTokenStore::default();
+        let expires_at = Utc::now() + Duration::minutes(10);
 
-        store.add_token(&token1).await.unwrap();
-        assert_eq!(store.contains_token(&token1).await.unwrap(), true);
-        assert_eq!(store.contains_token(&token2).await.unwrap(), false);
+        store.add_token("jti1", expires_at).await.unwrap();
+        assert!(store.contains_token("jti1").await.unwrap());
+        assert!(!store.contains_token("jti2").await.unwrap());
     }
 }
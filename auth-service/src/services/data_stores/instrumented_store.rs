@@ -0,0 +1,208 @@
+use std::{future::Future, time::Instant};
+
+use chrono::{DateTime, Utc};
+use secrecy::SecretString;
+use uuid::Uuid;
+
+use crate::{
+    domain::{
+        BannedTokenStore, BannedTokenStoreError, Email, EmailOutboxStore, EmailOutboxStoreError,
+        LoginAttemptId, OutboxEmail, TwoFACode, TwoFACodeStore, TwoFACodeStoreError, User,
+        UserStore, UserStoreError, WebhookDelivery, WebhookDeliveryAttempt, WebhookStore,
+        WebhookStoreError,
+    },
+    settings::StorageBackend,
+    utils::metrics::record_store_operation,
+};
+
+// Wraps any store to record how long each operation takes, labelled with the store's backend
+pub struct Instrumented<S> {
+    inner: S,
+    backend: StorageBackend,
+}
+
+impl<S> Instrumented<S> {
+    pub fn new(inner: S, backend: StorageBackend) -> Self {
+        Self { inner, backend }
+    }
+}
+
+async fn timed<T>(
+    store: &'static str,
+    backend: StorageBackend,
+    operation: &'static str,
+    future: impl Future<Output = T>,
+) -> T {
+    let start = Instant::now();
+    let output = future.await;
+    record_store_operation(store, backend.as_str(), operation, start.elapsed());
+    output
+}
+
+#[async_trait::async_trait]
+impl<S: UserStore + Send + Sync> UserStore for Instrumented<S> {
+    async fn add_user(&self, user: User) -> Result<(), UserStoreError> {
+        timed("users", self.backend, "add_user", self.inner.add_user(user)).await
+    }
+
+    async fn add_user_with_deliveries(
+        &self,
+        user: User,
+        deliveries: Vec<WebhookDelivery>,
+    ) -> Result<Vec<WebhookDelivery>, UserStoreError> {
+        let add = self.inner.add_user_with_deliveries(user, deliveries);
+        timed("users", self.backend, "add_user", add).await
+    }
+
+    async fn get_user(&self, email: &Email) -> Result<User, UserStoreError> {
+        timed(
+            "users",
+            self.backend,
+            "get_user",
+            self.inner.get_user(email),
+        )
+        .await
+    }
+
+    async fn validate_user(
+        &self,
+        email: &Email,
+        raw_password: &SecretString,
+    ) -> Result<(), UserStoreError> {
+        let validation = self.inner.validate_user(email, raw_password);
+        timed("users", self.backend, "validate_user", validation).await
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: BannedTokenStore + Send + Sync> BannedTokenStore for Instrumented<S> {
+    async fn add_token(
+        &self,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), BannedTokenStoreError> {
+        let add = self.inner.add_token(jti, expires_at);
+        timed("banned_tokens", self.backend, "add_token", add).await
+    }
+
+    async fn contains_token(&self, jti: &str) -> Result<bool, BannedTokenStoreError> {
+        let contains = self.inner.contains_token(jti);
+        timed("banned_tokens", self.backend, "contains_token", contains).await
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: TwoFACodeStore + Send + Sync> TwoFACodeStore for Instrumented<S> {
+    async fn add_code(
+        &self,
+        email: Email,
+        login_attempt_id: LoginAttemptId,
+        code: TwoFACode,
+    ) -> Result<(), TwoFACodeStoreError> {
+        let add = self.inner.add_code(email, login_attempt_id, code);
+        timed("two_fa_codes", self.backend, "add_code", add).await
+    }
+
+    async fn remove_code(&self, email: &Email) -> Result<(), TwoFACodeStoreError> {
+        let remove = self.inner.remove_code(email);
+        timed("two_fa_codes", self.backend, "remove_code", remove).await
+    }
+
+    async fn get_code(
+        &self,
+        email: &Email,
+    ) -> Result<(LoginAttemptId, TwoFACode), TwoFACodeStoreError> {
+        let get = self.inner.get_code(email);
+        timed("two_fa_codes", self.backend, "get_code", get).await
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: EmailOutboxStore + Send + Sync> EmailOutboxStore for Instrumented<S> {
+    async fn enqueue_email(&self, email: OutboxEmail) -> Result<(), EmailOutboxStoreError> {
+        let backend = self.backend;
+        let enqueue = self.inner.enqueue_email(email);
+        timed("email_outbox", backend, "enqueue_email", enqueue).await
+    }
+
+    async fn claim_due_emails(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<OutboxEmail>, EmailOutboxStoreError> {
+        let backend = self.backend;
+        let claim = self.inner.claim_due_emails(now, lease_until, limit);
+        timed("email_outbox", backend, "claim_due_emails", claim).await
+    }
+
+    async fn mark_sent(&self, id: &Uuid) -> Result<(), EmailOutboxStoreError> {
+        let backend = self.backend;
+        timed(
+            "email_outbox",
+            backend,
+            "mark_sent",
+            self.inner.mark_sent(id),
+        )
+        .await
+    }
+
+    async fn record_failure(&self, email: &OutboxEmail) -> Result<(), EmailOutboxStoreError> {
+        let backend = self.backend;
+        let record = self.inner.record_failure(email);
+        timed("email_outbox", backend, "record_failure", record).await
+    }
+
+    async fn get_email(&self, id: &Uuid) -> Result<OutboxEmail, EmailOutboxStoreError> {
+        timed(
+            "email_outbox",
+            self.backend,
+            "get_email",
+            self.inner.get_email(id),
+        )
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: WebhookStore + Send + Sync> WebhookStore for Instrumented<S> {
+    async fn enqueue_delivery(&self, delivery: WebhookDelivery) -> Result<(), WebhookStoreError> {
+        let backend = self.backend;
+        let enqueue = self.inner.enqueue_delivery(delivery);
+        timed("webhooks", backend, "enqueue_delivery", enqueue).await
+    }
+
+    async fn claim_due_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<WebhookDelivery>, WebhookStoreError> {
+        let backend = self.backend;
+        let claim = self.inner.claim_due_deliveries(now, lease_until, limit);
+        timed("webhooks", backend, "claim_due_deliveries", claim).await
+    }
+
+    async fn record_attempt(
+        &self,
+        delivery: &WebhookDelivery,
+        attempt: WebhookDeliveryAttempt,
+    ) -> Result<(), WebhookStoreError> {
+        let backend = self.backend;
+        let record = self.inner.record_attempt(delivery, attempt);
+        timed("webhooks", backend, "record_attempt", record).await
+    }
+
+    async fn get_delivery(&self, id: &Uuid) -> Result<WebhookDelivery, WebhookStoreError> {
+        let get = self.inner.get_delivery(id);
+        timed("webhooks", self.backend, "get_delivery", get).await
+    }
+
+    async fn get_delivery_log(
+        &self,
+        id: &Uuid,
+    ) -> Result<Vec<WebhookDeliveryAttempt>, WebhookStoreError> {
+        let get = self.inner.get_delivery_log(id);
+        timed("webhooks", self.backend, "get_delivery_log", get).await
+    }
+}
@@ -1,13 +1,49 @@
+mod hashmap_email_outbox_store;
 mod hashmap_two_fa_code_store;
 mod hashmap_user_store;
+mod hashmap_webhook_store;
 mod hashset_banned_token_store;
+mod instrumented_store;
+#[cfg(feature = "postgres")]
+mod postgres_banned_token_store;
+#[cfg(feature = "postgres")]
+mod postgres_email_outbox_store;
+#[cfg(feature = "postgres")]
+mod postgres_two_fa_code_store;
+#[cfg(feature = "postgres")]
 mod postgres_user_store;
+#[cfg(feature = "postgres")]
+mod postgres_webhook_store;
+#[cfg(feature = "redis")]
 mod redis_banned_token_store;
+#[cfg(feature = "redis")]
+mod redis_connection;
+#[cfg(feature = "redis")]
 mod redis_two_fa_code_store;
+#[cfg(feature = "sqlite")]
+mod sqlite_user_store;
 
+pub use hashmap_email_outbox_store::*;
 pub use hashmap_two_fa_code_store::*;
 pub use hashmap_user_store::*;
+pub use hashmap_webhook_store::*;
 pub use hashset_banned_token_store::*;
+pub use instrumented_store::*;
+#[cfg(feature = "postgres")]
+pub use postgres_banned_token_store::*;
+#[cfg(feature = "postgres")]
+pub use postgres_email_outbox_store::*;
+#[cfg(feature = "postgres")]
+pub use postgres_two_fa_code_store::*;
+#[cfg(feature = "postgres")]
 pub use postgres_user_store::*;
+#[cfg(feature = "postgres")]
+pub use postgres_webhook_store::*;
+#[cfg(feature = "redis")]
 pub use redis_banned_token_store::*;
+#[cfg(feature = "redis")]
+pub use redis_connection::*;
+#[cfg(feature = "redis")]
 pub use redis_two_fa_code_store::*;
+#[cfg(feature = "sqlite")]
+pub use sqlite_user_store::*;
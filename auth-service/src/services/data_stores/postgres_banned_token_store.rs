@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Context, Result};
+use sqlx::PgPool;
+
+use crate::{
+    app_state::ClockType,
+    domain::{BannedTokenStore, BannedTokenStoreError, ExpiringStore},
+    services::system_clock::SystemClock,
+};
+
+pub struct PostgresBannedTokenStore {
+    pool: PgPool,
+    clock: ClockType,
+}
+
+impl PostgresBannedTokenStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: ClockType) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl BannedTokenStore for PostgresBannedTokenStore {
+    #[tracing::instrument(name = "Banning token in PostgreSQL", skip_all)]
+    async fn add_token(
+        &self,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), BannedTokenStoreError> {
+        sqlx::query!(
+            r#"
+                INSERT INTO banned_tokens (jti, expires_at)
+                VALUES ($1, $2)
+                ON CONFLICT (jti) DO UPDATE SET expires_at = EXCLUDED.expires_at
+            "#,
+            jti,
+            expires_at,
+        )
+        .execute(&self.pool)
+        .await
+        .wrap_err("failed to insert banned token into PostgreSQL")
+        .map_err(BannedTokenStoreError::UnexpectedError)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Checking banned token in PostgreSQL", skip_all)]
+    async fn contains_token(&self, jti: &str) -> Result<bool, BannedTokenStoreError> {
+        sqlx::query_scalar!(
+            r#"
+                SELECT EXISTS(
+                    SELECT 1 FROM banned_tokens WHERE jti = $1 AND expires_at > $2
+                ) AS "exists!"
+            "#,
+            jti,
+            self.clock.now(),
+        )
+        .fetch_one(&self.pool)
+        .await
+        .wrap_err("failed to check banned token in PostgreSQL")
+        .map_err(BannedTokenStoreError::UnexpectedError)
+    }
+}
+
+#[async_trait::async_trait]
+impl ExpiringStore for PostgresBannedTokenStore {
+    #[tracing::instrument(name = "Purging expired banned tokens from PostgreSQL", skip_all)]
+    async fn purge_expired(&self, now: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query!("DELETE FROM banned_tokens WHERE expires_at <= $1", now)
+            .execute(&self.pool)
+            .await
+            .wrap_err("failed to purge expired banned tokens")?;
+
+        Ok(result.rows_affected())
+    }
+}
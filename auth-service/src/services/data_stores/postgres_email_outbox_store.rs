@@ -0,0 +1,188 @@
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Context, Result};
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::{Email, EmailOutboxStore, EmailOutboxStoreError, OutboxEmail};
+
+pub struct PostgresEmailOutboxStore {
+    pool: PgPool,
+}
+
+impl PostgresEmailOutboxStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailOutboxStore for PostgresEmailOutboxStore {
+    #[tracing::instrument(name = "Enqueueing email in PostgreSQL outbox", skip_all)]
+    async fn enqueue_email(&self, email: OutboxEmail) -> Result<(), EmailOutboxStoreError> {
+        sqlx::query!(
+            r#"
+                INSERT INTO email_outbox
+                    (id, recipient, subject, html_body, text_body, status, attempts, next_attempt_at, last_error, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            "#,
+            email.id,
+            email.recipient.as_ref().expose_secret(),
+            email.subject,
+            email.html_body,
+            email.text_body,
+            email.status.as_str(),
+            email.attempts as i32,
+            email.next_attempt_at,
+            email.last_error,
+            email.created_at,
+        )
+        .execute(&self.pool)
+        .await
+        .wrap_err("failed to insert email into outbox")
+        .map_err(EmailOutboxStoreError::UnexpectedError)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Claiming due emails from PostgreSQL outbox", skip_all)]
+    async fn claim_due_emails(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<OutboxEmail>, EmailOutboxStoreError> {
+        let records = sqlx::query_as!(
+            OutboxEmailRow,
+            r#"
+                UPDATE email_outbox
+                SET next_attempt_at = $2
+                WHERE id IN (
+                    SELECT id
+                    FROM email_outbox
+                    WHERE status = 'pending' AND next_attempt_at <= $1
+                    ORDER BY next_attempt_at
+                    LIMIT $3
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING id, recipient, subject, html_body, text_body, status, attempts, next_attempt_at, last_error, created_at
+            "#,
+            now,
+            lease_until,
+            limit as i64,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .wrap_err("failed to claim due emails from outbox")
+        .map_err(EmailOutboxStoreError::UnexpectedError)?;
+
+        records
+            .into_iter()
+            .map(OutboxEmail::try_from)
+            .collect::<Result<Vec<_>>>()
+            .map_err(EmailOutboxStoreError::UnexpectedError)
+    }
+
+    #[tracing::instrument(name = "Removing sent email from PostgreSQL outbox", skip_all)]
+    async fn mark_sent(&self, id: &Uuid) -> Result<(), EmailOutboxStoreError> {
+        let deleted = sqlx::query!(
+            r#"
+                DELETE FROM email_outbox
+                WHERE id = $1
+            "#,
+            id,
+        )
+        .execute(&self.pool)
+        .await
+        .wrap_err("failed to delete sent email from outbox")
+        .map_err(EmailOutboxStoreError::UnexpectedError)?;
+
+        match deleted.rows_affected() {
+            0 => Err(EmailOutboxStoreError::EmailNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    #[tracing::instrument(
+        name = "Recording failed email delivery in PostgreSQL outbox",
+        skip_all
+    )]
+    async fn record_failure(&self, email: &OutboxEmail) -> Result<(), EmailOutboxStoreError> {
+        let updated = sqlx::query!(
+            r#"
+                UPDATE email_outbox
+                SET status = $2, attempts = $3, next_attempt_at = $4, last_error = $5,
+                    html_body = $6, text_body = $7
+                WHERE id = $1
+            "#,
+            email.id,
+            email.status.as_str(),
+            email.attempts as i32,
+            email.next_attempt_at,
+            email.last_error,
+            email.html_body,
+            email.text_body,
+        )
+        .execute(&self.pool)
+        .await
+        .wrap_err("failed to update email in outbox")
+        .map_err(EmailOutboxStoreError::UnexpectedError)?;
+
+        match updated.rows_affected() {
+            0 => Err(EmailOutboxStoreError::EmailNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    #[tracing::instrument(name = "Retrieving email from PostgreSQL outbox", skip_all)]
+    async fn get_email(&self, id: &Uuid) -> Result<OutboxEmail, EmailOutboxStoreError> {
+        let record = sqlx::query_as!(
+            OutboxEmailRow,
+            r#"
+                SELECT id, recipient, subject, html_body, text_body, status, attempts, next_attempt_at, last_error, created_at
+                FROM email_outbox
+                WHERE id = $1
+            "#,
+            id,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .wrap_err("failed to retrieve email from outbox")
+        .map_err(EmailOutboxStoreError::UnexpectedError)?
+        .ok_or(EmailOutboxStoreError::EmailNotFound)?;
+
+        OutboxEmail::try_from(record).map_err(EmailOutboxStoreError::UnexpectedError)
+    }
+}
+
+struct OutboxEmailRow {
+    id: Uuid,
+    recipient: String,
+    subject: String,
+    html_body: String,
+    text_body: String,
+    status: String,
+    attempts: i32,
+    next_attempt_at: DateTime<Utc>,
+    last_error: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+impl TryFrom<OutboxEmailRow> for OutboxEmail {
+    type Error = color_eyre::eyre::Report;
+
+    fn try_from(row: OutboxEmailRow) -> Result<Self> {
+        Ok(Self {
+            id: row.id,
+            recipient: Email::parse(SecretString::new(row.recipient.into_boxed_str()))?,
+            subject: row.subject,
+            html_body: row.html_body,
+            text_body: row.text_body,
+            status: row.status.parse()?,
+            attempts: row.attempts.try_into()?,
+            next_attempt_at: row.next_attempt_at,
+            last_error: row.last_error,
+            created_at: row.created_at,
+        })
+    }
+}
@@ -0,0 +1,129 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Context, Result};
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::PgPool;
+
+use crate::{
+    app_state::ClockType,
+    domain::{
+        Email, ExpiringStore, LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError,
+        TWO_FA_CODE_TTL,
+    },
+    services::system_clock::SystemClock,
+};
+
+pub struct PostgresTwoFACodeStore {
+    pool: PgPool,
+    clock: ClockType,
+}
+
+impl PostgresTwoFACodeStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: ClockType) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl TwoFACodeStore for PostgresTwoFACodeStore {
+    #[tracing::instrument(name = "Adding 2FA code to PostgreSQL", skip_all)]
+    async fn add_code(
+        &self,
+        email: Email,
+        login_attempt_id: LoginAttemptId,
+        code: TwoFACode,
+    ) -> Result<(), TwoFACodeStoreError> {
+        let expires_at = self.clock.now() + TWO_FA_CODE_TTL;
+
+        // A new login replaces the code of the previous attempt
+        sqlx::query!(
+            r#"
+                INSERT INTO two_fa_codes (email, login_attempt_id, code, expires_at)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (email) DO UPDATE
+                SET login_attempt_id = EXCLUDED.login_attempt_id,
+                    code = EXCLUDED.code,
+                    expires_at = EXCLUDED.expires_at
+            "#,
+            email.as_ref().expose_secret(),
+            login_attempt_id.as_ref().expose_secret(),
+            code.as_ref().expose_secret(),
+            expires_at,
+        )
+        .execute(&self.pool)
+        .await
+        .wrap_err("failed to insert 2FA code into PostgreSQL")
+        .map_err(TwoFACodeStoreError::UnexpectedError)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Removing 2FA code from PostgreSQL", skip_all)]
+    async fn remove_code(&self, email: &Email) -> Result<(), TwoFACodeStoreError> {
+        let result = sqlx::query!(
+            "DELETE FROM two_fa_codes WHERE email = $1 AND expires_at > $2",
+            email.as_ref().expose_secret(),
+            self.clock.now(),
+        )
+        .execute(&self.pool)
+        .await
+        .wrap_err("failed to delete 2FA code from PostgreSQL")
+        .map_err(TwoFACodeStoreError::UnexpectedError)?;
+
+        match result.rows_affected() {
+            0 => Err(TwoFACodeStoreError::LoginAttemptIdNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    #[tracing::instrument(name = "Retrieving 2FA code from PostgreSQL", skip_all)]
+    async fn get_code(
+        &self,
+        email: &Email,
+    ) -> Result<(LoginAttemptId, TwoFACode), TwoFACodeStoreError> {
+        let record = sqlx::query!(
+            r#"
+                SELECT login_attempt_id, code
+                FROM two_fa_codes
+                WHERE email = $1 AND expires_at > $2
+            "#,
+            email.as_ref().expose_secret(),
+            self.clock.now(),
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .wrap_err("failed to get 2FA code from PostgreSQL")
+        .map_err(TwoFACodeStoreError::UnexpectedError)?
+        .ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)?;
+
+        let login_attempt_id =
+            LoginAttemptId::parse(SecretString::new(record.login_attempt_id.into_boxed_str()))
+                .map_err(TwoFACodeStoreError::UnexpectedError)?;
+        let two_fa_code = TwoFACode::parse(SecretString::new(record.code.into_boxed_str()))
+            .map_err(TwoFACodeStoreError::UnexpectedError)?;
+
+        Ok((login_attempt_id, two_fa_code))
+    }
+}
+
+#[async_trait::async_trait]
+impl ExpiringStore for PostgresTwoFACodeStore {
+    #[tracing::instrument(name = "Purging expired 2FA codes from PostgreSQL", skip_all)]
+    async fn purge_expired(&self, now: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query!("DELETE FROM two_fa_codes WHERE expires_at <= $1", now)
+            .execute(&self.pool)
+            .await
+            .wrap_err("failed to purge expired 2FA codes")?;
+
+        Ok(result.rows_affected())
+    }
+}
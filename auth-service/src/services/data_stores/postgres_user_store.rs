@@ -1,9 +1,12 @@
-use color_eyre::eyre::eyre;
-use sqlx::PgPool;
+use color_eyre::eyre::{eyre, Context};
+use sqlx::{PgPool, Postgres, Transaction};
 
-use crate::domain::{Email, Password, User, UserStore, UserStoreError};
+use super::postgres_webhook_store::insert_delivery;
+use crate::domain::{Email, Password, User, UserStore, UserStoreError, WebhookDelivery};
 use secrecy::{ExposeSecret, SecretString};
 
+// Webhook deliveries are queued in the same transaction as the change that triggers them, so the
+// webhook store must be on the same database
 pub struct PostgresUserStore {
     pool: PgPool,
 }
@@ -16,42 +19,54 @@ impl PostgresUserStore {
 
 #[async_trait::async_trait]
 impl UserStore for PostgresUserStore {
+    async fn add_user(&self, user: User) -> Result<(), UserStoreError> {
+        self.add_user_with_deliveries(user, Vec::new())
+            .await
+            .map(|_| ())
+    }
+
     #[tracing::instrument(name = "Adding user to PostgreSQL", skip_all)] // New!
-    async fn add_user(&mut self, user: User) -> Result<(), UserStoreError> {
+    async fn add_user_with_deliveries(
+        &self,
+        user: User,
+        deliveries: Vec<WebhookDelivery>,
+    ) -> Result<Vec<WebhookDelivery>, UserStoreError> {
         let email = user.email().as_ref().expose_secret();
         let password_hash = user.password().as_ref().expose_secret();
         let requires_2fa = user.requires_2fa();
+        let locale = user.locale().map(|locale| locale.as_str());
 
+        let mut transaction = self.begin().await?;
         let res = sqlx::query!(
             r#"
-                INSERT INTO users (email, password_hash, requires_2fa)
-                VALUES ($1, $2, $3)
+                INSERT INTO users (email, password_hash, requires_2fa, locale)
+                VALUES ($1, $2, $3, $4)
             "#,
             email,
             password_hash,
-            requires_2fa
+            requires_2fa,
+            locale
         )
-        .execute(&self.pool)
+        .execute(&mut *transaction)
         .await;
 
-        match res {
-            Ok(_) => Ok(()),
-            Err(e) => {
-                if let Some(db_err) = e.as_database_error() {
-                    if db_err.code().as_deref() == Some("23505") {
-                        return Err(UserStoreError::UserAlreadyExists);
-                    }
+        if let Err(e) = res {
+            if let Some(db_err) = e.as_database_error() {
+                if db_err.code().as_deref() == Some("23505") {
+                    return Err(UserStoreError::UserAlreadyExists);
                 }
-                Err(UserStoreError::UnexpectedError(e.into()))
             }
+            return Err(UserStoreError::UnexpectedError(e.into()));
         }
+        commit_with_deliveries(transaction, deliveries).await?;
+        Ok(Vec::new())
     }
 
     #[tracing::instrument(name = "Retrieving user from PostgreSQL", skip_all)] // New!
     async fn get_user(&self, email: &Email) -> Result<User, UserStoreError> {
         let record = sqlx::query!(
             r#"
-                SELECT email, password_hash, requires_2fa
+                SELECT email, password_hash, requires_2fa, locale
                 FROM users
                 WHERE email = $1
             "#,
@@ -67,8 +82,13 @@ impl UserStore for PostgresUserStore {
             Password::parse_password_hash(SecretString::new(record.password_hash.into_boxed_str()))
                 .map_err(|e| UserStoreError::UnexpectedError(eyre!(e)))?;
         let requires_2fa = record.requires_2fa;
+        let locale = record
+            .locale
+            .map(|locale| locale.parse())
+            .transpose()
+            .map_err(UserStoreError::UnexpectedError)?;
 
-        Ok(User::new(email, password, requires_2fa))
+        Ok(User::new(email, password, requires_2fa).with_locale(locale))
     }
 
     #[tracing::instrument(name = "Validating user credentials in PostgreSQL", skip_all)] // New!
@@ -84,3 +104,30 @@ impl UserStore for PostgresUserStore {
             .map_err(|_| UserStoreError::InvalidCredentials)
     }
 }
+
+impl PostgresUserStore {
+    async fn begin(&self) -> Result<Transaction<'static, Postgres>, UserStoreError> {
+        self.pool
+            .begin()
+            .await
+            .wrap_err("failed to begin transaction")
+            .map_err(UserStoreError::UnexpectedError)
+    }
+}
+
+async fn commit_with_deliveries(
+    mut transaction: Transaction<'static, Postgres>,
+    deliveries: Vec<WebhookDelivery>,
+) -> Result<(), UserStoreError> {
+    for delivery in &deliveries {
+        insert_delivery(&mut *transaction, delivery)
+            .await
+            .wrap_err("failed to insert webhook delivery")
+            .map_err(UserStoreError::UnexpectedError)?;
+    }
+    transaction
+        .commit()
+        .await
+        .wrap_err("failed to commit transaction")
+        .map_err(UserStoreError::UnexpectedError)
+}
@@ -0,0 +1,234 @@
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Context, Result};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::domain::{WebhookDelivery, WebhookDeliveryAttempt, WebhookStore, WebhookStoreError};
+
+pub struct PostgresWebhookStore {
+    pool: PgPool,
+}
+
+impl PostgresWebhookStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl WebhookStore for PostgresWebhookStore {
+    #[tracing::instrument(name = "Enqueueing webhook delivery in PostgreSQL", skip_all)]
+    async fn enqueue_delivery(&self, delivery: WebhookDelivery) -> Result<(), WebhookStoreError> {
+        insert_delivery(&self.pool, &delivery)
+            .await
+            .wrap_err("failed to insert webhook delivery")
+            .map_err(WebhookStoreError::UnexpectedError)
+    }
+
+    #[tracing::instrument(name = "Claiming due webhook deliveries in PostgreSQL", skip_all)]
+    async fn claim_due_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<WebhookDelivery>, WebhookStoreError> {
+        // SKIP LOCKED lets several auth-service instances drain the queue without double delivery
+        let records = sqlx::query_as!(
+            WebhookDeliveryRow,
+            r#"
+                UPDATE webhook_deliveries
+                SET next_attempt_at = $2
+                WHERE id IN (
+                    SELECT id
+                    FROM webhook_deliveries
+                    WHERE status = 'pending' AND next_attempt_at <= $1
+                    ORDER BY next_attempt_at
+                    LIMIT $3
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING id, subscription_id, event_type, payload, status, attempts, next_attempt_at, created_at
+            "#,
+            now,
+            lease_until,
+            limit as i64,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .wrap_err("failed to claim due webhook deliveries")
+        .map_err(WebhookStoreError::UnexpectedError)?;
+
+        records
+            .into_iter()
+            .map(WebhookDelivery::try_from)
+            .collect::<Result<Vec<_>>>()
+            .map_err(WebhookStoreError::UnexpectedError)
+    }
+
+    #[tracing::instrument(name = "Recording webhook delivery attempt in PostgreSQL", skip_all)]
+    async fn record_attempt(
+        &self,
+        delivery: &WebhookDelivery,
+        attempt: WebhookDeliveryAttempt,
+    ) -> Result<(), WebhookStoreError> {
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .wrap_err("failed to begin transaction")
+            .map_err(WebhookStoreError::UnexpectedError)?;
+
+        let updated = sqlx::query!(
+            r#"
+                UPDATE webhook_deliveries
+                SET status = $2, attempts = $3, next_attempt_at = $4
+                WHERE id = $1
+            "#,
+            delivery.id,
+            delivery.status.as_str(),
+            delivery.attempts as i32,
+            delivery.next_attempt_at,
+        )
+        .execute(&mut *transaction)
+        .await
+        .wrap_err("failed to update webhook delivery")
+        .map_err(WebhookStoreError::UnexpectedError)?;
+
+        if updated.rows_affected() == 0 {
+            return Err(WebhookStoreError::DeliveryNotFound);
+        }
+
+        sqlx::query!(
+            r#"
+                INSERT INTO webhook_delivery_log
+                    (delivery_id, attempt, attempted_at, response_status, error)
+                VALUES ($1, $2, $3, $4, $5)
+            "#,
+            attempt.delivery_id,
+            attempt.attempt as i32,
+            attempt.attempted_at,
+            attempt.response_status.map(i32::from),
+            attempt.error,
+        )
+        .execute(&mut *transaction)
+        .await
+        .wrap_err("failed to insert webhook delivery log entry")
+        .map_err(WebhookStoreError::UnexpectedError)?;
+
+        transaction
+            .commit()
+            .await
+            .wrap_err("failed to commit transaction")
+            .map_err(WebhookStoreError::UnexpectedError)
+    }
+
+    #[tracing::instrument(name = "Retrieving webhook delivery from PostgreSQL", skip_all)]
+    async fn get_delivery(&self, id: &Uuid) -> Result<WebhookDelivery, WebhookStoreError> {
+        let record = sqlx::query_as!(
+            WebhookDeliveryRow,
+            r#"
+                SELECT id, subscription_id, event_type, payload, status, attempts, next_attempt_at, created_at
+                FROM webhook_deliveries
+                WHERE id = $1
+            "#,
+            id,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .wrap_err("failed to retrieve webhook delivery")
+        .map_err(WebhookStoreError::UnexpectedError)?
+        .ok_or(WebhookStoreError::DeliveryNotFound)?;
+
+        WebhookDelivery::try_from(record).map_err(WebhookStoreError::UnexpectedError)
+    }
+
+    #[tracing::instrument(name = "Retrieving webhook delivery log from PostgreSQL", skip_all)]
+    async fn get_delivery_log(
+        &self,
+        id: &Uuid,
+    ) -> Result<Vec<WebhookDeliveryAttempt>, WebhookStoreError> {
+        // Make sure unknown ids surface as DeliveryNotFound rather than an empty log
+        self.get_delivery(id).await?;
+
+        let records = sqlx::query!(
+            r#"
+                SELECT delivery_id, attempt, attempted_at, response_status, error
+                FROM webhook_delivery_log
+                WHERE delivery_id = $1
+                ORDER BY attempt
+            "#,
+            id,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .wrap_err("failed to retrieve webhook delivery log")
+        .map_err(WebhookStoreError::UnexpectedError)?;
+
+        records
+            .into_iter()
+            .map(|r| {
+                Ok(WebhookDeliveryAttempt {
+                    delivery_id: r.delivery_id,
+                    attempt: r.attempt.try_into()?,
+                    attempted_at: r.attempted_at,
+                    response_status: r.response_status.map(u16::try_from).transpose()?,
+                    error: r.error,
+                })
+            })
+            .collect::<Result<Vec<_>>>()
+            .map_err(WebhookStoreError::UnexpectedError)
+    }
+}
+
+// Also used by the user store, to queue deliveries in the same transaction as the change
+pub(super) async fn insert_delivery(
+    executor: impl PgExecutor<'_>,
+    delivery: &WebhookDelivery,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+            INSERT INTO webhook_deliveries
+                (id, subscription_id, event_type, payload, status, attempts, next_attempt_at, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        delivery.id,
+        delivery.subscription_id,
+        delivery.event_type.as_str(),
+        delivery.payload,
+        delivery.status.as_str(),
+        delivery.attempts as i32,
+        delivery.next_attempt_at,
+        delivery.created_at,
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+struct WebhookDeliveryRow {
+    id: Uuid,
+    subscription_id: String,
+    event_type: String,
+    payload: String,
+    status: String,
+    attempts: i32,
+    next_attempt_at: DateTime<Utc>,
+    created_at: DateTime<Utc>,
+}
+
+impl TryFrom<WebhookDeliveryRow> for WebhookDelivery {
+    type Error = color_eyre::eyre::Report;
+
+    fn try_from(row: WebhookDeliveryRow) -> Result<Self> {
+        Ok(Self {
+            id: row.id,
+            subscription_id: row.subscription_id,
+            event_type: row.event_type.parse()?,
+            payload: row.payload,
+            status: row.status.parse()?,
+            attempts: row.attempts.try_into()?,
+            next_attempt_at: row.next_attempt_at,
+            created_at: row.created_at,
+        })
+    }
+}
@@ -1,42 +1,48 @@
-use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::Context;
+use redis::{AsyncTypedCommands, SetExpiry, SetOptions};
 
-use color_eyre::eyre::{Context};
-use redis::{Connection, TypedCommands};
-use secrecy::{ExposeSecret, SecretString};
-use tokio::sync::RwLock;
-
-use crate::{
-    domain::{BannedTokenStore, BannedTokenStoreError},
-    utils::auth::TOKEN_TTL_SECONDS,
-};
+use super::RedisConnection;
+use crate::domain::{BannedTokenStore, BannedTokenStoreError};
 
 pub struct RedisBannedTokenStore {
-    connection: Arc<RwLock<Connection>>,
+    connection: RedisConnection,
 }
 
 impl RedisBannedTokenStore {
-    pub fn new(connection: Arc<RwLock<Connection>>) -> Self {
+    pub fn new(connection: RedisConnection) -> Self {
         Self { connection }
     }
+
+    fn get_key(&self, jti: &str) -> String {
+        self.connection.key(BANNED_TOKEN_KEY_KIND, jti)
+    }
 }
 
 #[async_trait::async_trait]
 impl BannedTokenStore for RedisBannedTokenStore {
     #[tracing::instrument(name = "RedisBannedTokenStore:add_token", skip_all)] // New!
-    async fn add_token(&mut self, token: &SecretString) -> Result<(), BannedTokenStoreError> {
-        let token_key = get_key(token.expose_secret());
+    async fn add_token(
+        &self,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), BannedTokenStoreError> {
+        let token_key = self.get_key(jti);
         let value = true;
 
-        let ttl: u64 = TOKEN_TTL_SECONDS
-            .try_into()
-            .wrap_err("failed to cast TOKEN_TTL_SECONDS to u64") // New!
+        // Redis drops the ban by itself once the token has expired
+        let expires_at = u64::try_from(expires_at.timestamp_millis())
+            .wrap_err("token expiry is before the Unix epoch")
             .map_err(BannedTokenStoreError::UnexpectedError)?;
 
-        let _: () = self
-            .connection
-            .write()
+        self.connection
+            .clone()
+            .set_options(
+                &token_key,
+                value,
+                SetOptions::default().with_expiration(SetExpiry::PXAT(expires_at)),
+            )
             .await
-            .set_ex(&token_key, value, ttl)
             .wrap_err("failed to set banned token in Redis") // New!
             .map_err(BannedTokenStoreError::UnexpectedError)?;
 
@@ -44,21 +50,17 @@ impl BannedTokenStore for RedisBannedTokenStore {
     }
 
     #[tracing::instrument(name = "RedisBannedTokenStore:contains_token", skip_all)] // New!
-    async fn contains_token(&self, token: &SecretString) -> Result<bool, BannedTokenStoreError> {
+    async fn contains_token(&self, jti: &str) -> Result<bool, BannedTokenStoreError> {
         // Check if the token exists by calling the exists method on the Redis connection
-        let token_key = get_key(token.expose_secret());
+        let token_key = self.get_key(jti);
         self.connection
-            .write()
-            .await
+            .clone()
             .exists(&token_key)
+            .await
             .wrap_err("failed to check if token exists in Redis") // New!
             .map_err(BannedTokenStoreError::UnexpectedError)
     }
 }
 
 // We are using a key prefix to prevent collisions and organize data!
-const BANNED_TOKEN_KEY_PREFIX: &str = "banned_token:";
-
-fn get_key(token: &str) -> String {
-    format!("{}{}", BANNED_TOKEN_KEY_PREFIX, token)
-}
+const BANNED_TOKEN_KEY_KIND: &str = "banned_token";
@@ -0,0 +1,287 @@
+use std::sync::{Arc, RwLock};
+
+use redis::{
+    aio::{ConnectionLike, ConnectionManager, ConnectionManagerConfig},
+    cluster::ClusterClientBuilder,
+    cluster_async::ClusterConnection,
+    sentinel::{SentinelClient, SentinelClientBuilder, SentinelServerType},
+    Client, Cmd, ConnectionAddr, ConnectionInfo, ErrorKind, Pipeline, RedisConnectionInfo,
+    RedisError, RedisFuture, RedisResult, TlsMode, Value,
+};
+use secrecy::ExposeSecret;
+use tokio::sync::Mutex;
+
+use crate::settings::{RedisMode, RedisSettings};
+
+// Requests wait for an ongoing reconnection, so keep it short to fail fast during an outage.
+// The next request after a failure starts a new reconnection.
+const RECONNECT_RETRIES: usize = 2;
+const RECONNECT_MAX_DELAY_MS: u64 = 200;
+
+// Multiplexed connection shared by every request, reconnecting after failures.
+// The stores run their commands on it whichever way the deployment is reached.
+#[derive(Clone)]
+pub struct RedisConnection {
+    inner: Connection,
+    key_prefix: Arc<str>,
+}
+
+#[derive(Clone)]
+enum Connection {
+    Standalone(ConnectionManager),
+    Sentinel(SentinelConnection),
+    Cluster(ClusterConnection),
+}
+
+impl RedisConnection {
+    pub async fn connect(settings: &RedisSettings) -> RedisResult<Self> {
+        if settings.tls {
+            // reqwest and lettre enable different rustls crypto providers, so Redis can't pick one
+            let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+        }
+
+        let inner = match settings.mode {
+            RedisMode::Standalone => {
+                let address = addresses(settings)?.remove(0);
+                let client = Client::open(connection_info(settings, address))?;
+                Connection::Standalone(
+                    ConnectionManager::new_with_config(client, manager_config(settings)).await?,
+                )
+            }
+            RedisMode::Sentinel => {
+                Connection::Sentinel(SentinelConnection::connect(settings).await?)
+            }
+            RedisMode::Cluster => Connection::Cluster(connect_to_cluster(settings).await?),
+        };
+
+        Ok(Self {
+            inner,
+            key_prefix: settings.key_prefix.as_str().into(),
+        })
+    }
+
+    // Keys look like `<key_prefix><kind>:{<id>}`. Redis Cluster only hashes the part in braces,
+    // so keys spread over the slots by id rather than piling up in one slot per kind.
+    pub fn key(&self, kind: &str, id: &str) -> String {
+        format!("{}{}:{{{}}}", self.key_prefix, kind, id)
+    }
+}
+
+impl ConnectionLike for RedisConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        match &mut self.inner {
+            Connection::Standalone(connection) => connection.req_packed_command(cmd),
+            Connection::Sentinel(connection) => connection.req_packed_command(cmd),
+            Connection::Cluster(connection) => connection.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        match &mut self.inner {
+            Connection::Standalone(connection) => {
+                connection.req_packed_commands(cmd, offset, count)
+            }
+            Connection::Sentinel(connection) => connection.req_packed_commands(cmd, offset, count),
+            Connection::Cluster(connection) => connection.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match &self.inner {
+            Connection::Standalone(connection) => connection.get_db(),
+            Connection::Sentinel(connection) => connection.get_db(),
+            Connection::Cluster(connection) => connection.get_db(),
+        }
+    }
+}
+
+// The connection manager reconnects to the address it was built with, but after a failover
+// the master is somewhere else. The sentinels are asked for the master again after a
+// connection error or a write rejected by a demoted master.
+#[derive(Clone)]
+struct SentinelConnection {
+    sentinel: Arc<Mutex<SentinelClient>>,
+    master: Arc<RwLock<Master>>,
+    config: ConnectionManagerConfig,
+}
+
+#[derive(Clone)]
+struct Master {
+    connection: ConnectionManager,
+    // Bumped on every failover, so concurrent failures only look the master up once
+    generation: u64,
+}
+
+impl SentinelConnection {
+    async fn connect(settings: &RedisSettings) -> RedisResult<Self> {
+        let sentinels = addresses(settings)?
+            .into_iter()
+            .map(|address| connection_addr(settings, address));
+        let mut builder = SentinelClientBuilder::new(
+            sentinels,
+            settings.master_name.clone(),
+            SentinelServerType::Master,
+        )?;
+        if settings.tls {
+            builder = builder
+                .set_client_to_redis_tls_mode(TlsMode::Secure)
+                .set_client_to_sentinel_tls_mode(TlsMode::Secure);
+        }
+        if !settings.username.is_empty() {
+            builder = builder.set_client_to_redis_username(settings.username.clone());
+        }
+        if !settings.password.expose_secret().is_empty() {
+            builder =
+                builder.set_client_to_redis_password(settings.password.expose_secret().to_owned());
+        }
+
+        let mut sentinel = builder.build()?;
+        let config = manager_config(settings);
+        let master = connect_to_master(&mut sentinel, &config).await?;
+
+        Ok(Self {
+            sentinel: Arc::new(Mutex::new(sentinel)),
+            master: Arc::new(RwLock::new(Master {
+                connection: master,
+                generation: 0,
+            })),
+            config,
+        })
+    }
+
+    fn current_master(&self) -> Master {
+        self.master.read().expect("poisoned lock").clone()
+    }
+
+    async fn refresh_master(&self, failed_generation: u64) {
+        let mut sentinel = self.sentinel.lock().await;
+        // Another request may have found the new master while this one waited for the lock
+        if self.current_master().generation != failed_generation {
+            return;
+        }
+
+        match connect_to_master(&mut sentinel, &self.config).await {
+            Ok(connection) => {
+                *self.master.write().expect("poisoned lock") = Master {
+                    connection,
+                    generation: failed_generation + 1,
+                }
+            }
+            Err(e) => tracing::warn!("Failed to find the Redis master through Sentinel: {}", e),
+        }
+    }
+}
+
+impl ConnectionLike for SentinelConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        Box::pin(async move {
+            let mut master = self.current_master();
+            let result = master.connection.req_packed_command(cmd).await;
+            if matches!(&result, Err(e) if master_may_have_moved(e)) {
+                self.refresh_master(master.generation).await;
+            }
+            result
+        })
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        Box::pin(async move {
+            let mut master = self.current_master();
+            let result = master
+                .connection
+                .req_packed_commands(cmd, offset, count)
+                .await;
+            if matches!(&result, Err(e) if master_may_have_moved(e)) {
+                self.refresh_master(master.generation).await;
+            }
+            result
+        })
+    }
+
+    fn get_db(&self) -> i64 {
+        self.current_master().connection.get_db()
+    }
+}
+
+async fn connect_to_master(
+    sentinel: &mut SentinelClient,
+    config: &ConnectionManagerConfig,
+) -> RedisResult<ConnectionManager> {
+    let client = sentinel.async_get_client().await?;
+    ConnectionManager::new_with_config(client, config.clone()).await
+}
+
+fn master_may_have_moved(e: &RedisError) -> bool {
+    e.is_unrecoverable_error() || e.is_timeout() || e.kind() == ErrorKind::ReadOnly
+}
+
+async fn connect_to_cluster(settings: &RedisSettings) -> RedisResult<ClusterConnection> {
+    let nodes = addresses(settings)?
+        .into_iter()
+        .map(|address| connection_info(settings, address))
+        .collect::<Vec<_>>();
+    let mut builder = ClusterClientBuilder::new(nodes)
+        .retries(RECONNECT_RETRIES as u32)
+        .max_retry_wait(RECONNECT_MAX_DELAY_MS)
+        .connection_timeout(settings.connection_timeout)
+        .response_timeout(settings.response_timeout);
+    if settings.tls {
+        builder = builder.tls(TlsMode::Secure);
+    }
+
+    builder.build()?.get_async_connection().await
+}
+
+fn manager_config(settings: &RedisSettings) -> ConnectionManagerConfig {
+    ConnectionManagerConfig::new()
+        .set_number_of_retries(RECONNECT_RETRIES)
+        .set_max_delay(RECONNECT_MAX_DELAY_MS)
+        .set_connection_timeout(settings.connection_timeout)
+        .set_response_timeout(settings.response_timeout)
+}
+
+fn addresses(settings: &RedisSettings) -> RedisResult<Vec<(String, u16)>> {
+    settings.addresses().map_err(|problem| {
+        RedisError::from((
+            ErrorKind::InvalidClientConfig,
+            "invalid Redis address",
+            problem,
+        ))
+    })
+}
+
+fn connection_addr(settings: &RedisSettings, (host, port): (String, u16)) -> ConnectionAddr {
+    if settings.tls {
+        ConnectionAddr::TcpTls {
+            host,
+            port,
+            insecure: false,
+            tls_params: None,
+        }
+    } else {
+        ConnectionAddr::Tcp(host, port)
+    }
+}
+
+fn connection_info(settings: &RedisSettings, address: (String, u16)) -> ConnectionInfo {
+    let non_empty = |value: &str| (!value.is_empty()).then(|| value.to_owned());
+
+    ConnectionInfo {
+        addr: connection_addr(settings, address),
+        redis: RedisConnectionInfo {
+            username: non_empty(&settings.username),
+            password: non_empty(settings.password.expose_secret()),
+            ..Default::default()
+        },
+    }
+}
@@ -1,20 +1,40 @@
 use std::sync::Arc;
 
 use color_eyre::eyre::{eyre, Context};
-use redis::{Connection, TypedCommands};
+use redis::{AsyncTypedCommands, SetExpiry, SetOptions};
 use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
 
-use crate::domain::{Email, LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError};
+use super::RedisConnection;
+use crate::{
+    app_state::ClockType,
+    domain::{
+        Email, LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError, TWO_FA_CODE_TTL,
+    },
+    services::system_clock::SystemClock,
+};
 
 pub struct RedisTwoFACodeStore {
-    connection: Arc<RwLock<Connection>>,
+    connection: RedisConnection,
+    clock: ClockType,
 }
 
 impl RedisTwoFACodeStore {
-    pub fn new(connection: Arc<RwLock<Connection>>) -> Self {
-        Self { connection }
+    pub fn new(connection: RedisConnection) -> Self {
+        Self {
+            connection,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: ClockType) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn get_key(&self, email: &Email) -> String {
+        self.connection
+            .key(TWO_FA_CODE_KEY_KIND, email.as_ref().expose_secret())
     }
 }
 
@@ -22,12 +42,12 @@ impl RedisTwoFACodeStore {
 impl TwoFACodeStore for RedisTwoFACodeStore {
     #[tracing::instrument(name = "RedisTwoFACodeStore:add_code", skip_all)] // New!
     async fn add_code(
-        &mut self,
+        &self,
         email: Email,
         login_attempt_id: LoginAttemptId,
         code: TwoFACode,
     ) -> Result<(), TwoFACodeStoreError> {
-        let key = get_key(&email);
+        let key = self.get_key(&email);
         let two_fa_info = TwoFATuple(
             login_attempt_id.as_ref().expose_secret().to_string(),
             code.as_ref().expose_secret().to_string(),
@@ -35,23 +55,32 @@ impl TwoFACodeStore for RedisTwoFACodeStore {
         let two_fa_info = serde_json::to_string(&two_fa_info)
             .wrap_err("Failed to serialize 2FA tuple")
             .map_err(TwoFACodeStoreError::UnexpectedError)?;
+        let expires_at = u64::try_from((self.clock.now() + TWO_FA_CODE_TTL).timestamp_millis())
+            .wrap_err("2FA code expiry is before the Unix epoch")
+            .map_err(TwoFACodeStoreError::UnexpectedError)?;
 
         self.connection
-            .write()
+            .clone()
+            .set_options(
+                key,
+                two_fa_info,
+                SetOptions::default().with_expiration(SetExpiry::PXAT(expires_at)),
+            )
             .await
-            .set_ex(key, two_fa_info, TEN_MINUTES_IN_SECONDS)
             .wrap_err("failed to set 2FA code in Redis")
-            .map_err(TwoFACodeStoreError::UnexpectedError)
+            .map_err(TwoFACodeStoreError::UnexpectedError)?;
+
+        Ok(())
     }
 
     #[tracing::instrument(name = "RedisTwoFACodeStore:remove_code", skip_all)] // New!
-    async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError> {
-        let key = get_key(&email);
+    async fn remove_code(&self, email: &Email) -> Result<(), TwoFACodeStoreError> {
+        let key = self.get_key(email);
         let deleted: usize = self
             .connection
-            .write()
-            .await
+            .clone()
             .del(key)
+            .await
             .wrap_err("failed to delete 2FA code from Redis") // New!
             .map_err(TwoFACodeStoreError::UnexpectedError)?;
         match deleted {
@@ -68,15 +97,14 @@ impl TwoFACodeStore for RedisTwoFACodeStore {
         &self,
         email: &Email,
     ) -> Result<(LoginAttemptId, TwoFACode), TwoFACodeStoreError> {
-        let key = get_key(&email);
+        let key = self.get_key(email);
         let two_fa_info = self
             .connection
-            .write()
-            .await
+            .clone()
             .get(key)
-            .map_err(|_| {
-                TwoFACodeStoreError::UnexpectedError(eyre!("Failed to get user by email"))
-            })?
+            .await
+            .wrap_err("failed to get 2FA code from Redis")
+            .map_err(TwoFACodeStoreError::UnexpectedError)?
             .ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)?;
 
         let two_fa_info: TwoFATuple = serde_json::from_str(&two_fa_info)
@@ -95,9 +123,4 @@ impl TwoFACodeStore for RedisTwoFACodeStore {
 #[derive(Serialize, Deserialize)]
 struct TwoFATuple(pub String, pub String);
 
-const TEN_MINUTES_IN_SECONDS: u64 = 600;
-const TWO_FA_CODE_PREFIX: &str = "two_fa_code:";
-
-fn get_key(email: &Email) -> String {
-    format!("{}{}", TWO_FA_CODE_PREFIX, email.as_ref().expose_secret())
-}
+const TWO_FA_CODE_KEY_KIND: &str = "two_fa_code";
@@ -0,0 +1,91 @@
+use color_eyre::eyre::eyre;
+use sqlx::{Row, SqlitePool};
+
+use crate::domain::{Email, Password, User, UserStore, UserStoreError};
+use secrecy::{ExposeSecret, SecretString};
+
+// Uses unchecked queries: the offline query data in `.sqlx` is prepared against PostgreSQL
+pub struct SqliteUserStore {
+    pool: SqlitePool,
+}
+
+impl SqliteUserStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl UserStore for SqliteUserStore {
+    #[tracing::instrument(name = "Adding user to SQLite", skip_all)]
+    async fn add_user(&self, user: User) -> Result<(), UserStoreError> {
+        let res = sqlx::query(
+            r#"
+                INSERT INTO users (email, password_hash, requires_2fa, locale)
+                VALUES (?, ?, ?, ?)
+            "#,
+        )
+        .bind(user.email().as_ref().expose_secret())
+        .bind(user.password().as_ref().expose_secret())
+        .bind(user.requires_2fa())
+        .bind(user.locale().map(|locale| locale.as_str()))
+        .execute(&self.pool)
+        .await;
+
+        if let Err(e) = res {
+            if let Some(db_err) = e.as_database_error() {
+                if db_err.is_unique_violation() {
+                    return Err(UserStoreError::UserAlreadyExists);
+                }
+            }
+            return Err(UserStoreError::UnexpectedError(e.into()));
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving user from SQLite", skip_all)]
+    async fn get_user(&self, email: &Email) -> Result<User, UserStoreError> {
+        let row = sqlx::query(
+            r#"
+                SELECT email, password_hash, requires_2fa, locale
+                FROM users
+                WHERE email = ?
+            "#,
+        )
+        .bind(email.as_ref().expose_secret())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?
+        .ok_or(UserStoreError::UserNotFound)?;
+
+        let email = Email::parse(SecretString::new(
+            row.get::<String, _>("email").into_boxed_str(),
+        ))
+        .map_err(|e| UserStoreError::UnexpectedError(eyre!(e)))?;
+        let password = Password::parse_password_hash(SecretString::new(
+            row.get::<String, _>("password_hash").into_boxed_str(),
+        ))
+        .map_err(|e| UserStoreError::UnexpectedError(eyre!(e)))?;
+        let requires_2fa = row.get("requires_2fa");
+        let locale = row
+            .get::<Option<String>, _>("locale")
+            .map(|locale| locale.parse())
+            .transpose()
+            .map_err(UserStoreError::UnexpectedError)?;
+
+        Ok(User::new(email, password, requires_2fa).with_locale(locale))
+    }
+
+    #[tracing::instrument(name = "Validating user credentials in SQLite", skip_all)]
+    async fn validate_user(
+        &self,
+        email: &Email,
+        raw_password: &SecretString,
+    ) -> Result<(), UserStoreError> {
+        let user = self.get_user(email).await?;
+        user.password()
+            .verify_raw_password(raw_password)
+            .await
+            .map_err(|_| UserStoreError::InvalidCredentials)
+    }
+}
@@ -0,0 +1,368 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Context, Result};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::{
+    app_state::{ClockType, EmailClientType, EmailOutboxStoreType, IdGeneratorType},
+    domain::{Email, OutboxEmail},
+    services::{
+        email_templates::RenderedEmail, random_id_generator::RandomIdGenerator,
+        system_clock::SystemClock,
+    },
+    utils::{
+        poller::{JobQueue, Poller},
+        retry::RetryPolicy,
+        shutdown::Shutdown,
+    },
+};
+
+// Number of emails the worker claims per poll
+const CLAIM_BATCH_SIZE: usize = 20;
+// How long a claimed email stays hidden from other workers before it is retried
+const SEND_LEASE: Duration = Duration::from_secs(120);
+
+// Durably queues transactional emails. A request only has to wait for the email
+// to be stored; handing it to the provider happens in `EmailOutboxWorker`.
+pub struct EmailOutbox {
+    store: EmailOutboxStoreType,
+    notify: Arc<Notify>,
+    clock: ClockType,
+    id_generator: IdGeneratorType,
+}
+
+impl EmailOutbox {
+    pub fn new(store: EmailOutboxStoreType) -> Self {
+        Self {
+            store,
+            notify: Arc::new(Notify::new()),
+            clock: Arc::new(SystemClock),
+            id_generator: Arc::new(RandomIdGenerator),
+        }
+    }
+
+    // Timestamps queued emails and schedules their retries, here and in the worker
+    pub fn with_clock(mut self, clock: ClockType) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_id_generator(mut self, id_generator: IdGeneratorType) -> Self {
+        self.id_generator = id_generator;
+        self
+    }
+
+    #[tracing::instrument(name = "Enqueueing email", skip_all)]
+    pub async fn enqueue(&self, recipient: &Email, content: RenderedEmail) -> Result<()> {
+        let email = OutboxEmail::new(
+            recipient.clone(),
+            content.subject,
+            content.html_body,
+            content.text_body,
+            &*self.clock,
+            &*self.id_generator,
+        );
+        self.store
+            .enqueue_email(email)
+            .await
+            .wrap_err("failed to enqueue email")?;
+
+        // Wake the worker so the email goes out without waiting for the next poll
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    pub fn worker(
+        &self,
+        email_client: EmailClientType,
+        retry_policy: RetryPolicy,
+        poll_interval: Duration,
+    ) -> EmailOutboxWorker {
+        EmailOutboxWorker {
+            store: self.store.clone(),
+            email_client,
+            retry_policy,
+            poller: Poller::new(poll_interval, SEND_LEASE, CLAIM_BATCH_SIZE)
+                .with_wake(self.notify.clone())
+                .with_final_poll()
+                .with_clock(self.clock.clone()),
+            clock: self.clock.clone(),
+        }
+    }
+}
+
+// Background task draining the outbox through the `EmailClient`
+pub struct EmailOutboxWorker {
+    store: EmailOutboxStoreType,
+    email_client: EmailClientType,
+    retry_policy: RetryPolicy,
+    poller: Poller,
+    clock: ClockType,
+}
+
+impl EmailOutboxWorker {
+    // Runs until `shutdown` is triggered, then sends what's due one last time, as the last
+    // requests served may have just enqueued emails
+    pub async fn run(self, shutdown: Shutdown) {
+        self.poller.run(&self, shutdown).await
+    }
+
+    // Attempts every email that is currently due and returns how many were attempted
+    #[tracing::instrument(name = "Sending due emails", skip_all)]
+    pub async fn send_due(&self) -> Result<usize> {
+        self.poller.poll(self).await
+    }
+
+    #[tracing::instrument(name = "Sending queued email", skip_all, fields(email_id = %email.id))]
+    async fn send(&self, mut email: OutboxEmail) -> Result<()> {
+        let attempted_at = self.clock.now();
+
+        let outcome = self
+            .email_client
+            .send_email(
+                &email.recipient,
+                &email.subject,
+                &email.html_body,
+                &email.text_body,
+            )
+            .await;
+
+        match outcome {
+            Ok(()) => self
+                .store
+                .mark_sent(&email.id)
+                .await
+                .wrap_err("failed to remove sent email from outbox"),
+            Err(e) => {
+                email.attempts += 1;
+                email.last_error = Some(e.to_string());
+
+                match self
+                    .retry_policy
+                    .next_attempt_at(email.attempts, attempted_at)
+                {
+                    Some(next_attempt_at) => {
+                        email.next_attempt_at = next_attempt_at;
+                        tracing::warn!(attempt = email.attempts, "Failed to send email: {:?}", e);
+                    }
+                    None => {
+                        email.dead_letter();
+                        tracing::error!(
+                            attempts = email.attempts,
+                            "Giving up on email, moved to dead letters: {:?}",
+                            e
+                        );
+                    }
+                }
+
+                self.store
+                    .record_failure(&email)
+                    .await
+                    .wrap_err("failed to record email failure in outbox")
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl JobQueue for EmailOutboxWorker {
+    type Job = OutboxEmail;
+
+    fn job_id(email: &OutboxEmail) -> Uuid {
+        email.id
+    }
+
+    async fn claim_due(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<OutboxEmail>> {
+        self.store
+            .claim_due_emails(now, lease_until, limit)
+            .await
+            .wrap_err("failed to claim due emails")
+    }
+
+    async fn attempt(&self, email: OutboxEmail) -> Result<()> {
+        self.send(email).await
+    }
+}
+
+#[cfg(all(test, feature = "postmark"))]
+mod tests {
+    use reqwest::Client;
+    use secrecy::SecretString;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::{
+        domain::OutboxEmailStatus,
+        services::{
+            data_stores::HashMapEmailOutboxStore, postmark_email_client::PostmarkEmailClient,
+        },
+        utils::{constants::test, poller::claim_only},
+    };
+
+    fn email() -> Email {
+        Email::parse(SecretString::new(
+            "test@example.com".to_owned().into_boxed_str(),
+        ))
+        .unwrap()
+    }
+
+    fn rendered_email() -> RenderedEmail {
+        RenderedEmail {
+            subject: "Subject".to_owned(),
+            html_body: "<p>Content</p>".to_owned(),
+            text_body: "Content".to_owned(),
+        }
+    }
+
+    fn store() -> EmailOutboxStoreType {
+        Arc::new(HashMapEmailOutboxStore::default())
+    }
+
+    fn worker(outbox: &EmailOutbox, mock_server: &MockServer) -> EmailOutboxWorker {
+        worker_polling_every(outbox, mock_server, test::email_outbox::POLL_INTERVAL)
+    }
+
+    fn worker_polling_every(
+        outbox: &EmailOutbox,
+        mock_server: &MockServer,
+        poll_interval: Duration,
+    ) -> EmailOutboxWorker {
+        let http_client = Client::builder()
+            .timeout(test::email_client::TIMEOUT)
+            .build()
+            .unwrap();
+        let email_client = Arc::new(PostmarkEmailClient::new(
+            mock_server.uri(),
+            email(),
+            SecretString::new("auth_token".to_owned().into_boxed_str()),
+            http_client,
+        ));
+        outbox.worker(
+            email_client,
+            RetryPolicy::new(2, Duration::from_secs(60), Duration::from_secs(3600)),
+            poll_interval,
+        )
+    }
+
+    #[tokio::test]
+    async fn sent_email_is_removed_from_outbox() {
+        let mock_server = MockServer::start().await;
+        let store = store();
+        let outbox = EmailOutbox::new(store.clone());
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        outbox.enqueue(&email(), rendered_email()).await.unwrap();
+
+        let worker = worker(&outbox, &mock_server);
+        assert_eq!(worker.send_due().await.unwrap(), 1);
+
+        // Nothing is left to send
+        assert_eq!(worker.send_due().await.unwrap(), 0);
+        let now = Utc::now() + chrono::Duration::days(1);
+        let remaining = store.claim_due_emails(now, now, 10).await.unwrap();
+        assert!(remaining.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_email_is_rescheduled_with_backoff() {
+        let mock_server = MockServer::start().await;
+        let store = store();
+        let outbox = EmailOutbox::new(store.clone());
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        outbox.enqueue(&email(), rendered_email()).await.unwrap();
+
+        let worker = worker(&outbox, &mock_server);
+        assert_eq!(worker.send_due().await.unwrap(), 1);
+
+        // The retry is not due yet
+        assert_eq!(worker.send_due().await.unwrap(), 0);
+
+        let email = claim_only(&worker).await;
+        assert_eq!(email.status, OutboxEmailStatus::Pending);
+        assert_eq!(email.attempts, 1);
+        assert!(email.last_error.is_some());
+    }
+
+    #[tokio::test]
+    async fn email_is_dead_lettered_after_max_attempts() {
+        let mock_server = MockServer::start().await;
+        let store = store();
+        let outbox = EmailOutbox::new(store.clone());
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        outbox.enqueue(&email(), rendered_email()).await.unwrap();
+        let worker = worker(&outbox, &mock_server);
+
+        // First attempt fails and is rescheduled, the second exhausts the policy
+        let queued = claim_only(&worker).await;
+        worker.send(queued.clone()).await.unwrap();
+        let queued = store.get_email(&queued.id).await.unwrap();
+        worker.send(queued.clone()).await.unwrap();
+
+        let queued = store.get_email(&queued.id).await.unwrap();
+        assert_eq!(queued.status, OutboxEmailStatus::DeadLettered);
+        assert_eq!(queued.attempts, 2);
+        // The bodies may hold a 2FA code
+        assert!(queued.html_body.is_empty());
+        assert!(queued.text_body.is_empty());
+
+        // Dead letters are never claimed again
+        let now = Utc::now() + chrono::Duration::days(1);
+        let claimed = store.claim_due_emails(now, now, 10).await.unwrap();
+        assert!(claimed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn enqueue_wakes_the_worker() {
+        let mock_server = MockServer::start().await;
+        let store = store();
+        let outbox = EmailOutbox::new(store.clone());
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // A poll interval this long means only the notification can trigger the send
+        let worker = worker_polling_every(&outbox, &mock_server, Duration::from_secs(3600));
+        let handle = tokio::spawn(worker.run(Shutdown::default()));
+
+        outbox.enqueue(&email(), rendered_email()).await.unwrap();
+
+        let mut sent = false;
+        for _ in 0..100 {
+            if !mock_server.received_requests().await.unwrap().is_empty() {
+                sent = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        handle.abort();
+        assert!(sent);
+    }
+}
@@ -0,0 +1,148 @@
+use askama::Template;
+use color_eyre::eyre::{Context, Result};
+use secrecy::ExposeSecret;
+use serde::Deserialize;
+
+use crate::domain::{Locale, TwoFACode};
+
+// Brand variables shared by every email template
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmailBrand {
+    pub name: String,
+    pub url: String,
+    pub support_email: String,
+    pub primary_color: String,
+}
+
+// A fully rendered message, ready to be queued in the outbox
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+}
+
+// Each message type has an HTML and a plain-text template per locale under `templates/emails`
+macro_rules! two_fa_code_template {
+    ($name:ident, $path:literal) => {
+        #[derive(Template)]
+        #[template(path = $path)]
+        struct $name<'a> {
+            brand: &'a EmailBrand,
+            code: &'a str,
+        }
+    };
+}
+
+two_fa_code_template!(TwoFACodeEnHtml, "emails/two_fa_code.en.html");
+two_fa_code_template!(TwoFACodeEnText, "emails/two_fa_code.en.txt");
+two_fa_code_template!(TwoFACodeEsHtml, "emails/two_fa_code.es.html");
+two_fa_code_template!(TwoFACodeEsText, "emails/two_fa_code.es.txt");
+two_fa_code_template!(TwoFACodeFrHtml, "emails/two_fa_code.fr.html");
+two_fa_code_template!(TwoFACodeFrText, "emails/two_fa_code.fr.txt");
+
+pub struct EmailTemplates {
+    brand: EmailBrand,
+}
+
+impl EmailTemplates {
+    pub fn new(brand: EmailBrand) -> Self {
+        Self { brand }
+    }
+
+    pub fn two_fa_code(&self, locale: Locale, code: &TwoFACode) -> Result<RenderedEmail> {
+        let brand = &self.brand;
+        let code = code.as_ref().expose_secret();
+
+        let (subject, html_body, text_body) = match locale {
+            Locale::En => (
+                format!("Your {} verification code", brand.name),
+                TwoFACodeEnHtml { brand, code }.render(),
+                TwoFACodeEnText { brand, code }.render(),
+            ),
+            Locale::Es => (
+                format!("Tu código de verificación de {}", brand.name),
+                TwoFACodeEsHtml { brand, code }.render(),
+                TwoFACodeEsText { brand, code }.render(),
+            ),
+            Locale::Fr => (
+                format!("Votre code de vérification {}", brand.name),
+                TwoFACodeFrHtml { brand, code }.render(),
+                TwoFACodeFrText { brand, code }.render(),
+            ),
+        };
+
+        Ok(RenderedEmail {
+            subject,
+            html_body: html_body.wrap_err("failed to render 2FA code HTML email")?,
+            text_body: text_body.wrap_err("failed to render 2FA code text email")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::SecretString;
+
+    use super::*;
+
+    fn templates(name: &str) -> EmailTemplates {
+        EmailTemplates::new(EmailBrand {
+            name: name.to_owned(),
+            url: "https://example.com".to_owned(),
+            support_email: "support@example.com".to_owned(),
+            primary_color: "#ff6600".to_owned(),
+        })
+    }
+
+    fn code() -> TwoFACode {
+        TwoFACode::parse(SecretString::new("123456".to_owned().into_boxed_str())).unwrap()
+    }
+
+    #[test]
+    fn two_fa_code_email_contains_code_and_brand_in_every_locale() {
+        let templates = templates("Acme");
+
+        for locale in [Locale::En, Locale::Es, Locale::Fr] {
+            let email = templates.two_fa_code(locale, &code()).unwrap();
+
+            assert!(email.subject.contains("Acme"));
+            for body in [&email.html_body, &email.text_body] {
+                assert!(body.contains("123456"));
+                assert!(body.contains("Acme"));
+                assert!(body.contains("support@example.com"));
+            }
+            assert!(email
+                .html_body
+                .contains(&format!("lang=\"{}\"", locale.as_str())));
+            assert!(email.html_body.contains("#ff6600"));
+            assert!(!email.text_body.contains('<'));
+        }
+    }
+
+    #[test]
+    fn two_fa_code_email_is_localized() {
+        let templates = templates("Acme");
+
+        let en = templates.two_fa_code(Locale::En, &code()).unwrap();
+        let es = templates.two_fa_code(Locale::Es, &code()).unwrap();
+        let fr = templates.two_fa_code(Locale::Fr, &code()).unwrap();
+
+        assert_eq!(en.subject, "Your Acme verification code");
+        assert_eq!(es.subject, "Tu código de verificación de Acme");
+        assert_eq!(fr.subject, "Votre code de vérification Acme");
+        assert!(es.text_body.starts_with("Hola"));
+        assert!(fr.text_body.starts_with("Bonjour"));
+    }
+
+    #[test]
+    fn brand_variables_are_escaped_in_html_only() {
+        let templates = templates("<Acme & Co>");
+
+        let email = templates.two_fa_code(Locale::En, &code()).unwrap();
+
+        assert!(email.html_body.contains("&#60;Acme &#38; Co&#62;"));
+        assert!(!email.html_body.contains("<Acme"));
+        assert!(email.text_body.contains("<Acme & Co>"));
+    }
+}
@@ -0,0 +1,124 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    app_state::ClockType, domain::ExpiringStore, services::system_clock::SystemClock,
+    utils::shutdown::Shutdown,
+};
+
+pub type ExpiringStoreType = Arc<dyn ExpiringStore + Send + Sync>;
+
+// Background task deleting expired entries, which the stores already ignore,
+// so the tables don't grow without bound
+pub struct ExpiryPurgeWorker {
+    stores: Vec<ExpiringStoreType>,
+    interval: Duration,
+    clock: ClockType,
+}
+
+impl ExpiryPurgeWorker {
+    pub fn new(stores: Vec<ExpiringStoreType>, interval: Duration) -> Self {
+        Self {
+            stores,
+            interval,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: ClockType) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // Runs until `shutdown` is triggered
+    pub async fn run(self, shutdown: Shutdown) {
+        let stopped = shutdown.wait();
+        tokio::pin!(stopped);
+        loop {
+            self.purge_expired().await;
+            tokio::select! {
+                _ = &mut stopped => break,
+                _ = tokio::time::sleep(self.interval) => {}
+            }
+        }
+    }
+
+    // Purges every store and returns how many entries were removed. A store that fails is
+    // retried on the next pass and doesn't keep the others from being purged
+    #[tracing::instrument(name = "Purging expired entries", skip_all)]
+    pub async fn purge_expired(&self) -> u64 {
+        let now = self.clock.now();
+        let mut purged = 0;
+        for store in &self.stores {
+            match store.purge_expired(now).await {
+                Ok(count) => purged += count,
+                Err(e) => tracing::error!("Failed to purge expired entries: {:?}", e),
+            }
+        }
+
+        purged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use chrono::{DateTime, Utc};
+    use color_eyre::eyre::{eyre, Result};
+
+    use super::*;
+
+    // Pretends to hold `expired` entries, all removed by the first purge
+    struct FakeExpiringStore {
+        expired: AtomicU64,
+    }
+
+    #[async_trait::async_trait]
+    impl ExpiringStore for FakeExpiringStore {
+        async fn purge_expired(&self, _now: DateTime<Utc>) -> Result<u64> {
+            Ok(self.expired.swap(0, Ordering::SeqCst))
+        }
+    }
+
+    struct FailingExpiringStore;
+
+    #[async_trait::async_trait]
+    impl ExpiringStore for FailingExpiringStore {
+        async fn purge_expired(&self, _now: DateTime<Utc>) -> Result<u64> {
+            Err(eyre!("backend unavailable"))
+        }
+    }
+
+    #[tokio::test]
+    async fn purges_every_store() {
+        let worker = ExpiryPurgeWorker::new(
+            vec![
+                Arc::new(FakeExpiringStore {
+                    expired: AtomicU64::new(2),
+                }),
+                Arc::new(FakeExpiringStore {
+                    expired: AtomicU64::new(3),
+                }),
+            ],
+            Duration::from_secs(60),
+        );
+
+        assert_eq!(worker.purge_expired().await, 5);
+        assert_eq!(worker.purge_expired().await, 0);
+    }
+
+    #[tokio::test]
+    async fn failing_store_does_not_stop_the_others() {
+        let worker = ExpiryPurgeWorker::new(
+            vec![
+                Arc::new(FailingExpiringStore),
+                Arc::new(FakeExpiringStore {
+                    expired: AtomicU64::new(2),
+                }),
+            ],
+            Duration::from_secs(60),
+        );
+
+        assert_eq!(worker.purge_expired().await, 2);
+    }
+}
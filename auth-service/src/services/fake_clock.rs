@@ -0,0 +1,57 @@
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::domain::Clock;
+
+// Clock that only moves when told to, for tests of time-based behaviour
+pub struct FakeClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl FakeClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Mutex::new(now),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+impl Default for FakeClock {
+    fn default() -> Self {
+        Self::new(Utc::now())
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_only_moves_when_told_to() {
+        let start = Utc::now();
+        let clock = FakeClock::new(start);
+
+        assert_eq!(clock.now(), start);
+
+        clock.advance(Duration::minutes(10));
+        assert_eq!(clock.now(), start + Duration::minutes(10));
+
+        clock.set(start);
+        assert_eq!(clock.now(), start);
+    }
+}
@@ -0,0 +1,44 @@
+use std::sync::Mutex;
+
+use uuid::Uuid;
+
+use crate::domain::{IdGenerator, LoginAttemptId, TwoFACode};
+
+// Hands out random login attempt ids, 2FA codes and uuids until told which ones to hand out,
+// for tests
+#[derive(Default)]
+pub struct FakeIdGenerator {
+    fixed: Mutex<Option<(LoginAttemptId, TwoFACode)>>,
+    fixed_uuid: Mutex<Option<Uuid>>,
+}
+
+impl FakeIdGenerator {
+    pub fn set(&self, login_attempt_id: LoginAttemptId, two_fa_code: TwoFACode) {
+        *self.fixed.lock().unwrap() = Some((login_attempt_id, two_fa_code));
+    }
+
+    // Every uuid handed out from now on is `uuid`, e.g. the jti of the next token issued
+    pub fn set_uuid(&self, uuid: Uuid) {
+        *self.fixed_uuid.lock().unwrap() = Some(uuid);
+    }
+}
+
+impl IdGenerator for FakeIdGenerator {
+    fn login_attempt_id(&self) -> LoginAttemptId {
+        match &*self.fixed.lock().unwrap() {
+            Some((login_attempt_id, _)) => login_attempt_id.clone(),
+            None => LoginAttemptId::default(),
+        }
+    }
+
+    fn two_fa_code(&self) -> TwoFACode {
+        match &*self.fixed.lock().unwrap() {
+            Some((_, two_fa_code)) => two_fa_code.clone(),
+            None => TwoFACode::default(),
+        }
+    }
+
+    fn uuid(&self) -> Uuid {
+        self.fixed_uuid.lock().unwrap().unwrap_or_else(Uuid::new_v4)
+    }
+}
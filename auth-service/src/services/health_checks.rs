@@ -0,0 +1,287 @@
+use std::{collections::BTreeMap, time::Duration};
+
+#[cfg(any(feature = "postgres", feature = "sqlite", feature = "redis"))]
+use color_eyre::eyre::Result;
+#[cfg(feature = "redis")]
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "postgres")]
+use sqlx::PgPool;
+#[cfg(feature = "sqlite")]
+use sqlx::SqlitePool;
+
+use crate::app_state::HealthCheckType;
+#[cfg(any(feature = "postgres", feature = "sqlite", feature = "redis"))]
+use crate::domain::HealthCheck;
+#[cfg(feature = "redis")]
+use crate::services::data_stores::RedisConnection;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Probes every dependency the service needs to handle requests, each within `timeout`
+pub struct HealthChecks {
+    checks: Vec<HealthCheckType>,
+    timeout: Duration,
+}
+
+impl Default for HealthChecks {
+    fn default() -> Self {
+        Self::new(Vec::new(), DEFAULT_TIMEOUT)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadinessStatus {
+    Ready,
+    NotReady,
+    // Reported without probing the dependencies
+    ShuttingDown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DependencyStatus {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DependencyReport {
+    pub status: DependencyStatus,
+    // Why the dependency is down. The full error is only logged, it may name hosts or users.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadinessReport {
+    pub status: ReadinessStatus,
+    pub checks: BTreeMap<String, DependencyReport>,
+}
+
+impl HealthChecks {
+    pub fn new(checks: Vec<HealthCheckType>, timeout: Duration) -> Self {
+        Self { checks, timeout }
+    }
+
+    // Runs the checks concurrently, so the report takes at most `timeout`
+    pub async fn run(&self) -> ReadinessReport {
+        let timeout = self.timeout;
+        let handles: Vec<_> = self
+            .checks
+            .iter()
+            .map(|check| {
+                let check = check.clone();
+                tokio::spawn(async move {
+                    let outcome = tokio::time::timeout(timeout, check.check()).await;
+                    (check.name(), outcome)
+                })
+            })
+            .collect();
+
+        let mut checks = BTreeMap::new();
+        for handle in handles {
+            let (name, report) = match handle.await {
+                Ok((name, Ok(Ok(())))) => (name, DependencyReport::up()),
+                Ok((name, Ok(Err(e)))) => {
+                    tracing::warn!(dependency = name, error = ?e, "Health check failed");
+                    (name, DependencyReport::down("unavailable"))
+                }
+                Ok((name, Err(_))) => {
+                    tracing::warn!(dependency = name, ?timeout, "Health check timed out");
+                    (name, DependencyReport::down("timed out"))
+                }
+                Err(e) => {
+                    tracing::error!(error = ?e, "Health check panicked");
+                    continue;
+                }
+            };
+            checks.insert(name.to_owned(), report);
+        }
+
+        // A panicked check has no entry, but still makes the service not ready
+        let status = match checks.len() == self.checks.len()
+            && checks
+                .values()
+                .all(|check| check.status == DependencyStatus::Up)
+        {
+            true => ReadinessStatus::Ready,
+            false => ReadinessStatus::NotReady,
+        };
+        ReadinessReport { status, checks }
+    }
+}
+
+impl ReadinessReport {
+    pub fn shutting_down() -> Self {
+        Self {
+            status: ReadinessStatus::ShuttingDown,
+            checks: BTreeMap::new(),
+        }
+    }
+}
+
+impl DependencyReport {
+    fn up() -> Self {
+        Self {
+            status: DependencyStatus::Up,
+            error: None,
+        }
+    }
+
+    fn down(error: &str) -> Self {
+        Self {
+            status: DependencyStatus::Down,
+            error: Some(error.to_owned()),
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+pub struct PostgresHealthCheck {
+    pool: PgPool,
+}
+
+#[cfg(feature = "postgres")]
+impl PostgresHealthCheck {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[async_trait::async_trait]
+impl HealthCheck for PostgresHealthCheck {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn check(&self) -> Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub struct SqliteHealthCheck {
+    pool: SqlitePool,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteHealthCheck {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait::async_trait]
+impl HealthCheck for SqliteHealthCheck {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn check(&self) -> Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "redis")]
+pub struct RedisHealthCheck {
+    conn: RedisConnection,
+}
+
+#[cfg(feature = "redis")]
+impl RedisHealthCheck {
+    pub fn new(conn: RedisConnection) -> Self {
+        Self { conn }
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait::async_trait]
+impl HealthCheck for RedisHealthCheck {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    async fn check(&self) -> Result<()> {
+        let mut conn = self.conn.clone();
+        conn.ping::<String>().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use color_eyre::eyre::{eyre, Result};
+
+    use super::*;
+    use crate::domain::HealthCheck;
+
+    enum FakeCheck {
+        Up,
+        Down,
+        Hangs,
+    }
+
+    #[async_trait::async_trait]
+    impl HealthCheck for FakeCheck {
+        fn name(&self) -> &'static str {
+            match self {
+                Self::Up => "up",
+                Self::Down => "down",
+                Self::Hangs => "hangs",
+            }
+        }
+
+        async fn check(&self) -> Result<()> {
+            match self {
+                Self::Up => Ok(()),
+                Self::Down => Err(eyre!("connection refused to db.internal:5432")),
+                Self::Hangs => std::future::pending().await,
+            }
+        }
+    }
+
+    fn checks(checks: Vec<FakeCheck>) -> HealthChecks {
+        let checks = checks
+            .into_iter()
+            .map(|check| Arc::new(check) as HealthCheckType)
+            .collect();
+        HealthChecks::new(checks, Duration::from_millis(100))
+    }
+
+    #[tokio::test]
+    async fn test_ready_without_dependencies() {
+        let report = HealthChecks::default().run().await;
+
+        assert_eq!(report.status, ReadinessStatus::Ready);
+        assert!(report.checks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ready_when_every_dependency_is_up() {
+        let report = checks(vec![FakeCheck::Up]).run().await;
+
+        assert_eq!(report.status, ReadinessStatus::Ready);
+        assert_eq!(report.checks["up"], DependencyReport::up());
+    }
+
+    #[tokio::test]
+    async fn test_not_ready_when_a_dependency_fails_or_times_out() {
+        let report = checks(vec![FakeCheck::Up, FakeCheck::Down, FakeCheck::Hangs])
+            .run()
+            .await;
+
+        assert_eq!(report.status, ReadinessStatus::NotReady);
+        assert_eq!(report.checks["up"].status, DependencyStatus::Up);
+        // The error itself is only logged
+        assert_eq!(report.checks["down"], DependencyReport::down("unavailable"));
+        assert_eq!(report.checks["hangs"], DependencyReport::down("timed out"));
+    }
+}
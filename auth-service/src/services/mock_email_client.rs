@@ -1,19 +1,20 @@
 use crate::domain::{Email, EmailClient};
 use color_eyre::eyre::Result;
-use secrecy::ExposeSecret;
 
 pub struct MockEmailClient;
 
 #[async_trait::async_trait]
 impl EmailClient for MockEmailClient {
-    async fn send_email(&self, recipient: &Email, subject: &str, content: &str) -> Result<()> {
-        // Our mock email client will simply log the recipient, subject, and content to standard output
-        tracing::debug!(
-            "Sending email to {} with subject: {} and content: {}",
-            recipient.as_ref().expose_secret(),
-            subject,
-            content
-        );
+    async fn send_email(
+        &self,
+        _recipient: &Email,
+        subject: &str,
+        _html_body: &str,
+        _text_body: &str,
+    ) -> Result<()> {
+        // Our mock email client will simply log that an email was sent. The recipient and
+        // content would put addresses and 2FA codes in the logs, use the `capture` client to read them
+        tracing::debug!("Sending email with subject: {}", subject);
 
         Ok(())
     }
@@ -1,3 +1,16 @@
+pub mod capturing_email_client;
 pub mod data_stores;
+pub mod email_outbox;
+pub mod email_templates;
+pub mod expiry_purge;
+pub mod fake_clock;
+pub mod fake_id_generator;
+pub mod health_checks;
 pub mod mock_email_client;
+#[cfg(feature = "postmark")]
 pub mod postmark_email_client;
+pub mod random_id_generator;
+#[cfg(feature = "smtp")]
+pub mod smtp_email_client;
+pub mod system_clock;
+pub mod webhooks;
@@ -2,7 +2,8 @@ use color_eyre::eyre::Result; // For improved error handling and reporting
 use reqwest::{Client, Url}; // For making HTTP requests
 use secrecy::{ExposeSecret, SecretString}; // For securely handling sensitive data
 
-use crate::domain::{Email, EmailClient}; // Import domain-specific modules
+use crate::domain::{Email, EmailClient, HealthCheck}; // Import domain-specific modules
+use crate::utils::tracing::trace_context_headers; // For propagating the current trace
 
 // Define the PostmarkEmailClient struct
 pub struct PostmarkEmailClient {
@@ -32,19 +33,24 @@ impl PostmarkEmailClient {
 #[async_trait::async_trait]
 impl EmailClient for PostmarkEmailClient {
     #[tracing::instrument(name = "Sending email", skip_all)] // Trace this function, skipping logging its parameters
-    async fn send_email(&self, recipient: &Email, subject: &str, content: &str) -> Result<()> {
+    async fn send_email(
+        &self,
+        recipient: &Email,
+        subject: &str,
+        html_body: &str,
+        text_body: &str,
+    ) -> Result<()> {
         // Parse the base URL and join it with the email endpoint
         let base = Url::parse(&self.base_url)?;
         let url = base.join("/email")?;
-        println!("{}", recipient.as_ref().expose_secret());
 
         // Create the request body for sending the email
         let request_body = SendEmailRequest {
             from: self.sender.as_ref().expose_secret(),
             to: recipient.as_ref().expose_secret(),
             subject,
-            html_body: content,
-            text_body: content,
+            html_body,
+            text_body,
             message_stream: MESSAGE_STREAM,
         };
 
@@ -56,6 +62,7 @@ impl EmailClient for PostmarkEmailClient {
                 POSTMARK_AUTH_HEADER,
                 self.authorization_token.expose_secret(), // Securely expose the authorization token
             )
+            .headers(trace_context_headers()) // Continue the current trace downstream
             .json(&request_body);
 
         // Send the request and handle the response
@@ -65,6 +72,31 @@ impl EmailClient for PostmarkEmailClient {
     }
 }
 
+// Fetching the server's details checks both that Postmark is reachable and that the token is valid
+#[async_trait::async_trait]
+impl HealthCheck for PostmarkEmailClient {
+    fn name(&self) -> &'static str {
+        "postmark"
+    }
+
+    async fn check(&self) -> Result<()> {
+        let url = Url::parse(&self.base_url)?.join("/server")?;
+
+        self.http_client
+            .get(url)
+            .header(
+                POSTMARK_AUTH_HEADER,
+                self.authorization_token.expose_secret(),
+            )
+            .headers(trace_context_headers())
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
 // Constants for message stream and authorization header
 const MESSAGE_STREAM: &str = "outbound";
 const POSTMARK_AUTH_HEADER: &str = "X-Postmark-Server-Token";
@@ -90,7 +122,7 @@ mod tests {
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph, Sentence};
     use fake::{Fake, Faker};
-    use wiremock::matchers::{any, header, header_exists, method, path};
+    use wiremock::matchers::{any, body_partial_json, header, header_exists, method, path};
     use wiremock::{Mock, MockServer, Request, ResponseTemplate};
 
     use super::PostmarkEmailClient;
@@ -165,7 +197,30 @@ mod tests {
 
         // Execute the send_email function and check the outcome
         let outcome = email_client
-            .send_email(&email(), &subject(), &content())
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert!(outcome.is_ok());
+    }
+
+    // Test to ensure the HTML and text bodies are sent separately
+    #[tokio::test]
+    async fn send_email_sends_html_and_text_bodies_separately() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email"))
+            .and(body_partial_json(serde_json::json!({
+                "HtmlBody": "<p>Hello</p>",
+                "TextBody": "Hello",
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), "<p>Hello</p>", "Hello")
             .await;
 
         assert!(outcome.is_ok());
@@ -186,7 +241,7 @@ mod tests {
 
         // Execute the send_email function and check the outcome
         let outcome = email_client
-            .send_email(&email(), &subject(), &content())
+            .send_email(&email(), &subject(), &content(), &content())
             .await;
 
         assert!(outcome.is_err());
@@ -208,9 +263,39 @@ mod tests {
 
         // Execute the send_email function and check the outcome
         let outcome = email_client
-            .send_email(&email(), &subject(), &content())
+            .send_email(&email(), &subject(), &content(), &content())
             .await;
 
         assert!(outcome.is_err());
     }
+
+    #[tokio::test]
+    async fn health_check_fetches_the_server_with_the_token() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists(POSTMARK_AUTH_HEADER))
+            .and(path("/server"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert!(email_client.check().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn health_check_fails_if_the_token_is_rejected() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/server"))
+            .respond_with(ResponseTemplate::new(401))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert!(email_client.check().await.is_err());
+    }
 }
@@ -0,0 +1,19 @@
+use uuid::Uuid;
+
+use crate::domain::{IdGenerator, LoginAttemptId, TwoFACode};
+
+pub struct RandomIdGenerator;
+
+impl IdGenerator for RandomIdGenerator {
+    fn login_attempt_id(&self) -> LoginAttemptId {
+        LoginAttemptId::default()
+    }
+
+    fn two_fa_code(&self) -> TwoFACode {
+        TwoFACode::default()
+    }
+
+    fn uuid(&self) -> Uuid {
+        Uuid::new_v4()
+    }
+}
@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result};
+use lettre::{
+    message::MultiPart,
+    transport::smtp::{
+        authentication::Credentials,
+        client::{Certificate, Tls, TlsParameters},
+    },
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use secrecy::ExposeSecret;
+
+use crate::{
+    domain::{Email, EmailClient, HealthCheck},
+    settings::{SmtpCredentials, SmtpTlsMode},
+};
+
+pub struct SmtpEmailClient {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: Email,
+}
+
+impl SmtpEmailClient {
+    // `ca_certificate` is a PEM certificate trusted on top of the public CAs, for relays whose
+    // certificate isn't publicly trusted
+    pub fn new(
+        host: &str,
+        port: u16,
+        tls_mode: SmtpTlsMode,
+        ca_certificate: Option<&[u8]>,
+        credentials: Option<SmtpCredentials>,
+        sender: Email,
+        timeout: Duration,
+    ) -> Result<Self> {
+        let tls_parameters = || -> Result<TlsParameters> {
+            let mut parameters = TlsParameters::builder(host.to_owned());
+            if let Some(pem) = ca_certificate {
+                parameters = parameters.add_root_certificate(Certificate::from_pem(pem)?);
+            }
+            Ok(parameters.build()?)
+        };
+        let tls = match tls_mode {
+            SmtpTlsMode::None => Tls::None,
+            SmtpTlsMode::StartTls => Tls::Required(tls_parameters()?),
+            SmtpTlsMode::Tls => Tls::Wrapper(tls_parameters()?),
+        };
+
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+            .port(port)
+            .tls(tls)
+            .timeout(Some(timeout));
+        if let Some(credentials) = credentials {
+            builder = builder.credentials(Credentials::new(
+                credentials.username,
+                credentials.password.expose_secret().to_owned(),
+            ));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            sender,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailClient for SmtpEmailClient {
+    #[tracing::instrument(name = "Sending email over SMTP", skip_all)]
+    async fn send_email(
+        &self,
+        recipient: &Email,
+        subject: &str,
+        html_body: &str,
+        text_body: &str,
+    ) -> Result<()> {
+        let message = Message::builder()
+            .from(self.sender.as_ref().expose_secret().parse()?)
+            .to(recipient.as_ref().expose_secret().parse()?)
+            .subject(subject)
+            .multipart(MultiPart::alternative_plain_html(
+                text_body.to_owned(),
+                html_body.to_owned(),
+            ))?;
+
+        self.transport.send(message).await?;
+
+        Ok(())
+    }
+}
+
+// Connects and greets the server without sending anything
+#[async_trait::async_trait]
+impl HealthCheck for SmtpEmailClient {
+    fn name(&self) -> &'static str {
+        "smtp"
+    }
+
+    async fn check(&self) -> Result<()> {
+        match self.transport.test_connection().await? {
+            true => Ok(()),
+            false => Err(eyre!("SMTP server did not accept the connection")),
+        }
+    }
+}
@@ -0,0 +1,571 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Context, Result};
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{
+    app_state::{ClockType, IdGeneratorType, WebhookStoreType},
+    domain::{
+        WebhookDelivery, WebhookDeliveryAttempt, WebhookDeliveryStatus, WebhookEvent,
+        WebhookSubscription,
+    },
+    services::{random_id_generator::RandomIdGenerator, system_clock::SystemClock},
+    utils::{
+        poller::{JobQueue, Poller},
+        retry::RetryPolicy,
+        shutdown::Shutdown,
+        tracing::trace_context_headers,
+    },
+};
+
+pub const WEBHOOK_ID_HEADER: &str = "X-Webhook-Id";
+pub const WEBHOOK_EVENT_HEADER: &str = "X-Webhook-Event";
+pub const WEBHOOK_TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+// Number of deliveries the worker claims per poll
+const CLAIM_BATCH_SIZE: usize = 20;
+// How long a claimed delivery stays hidden from other workers before it is retried
+const DELIVERY_LEASE: Duration = Duration::from_secs(300);
+
+// Turns events into a delivery for every subscription interested in them, which are queued along
+// with the user that caused them, or right after it. Actual delivery happens asynchronously in
+// `WebhookWorker`.
+pub struct WebhookPublisher {
+    subscriptions: Vec<WebhookSubscription>,
+    store: WebhookStoreType,
+    clock: ClockType,
+    id_generator: IdGeneratorType,
+}
+
+impl WebhookPublisher {
+    pub fn new(subscriptions: Vec<WebhookSubscription>, store: WebhookStoreType) -> Self {
+        Self {
+            subscriptions,
+            store,
+            clock: Arc::new(SystemClock),
+            id_generator: Arc::new(RandomIdGenerator),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: ClockType) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_id_generator(mut self, id_generator: IdGeneratorType) -> Self {
+        self.id_generator = id_generator;
+        self
+    }
+
+    pub fn deliveries(&self, events: &[WebhookEvent]) -> Result<Vec<WebhookDelivery>> {
+        let mut deliveries = Vec::new();
+        for event in events {
+            let payload =
+                serde_json::to_string(event).wrap_err("failed to serialize webhook event")?;
+            deliveries.extend(
+                self.subscriptions
+                    .iter()
+                    .filter(|s| s.is_subscribed_to(event.event_type))
+                    .map(|s| {
+                        WebhookDelivery::new(
+                            s.id.clone(),
+                            event.event_type,
+                            payload.clone(),
+                            &*self.clock,
+                            &*self.id_generator,
+                        )
+                    }),
+            );
+        }
+
+        Ok(deliveries)
+    }
+
+    // Queues deliveries the user store handed back. The change that triggered them is already
+    // made by then, so a delivery that can't be queued is logged rather than failing the request
+    #[tracing::instrument(name = "Enqueueing webhook deliveries", skip_all)]
+    pub async fn enqueue(&self, deliveries: Vec<WebhookDelivery>) {
+        for delivery in deliveries {
+            if let Err(e) = self.store.enqueue_delivery(delivery).await {
+                tracing::error!("Failed to enqueue webhook delivery: {:?}", e);
+            }
+        }
+    }
+}
+
+// Background task draining the persistent delivery queue
+pub struct WebhookWorker {
+    subscriptions: Vec<WebhookSubscription>,
+    store: WebhookStoreType,
+    http_client: Client,
+    retry_policy: RetryPolicy,
+    poller: Poller,
+    clock: ClockType,
+}
+
+impl WebhookWorker {
+    pub fn new(
+        subscriptions: Vec<WebhookSubscription>,
+        store: WebhookStoreType,
+        http_client: Client,
+        retry_policy: RetryPolicy,
+        poll_interval: Duration,
+    ) -> Self {
+        Self {
+            subscriptions,
+            store,
+            http_client,
+            retry_policy,
+            poller: Poller::new(poll_interval, DELIVERY_LEASE, CLAIM_BATCH_SIZE),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    // Schedules retries and timestamps the signatures and the delivery log
+    pub fn with_clock(mut self, clock: ClockType) -> Self {
+        self.poller = self.poller.with_clock(clock.clone());
+        self.clock = clock;
+        self
+    }
+
+    // Runs until `shutdown` is triggered. Deliveries still due are left to the next start,
+    // or to another instance.
+    pub async fn run(self, shutdown: Shutdown) {
+        self.poller.run(&self, shutdown).await
+    }
+
+    // Attempts every delivery that is currently due and returns how many were attempted
+    #[tracing::instrument(name = "Delivering due webhooks", skip_all)]
+    pub async fn deliver_due(&self) -> Result<usize> {
+        self.poller.poll(self).await
+    }
+
+    #[tracing::instrument(name = "Delivering webhook", skip_all, fields(delivery_id = %delivery.id))]
+    async fn deliver(&self, mut delivery: WebhookDelivery) -> Result<()> {
+        let attempted_at = self.clock.now();
+        delivery.attempts += 1;
+
+        let outcome = match self
+            .subscriptions
+            .iter()
+            .find(|s| s.id == delivery.subscription_id)
+        {
+            Some(subscription) => self.send(subscription, &delivery).await,
+            None => Err((
+                None,
+                format!(
+                    "subscription {} is not configured",
+                    delivery.subscription_id
+                ),
+            )),
+        };
+
+        let (response_status, error) = match outcome {
+            Ok(status) => {
+                delivery.status = WebhookDeliveryStatus::Delivered;
+                (Some(status), None)
+            }
+            Err((status, error)) => {
+                tracing::warn!(
+                    attempt = delivery.attempts,
+                    "Webhook delivery failed: {}",
+                    error
+                );
+                match self
+                    .retry_policy
+                    .next_attempt_at(delivery.attempts, attempted_at)
+                {
+                    Some(next_attempt_at) => delivery.next_attempt_at = next_attempt_at,
+                    None => delivery.status = WebhookDeliveryStatus::Failed,
+                }
+                (status, Some(error))
+            }
+        };
+
+        let attempt = WebhookDeliveryAttempt {
+            delivery_id: delivery.id,
+            attempt: delivery.attempts,
+            attempted_at,
+            response_status,
+            error,
+        };
+
+        self.store
+            .record_attempt(&delivery, attempt)
+            .await
+            .wrap_err("failed to record webhook delivery attempt")
+    }
+
+    // Returns the response status on success, or the status (if any) and a reason on failure
+    async fn send(
+        &self,
+        subscription: &WebhookSubscription,
+        delivery: &WebhookDelivery,
+    ) -> std::result::Result<u16, (Option<u16>, String)> {
+        let timestamp = self.clock.now().timestamp();
+        let signature = sign_payload(&subscription.secret, timestamp, &delivery.payload)
+            .map_err(|e| (None, e.to_string()))?;
+
+        let response = self
+            .http_client
+            .post(&subscription.url)
+            .header(WEBHOOK_ID_HEADER, delivery.id.to_string())
+            .header(WEBHOOK_EVENT_HEADER, delivery.event_type.as_str())
+            .header(WEBHOOK_TIMESTAMP_HEADER, timestamp.to_string())
+            .header(WEBHOOK_SIGNATURE_HEADER, signature)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            // Lets receivers link the delivery to this service's trace
+            .headers(trace_context_headers())
+            .body(delivery.payload.clone())
+            .send()
+            .await
+            .map_err(|e| (None, e.to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(status.as_u16())
+        } else {
+            Err((
+                Some(status.as_u16()),
+                format!("receiver responded with {}", status),
+            ))
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl JobQueue for WebhookWorker {
+    type Job = WebhookDelivery;
+
+    fn job_id(delivery: &WebhookDelivery) -> Uuid {
+        delivery.id
+    }
+
+    async fn claim_due(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<WebhookDelivery>> {
+        self.store
+            .claim_due_deliveries(now, lease_until, limit)
+            .await
+            .wrap_err("failed to claim due webhook deliveries")
+    }
+
+    async fn attempt(&self, delivery: WebhookDelivery) -> Result<()> {
+        self.deliver(delivery).await
+    }
+}
+
+// Computes the `X-Webhook-Signature` header value: an HMAC-SHA256 over "{timestamp}.{payload}".
+// Receivers recompute it with their shared secret to authenticate the request.
+pub fn sign_payload(secret: &SecretString, timestamp: i64, payload: &str) -> Result<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .map_err(|e| eyre!("invalid webhook secret: {}", e))?;
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(payload.as_bytes());
+    Ok(format!(
+        "sha256={}",
+        hex::encode(mac.finalize().into_bytes())
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use wiremock::matchers::{header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    use super::*;
+    use crate::{
+        domain::{Clock, Email, WebhookEventType},
+        services::{data_stores::HashMapWebhookStore, fake_clock::FakeClock},
+        utils::{constants::test, poller::claim_only},
+    };
+
+    fn secret() -> SecretString {
+        SecretString::new("webhook-secret".to_owned().into_boxed_str())
+    }
+
+    fn email() -> Email {
+        Email::parse(SecretString::new(
+            "test@example.com".to_owned().into_boxed_str(),
+        ))
+        .unwrap()
+    }
+
+    fn event(event_type: WebhookEventType) -> WebhookEvent {
+        WebhookEvent::new(event_type, &email(), &SystemClock, &RandomIdGenerator)
+    }
+
+    fn subscription(url: String, events: Vec<WebhookEventType>) -> WebhookSubscription {
+        WebhookSubscription {
+            id: "test-subscription".to_owned(),
+            url,
+            secret: secret(),
+            events,
+        }
+    }
+
+    fn store() -> WebhookStoreType {
+        Arc::new(HashMapWebhookStore::default())
+    }
+
+    fn worker(subscriptions: Vec<WebhookSubscription>, store: WebhookStoreType) -> WebhookWorker {
+        let http_client = Client::builder()
+            .timeout(test::webhooks::TIMEOUT)
+            .build()
+            .unwrap();
+        WebhookWorker::new(
+            subscriptions,
+            store,
+            http_client,
+            RetryPolicy::new(2, Duration::from_secs(60), Duration::from_secs(3600)),
+            test::webhooks::POLL_INTERVAL,
+        )
+    }
+
+    // Only matches requests whose signature header is valid for the body
+    struct ValidSignatureMatcher;
+
+    impl wiremock::Match for ValidSignatureMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let timestamp = request
+                .headers
+                .get(WEBHOOK_TIMESTAMP_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<i64>().ok());
+            let signature = request
+                .headers
+                .get(WEBHOOK_SIGNATURE_HEADER)
+                .and_then(|v| v.to_str().ok());
+            let body = std::str::from_utf8(&request.body).unwrap_or_default();
+
+            match (timestamp, signature) {
+                (Some(timestamp), Some(signature)) => {
+                    sign_payload(&secret(), timestamp, body).unwrap() == signature
+                }
+                _ => false,
+            }
+        }
+    }
+
+    async fn enqueue_signup(store: &WebhookStoreType, subscriptions: &[WebhookSubscription]) {
+        let publisher = WebhookPublisher::new(subscriptions.to_vec(), store.clone());
+        let deliveries = publisher
+            .deliveries(&[event(WebhookEventType::UserSignedUp)])
+            .unwrap();
+        publisher.enqueue(deliveries).await;
+    }
+
+    #[test]
+    fn signature_depends_on_secret_timestamp_and_payload() {
+        let signature = sign_payload(&secret(), 1, "{}").unwrap();
+        assert!(signature.starts_with("sha256="));
+        assert_eq!(signature, sign_payload(&secret(), 1, "{}").unwrap());
+
+        let other_secret = SecretString::new("other".to_owned().into_boxed_str());
+        assert_ne!(signature, sign_payload(&other_secret, 1, "{}").unwrap());
+        assert_ne!(signature, sign_payload(&secret(), 2, "{}").unwrap());
+        assert_ne!(signature, sign_payload(&secret(), 1, "[]").unwrap());
+    }
+
+    #[test]
+    fn publisher_only_delivers_to_subscribers_of_the_event() {
+        let subscriptions = vec![
+            WebhookSubscription {
+                id: "two-factor".to_owned(),
+                ..subscription(
+                    "http://localhost".to_owned(),
+                    vec![WebhookEventType::TwoFactorEnabled],
+                )
+            },
+            subscription(
+                "http://localhost".to_owned(),
+                vec![WebhookEventType::UserSignedUp],
+            ),
+        ];
+        let publisher = WebhookPublisher::new(subscriptions, store());
+
+        let deliveries = publisher
+            .deliveries(&[
+                event(WebhookEventType::UserSignedUp),
+                event(WebhookEventType::TwoFactorEnabled),
+            ])
+            .unwrap();
+
+        let delivered: Vec<_> = deliveries
+            .iter()
+            .map(|d| (d.subscription_id.as_str(), d.event_type))
+            .collect();
+        assert_eq!(
+            delivered,
+            vec![
+                ("test-subscription", WebhookEventType::UserSignedUp),
+                ("two-factor", WebhookEventType::TwoFactorEnabled),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn worker_delivers_signed_payload() {
+        let mock_server = MockServer::start().await;
+        let store = store();
+        let subscriptions = vec![subscription(
+            format!("{}/hooks", mock_server.uri()),
+            vec![WebhookEventType::UserSignedUp],
+        )];
+
+        Mock::given(path("/hooks"))
+            .and(method("POST"))
+            .and(header("Content-Type", "application/json"))
+            .and(header(WEBHOOK_EVENT_HEADER, "user.signed_up"))
+            .and(header_exists(WEBHOOK_ID_HEADER))
+            .and(ValidSignatureMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        enqueue_signup(&store, &subscriptions).await;
+
+        let worker = worker(subscriptions, store.clone());
+        assert_eq!(worker.deliver_due().await.unwrap(), 1);
+
+        let received = mock_server.received_requests().await.unwrap();
+        let event: WebhookEvent = serde_json::from_slice(&received[0].body).unwrap();
+        assert_eq!(event.event_type, WebhookEventType::UserSignedUp);
+        assert_eq!(event.data.email, "test@example.com");
+
+        // Nothing is left to deliver
+        assert_eq!(worker.deliver_due().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn worker_signs_with_the_time_of_its_clock() {
+        let mock_server = MockServer::start().await;
+        let store = store();
+        let subscriptions = vec![subscription(
+            mock_server.uri(),
+            vec![WebhookEventType::UserSignedUp],
+        )];
+        let clock = Arc::new(FakeClock::new(Utc::now() + chrono::Duration::hours(1)));
+
+        Mock::given(method("POST"))
+            .and(header(
+                WEBHOOK_TIMESTAMP_HEADER,
+                clock.now().timestamp().to_string().as_str(),
+            ))
+            .and(ValidSignatureMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        enqueue_signup(&store, &subscriptions).await;
+
+        let worker = worker(subscriptions, store.clone()).with_clock(clock);
+        assert_eq!(worker.deliver_due().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_delivery_is_rescheduled_with_backoff() {
+        let mock_server = MockServer::start().await;
+        let store = store();
+        let subscriptions = vec![subscription(
+            mock_server.uri(),
+            vec![WebhookEventType::UserSignedUp],
+        )];
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        enqueue_signup(&store, &subscriptions).await;
+
+        let worker = worker(subscriptions, store.clone());
+        let before = Utc::now();
+        assert_eq!(worker.deliver_due().await.unwrap(), 1);
+
+        // The retry is not due yet
+        assert_eq!(worker.deliver_due().await.unwrap(), 0);
+
+        let delivery = claim_only(&worker).await;
+        assert_eq!(delivery.status, WebhookDeliveryStatus::Pending);
+        assert_eq!(delivery.attempts, 1);
+
+        let log = store.get_delivery_log(&delivery.id).await.unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].response_status, Some(500));
+        assert!(log[0].error.is_some());
+        assert!(log[0].attempted_at >= before);
+    }
+
+    #[tokio::test]
+    async fn delivery_fails_permanently_after_max_attempts() {
+        let mock_server = MockServer::start().await;
+        let store = store();
+        let subscriptions = vec![subscription(
+            mock_server.uri(),
+            vec![WebhookEventType::UserSignedUp],
+        )];
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        enqueue_signup(&store, &subscriptions).await;
+        let worker = worker(subscriptions, store.clone());
+
+        // First attempt fails and is rescheduled, the second exhausts the policy
+        let delivery = claim_only(&worker).await;
+        worker.deliver(delivery.clone()).await.unwrap();
+        let delivery = store.get_delivery(&delivery.id).await.unwrap();
+        worker.deliver(delivery.clone()).await.unwrap();
+
+        let delivery = store.get_delivery(&delivery.id).await.unwrap();
+        assert_eq!(delivery.status, WebhookDeliveryStatus::Failed);
+        assert_eq!(delivery.attempts, 2);
+
+        let log = store.get_delivery_log(&delivery.id).await.unwrap();
+        assert_eq!(log.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn worker_times_out_if_the_receiver_takes_too_long() {
+        let mock_server = MockServer::start().await;
+        let store = store();
+        let subscriptions = vec![subscription(
+            mock_server.uri(),
+            vec![WebhookEventType::UserSignedUp],
+        )];
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(180)))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        enqueue_signup(&store, &subscriptions).await;
+
+        let worker = worker(subscriptions, store.clone());
+        assert_eq!(worker.deliver_due().await.unwrap(), 1);
+
+        let delivery = claim_only(&worker).await;
+        assert_eq!(delivery.status, WebhookDeliveryStatus::Pending);
+        assert_eq!(delivery.attempts, 1);
+    }
+}
@@ -0,0 +1,1082 @@
+use std::{collections::HashMap, fmt, net::SocketAddr, path::Path, time::Duration};
+
+use color_eyre::eyre::Result;
+use config::{Config, ConfigError, Environment, File};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use thiserror::Error;
+
+use crate::{
+    domain::{Email, WebhookSubscription},
+    services::email_templates::EmailBrand,
+    utils::{cors::OriginPattern, retry::RetryPolicy},
+};
+
+// Directory holding `base.toml` and the per-environment files
+pub const CONFIG_DIR_ENV_VAR: &str = "APP_CONFIG_DIR";
+pub const ENVIRONMENT_ENV_VAR: &str = "APP_ENVIRONMENT";
+pub const DEFAULT_CONFIG_DIR: &str = "config";
+pub const DEFAULT_ENVIRONMENT: &str = "local";
+// Where `email.client = "capture"` may be used, as it serves emails to anyone on the machine
+const CAPTURE_ENVIRONMENTS: [&str; 2] = ["local", "test"];
+// Environment variables starting with `APP__` override file values,
+// e.g. `APP__AUTH__JWT_SECRET` sets `auth.jwt_secret`
+const ENV_OVERRIDE_PREFIX: &str = "APP";
+const ENV_OVERRIDE_SEPARATOR: &str = "__";
+
+#[derive(Debug, Error)]
+pub enum SettingsError {
+    #[error("failed to load configuration: {0}")]
+    Load(#[from] ConfigError),
+    #[error("invalid configuration:\n{}", format_problems(.0))]
+    Invalid(Vec<String>),
+}
+
+fn format_problems(problems: &[String]) -> String {
+    problems
+        .iter()
+        .map(|problem| format!("  - {}", problem))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Settings {
+    // The `APP_ENVIRONMENT` the settings were loaded for, e.g. `local` or `production`
+    pub environment: String,
+    pub application: ApplicationSettings,
+    pub auth: AuthSettings,
+    pub storage: StorageSettings,
+    pub database: DatabaseSettings,
+    pub redis: RedisSettings,
+    pub email: EmailSettings,
+    pub email_outbox: EmailOutboxSettings,
+    pub webhooks: WebhookSettings,
+    pub health: HealthSettings,
+    pub tracing: TracingSettings,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApplicationSettings {
+    pub address: String,
+    // Browser origins allowed to make credentialed CORS requests, each either exact or
+    // `https://*.example.com` for every subdomain, see `OriginPattern`
+    pub allowed_origins: Vec<String>,
+    // Serves `/metrics` on this address instead of `address`, e.g. an admin port kept private
+    pub metrics_address: Option<String>,
+    // On SIGTERM, how long to keep serving with `/health/ready` failing, so load balancers
+    // stop sending traffic here before new connections are refused
+    #[serde(with = "humantime_serde")]
+    pub shutdown_delay: Duration,
+    // Then how long in-flight requests, and afterwards background workers, get to finish
+    #[serde(with = "humantime_serde")]
+    pub drain_timeout: Duration,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthSettings {
+    pub jwt_secret: SecretString,
+    // Lifetime of issued JWTs; a banned token is kept until it expires
+    #[serde(with = "humantime_serde")]
+    pub token_ttl: Duration,
+    pub cookie: CookieSettings,
+}
+
+// Attributes of the cookie holding the JWT
+#[derive(Debug, Clone, Deserialize)]
+pub struct CookieSettings {
+    pub name: String,
+    // Shares the cookie with the subdomains of e.g. `example.com`; only sent back to the
+    // auth service's own host when unset
+    pub domain: Option<String>,
+    // Only sent over HTTPS
+    pub secure: bool,
+    pub same_site: CookieSameSite,
+    // How long browsers keep the cookie, `auth.token_ttl` when unset
+    #[serde(default, with = "humantime_serde")]
+    pub max_age: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CookieSameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl AuthSettings {
+    pub fn cookie_max_age(&self) -> Duration {
+        self.cookie.max_age.unwrap_or(self.token_ttl)
+    }
+}
+
+impl CookieSettings {
+    // Attributes browsers would reject the cookie for, or that defeat its purpose
+    fn problems(&self, token_ttl: Duration) -> Vec<String> {
+        let mut problems = Vec::new();
+        let is_token_char = |c: char| c.is_ascii_graphic() && !"()<>@,;:\\\"/[]?={}".contains(c);
+        if self.name.is_empty() || !self.name.chars().all(is_token_char) {
+            problems.push(format!(
+                "auth.cookie.name `{}` is not a valid cookie name",
+                self.name
+            ));
+        }
+        if let Some(domain) = &self.domain {
+            if domain.is_empty()
+                || domain.contains(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '.')
+            {
+                problems.push(format!(
+                    "auth.cookie.domain `{}` must be a bare domain, e.g. `example.com`",
+                    domain
+                ));
+            }
+        }
+        if self.same_site == CookieSameSite::None && !self.secure {
+            problems.push(
+                "auth.cookie.secure must be true when auth.cookie.same_site is `none`".to_owned(),
+            );
+        }
+        // Browsers enforce these name prefixes
+        if self.name.starts_with("__Secure-") && !self.secure {
+            problems
+                .push("auth.cookie.secure must be true for a `__Secure-` cookie name".to_owned());
+        }
+        if self.name.starts_with("__Host-") && (!self.secure || self.domain.is_some()) {
+            problems.push(
+                "a `__Host-` cookie name requires auth.cookie.secure and no auth.cookie.domain"
+                    .to_owned(),
+            );
+        }
+        if let Some(max_age) = self.max_age {
+            if max_age.is_zero() || max_age > token_ttl {
+                problems.push(
+                    "auth.cookie.max_age must be greater than 0 and at most auth.token_ttl"
+                        .to_owned(),
+                );
+            }
+        }
+        problems
+    }
+}
+
+// Where a store keeps its data. Not every store supports every backend,
+// and the external ones are only available when their cargo feature is enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    Memory,
+    Postgres,
+    Redis,
+    Sqlite,
+}
+
+impl StorageBackend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Memory => "memory",
+            Self::Postgres => "postgres",
+            Self::Redis => "redis",
+            Self::Sqlite => "sqlite",
+        }
+    }
+
+    pub fn is_compiled_in(&self) -> bool {
+        match self {
+            Self::Memory => true,
+            Self::Postgres => cfg!(feature = "postgres"),
+            Self::Redis => cfg!(feature = "redis"),
+            Self::Sqlite => cfg!(feature = "sqlite"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StorageSettings {
+    pub users: StorageBackend,
+    pub banned_tokens: StorageBackend,
+    pub two_fa_codes: StorageBackend,
+    pub email_outbox: StorageBackend,
+    pub webhooks: StorageBackend,
+    // How often expired banned tokens and 2FA codes are deleted from Postgres or memory
+    #[serde(with = "humantime_serde")]
+    pub purge_interval: Duration,
+}
+
+impl StorageSettings {
+    pub fn uses(&self, backend: StorageBackend) -> bool {
+        self.stores()
+            .any(|(_, store_backend, _)| store_backend == backend)
+    }
+
+    // Every store with its configured backend and the backends it supports
+    fn stores(
+        &self,
+    ) -> impl Iterator<Item = (&'static str, StorageBackend, &'static [StorageBackend])> {
+        use StorageBackend::*;
+
+        [
+            ("users", self.users, &[Memory, Postgres, Sqlite][..]),
+            (
+                "banned_tokens",
+                self.banned_tokens,
+                &[Memory, Postgres, Redis][..],
+            ),
+            (
+                "two_fa_codes",
+                self.two_fa_codes,
+                &[Memory, Postgres, Redis][..],
+            ),
+            ("email_outbox", self.email_outbox, &[Memory, Postgres][..]),
+            ("webhooks", self.webhooks, &[Memory, Postgres][..]),
+        ]
+        .into_iter()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DatabaseSettings {
+    pub url: SecretString,
+}
+
+// How the Redis deployment behind the banned token and 2FA code stores is reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedisMode {
+    Standalone,
+    Sentinel,
+    Cluster,
+}
+
+impl RedisMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Standalone => "standalone",
+            Self::Sentinel => "sentinel",
+            Self::Cluster => "cluster",
+        }
+    }
+
+    fn default_port(&self) -> u16 {
+        match self {
+            Self::Sentinel => 26379,
+            Self::Standalone | Self::Cluster => 6379,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RedisSettings {
+    pub mode: RedisMode,
+    // `host[:port]` of the server in standalone mode
+    pub host: String,
+    // `host[:port]` of the sentinels in sentinel mode, or of the seed nodes in cluster mode
+    pub nodes: Vec<String>,
+    // Name of the master the sentinels monitor
+    pub master_name: String,
+    pub tls: bool,
+    // Leave both empty when Redis doesn't require authentication, the username is for ACL users
+    pub username: String,
+    pub password: SecretString,
+    // Prepended to every key, so several environments can share one Redis
+    pub key_prefix: String,
+    // Bound how long a request waits on Redis, so an outage fails fast instead of hanging
+    #[serde(with = "humantime_serde")]
+    pub connection_timeout: Duration,
+    #[serde(with = "humantime_serde")]
+    pub response_timeout: Duration,
+}
+
+impl RedisSettings {
+    // The `host` in standalone mode and the `nodes` otherwise, as `(host, port)` pairs
+    pub fn addresses(&self) -> Result<Vec<(String, u16)>, String> {
+        let addresses = match self.mode {
+            RedisMode::Standalone => std::slice::from_ref(&self.host),
+            RedisMode::Sentinel | RedisMode::Cluster => &self.nodes[..],
+        };
+
+        addresses
+            .iter()
+            .map(|address| {
+                parse_redis_address(address, self.mode.default_port()).ok_or_else(|| {
+                    format!("redis address `{}` is not a valid `host[:port]`", address)
+                })
+            })
+            .collect()
+    }
+
+    fn validate(&self, problems: &mut Vec<String>) {
+        match self.mode {
+            RedisMode::Standalone if self.host.is_empty() => {
+                problems.push("redis.host must be set when redis.mode is `standalone`".to_owned());
+            }
+            RedisMode::Sentinel | RedisMode::Cluster if self.nodes.is_empty() => {
+                problems.push(format!(
+                    "redis.nodes must be set when redis.mode is `{}`",
+                    self.mode.as_str()
+                ));
+            }
+            _ => {
+                if let Err(problem) = self.addresses() {
+                    problems.push(problem);
+                }
+            }
+        }
+        if self.mode == RedisMode::Sentinel && self.master_name.is_empty() {
+            problems.push("redis.master_name must be set when redis.mode is `sentinel`".to_owned());
+        }
+        if !self.username.is_empty() && self.password.expose_secret().is_empty() {
+            problems.push("redis.password must be set when redis.username is set".to_owned());
+        }
+        // Keys end with a `{<id>}` hash tag, a brace in the prefix would become the tag instead
+        if self.key_prefix.contains(['{', '}']) {
+            problems.push("redis.key_prefix must not contain `{` or `}`".to_owned());
+        }
+    }
+}
+
+fn parse_redis_address(address: &str, default_port: u16) -> Option<(String, u16)> {
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (address, default_port),
+    };
+
+    (!host.is_empty()).then(|| (host.to_owned(), port))
+}
+
+// Which `EmailClient` implementation the binary sends emails with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailClientKind {
+    Postmark,
+    Smtp,
+    Mock,
+    // Keeps emails in memory and shows them at `/dev/mailbox`, for local development
+    Capture,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmailSettings {
+    pub client: EmailClientKind,
+    pub sender: String,
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+    pub postmark: PostmarkSettings,
+    pub smtp: SmtpSettings,
+    pub brand: EmailBrand,
+}
+
+impl EmailSettings {
+    pub fn sender(&self) -> Result<Email> {
+        Email::parse(SecretString::new(self.sender.clone().into_boxed_str()))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PostmarkSettings {
+    pub base_url: String,
+    pub auth_token: SecretString,
+}
+
+// How the connection to the SMTP relay is secured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTlsMode {
+    // Plaintext only, meant for trusted local relays
+    None,
+    // Plaintext connection upgraded with STARTTLS, failing if the relay doesn't support it
+    StartTls,
+    // TLS from the first byte (a.k.a. SMTPS)
+    Tls,
+}
+
+impl SmtpTlsMode {
+    pub fn default_port(&self) -> u16 {
+        match self {
+            Self::None => 25,
+            Self::StartTls => 587,
+            Self::Tls => 465,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SmtpCredentials {
+    pub username: String,
+    pub password: SecretString,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmtpSettings {
+    pub host: String,
+    // Defaults to the standard port of the TLS mode
+    pub port: Option<u16>,
+    pub tls: SmtpTlsMode,
+    // PEM file of a CA trusted besides the public ones, for relays with a private certificate
+    pub ca_certificate: Option<String>,
+    // Leave both empty for relays that don't require authentication
+    pub username: String,
+    pub password: SecretString,
+}
+
+impl SmtpSettings {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(self.tls.default_port())
+    }
+
+    // Credentials are optional, but the username and password go together
+    pub fn credentials(&self) -> Option<SmtpCredentials> {
+        (!self.username.is_empty()).then(|| SmtpCredentials {
+            username: self.username.clone(),
+            password: self.password.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmailOutboxSettings {
+    #[serde(with = "humantime_serde")]
+    pub poll_interval: Duration,
+    pub retry: RetryPolicy,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookSettings {
+    #[serde(deserialize_with = "deserialize_subscriptions")]
+    pub subscriptions: Vec<WebhookSubscription>,
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+    #[serde(with = "humantime_serde")]
+    pub poll_interval: Duration,
+    pub retry: RetryPolicy,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HealthSettings {
+    // How long `/health/ready` waits on each dependency
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+    // Also probe Postmark or the SMTP relay, which readiness doesn't depend on by default
+    // since emails wait in the outbox while the provider is down
+    pub check_email_provider: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TracingSettings {
+    pub log_format: LogFormat,
+    // `service.name` of the exported spans
+    pub service_name: String,
+    // Base URL of an OTLP/HTTP collector, e.g. "http://localhost:4318". Spans are only
+    // exported when it is set, trace context is propagated either way
+    pub otlp_endpoint: Option<String>,
+    pub otlp_protocol: OtlpProtocol,
+    #[serde(with = "humantime_serde")]
+    pub otlp_timeout: Duration,
+}
+
+// `json` writes one object per line for log collectors, `text` is meant for people
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+// Encoding of the spans sent to the collector
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OtlpProtocol {
+    Protobuf,
+    Json,
+}
+
+impl Settings {
+    // Loads `base.toml`, then `<APP_ENVIRONMENT>.toml`, then `APP__*` environment variables
+    pub fn load() -> Result<Self, SettingsError> {
+        dotenvy::dotenv().ok();
+        let config_dir = std::env::var(CONFIG_DIR_ENV_VAR).unwrap_or(DEFAULT_CONFIG_DIR.to_owned());
+        let environment =
+            std::env::var(ENVIRONMENT_ENV_VAR).unwrap_or(DEFAULT_ENVIRONMENT.to_owned());
+
+        Self::load_from(Path::new(&config_dir), &environment, std::env::vars())
+    }
+
+    // Same as `load`, with the environment and its variables passed in explicitly
+    pub fn load_from(
+        config_dir: &Path,
+        environment: &str,
+        env_vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, SettingsError> {
+        let prefix = format!("{}{}", ENV_OVERRIDE_PREFIX, ENV_OVERRIDE_SEPARATOR);
+        let overrides: HashMap<String, String> = env_vars
+            .into_iter()
+            .filter(|(key, _)| key.starts_with(&prefix))
+            .collect();
+
+        let settings: Settings = Config::builder()
+            .add_source(File::from(config_dir.join("base.toml")))
+            .add_source(
+                File::from(config_dir.join(format!("{}.toml", environment))).required(false),
+            )
+            .add_source(
+                Environment::with_prefix(ENV_OVERRIDE_PREFIX)
+                    .prefix_separator(ENV_OVERRIDE_SEPARATOR)
+                    .separator(ENV_OVERRIDE_SEPARATOR)
+                    .ignore_empty(true)
+                    .try_parsing(true)
+                    .list_separator(",")
+                    .with_list_parse_key("application.allowed_origins")
+                    .with_list_parse_key("redis.nodes")
+                    .source(Some(overrides.into_iter().collect())),
+            )
+            // Not overridable, it says which file was layered on top
+            .set_override("environment", environment)?
+            .build()?
+            .try_deserialize()?;
+
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    // Reports every problem at once so a misconfigured deployment can be fixed in one go
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        let mut problems = Vec::new();
+
+        if self.application.address.parse::<SocketAddr>().is_err() {
+            problems.push(format!(
+                "application.address `{}` is not a valid socket address",
+                self.application.address
+            ));
+        }
+        if let Some(metrics_address) = &self.application.metrics_address {
+            if metrics_address.parse::<SocketAddr>().is_err() {
+                problems.push(format!(
+                    "application.metrics_address `{}` is not a valid socket address",
+                    metrics_address
+                ));
+            }
+        }
+        for origin in &self.application.allowed_origins {
+            if OriginPattern::parse(origin).is_none() {
+                problems.push(format!(
+                    "application.allowed_origins entry `{}` is not a valid origin",
+                    origin
+                ));
+            }
+        }
+
+        if self.auth.jwt_secret.expose_secret().is_empty() {
+            problems.push("auth.jwt_secret must be set".to_owned());
+        }
+        if self.auth.token_ttl < Duration::from_secs(1) {
+            problems.push("auth.token_ttl must be at least 1s".to_owned());
+        }
+        problems.extend(self.auth.cookie.problems(self.auth.token_ttl));
+
+        for (store, backend, supported) in self.storage.stores() {
+            if !supported.contains(&backend) {
+                let supported: Vec<_> = supported.iter().map(StorageBackend::as_str).collect();
+                problems.push(format!(
+                    "storage.{} must be one of: {}",
+                    store,
+                    supported.join(", ")
+                ));
+            } else if !backend.is_compiled_in() {
+                problems.push(format!(
+                    "storage.{} is `{}`, but the service was built without the `{}` feature",
+                    store,
+                    backend.as_str(),
+                    backend.as_str()
+                ));
+            }
+        }
+        // Postgres users queue their webhook deliveries in the same transaction
+        if self.storage.users == StorageBackend::Postgres
+            && self.storage.webhooks != StorageBackend::Postgres
+        {
+            problems.push(
+                "storage.webhooks must be `postgres` when storage.users is `postgres`".to_owned(),
+            );
+        }
+        if self.storage.purge_interval.is_zero() {
+            problems.push("storage.purge_interval must be greater than 0".to_owned());
+        }
+        let database_url = self.database.url.expose_secret();
+        if self.storage.uses(StorageBackend::Postgres) {
+            if database_url.is_empty() {
+                problems.push("database.url must be set when a store uses `postgres`".to_owned());
+            } else if !database_url.starts_with("postgres://")
+                && !database_url.starts_with("postgresql://")
+            {
+                problems.push(
+                    "database.url must be a `postgres://` URL when a store uses `postgres`"
+                        .to_owned(),
+                );
+            }
+        }
+        if self.storage.uses(StorageBackend::Sqlite) && !database_url.starts_with("sqlite:") {
+            problems.push(
+                "database.url must be a `sqlite://` URL when a store uses `sqlite`".to_owned(),
+            );
+        }
+        if self.storage.uses(StorageBackend::Redis) {
+            self.redis.validate(&mut problems);
+        }
+
+        if let Err(e) = self.email.sender() {
+            problems.push(format!("email.sender: {}", e));
+        }
+        match self.email.client {
+            EmailClientKind::Postmark => {
+                if !cfg!(feature = "postmark") {
+                    problems.push(
+                        "email.client is `postmark`, but the service was built without the \
+                         `postmark` feature"
+                            .to_owned(),
+                    );
+                }
+                if self.email.postmark.auth_token.expose_secret().is_empty() {
+                    problems.push(
+                        "email.postmark.auth_token must be set when email.client is `postmark`"
+                            .to_owned(),
+                    );
+                }
+            }
+            EmailClientKind::Smtp => {
+                if !cfg!(feature = "smtp") {
+                    problems.push(
+                        "email.client is `smtp`, but the service was built without the `smtp` \
+                         feature"
+                            .to_owned(),
+                    );
+                }
+                if self.email.smtp.host.is_empty() {
+                    problems
+                        .push("email.smtp.host must be set when email.client is `smtp`".to_owned());
+                }
+                if let Some(path) = &self.email.smtp.ca_certificate {
+                    if !Path::new(path).is_file() {
+                        problems.push(format!(
+                            "email.smtp.ca_certificate `{}` is not a readable file",
+                            path
+                        ));
+                    }
+                }
+                if self.email.smtp.username.is_empty()
+                    != self.email.smtp.password.expose_secret().is_empty()
+                {
+                    problems.push(
+                        "email.smtp.username and email.smtp.password must be set together"
+                            .to_owned(),
+                    );
+                }
+            }
+            EmailClientKind::Capture => {
+                // Captured emails, 2FA codes included, are served by `/dev/mailbox`
+                if !CAPTURE_ENVIRONMENTS.contains(&self.environment.as_str()) {
+                    problems.push(format!(
+                        "email.client `capture` is only allowed in the {} environments, not `{}`",
+                        CAPTURE_ENVIRONMENTS.join(" and "),
+                        self.environment
+                    ));
+                }
+            }
+            EmailClientKind::Mock => {}
+        }
+
+        validate_retry_policy(
+            "email_outbox.retry",
+            &self.email_outbox.retry,
+            &mut problems,
+        );
+        validate_retry_policy("webhooks.retry", &self.webhooks.retry, &mut problems);
+
+        if self.health.timeout.is_zero() {
+            problems.push("health.timeout must be greater than 0".to_owned());
+        }
+
+        if let Some(endpoint) = &self.tracing.otlp_endpoint {
+            if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+                problems.push(format!(
+                    "tracing.otlp_endpoint `{}` must be an http:// or https:// URL",
+                    endpoint
+                ));
+            }
+        }
+        if self.tracing.otlp_timeout.is_zero() {
+            problems.push("tracing.otlp_timeout must be greater than 0".to_owned());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(SettingsError::Invalid(problems))
+        }
+    }
+}
+
+fn validate_retry_policy(name: &str, policy: &RetryPolicy, problems: &mut Vec<String>) {
+    if policy.max_attempts == 0 {
+        problems.push(format!("{}.max_attempts must be at least 1", name));
+    }
+    if policy.base_delay > policy.max_delay {
+        problems.push(format!(
+            "{}.base_delay must not be greater than {}.max_delay",
+            name, name
+        ));
+    }
+}
+
+// Subscriptions are an array of tables in TOML, but a single environment variable
+// can only carry them as a JSON string
+fn deserialize_subscriptions<'de, D>(deserializer: D) -> Result<Vec<WebhookSubscription>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct SubscriptionsVisitor;
+
+    impl<'de> Visitor<'de> for SubscriptionsVisitor {
+        type Value = Vec<WebhookSubscription>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a list of webhook subscriptions or a JSON array string")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+            if value.trim().is_empty() {
+                return Ok(Vec::new());
+            }
+            serde_json::from_str(value).map_err(E::custom)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut subscriptions = Vec::new();
+            while let Some(subscription) = seq.next_element()? {
+                subscriptions.push(subscription);
+            }
+            Ok(subscriptions)
+        }
+    }
+
+    deserializer.deserialize_any(SubscriptionsVisitor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::WebhookEventType;
+
+    fn config_dir() -> &'static Path {
+        Path::new(DEFAULT_CONFIG_DIR)
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn problems(result: Result<Settings, SettingsError>) -> Vec<String> {
+        match result {
+            Err(SettingsError::Invalid(problems)) => problems,
+            other => panic!("expected invalid settings, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn local_settings_are_valid_without_env_vars() {
+        let settings = Settings::load_from(config_dir(), "local", Vec::new()).unwrap();
+
+        assert_eq!(settings.application.address, "0.0.0.0:3000");
+        assert_eq!(settings.auth.token_ttl, Duration::from_secs(600));
+        assert_eq!(settings.email.client, EmailClientKind::Capture);
+        assert!(!settings.storage.uses(StorageBackend::Postgres));
+        assert!(!settings.storage.uses(StorageBackend::Redis));
+        assert!(settings.webhooks.subscriptions.is_empty());
+        assert!(settings.tracing.otlp_endpoint.is_none());
+        assert_eq!(settings.tracing.log_format, LogFormat::Text);
+        assert_eq!(settings.application.shutdown_delay, Duration::ZERO);
+        assert_eq!(settings.application.drain_timeout, Duration::from_secs(30));
+        assert_eq!(settings.auth.cookie.name, "jwt");
+        assert_eq!(settings.auth.cookie.same_site, CookieSameSite::Lax);
+        assert!(!settings.auth.cookie.secure);
+        assert!(settings.auth.cookie.domain.is_none());
+        assert_eq!(settings.auth.cookie_max_age(), settings.auth.token_ttl);
+    }
+
+    #[test]
+    fn env_vars_override_file_values() {
+        let env = vars(&[
+            ("APP__AUTH__JWT_SECRET", "12345"),
+            ("APP__REDIS__HOST", ""),
+            ("APP__AUTH__TOKEN_TTL", "15m"),
+            (
+                "APP__APPLICATION__ALLOWED_ORIGINS",
+                "https://a.test,https://b.test",
+            ),
+            ("APP__EMAIL__SMTP__PORT", "2525"),
+            ("JWT_SECRET", "ignored"),
+        ]);
+
+        let settings = Settings::load_from(config_dir(), "local", env).unwrap();
+
+        assert_eq!(settings.auth.jwt_secret.expose_secret(), "12345");
+        assert_eq!(settings.auth.token_ttl, Duration::from_secs(15 * 60));
+        assert_eq!(
+            settings.application.allowed_origins,
+            vec!["https://a.test", "https://b.test"]
+        );
+        assert_eq!(settings.email.smtp.port(), 2525);
+        // Empty variables are treated as unset
+        assert_eq!(settings.redis.host, "127.0.0.1");
+    }
+
+    #[test]
+    fn webhook_subscriptions_can_be_set_as_json() {
+        let env = vars(&[(
+            "APP__WEBHOOKS__SUBSCRIPTIONS",
+            r#"[{"id": "billing", "url": "https://billing.test/hooks", "secret": "s", "events": ["user.signed_up"]}]"#,
+        )]);
+
+        let settings = Settings::load_from(config_dir(), "local", env).unwrap();
+
+        assert_eq!(settings.webhooks.subscriptions.len(), 1);
+        assert_eq!(settings.webhooks.subscriptions[0].id, "billing");
+    }
+
+    #[test]
+    fn webhook_subscriptions_can_name_every_lifecycle_event() {
+        let env = vars(&[(
+            "APP__WEBHOOKS__SUBSCRIPTIONS",
+            r#"[{"id": "crm", "url": "https://crm.test/hooks", "secret": "s", "events": ["user.password_changed", "user.deleted"]}]"#,
+        )]);
+
+        let settings = Settings::load_from(config_dir(), "local", env).unwrap();
+
+        assert_eq!(
+            settings.webhooks.subscriptions[0].events,
+            vec![
+                WebhookEventType::PasswordChanged,
+                WebhookEventType::UserDeleted
+            ]
+        );
+    }
+
+    #[test]
+    fn production_settings_require_secrets() {
+        let problems = problems(Settings::load_from(config_dir(), "production", Vec::new()));
+
+        assert!(problems.contains(&"auth.jwt_secret must be set".to_owned()));
+        assert!(
+            problems.contains(&"database.url must be set when a store uses `postgres`".to_owned())
+        );
+        assert!(problems.contains(
+            &"email.postmark.auth_token must be set when email.client is `postmark`".to_owned()
+        ));
+    }
+
+    #[test]
+    fn capture_email_client_is_only_allowed_locally() {
+        let env = vars(&[
+            ("APP__EMAIL__CLIENT", "capture"),
+            ("APP__ENVIRONMENT", "local"),
+        ]);
+
+        let problems = problems(Settings::load_from(config_dir(), "production", env));
+
+        assert!(problems.contains(
+            &"email.client `capture` is only allowed in the local and test environments, not `production`"
+                .to_owned()
+        ));
+    }
+
+    #[test]
+    fn invalid_values_are_all_reported() {
+        let env = vars(&[
+            ("APP__APPLICATION__ADDRESS", "not-an-address"),
+            ("APP__APPLICATION__ALLOWED_ORIGINS", "localhost:8000"),
+            ("APP__EMAIL__CLIENT", "smtp"),
+            ("APP__EMAIL__SENDER", "not-an-email"),
+            ("APP__EMAIL__SMTP__USERNAME", "mailer"),
+            ("APP__HEALTH__TIMEOUT", "0s"),
+            ("APP__APPLICATION__METRICS_ADDRESS", "localhost"),
+            ("APP__TRACING__OTLP_ENDPOINT", "localhost:4318"),
+        ]);
+
+        let problems = problems(Settings::load_from(config_dir(), "local", env));
+
+        assert_eq!(problems.len(), 8, "{:?}", problems);
+        let error = SettingsError::Invalid(problems).to_string();
+        assert!(error.starts_with("invalid configuration:\n  - application.address"));
+        assert!(error.contains("email.smtp.host must be set"));
+        assert!(error.contains("health.timeout must be greater than 0"));
+        assert!(error.contains("application.metrics_address `localhost`"));
+        assert!(error.contains("tracing.otlp_endpoint `localhost:4318`"));
+    }
+
+    #[test]
+    fn wildcard_origins_are_accepted() {
+        let env = vars(&[(
+            "APP__APPLICATION__ALLOWED_ORIGINS",
+            "https://app.example.com,https://*.example.com",
+        )]);
+
+        let settings = Settings::load_from(config_dir(), "local", env).unwrap();
+
+        assert_eq!(settings.application.allowed_origins.len(), 2);
+    }
+
+    #[test]
+    fn cookie_attributes_browsers_would_reject_are_reported() {
+        let env = vars(&[
+            ("APP__AUTH__COOKIE__NAME", "__Host-jwt"),
+            ("APP__AUTH__COOKIE__DOMAIN", "https://example.com"),
+            ("APP__AUTH__COOKIE__SAME_SITE", "none"),
+            ("APP__AUTH__COOKIE__MAX_AGE", "1h"),
+        ]);
+
+        let problems = problems(Settings::load_from(config_dir(), "local", env));
+
+        assert_eq!(
+            problems,
+            vec![
+                "auth.cookie.domain `https://example.com` must be a bare domain, e.g. `example.com`",
+                "auth.cookie.secure must be true when auth.cookie.same_site is `none`",
+                "a `__Host-` cookie name requires auth.cookie.secure and no auth.cookie.domain",
+                "auth.cookie.max_age must be greater than 0 and at most auth.token_ttl",
+            ]
+        );
+    }
+
+    #[test]
+    fn unsupported_storage_backends_are_reported() {
+        let env = vars(&[
+            ("APP__STORAGE__USERS", "redis"),
+            ("APP__STORAGE__WEBHOOKS", "redis"),
+        ]);
+
+        let problems = problems(Settings::load_from(config_dir(), "local", env));
+
+        assert!(
+            problems.contains(&"storage.users must be one of: memory, postgres, sqlite".to_owned())
+        );
+        assert!(problems.contains(&"storage.webhooks must be one of: memory, postgres".to_owned()));
+    }
+
+    #[test]
+    fn postgres_users_need_postgres_webhooks() {
+        let env = vars(&[
+            ("APP__STORAGE__USERS", "postgres"),
+            ("APP__DATABASE__URL", "postgres://localhost/auth"),
+        ]);
+
+        let problems = problems(Settings::load_from(config_dir(), "local", env));
+
+        assert_eq!(
+            problems,
+            ["storage.webhooks must be `postgres` when storage.users is `postgres`"]
+        );
+    }
+
+    #[test]
+    fn database_url_must_match_the_sql_backend() {
+        let env = vars(&[
+            ("APP__STORAGE__USERS", "sqlite"),
+            ("APP__STORAGE__WEBHOOKS", "postgres"),
+            ("APP__DATABASE__URL", "mysql://localhost/auth"),
+        ]);
+
+        let problems = problems(Settings::load_from(config_dir(), "local", env));
+
+        assert!(problems.contains(
+            &"database.url must be a `postgres://` URL when a store uses `postgres`".to_owned()
+        ));
+        assert!(problems.contains(
+            &"database.url must be a `sqlite://` URL when a store uses `sqlite`".to_owned()
+        ));
+    }
+
+    #[test]
+    fn users_can_be_kept_in_sqlite() {
+        let env = vars(&[
+            ("APP__STORAGE__USERS", "sqlite"),
+            ("APP__DATABASE__URL", "sqlite://auth.db"),
+        ]);
+
+        let settings = Settings::load_from(config_dir(), "local", env).unwrap();
+
+        assert!(settings.storage.uses(StorageBackend::Sqlite));
+        assert!(!settings.storage.uses(StorageBackend::Postgres));
+    }
+
+    #[test]
+    fn redis_settings_are_validated_for_the_selected_mode() {
+        let env = vars(&[
+            ("APP__STORAGE__BANNED_TOKENS", "redis"),
+            ("APP__REDIS__MODE", "sentinel"),
+            ("APP__REDIS__USERNAME", "auth"),
+            ("APP__REDIS__KEY_PREFIX", "{staging}:"),
+        ]);
+
+        let problems = problems(Settings::load_from(config_dir(), "local", env));
+
+        assert!(
+            problems.contains(&"redis.nodes must be set when redis.mode is `sentinel`".to_owned())
+        );
+        assert!(problems
+            .contains(&"redis.master_name must be set when redis.mode is `sentinel`".to_owned()));
+        assert!(
+            problems.contains(&"redis.password must be set when redis.username is set".to_owned())
+        );
+        assert!(problems.contains(&"redis.key_prefix must not contain `{` or `}`".to_owned()));
+    }
+
+    #[test]
+    fn redis_nodes_default_to_the_mode_port() {
+        let env = vars(&[
+            ("APP__STORAGE__TWO_FA_CODES", "redis"),
+            ("APP__REDIS__MODE", "cluster"),
+            ("APP__REDIS__NODES", "redis-1:7000,redis-2"),
+        ]);
+
+        let settings = Settings::load_from(config_dir(), "local", env).unwrap();
+
+        assert_eq!(
+            settings.redis.addresses().unwrap(),
+            vec![("redis-1".to_owned(), 7000), ("redis-2".to_owned(), 6379)]
+        );
+
+        let env = vars(&[
+            ("APP__STORAGE__TWO_FA_CODES", "redis"),
+            ("APP__REDIS__MODE", "cluster"),
+            ("APP__REDIS__NODES", "redis-1:port"),
+        ]);
+
+        let problems = problems(Settings::load_from(config_dir(), "local", env));
+
+        assert_eq!(
+            problems,
+            vec!["redis address `redis-1:port` is not a valid `host[:port]`"]
+        );
+    }
+
+    #[test]
+    fn malformed_values_fail_to_load() {
+        let env = vars(&[("APP__EMAIL__CLIENT", "carrier-pigeon")]);
+
+        let result = Settings::load_from(config_dir(), "local", env);
+
+        assert!(matches!(result, Err(SettingsError::Load(_))));
+    }
+
+    #[test]
+    fn default_port_follows_tls_mode() {
+        assert_eq!(SmtpTlsMode::None.default_port(), 25);
+        assert_eq!(SmtpTlsMode::StartTls.default_port(), 587);
+        assert_eq!(SmtpTlsMode::Tls.default_port(), 465);
+    }
+}
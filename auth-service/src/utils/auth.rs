@@ -1,46 +1,108 @@
 use axum_extra::extract::cookie::{Cookie, SameSite};
-use chrono::Utc;
-use color_eyre::eyre::{eyre, Context, ContextCompat, Result};
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Context, Result};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Validation};
 use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
 
-use crate::{app_state::BannedTokenStoreType, domain::Email};
-
-use super::constants::{JWT_COOKIE_NAME, JWT_SECRET};
+use crate::{
+    app_state::BannedTokenStoreType,
+    domain::{Clock, Email, IdGenerator},
+    settings::{AuthSettings, CookieSameSite},
+    utils::csrf::{create_csrf_cookie, csrf_token},
+};
 
 // Create cookie with a new JWT auth token
 #[tracing::instrument(name = "auth:generate_auth_cookie", skip_all)] // New!
-pub fn generate_auth_cookie(email: &Email) -> Result<Cookie<'static>> {
-    let token = generate_auth_token(email)?;
-    Ok(create_auth_cookie(token))
+pub fn generate_auth_cookie(
+    email: &Email,
+    settings: &AuthSettings,
+    clock: &(dyn Clock + Send + Sync),
+    id_generator: &(dyn IdGenerator + Send + Sync),
+) -> Result<Cookie<'static>> {
+    let token = generate_auth_token(email, settings, clock, id_generator)?;
+    Ok(create_auth_cookie(token, settings))
+}
+
+// Create the auth cookie with a new JWT auth token, and the CSRF cookie for the same session
+#[tracing::instrument(name = "auth:generate_auth_cookies", skip_all)]
+pub fn generate_auth_cookies(
+    email: &Email,
+    settings: &AuthSettings,
+    clock: &(dyn Clock + Send + Sync),
+    id_generator: &(dyn IdGenerator + Send + Sync),
+) -> Result<(Cookie<'static>, Cookie<'static>)> {
+    let claims = generate_claims(email, settings, clock, id_generator)?;
+    let token = create_token(&claims, &settings.jwt_secret)?;
+    let csrf_token = csrf_token(&claims.jti, &settings.jwt_secret)?;
+
+    Ok((
+        create_auth_cookie(token, settings),
+        create_csrf_cookie(csrf_token, settings),
+    ))
 }
 
 // Create cookie and set the value to the passed-in token string
 #[tracing::instrument(name = "auth:create_auth_cookie", skip_all)] // New!
-fn create_auth_cookie(token: String) -> Cookie<'static> {
-    let cookie = Cookie::build((JWT_COOKIE_NAME, token))
+pub(crate) fn create_auth_cookie(token: String, settings: &AuthSettings) -> Cookie<'static> {
+    let max_age =
+        time::Duration::try_from(settings.cookie_max_age()).unwrap_or(time::Duration::MAX);
+    let mut cookie = auth_cookie(settings);
+    cookie.set_value(token);
+    cookie.set_max_age(max_age); // browsers drop the cookie once the token expires
+
+    cookie
+}
+
+// The auth cookie with its attributes but no value, e.g. for `CookieJar::remove`,
+// which only clears a cookie with the same path and domain
+pub fn auth_cookie(settings: &AuthSettings) -> Cookie<'static> {
+    let cookie = &settings.cookie;
+    let mut builder = Cookie::build((cookie.name.clone(), ""))
         .path("/") // apply cookie to all URLs on the server
         .http_only(true) // prevent JavaScript from accessing the cookie
-        .same_site(SameSite::Lax) // send cookie with "same-site" requests, and with "cross-site" top-level navigations.
-        .build();
+        .secure(cookie.secure)
+        .same_site(match cookie.same_site {
+            CookieSameSite::Strict => SameSite::Strict,
+            // send cookie with "same-site" requests, and with "cross-site" top-level navigations.
+            CookieSameSite::Lax => SameSite::Lax,
+            CookieSameSite::None => SameSite::None,
+        });
+    if let Some(domain) = &cookie.domain {
+        builder = builder.domain(domain.clone());
+    }
 
-    cookie
+    builder.build()
 }
 
 // This value determines how long the JWT auth token is valid for
-pub const TOKEN_TTL_SECONDS: i64 = 600; // 10 minutes
-
 // Create JWT auth token
 #[tracing::instrument(name = "auth:generate_auth_token", skip_all)] // New!
-fn generate_auth_token(email: &Email) -> Result<String> {
-    let delta = chrono::Duration::try_seconds(TOKEN_TTL_SECONDS)
-        .wrap_err("failed to create 10 minute time delta")?;
+pub fn generate_auth_token(
+    email: &Email,
+    settings: &AuthSettings,
+    clock: &(dyn Clock + Send + Sync),
+    id_generator: &(dyn IdGenerator + Send + Sync),
+) -> Result<String> {
+    let claims = generate_claims(email, settings, clock, id_generator)?;
+
+    create_token(&claims, &settings.jwt_secret)
+}
+
+fn generate_claims(
+    email: &Email,
+    settings: &AuthSettings,
+    clock: &(dyn Clock + Send + Sync),
+    id_generator: &(dyn IdGenerator + Send + Sync),
+) -> Result<Claims> {
+    let delta = chrono::Duration::from_std(settings.token_ttl)
+        .wrap_err("failed to create token TTL time delta")?;
 
     // Create JWT expiration time
-    let exp = Utc::now()
+    let now = clock.now();
+    let exp = now
         .checked_add_signed(delta)
-        .ok_or(eyre!("failed to add 10 minutes to current time"))?
+        .ok_or(eyre!("failed to add token TTL to current time"))?
         .timestamp();
 
     // Cast exp to a usize, which is what Claims expects
@@ -48,12 +110,16 @@ fn generate_auth_token(email: &Email) -> Result<String> {
         "failed to cast exp time to usize. exp time: {}",
         exp
     ))?;
+    let iat: usize = now
+        .timestamp()
+        .try_into()
+        .wrap_err("failed to cast iat time to usize")?;
 
     let sub = email.as_ref().expose_secret().to_owned();
+    // Identifies the token when it is banned, so the ban doesn't need the whole token
+    let jti = id_generator.uuid().to_string();
 
-    let claims = Claims { sub, exp };
-
-    create_token(&claims)
+    Ok(Claims { sub, exp, iat, jti })
 }
 
 // Check if JWT auth token is valid by decoding it using the JWT secret
@@ -61,8 +127,12 @@ fn generate_auth_token(email: &Email) -> Result<String> {
 pub async fn validate_token(
     token: &SecretString,
     banned_token_store: BannedTokenStoreType,
+    settings: &AuthSettings,
+    clock: &(dyn Clock + Send + Sync),
 ) -> Result<Claims> {
-    match banned_token_store.read().await.contains_token(token).await {
+    let claims = decode_token(token, settings, clock)?;
+
+    match banned_token_store.contains_token(&claims.jti).await {
         Ok(value) => {
             if value {
                 return Err(eyre!("token is banned"));
@@ -71,22 +141,42 @@ pub async fn validate_token(
         Err(e) => return Err(e.into()),
     }
 
-    decode::<Claims>(
+    Ok(claims)
+}
+
+// Checks the token's signature and expiry, but not whether it was banned
+pub fn decode_token(
+    token: &SecretString,
+    settings: &AuthSettings,
+    clock: &(dyn Clock + Send + Sync),
+) -> Result<Claims> {
+    // Expiry is checked against our clock rather than the system time jsonwebtoken would use
+    let mut validation = Validation::default();
+    validation.validate_exp = false;
+
+    let claims = decode::<Claims>(
         token.expose_secret(),
-        &DecodingKey::from_secret(JWT_SECRET.expose_secret().as_bytes()),
-        &Validation::default(),
+        &DecodingKey::from_secret(settings.jwt_secret.expose_secret().as_bytes()),
+        &validation,
     )
     .map(|data| data.claims)
-    .wrap_err("failed to decode token")
+    .wrap_err("failed to decode token")?;
+
+    // No leeway: a ban only lasts until `exp`, so the token mustn't be accepted past it
+    if claims.expires_at()? <= clock.now() {
+        return Err(eyre!("token has expired"));
+    }
+
+    Ok(claims)
 }
 
 // Create JWT auth token by encoding claims using the JWT secret
 #[tracing::instrument(name = "auth:create_token", skip_all)] // New!
-fn create_token(claims: &Claims) -> Result<String> {
+fn create_token(claims: &Claims, jwt_secret: &SecretString) -> Result<String> {
     encode(
         &jsonwebtoken::Header::default(),
         &claims,
-        &EncodingKey::from_secret(JWT_SECRET.expose_secret().as_bytes()),
+        &EncodingKey::from_secret(jwt_secret.expose_secret().as_bytes()),
     )
     .wrap_err("failed to create token")
 }
@@ -95,18 +185,51 @@ fn create_token(claims: &Claims) -> Result<String> {
 pub struct Claims {
     pub sub: String,
     pub exp: usize,
+    pub iat: usize,
+    pub jti: String,
+}
+
+impl Claims {
+    pub fn expires_at(&self) -> Result<DateTime<Utc>> {
+        i64::try_from(self.exp)
+            .ok()
+            .and_then(|exp| DateTime::from_timestamp(exp, 0))
+            .ok_or(eyre!("token exp is out of range"))
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::services::data_stores::HashSetBannedTokenStore;
+    use crate::{
+        services::{
+            data_stores::HashSetBannedTokenStore, fake_clock::FakeClock,
+            fake_id_generator::FakeIdGenerator, random_id_generator::RandomIdGenerator,
+            system_clock::SystemClock,
+        },
+        settings::CookieSettings,
+        utils::constants::JWT_COOKIE_NAME,
+    };
+    use uuid::Uuid;
 
     use super::*;
     use std::sync::Arc;
-    use tokio::sync::RwLock;
+
+    fn auth_settings() -> AuthSettings {
+        AuthSettings {
+            jwt_secret: SecretString::new("secret".to_owned().into_boxed_str()),
+            token_ttl: std::time::Duration::from_secs(600),
+            cookie: CookieSettings {
+                name: JWT_COOKIE_NAME.to_owned(),
+                domain: None,
+                secure: false,
+                same_site: CookieSameSite::Lax,
+                max_age: None,
+            },
+        }
+    }
 
     fn empty_banned_store() -> BannedTokenStoreType {
-        Arc::new(RwLock::new(HashSetBannedTokenStore::default()))
+        Arc::new(HashSetBannedTokenStore::default())
     }
 
     fn create_email(s: &str) -> Email {
@@ -124,7 +247,9 @@ mod tests {
     async fn test_generate_auth_cookie() {
         let email = create_email("test@example.com"); // updated
 
-        let cookie = generate_auth_cookie(&email).unwrap();
+        let cookie =
+            generate_auth_cookie(&email, &auth_settings(), &SystemClock, &RandomIdGenerator)
+                .unwrap();
         assert_eq!(cookie.name(), JWT_COOKIE_NAME);
         assert_eq!(cookie.value().split('.').count(), 3);
         assert_eq!(cookie.path(), Some("/"));
@@ -132,35 +257,85 @@ mod tests {
         assert_eq!(cookie.same_site(), Some(SameSite::Lax));
     }
 
+    #[tokio::test]
+    async fn test_generate_auth_cookies() {
+        let email = create_email("test@example.com");
+        let settings = auth_settings();
+
+        let (auth_cookie, csrf_cookie) =
+            generate_auth_cookies(&email, &settings, &SystemClock, &RandomIdGenerator).unwrap();
+
+        let token = secret_token(auth_cookie.value().to_owned());
+        let claims = decode_token(&token, &settings, &SystemClock).unwrap();
+        assert_eq!(csrf_cookie.name(), "csrf_token");
+        assert_eq!(
+            csrf_cookie.value(),
+            csrf_token(&claims.jti, &settings.jwt_secret).unwrap()
+        );
+        assert_eq!(csrf_cookie.http_only(), Some(false));
+        assert_eq!(csrf_cookie.max_age(), auth_cookie.max_age());
+    }
+
     #[tokio::test]
     async fn test_create_auth_cookie() {
         let token = "test_token".to_owned();
-        let cookie = create_auth_cookie(token.clone());
+        let cookie = create_auth_cookie(token.clone(), &auth_settings());
         assert_eq!(cookie.name(), JWT_COOKIE_NAME);
         assert_eq!(cookie.value(), token);
         assert_eq!(cookie.path(), Some("/"));
         assert_eq!(cookie.http_only(), Some(true));
         assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+        assert_eq!(cookie.secure(), Some(false));
+        assert_eq!(cookie.domain(), None);
+        // Lasts as long as the token
+        assert_eq!(cookie.max_age(), Some(time::Duration::seconds(600)));
+    }
+
+    #[tokio::test]
+    async fn test_create_auth_cookie_with_configured_attributes() {
+        let mut settings = auth_settings();
+        settings.cookie = CookieSettings {
+            name: "__Secure-session".to_owned(),
+            domain: Some("example.com".to_owned()),
+            secure: true,
+            same_site: CookieSameSite::None,
+            max_age: Some(std::time::Duration::from_secs(300)),
+        };
+
+        let cookie = create_auth_cookie("test_token".to_owned(), &settings);
+
+        assert_eq!(
+            cookie.to_string(),
+            "__Secure-session=test_token; HttpOnly; SameSite=None; Secure; Path=/; \
+             Domain=example.com; Max-Age=300"
+        );
     }
 
     #[tokio::test]
     async fn test_generate_auth_token() {
         let email = create_email("test@example.com"); // updated
-        let result = generate_auth_token(&email).unwrap();
+        let result =
+            generate_auth_token(&email, &auth_settings(), &SystemClock, &RandomIdGenerator)
+                .unwrap();
         assert_eq!(result.split('.').count(), 3);
     }
 
     #[tokio::test]
     async fn test_validate_token_with_valid_token() {
         let email = create_email("test@example.com"); // updated
-        let token = secret_token(generate_auth_token(&email).unwrap()); // updated
+        let token = secret_token(
+            generate_auth_token(&email, &auth_settings(), &SystemClock, &RandomIdGenerator)
+                .unwrap(),
+        ); // updated
 
         let banned_token_store = empty_banned_store();
 
-        let result = validate_token(&token, banned_token_store).await.unwrap(); // updated
+        let result = validate_token(&token, banned_token_store, &auth_settings(), &SystemClock)
+            .await
+            .unwrap(); // updated
         assert_eq!(result.sub, "test@example.com");
 
-        let exp = Utc::now()
+        let exp = chrono::Utc::now()
             .checked_add_signed(chrono::Duration::try_minutes(9).expect("valid duration"))
             .expect("valid timestamp")
             .timestamp();
@@ -168,33 +343,106 @@ mod tests {
         assert!(result.exp > exp as usize);
     }
 
+    #[tokio::test]
+    async fn test_jti_comes_from_the_id_generator() {
+        let email = create_email("test@example.com");
+        let id_generator = FakeIdGenerator::default();
+        let jti = Uuid::new_v4();
+        id_generator.set_uuid(jti);
+
+        let token = secret_token(
+            generate_auth_token(&email, &auth_settings(), &SystemClock, &id_generator).unwrap(),
+        );
+        let claims = validate_token(&token, empty_banned_store(), &auth_settings(), &SystemClock)
+            .await
+            .unwrap();
+
+        assert_eq!(claims.jti, jti.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_tokens_are_identified_by_jti() {
+        let email = create_email("test@example.com");
+        let clock = FakeClock::default();
+        let first = secret_token(
+            generate_auth_token(&email, &auth_settings(), &clock, &RandomIdGenerator).unwrap(),
+        );
+        let second = secret_token(
+            generate_auth_token(&email, &auth_settings(), &clock, &RandomIdGenerator).unwrap(),
+        );
+
+        let first = validate_token(&first, empty_banned_store(), &auth_settings(), &clock)
+            .await
+            .unwrap();
+        let second = validate_token(&second, empty_banned_store(), &auth_settings(), &clock)
+            .await
+            .unwrap();
+
+        assert_ne!(first.jti, second.jti);
+        assert_eq!(first.iat as i64, clock.now().timestamp());
+        assert_eq!(
+            first.expires_at().unwrap().timestamp(),
+            (clock.now() + chrono::Duration::minutes(10)).timestamp()
+        );
+    }
+
     #[tokio::test]
     async fn test_validate_token_with_invalid_token() {
         let token = SecretString::new("invalid_token".to_owned().into_boxed_str()); // updated
 
         let banned_token_store = empty_banned_store();
 
-        let result = validate_token(&token, banned_token_store).await; // updated
+        let result =
+            validate_token(&token, banned_token_store, &auth_settings(), &SystemClock).await; // updated
         assert!(result.is_err());
     }
 
     #[tokio::test]
     async fn test_validate_token_with_banned_token() {
         let email = create_email("test@example.com"); // updated
-        let token = secret_token(generate_auth_token(&email).unwrap()); // updated
+        let token = secret_token(
+            generate_auth_token(&email, &auth_settings(), &SystemClock, &RandomIdGenerator)
+                .unwrap(),
+        ); // updated
 
         let banned_token_store = empty_banned_store();
-
-        // Mark token as banned using whatever method your store provides.
-        // In your logout handler you called `.ban_token(token)`, so we mirror that.
-        let mut store = banned_token_store.write().await;
-        store
-            .add_token(&token) // updated
+        let claims = validate_token(
+            &token,
+            banned_token_store.clone(),
+            &auth_settings(),
+            &SystemClock,
+        )
+        .await
+        .unwrap();
+
+        // Mark token as banned the way the logout handler does
+        banned_token_store
+            .add_token(&claims.jti, claims.expires_at().unwrap())
             .await
             .expect("failed to ban token in test");
-        drop(store); // release the write lock
 
-        let result = validate_token(&token, banned_token_store).await; // updated
+        let result =
+            validate_token(&token, banned_token_store, &auth_settings(), &SystemClock).await; // updated
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_token_with_expired_token() {
+        let email = create_email("test@example.com");
+        let clock = FakeClock::default();
+        let token = secret_token(
+            generate_auth_token(&email, &auth_settings(), &clock, &RandomIdGenerator).unwrap(),
+        );
+
+        clock.advance(chrono::Duration::minutes(9));
+        assert!(
+            validate_token(&token, empty_banned_store(), &auth_settings(), &clock)
+                .await
+                .is_ok()
+        );
+
+        clock.advance(chrono::Duration::minutes(1));
+        let result = validate_token(&token, empty_banned_store(), &auth_settings(), &clock).await;
         assert!(result.is_err());
     }
 }
@@ -208,7 +456,7 @@ mod tests {
 //     use tokio::sync::RwLock;
 //
 //     fn empty_banned_store() -> BannedTokenStoreType {
-//         Arc::new(RwLock::new(HashSetBannedTokenStore::default()))
+//         Arc::new(HashSetBannedTokenStore::default())
 //     }
 //
 //     #[tokio::test]
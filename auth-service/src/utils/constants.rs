@@ -1,77 +1,25 @@
-use dotenvy::dotenv;
-use lazy_static::lazy_static;
-use secrecy::SecretString;
-use std::env as std_env;
-
-// Define a lazily evaluated static. lazy_static is needed because std_env::var is not a const function.
-lazy_static! {
-    pub static ref JWT_SECRET: SecretString = set_token();
-    pub static ref DATABASE_URL: SecretString = set_database_url();
-    pub static ref REDIS_HOST_NAME: String = set_redis_host();
-    pub static ref POSTMARK_AUTH_TOKEN: SecretString = set_postmark_auth_token(); // New!
-}
-
-fn set_token() -> SecretString {
-    dotenv().ok(); // Load environment variables
-    let secret = std_env::var(env::JWT_SECRET_ENV_VAR).expect("JWT_SECRET must be set.");
-    if secret.is_empty() {
-        panic!("JWT_SECRET must not be empty.");
-    }
-    SecretString::new(secret.into_boxed_str())
-}
-
-fn set_database_url() -> SecretString {
-    dotenv().ok(); // Load environment variables
-    let db_url = std_env::var(env::DATABASE_URL_ENV_VAR).expect("DATABASE_URL must be set.");
-    if db_url.is_empty() {
-        panic!("DATABASE_URL must not be empty.");
-    }
-    SecretString::new(db_url.into_boxed_str())
-}
-
-fn set_redis_host() -> String {
-    dotenv().ok();
-    std_env::var(env::REDIS_HOST_NAME_ENV_VAR).unwrap_or(DEFAULT_REDIS_HOSTNAME.to_owned())
-}
-
-fn set_postmark_auth_token() -> SecretString {
-    dotenv().ok();
-    let postmark_tkn =
-        std_env::var(env::POSTMARK_AUTH_TOKEN_ENV_VAR).expect("POSTMARK_AUTH_TOKEN must be set.");
-    if postmark_tkn.is_empty() {
-        panic!("POSTMARK_AUTH_TOKEN must not be empty.");
-    }
-    SecretString::new(postmark_tkn.into_boxed_str())
-}
-
-pub mod env {
-    pub const JWT_SECRET_ENV_VAR: &str = "JWT_SECRET";
-    pub const DATABASE_URL_ENV_VAR: &str = "DATABASE_URL";
-    pub const REDIS_HOST_NAME_ENV_VAR: &str = "REDIS_HOST_NAME";
-    pub const POSTMARK_AUTH_TOKEN_ENV_VAR: &str = "POSTMARK_AUTH_TOKEN"; // New!
-}
-
+// Default name of the auth cookie, see `auth.cookie.name`
 pub const JWT_COOKIE_NAME: &str = "jwt";
-pub const DEFAULT_REDIS_HOSTNAME: &str = "127.0.0.1";
+// Set next to the auth cookie for pages to read, and echoed in `CSRF_HEADER_NAME`
+pub const CSRF_COOKIE_NAME: &str = "csrf_token";
+pub const CSRF_HEADER_NAME: &str = "x-csrf-token";
 
-pub mod prod {
-    pub const APP_ADDRESS: &str = "0.0.0.0:3000";
+pub mod test {
     pub mod email_client {
         use std::time::Duration;
 
-        pub const BASE_URL: &str = "https://api.postmarkapp.com/email";
-        // If you created your own Postmark account, make sure to use your email address!
-        pub const SENDER: &str = "bogdan@codeiron.io";
-        pub const TIMEOUT: Duration = std::time::Duration::from_secs(10);
+        pub const SENDER: &str = "test@email.com";
+        pub const TIMEOUT: Duration = std::time::Duration::from_millis(200);
     }
-}
-
-pub mod test {
-    pub const APP_ADDRESS: &str = "127.0.0.1:0";
-    pub mod email_client {
+    pub mod webhooks {
         use std::time::Duration;
 
-        pub const SENDER: &str = "test@email.com";
         pub const TIMEOUT: Duration = std::time::Duration::from_millis(200);
+        pub const POLL_INTERVAL: Duration = std::time::Duration::from_millis(50);
+    }
+    pub mod email_outbox {
+        use std::time::Duration;
+
+        pub const POLL_INTERVAL: Duration = std::time::Duration::from_millis(50);
     }
 }
@@ -0,0 +1,146 @@
+use axum::http::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    HeaderName, HeaderValue, Method,
+};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::utils::constants::CSRF_HEADER_NAME;
+
+// An entry of `application.allowed_origins`: either an exact origin such as
+// `https://app.example.com`, or `https://*.example.com` for every subdomain of `example.com`
+// (but not `example.com` itself), on that scheme and port only
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OriginPattern {
+    Exact(String),
+    Subdomains {
+        // e.g. `https://`
+        scheme: String,
+        // e.g. `.example.com` or `.example.com:8443`
+        suffix: String,
+    },
+}
+
+impl OriginPattern {
+    // `None` unless it's an http(s) origin, with no path and at most a leading `*.`
+    pub fn parse(pattern: &str) -> Option<Self> {
+        let pattern = pattern.to_ascii_lowercase();
+        let (scheme, host) = ["http://", "https://"]
+            .into_iter()
+            .find_map(|scheme| Some((scheme, pattern.strip_prefix(scheme)?)))?;
+        let is_valid_host = |host: &str| {
+            !host.is_empty()
+                && !host.starts_with('.')
+                && host
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | ':' | '[' | ']'))
+        };
+
+        match host.strip_prefix("*.") {
+            Some(domain) if is_valid_host(domain) => Some(Self::Subdomains {
+                scheme: scheme.to_owned(),
+                suffix: format!(".{}", domain),
+            }),
+            Some(_) => None,
+            None if is_valid_host(host) && HeaderValue::from_str(&pattern).is_ok() => {
+                Some(Self::Exact(pattern))
+            }
+            None => None,
+        }
+    }
+
+    pub fn matches(&self, origin: &str) -> bool {
+        let origin = origin.to_ascii_lowercase();
+        match self {
+            Self::Exact(allowed) => origin == *allowed,
+            Self::Subdomains { scheme, suffix } => origin
+                .strip_prefix(scheme.as_str())
+                .and_then(|host| host.strip_suffix(suffix.as_str()))
+                .is_some_and(|subdomain| {
+                    // One or more labels, e.g. `app` or `eu.app`
+                    subdomain
+                        .split('.')
+                        .all(|label| !label.is_empty() && label.chars().all(is_label_char))
+                }),
+        }
+    }
+}
+
+fn is_label_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-'
+}
+
+// Lets the allowed origins make credentialed requests, i.e. send and receive the auth cookie,
+// and send Bearer tokens and CSRF tokens
+pub fn cors_layer(allowed_origins: &[String]) -> Result<CorsLayer, String> {
+    let patterns = allowed_origins
+        .iter()
+        .map(|origin| {
+            OriginPattern::parse(origin).ok_or_else(|| format!("invalid origin `{}`", origin))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(CorsLayer::new()
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([
+            CONTENT_TYPE,
+            AUTHORIZATION,
+            HeaderName::from_static(CSRF_HEADER_NAME),
+        ])
+        .allow_credentials(true)
+        .allow_origin(AllowOrigin::predicate(move |origin, _| {
+            origin
+                .to_str()
+                .is_ok_and(|origin| patterns.iter().any(|pattern| pattern.matches(origin)))
+        })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_origins_match_only_themselves() {
+        let pattern = OriginPattern::parse("http://localhost:8000").unwrap();
+
+        assert!(pattern.matches("http://localhost:8000"));
+        assert!(!pattern.matches("http://localhost:8001"));
+        assert!(!pattern.matches("https://localhost:8000"));
+    }
+
+    #[test]
+    fn wildcards_match_subdomains_on_the_same_scheme_and_port() {
+        let pattern = OriginPattern::parse("https://*.Example.com").unwrap();
+
+        assert!(pattern.matches("https://app.example.com"));
+        assert!(pattern.matches("https://eu.app.example.com"));
+        assert!(pattern.matches("https://APP.example.com"));
+        assert!(!pattern.matches("https://example.com"));
+        assert!(!pattern.matches("https://.example.com"));
+        assert!(!pattern.matches("http://app.example.com"));
+        assert!(!pattern.matches("https://app.example.com:8443"));
+        assert!(!pattern.matches("https://evil-example.com"));
+        assert!(!pattern.matches("https://app.example.com.evil.com"));
+        assert!(!pattern.matches("https://a/b.example.com"));
+
+        let pattern = OriginPattern::parse("https://*.example.com:8443").unwrap();
+        assert!(pattern.matches("https://app.example.com:8443"));
+        assert!(!pattern.matches("https://app.example.com"));
+    }
+
+    #[test]
+    fn malformed_origins_are_rejected() {
+        for origin in [
+            "localhost:8000",
+            "ftp://example.com",
+            "https://",
+            "https://example.com/",
+            "https://*",
+            "https://*.",
+            "https://app.*.example.com",
+            "https://*example.com",
+            "https://*.*.example.com",
+        ] {
+            assert_eq!(OriginPattern::parse(origin), None, "{}", origin);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use axum_extra::extract::cookie::Cookie;
+use color_eyre::eyre::{eyre, Result};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+
+use crate::{
+    app_state::AppState,
+    domain::AuthAPIError,
+    settings::AuthSettings,
+    utils::{
+        auth::{auth_cookie, create_auth_cookie, decode_token},
+        constants::{CSRF_COOKIE_NAME, CSRF_HEADER_NAME},
+        extract::{AuthToken, TokenSource},
+    },
+};
+
+// Browsers send the auth cookie with requests other sites make them send, so a state-changing
+// request authenticated with it must also echo the CSRF cookie in the `X-CSRF-Token` header,
+// which only pages allowed to read the cookie can do. Bearer tokens are only ever sent by
+// clients holding them, so those requests are exempt.
+pub async fn require_csrf_token(
+    State(state): State<AppState>,
+    auth_token: Option<AuthToken>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AuthAPIError> {
+    let token = match auth_token {
+        Some(AuthToken {
+            token,
+            source: TokenSource::Cookie,
+        }) if !request.method().is_safe() => token,
+        // Without a token, the handler rejects the request itself
+        _ => return Ok(next.run(request).await),
+    };
+
+    let claims = decode_token(&token, &state.auth_settings, &*state.clock)
+        .map_err(|_| AuthAPIError::InvalidToken)?;
+    let sent = request
+        .headers()
+        .get(CSRF_HEADER_NAME)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if !verify_csrf_token(&claims.jti, &state.auth_settings.jwt_secret, sent) {
+        return Err(AuthAPIError::InvalidCsrfToken);
+    }
+
+    Ok(next.run(request).await)
+}
+
+// The CSRF token of the session whose token has this `jti`. It can't be made up without the
+// JWT secret, nor carried over to another session, so a cookie planted by a sibling subdomain
+// doesn't get past the check.
+pub fn csrf_token(jti: &str, jwt_secret: &SecretString) -> Result<String> {
+    Ok(hex::encode(
+        csrf_mac(jti, jwt_secret)?.finalize().into_bytes(),
+    ))
+}
+
+fn verify_csrf_token(jti: &str, jwt_secret: &SecretString, sent: &str) -> bool {
+    let Ok(sent) = hex::decode(sent) else {
+        return false;
+    };
+    // Compared in constant time
+    csrf_mac(jti, jwt_secret).is_ok_and(|mac| mac.verify_slice(&sent).is_ok())
+}
+
+fn csrf_mac(jti: &str, jwt_secret: &SecretString) -> Result<Hmac<Sha256>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(jwt_secret.expose_secret().as_bytes())
+        .map_err(|e| eyre!("invalid JWT secret: {}", e))?;
+    mac.update(b"csrf.");
+    mac.update(jti.as_bytes());
+    Ok(mac)
+}
+
+// Shares the auth cookie's attributes and lifetime, but pages can read it
+pub fn create_csrf_cookie(csrf_token: String, settings: &AuthSettings) -> Cookie<'static> {
+    let mut cookie = create_auth_cookie(csrf_token, settings);
+    cookie.set_name(CSRF_COOKIE_NAME);
+    cookie.set_http_only(false);
+
+    cookie
+}
+
+// The CSRF cookie with its attributes but no value, for `CookieJar::remove`
+pub fn csrf_cookie(settings: &AuthSettings) -> Cookie<'static> {
+    let mut cookie = auth_cookie(settings);
+    cookie.set_name(CSRF_COOKIE_NAME);
+    cookie.set_http_only(false);
+
+    cookie
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret(s: &str) -> SecretString {
+        SecretString::from(s.to_owned())
+    }
+
+    #[test]
+    fn csrf_tokens_are_only_valid_for_their_session() {
+        let token = csrf_token("jti-1", &secret("secret")).unwrap();
+
+        assert!(verify_csrf_token("jti-1", &secret("secret"), &token));
+        assert!(!verify_csrf_token("jti-2", &secret("secret"), &token));
+        assert!(!verify_csrf_token("jti-1", &secret("other"), &token));
+    }
+
+    #[test]
+    fn malformed_csrf_tokens_are_rejected() {
+        let token = csrf_token("jti-1", &secret("secret")).unwrap();
+
+        for sent in ["", "not hex", &token[..token.len() - 2]] {
+            assert!(
+                !verify_csrf_token("jti-1", &secret("secret"), sent),
+                "{}",
+                sent
+            );
+        }
+    }
+}
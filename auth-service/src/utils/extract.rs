@@ -0,0 +1,112 @@
+use std::convert::Infallible;
+
+use axum::{
+    extract::{FromRequestParts, OptionalFromRequestParts},
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap},
+};
+use axum_extra::extract::CookieJar;
+use secrecy::SecretString;
+
+use crate::{app_state::AppState, domain::AuthAPIError};
+
+// Where a request's token was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenSource {
+    Bearer,
+    Cookie,
+}
+
+// The token a request carries, from an `Authorization: Bearer` header or else from the auth
+// cookie. Handlers take `AuthToken` to require one (`MissingToken` otherwise), or
+// `Option<AuthToken>`. The token still has to be validated.
+pub struct AuthToken {
+    pub token: SecretString,
+    pub source: TokenSource,
+}
+
+impl AuthToken {
+    fn from_parts(parts: &Parts, state: &AppState) -> Option<Self> {
+        if let Some(token) = bearer_token(&parts.headers) {
+            return Some(Self {
+                token,
+                source: TokenSource::Bearer,
+            });
+        }
+
+        CookieJar::from_headers(&parts.headers)
+            .get(&state.auth_settings.cookie.name)
+            .map(|cookie| Self {
+                token: SecretString::from(cookie.value().to_owned()),
+                source: TokenSource::Cookie,
+            })
+    }
+}
+
+impl FromRequestParts<AppState> for AuthToken {
+    type Rejection = AuthAPIError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        Self::from_parts(parts, state).ok_or(AuthAPIError::MissingToken)
+    }
+}
+
+impl OptionalFromRequestParts<AppState> for AuthToken {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        Ok(Self::from_parts(parts, state))
+    }
+}
+
+// Other schemes are ignored, as if the header wasn't there
+pub fn bearer_token(headers: &HeaderMap) -> Option<SecretString> {
+    let (scheme, token) = headers.get(AUTHORIZATION)?.to_str().ok()?.split_once(' ')?;
+
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then(|| SecretString::from(token.trim().to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+    use secrecy::ExposeSecret;
+
+    use super::*;
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(authorization).unwrap());
+        headers
+    }
+
+    #[test]
+    fn bearer_tokens_are_read_whatever_the_scheme_case() {
+        for authorization in [
+            "Bearer abc.def.ghi",
+            "bearer abc.def.ghi",
+            "BEARER  abc.def.ghi ",
+        ] {
+            let token = bearer_token(&headers(authorization)).expect(authorization);
+            assert_eq!(token.expose_secret(), "abc.def.ghi");
+        }
+    }
+
+    #[test]
+    fn other_authorization_schemes_are_ignored() {
+        for authorization in ["Basic dXNlcjpwYXNz", "Bearer", "abc.def.ghi"] {
+            assert!(
+                bearer_token(&headers(authorization)).is_none(),
+                "{}",
+                authorization
+            );
+        }
+        assert!(bearer_token(&HeaderMap::new()).is_none());
+    }
+}
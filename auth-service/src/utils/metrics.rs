@@ -0,0 +1,147 @@
+use std::{
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::header::CONTENT_TYPE,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use metrics::{counter, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+// Latencies range from in-memory store calls to Argon2 hashes and slow providers
+const LATENCY_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+// How often histograms are compacted, they grow with every sample otherwise
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+static PROMETHEUS_HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+// Installs the process-wide recorder the `metrics` macros report to on first use,
+// so every app built in the process, e.g. by the API tests, shares it
+pub fn prometheus_handle() -> PrometheusHandle {
+    PROMETHEUS_HANDLE
+        .get_or_init(|| {
+            let handle = PrometheusBuilder::new()
+                .set_buckets_for_metric(Matcher::Suffix("_seconds".to_owned()), LATENCY_BUCKETS)
+                .expect("Latency buckets are not empty")
+                .install_recorder()
+                .expect("Failed to install the Prometheus recorder");
+
+            let upkeep_handle = handle.clone();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(UPKEEP_INTERVAL);
+                upkeep_handle.run_upkeep();
+            });
+            handle
+        })
+        .clone()
+}
+
+// Serves every metric in the Prometheus text format
+pub async fn render_metrics(State(handle): State<PrometheusHandle>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        handle.render(),
+    )
+}
+
+// Counts requests and their latency by route template rather than URI, so ids in paths
+// don't create a series each. Assets and unknown paths share the `fallback` route.
+pub async fn track_http_requests(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = route_template(&request);
+    let start = Instant::now();
+
+    let response = next.run(request).await;
+
+    let labels = [
+        ("method", method),
+        ("route", route),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    counter!("http_requests_total", &labels).increment(1);
+    histogram!("http_request_duration_seconds", &labels).record(start.elapsed().as_secs_f64());
+    response
+}
+
+// The route that matched the request, e.g. `/login`, or `fallback` for assets and unknown paths
+pub fn route_template<B>(request: &Request<B>) -> String {
+    request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| "fallback".to_owned())
+}
+
+pub fn record_login(outcome: LoginOutcome) {
+    counter!("auth_logins_total", "outcome" => outcome.as_str()).increment(1);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginOutcome {
+    // Logged in without 2FA
+    Success,
+    // Correct credentials, a 2FA code was sent
+    TwoFactorRequired,
+    // Malformed email or password
+    InvalidInput,
+    // Unknown user or wrong password
+    IncorrectCredentials,
+    Error,
+}
+
+impl LoginOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::TwoFactorRequired => "2fa_required",
+            Self::InvalidInput => "invalid_input",
+            Self::IncorrectCredentials => "incorrect_credentials",
+            Self::Error => "error",
+        }
+    }
+}
+
+pub fn record_signup() {
+    counter!("auth_signups_total").increment(1);
+}
+
+pub fn record_2fa_code_issued() {
+    counter!("auth_2fa_codes_issued_total").increment(1);
+}
+
+// A code that was checked, `verified` when it matched the login attempt
+pub fn record_2fa_verification(verified: bool) {
+    let outcome = if verified { "verified" } else { "failed" };
+    counter!("auth_2fa_verifications_total", "outcome" => outcome).increment(1);
+}
+
+pub fn record_token_banned() {
+    counter!("auth_tokens_banned_total").increment(1);
+}
+
+// `operation` is `hash` when a password is set, `verify` when one is checked on login
+pub fn record_password_hash(operation: &'static str, duration: Duration) {
+    histogram!("auth_password_hash_duration_seconds", "operation" => operation)
+        .record(duration.as_secs_f64());
+}
+
+pub fn record_store_operation(
+    store: &'static str,
+    backend: &'static str,
+    operation: &'static str,
+    duration: Duration,
+) {
+    histogram!(
+        "auth_store_operation_duration_seconds",
+        "store" => store,
+        "backend" => backend,
+        "operation" => operation
+    )
+    .record(duration.as_secs_f64());
+}
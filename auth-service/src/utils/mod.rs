@@ -1,5 +1,13 @@
-pub mod constants;
 pub mod auth;
+pub mod constants;
+pub mod cors;
+pub mod csrf;
+pub mod extract;
+pub mod metrics;
+pub mod poller;
+pub mod redaction;
+pub mod retry;
+pub mod shutdown;
 pub mod tracing;
 
 // pub use constants::*;
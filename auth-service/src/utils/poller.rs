@@ -0,0 +1,229 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::Result;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::{app_state::ClockType, services::system_clock::SystemClock, utils::shutdown::Shutdown};
+
+// A persistent queue drained by a `Poller`, e.g. the email outbox or the webhook deliveries
+#[async_trait::async_trait]
+pub trait JobQueue: Send + Sync {
+    type Job: Send;
+
+    fn job_id(job: &Self::Job) -> Uuid;
+    // Returns the jobs due at `now` and hides them from other workers until `lease_until`
+    async fn claim_due(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<Self::Job>>;
+    // Attempts a claimed job once and records the outcome, rescheduling it on failure
+    async fn attempt(&self, job: Self::Job) -> Result<()>;
+}
+
+// Claims due jobs in batches, every `poll_interval` or when woken up, and attempts them
+pub struct Poller {
+    poll_interval: Duration,
+    lease: Duration,
+    batch_size: usize,
+    wake: Option<Arc<Notify>>,
+    final_poll: bool,
+    clock: ClockType,
+}
+
+impl Poller {
+    pub fn new(poll_interval: Duration, lease: Duration, batch_size: usize) -> Self {
+        Self {
+            poll_interval,
+            lease,
+            batch_size,
+            wake: None,
+            final_poll: false,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    // Decides which jobs are due and until when they are leased
+    pub fn with_clock(mut self, clock: ClockType) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // Polls as soon as `wake` is notified, without waiting for the interval
+    pub fn with_wake(mut self, wake: Arc<Notify>) -> Self {
+        self.wake = Some(wake);
+        self
+    }
+
+    // Polls one last time once shutdown is triggered, for jobs queued by the last requests
+    pub fn with_final_poll(mut self) -> Self {
+        self.final_poll = true;
+        self
+    }
+
+    // Runs until `shutdown` is triggered
+    pub async fn run<Q: JobQueue>(&self, queue: &Q, shutdown: Shutdown) {
+        let stopped = shutdown.wait();
+        tokio::pin!(stopped);
+        loop {
+            if let Err(e) = self.poll(queue).await {
+                tracing::error!("Failed to claim due jobs: {:?}", e);
+            }
+            // Jobs queued by another instance are only picked up by the interval
+            let wait = async {
+                match &self.wake {
+                    Some(wake) => {
+                        let _ = tokio::time::timeout(self.poll_interval, wake.notified()).await;
+                    }
+                    None => tokio::time::sleep(self.poll_interval).await,
+                }
+            };
+            tokio::select! {
+                _ = &mut stopped => break,
+                _ = wait => {}
+            }
+        }
+        if self.final_poll {
+            if let Err(e) = self.poll(queue).await {
+                tracing::error!("Failed to claim due jobs: {:?}", e);
+            }
+        }
+    }
+
+    // Attempts every job that is currently due and returns how many were attempted
+    pub async fn poll<Q: JobQueue>(&self, queue: &Q) -> Result<usize> {
+        let now = self.clock.now();
+        // If this worker dies mid-attempt, the lease expires and another poll picks the job up
+        let lease_until = now + chrono::Duration::from_std(self.lease)?;
+
+        let jobs = queue.claim_due(now, lease_until, self.batch_size).await?;
+
+        let attempted = jobs.len();
+        for job in jobs {
+            // A job that can't be recorded is retried once its lease expires, and must not hold
+            // back the rest of the batch
+            let job_id = Q::job_id(&job);
+            if let Err(e) = queue.attempt(job).await {
+                tracing::error!(%job_id, "Failed to attempt job: {:?}", e);
+            }
+        }
+
+        Ok(attempted)
+    }
+}
+
+// Claims every due job, expecting exactly one
+#[cfg(test)]
+pub async fn claim_only<Q: JobQueue>(queue: &Q) -> Q::Job {
+    let now = Utc::now() + chrono::Duration::days(1);
+    let mut jobs = queue.claim_due(now, now, 10).await.unwrap();
+    assert_eq!(jobs.len(), 1);
+    jobs.remove(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use color_eyre::eyre::eyre;
+
+    use super::*;
+
+    // Jobs are ids, and attempting one fails when it is in `failing`
+    #[derive(Default)]
+    struct TestQueue {
+        due: Mutex<Vec<Uuid>>,
+        failing: Vec<Uuid>,
+        attempted: Mutex<Vec<Uuid>>,
+    }
+
+    #[async_trait::async_trait]
+    impl JobQueue for TestQueue {
+        type Job = Uuid;
+
+        fn job_id(job: &Uuid) -> Uuid {
+            *job
+        }
+
+        async fn claim_due(
+            &self,
+            _now: DateTime<Utc>,
+            _lease_until: DateTime<Utc>,
+            limit: usize,
+        ) -> Result<Vec<Uuid>> {
+            let mut due = self.due.lock().unwrap();
+            let count = limit.min(due.len());
+            Ok(due.drain(..count).collect())
+        }
+
+        async fn attempt(&self, job: Uuid) -> Result<()> {
+            self.attempted.lock().unwrap().push(job);
+            if self.failing.contains(&job) {
+                return Err(eyre!("attempt failed"));
+            }
+            Ok(())
+        }
+    }
+
+    fn poller() -> Poller {
+        Poller::new(Duration::from_secs(3600), Duration::from_secs(60), 2)
+    }
+
+    #[tokio::test]
+    async fn poll_attempts_at_most_a_batch() {
+        let jobs: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        let queue = TestQueue {
+            due: Mutex::new(jobs.clone()),
+            ..Default::default()
+        };
+
+        assert_eq!(poller().poll(&queue).await.unwrap(), 2);
+        assert_eq!(poller().poll(&queue).await.unwrap(), 1);
+        assert_eq!(poller().poll(&queue).await.unwrap(), 0);
+        assert_eq!(*queue.attempted.lock().unwrap(), jobs);
+    }
+
+    #[tokio::test]
+    async fn failing_job_does_not_hold_back_the_batch() {
+        let jobs = vec![Uuid::new_v4(), Uuid::new_v4()];
+        let queue = TestQueue {
+            due: Mutex::new(jobs.clone()),
+            failing: vec![jobs[0]],
+            ..Default::default()
+        };
+
+        assert_eq!(poller().poll(&queue).await.unwrap(), 2);
+        assert_eq!(*queue.attempted.lock().unwrap(), jobs);
+    }
+
+    #[tokio::test]
+    async fn wake_triggers_a_poll_before_the_interval() {
+        let wake = Arc::new(Notify::new());
+        let queue = Arc::new(TestQueue::default());
+        let shutdown = Shutdown::default();
+
+        let run = {
+            let (wake, queue, shutdown) = (wake.clone(), queue.clone(), shutdown.clone());
+            tokio::spawn(async move { poller().with_wake(wake).run(&*queue, shutdown).await })
+        };
+
+        let job = Uuid::new_v4();
+        queue.due.lock().unwrap().push(job);
+        wake.notify_one();
+
+        let mut attempted = false;
+        for _ in 0..100 {
+            if queue.attempted.lock().unwrap().contains(&job) {
+                attempted = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        shutdown.trigger();
+        run.await.unwrap();
+        assert!(attempted);
+    }
+}
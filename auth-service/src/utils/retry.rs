@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+// Exponential backoff schedule shared by the background delivery workers
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    #[serde(with = "humantime_serde")]
+    pub base_delay: Duration,
+    #[serde(with = "humantime_serde")]
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, base_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            max_attempts,
+            base_delay,
+            max_delay,
+        }
+    }
+
+    // Delay to wait after the given (1-based) failed attempt: base, 2 * base, 4 * base, ...
+    pub fn delay_after_attempt(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        self.base_delay
+            .checked_mul(1 << exponent)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+
+    pub fn is_exhausted(&self, attempts: u32) -> bool {
+        attempts >= self.max_attempts
+    }
+
+    // When to try again after `attempts` failed attempts, the last one at `attempted_at`, or
+    // `None` once the policy is exhausted
+    pub fn next_attempt_at(
+        &self,
+        attempts: u32,
+        attempted_at: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        if self.is_exhausted(attempts) {
+            return None;
+        }
+        let delay = chrono::Duration::from_std(self.delay_after_attempt(attempts))
+            .unwrap_or(chrono::Duration::MAX);
+        Some(
+            attempted_at
+                .checked_add_signed(delay)
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new(5, Duration::from_secs(1), Duration::from_secs(10))
+    }
+
+    #[test]
+    fn delay_doubles_after_each_attempt() {
+        let policy = policy();
+        assert_eq!(policy.delay_after_attempt(1), Duration::from_secs(1));
+        assert_eq!(policy.delay_after_attempt(2), Duration::from_secs(2));
+        assert_eq!(policy.delay_after_attempt(3), Duration::from_secs(4));
+        assert_eq!(policy.delay_after_attempt(4), Duration::from_secs(8));
+    }
+
+    #[test]
+    fn delay_is_capped_at_max_delay() {
+        let policy = policy();
+        assert_eq!(policy.delay_after_attempt(5), Duration::from_secs(10));
+        assert_eq!(policy.delay_after_attempt(100), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn policy_is_exhausted_after_max_attempts() {
+        let policy = policy();
+        assert!(!policy.is_exhausted(4));
+        assert!(policy.is_exhausted(5));
+    }
+
+    #[test]
+    fn next_attempt_is_scheduled_until_the_policy_is_exhausted() {
+        let policy = policy();
+        let now = Utc::now();
+        assert_eq!(
+            policy.next_attempt_at(3, now),
+            Some(now + chrono::Duration::seconds(4))
+        );
+        assert_eq!(policy.next_attempt_at(5, now), None);
+    }
+}
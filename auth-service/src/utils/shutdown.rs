@@ -0,0 +1,179 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use tokio::{sync::watch, task::JoinHandle};
+
+// Tells everything holding a clone that the service is shutting down
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            sender: Arc::new(watch::Sender::new(false)),
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    // Resolves once the shutdown is triggered, immediately if it already was
+    pub fn wait(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut receiver = self.sender.subscribe();
+        async move {
+            // Can't fail, `self` keeps the sender alive
+            let _ = receiver.wait_for(|triggered| *triggered).await;
+        }
+    }
+
+    // Triggers the shutdown on SIGTERM, as sent by Docker and Kubernetes, or on Ctrl+C
+    pub async fn trigger_on_signal(self) {
+        let ctrl_c = async {
+            tokio::signal::ctrl_c()
+                .await
+                .expect("Failed to listen for Ctrl+C");
+        };
+        #[cfg(unix)]
+        let terminate = async {
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                .expect("Failed to listen for SIGTERM")
+                .recv()
+                .await;
+        };
+        #[cfg(not(unix))]
+        let terminate = std::future::pending::<()>();
+
+        tokio::select! {
+            _ = ctrl_c => {}
+            _ = terminate => {}
+        }
+        tracing::info!("Shutdown signal received");
+        self.trigger();
+    }
+}
+
+// Background tasks, stopped one after the other in the order they were spawned
+#[derive(Default)]
+pub struct BackgroundWorkers {
+    workers: Vec<BackgroundWorker>,
+}
+
+struct BackgroundWorker {
+    name: &'static str,
+    shutdown: Shutdown,
+    handle: JoinHandle<()>,
+}
+
+impl BackgroundWorkers {
+    // `run` gets the worker's own `Shutdown`, and should return soon after it is triggered
+    pub fn spawn<F>(&mut self, name: &'static str, run: impl FnOnce(Shutdown) -> F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let shutdown = Shutdown::default();
+        let handle = tokio::spawn(run(shutdown.clone()));
+        self.workers.push(BackgroundWorker {
+            name,
+            shutdown,
+            handle,
+        });
+    }
+
+    // Lets each worker finish what it's doing, aborting whichever are still running after `timeout`
+    pub async fn stop(self, timeout: Duration) {
+        let deadline = tokio::time::Instant::now() + timeout;
+        for worker in self.workers {
+            worker.shutdown.trigger();
+            let mut handle = worker.handle;
+            match tokio::time::timeout_at(deadline, &mut handle).await {
+                Ok(Ok(())) => tracing::info!(worker = worker.name, "Background worker stopped"),
+                Ok(Err(e)) => {
+                    tracing::error!(worker = worker.name, error = ?e, "Background worker failed")
+                }
+                Err(_) => {
+                    tracing::warn!(
+                        worker = worker.name,
+                        "Background worker aborted after the drain timeout"
+                    );
+                    handle.abort();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn wait_resolves_once_triggered() {
+        let shutdown = Shutdown::default();
+        let waiting = tokio::spawn(shutdown.wait());
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        shutdown.trigger();
+
+        waiting.await.unwrap();
+        assert!(shutdown.is_triggered());
+        // Later waits resolve right away
+        shutdown.wait().await;
+    }
+
+    #[tokio::test]
+    async fn workers_are_stopped_in_order() {
+        let stopped = Arc::new(Mutex::new(Vec::new()));
+        let mut workers = BackgroundWorkers::default();
+        for name in ["email", "webhooks", "purge"] {
+            let stopped = stopped.clone();
+            workers.spawn(name, |shutdown| async move {
+                shutdown.wait().await;
+                // Stopping takes a while, so an out of order stop would overtake this one
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                stopped.lock().unwrap().push(name);
+            });
+        }
+
+        workers.stop(Duration::from_secs(5)).await;
+
+        assert_eq!(*stopped.lock().unwrap(), vec!["email", "webhooks", "purge"]);
+    }
+
+    #[tokio::test]
+    async fn workers_still_running_after_the_timeout_are_aborted() {
+        let dropped = Arc::new(Mutex::new(false));
+        let mut workers = BackgroundWorkers::default();
+        let on_drop = DropFlag(dropped.clone());
+        workers.spawn("stuck", |_| async move {
+            let _on_drop = on_drop;
+            std::future::pending::<()>().await;
+        });
+        let started = tokio::time::Instant::now();
+
+        workers.stop(Duration::from_millis(50)).await;
+
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        tokio::task::yield_now().await;
+        assert!(*dropped.lock().unwrap(), "The worker is still running");
+    }
+
+    // Records that the task owning it was dropped, i.e. aborted
+    struct DropFlag(Arc<Mutex<bool>>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            *self.0.lock().unwrap() = true;
+        }
+    }
+}
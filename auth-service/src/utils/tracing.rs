@@ -1,45 +1,297 @@
-use std::time::Duration;
-use color_eyre::eyre::Result;
-use axum::{body::Body, extract::Request, response::Response};
-use tracing::{Level, Span};
+use std::{fmt::Display, time::Duration};
+
+use axum::{body::Body, extract::Request, http::HeaderMap, middleware::Next, response::Response};
+use chrono::{SecondsFormat, Utc};
+use color_eyre::eyre::{Result, WrapErr};
+use hmac::{Hmac, Mac};
+use opentelemetry::{
+    propagation::TextMapPropagator,
+    trace::{TraceContextExt, TracerProvider as _},
+};
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::{Protocol, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{SdkTracer, SdkTracerProvider},
+    Resource,
+};
+use secrecy::{ExposeSecret, SecretString};
+use serde_json::{Map, Value};
+use sha2::Sha256;
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Span, Subscriber,
+};
 use tracing_error::ErrorLayer;
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
 use tracing_subscriber::prelude::*;
-use tracing_subscriber::{fmt, EnvFilter};
+use tracing_subscriber::{
+    fmt::{
+        self,
+        format::{JsonFields, Writer},
+        FmtContext, FormatEvent, FormattedFields, MakeWriter,
+    },
+    registry::LookupSpan,
+    EnvFilter, Layer,
+};
+
+use crate::{
+    settings::{LogFormat, OtlpProtocol, TracingSettings},
+    utils::{metrics::route_template, redaction::RedactingMakeWriter},
+};
 
-pub fn init_tracing() -> Result<()> {
-    // Create a formatting layer for tracing output with a compact format
-    let fmt_layer = fmt::layer().compact();
+// Kept from the caller when present, otherwise assigned, and echoed in the response
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+// Appended to `tracing.otlp_endpoint`, like the SDKs do with `OTEL_EXPORTER_OTLP_ENDPOINT`
+const OTLP_TRACES_PATH: &str = "/v1/traces";
 
+pub fn init_tracing(settings: &TracingSettings) -> Result<TracingGuard> {
     // Create a filter layer to control the verbosity of logs
     // Try to get the filter configuration from the environment variables
     // If it fails, default to the "info" log level
     let filter_layer = EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new("info"))?;
 
+    // Turns spans into OpenTelemetry ones, exported when a collector is configured
+    let (otel_layer, guard) = opentelemetry_layer(settings)?;
+
     // Build the tracing subscriber registry with the formatting layer,
     // the filter layer, and the error layer for enhanced error reporting
     tracing_subscriber::registry()
         .with(filter_layer) // Add the filter layer to control log verbosity
-        .with(fmt_layer) // Add the formatting layer for compact log output
+        .with(log_layer(settings.log_format, std::io::stdout)) // Add the formatting layer, which redacts secrets
+        .with(otel_layer) // Add the OpenTelemetry layer for trace propagation and export
         .with(ErrorLayer::default()) // Add the error layer to capture error contexts
         .init(); // Initialize the tracing subscriber
 
-    Ok(())
+    Ok(guard)
+}
+
+// Writes events in `format`, with secrets redacted from every line
+pub fn log_layer<S, W>(format: LogFormat, make_writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let make_writer = RedactingMakeWriter::new(make_writer);
+    match format {
+        LogFormat::Text => fmt::layer().compact().with_writer(make_writer).boxed(),
+        LogFormat::Json => fmt::layer()
+            .fmt_fields(JsonFields::new())
+            .event_format(JsonLogFormat)
+            .with_writer(make_writer)
+            .boxed(),
+    }
+}
+
+// One object per line, holding the event's fields on top of those of the spans it happened in,
+// so everything logged while handling a request carries its `request_id`, `route` and so on
+struct JsonLogFormat;
+
+impl<S> FormatEvent<S, JsonFields> for JsonLogFormat
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, JsonFields>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let metadata = event.metadata();
+        let mut fields = Map::new();
+        fields.insert(
+            "timestamp".to_owned(),
+            Utc::now()
+                .to_rfc3339_opts(SecondsFormat::Micros, true)
+                .into(),
+        );
+        fields.insert("level".to_owned(), metadata.level().as_str().into());
+        fields.insert("target".to_owned(), metadata.target().into());
+
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                // Fields recorded on the span so far, kept as a JSON object by `JsonFields`
+                if let Some(span_fields) = span.extensions().get::<FormattedFields<JsonFields>>() {
+                    if let Ok(Value::Object(span_fields)) = serde_json::from_str(span_fields) {
+                        fields.extend(span_fields);
+                    }
+                }
+                fields.insert("span".to_owned(), span.name().into());
+            }
+        }
+        event.record(&mut JsonVisitor(&mut fields));
+
+        writeln!(writer, "{}", Value::Object(fields))
+    }
+}
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_owned(), format!("{:?}", value).into());
+    }
+}
+
+// Without an endpoint nothing is exported, but spans still get trace and span ids
+// so incoming trace context reaches outgoing requests
+pub fn opentelemetry_layer<S>(
+    settings: &TracingSettings,
+) -> Result<(OpenTelemetryLayer<S, SdkTracer>, TracingGuard)>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let resource = Resource::builder()
+        .with_service_name(settings.service_name.clone())
+        .build();
+    let mut provider = SdkTracerProvider::builder().with_resource(resource);
+    if let Some(endpoint) = &settings.otlp_endpoint {
+        let protocol = match settings.otlp_protocol {
+            OtlpProtocol::Protobuf => Protocol::HttpBinary,
+            OtlpProtocol::Json => Protocol::HttpJson,
+        };
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!(
+                "{}{}",
+                endpoint.trim_end_matches('/'),
+                OTLP_TRACES_PATH
+            ))
+            .with_protocol(protocol)
+            .with_timeout(settings.otlp_timeout)
+            .build()
+            .wrap_err("Failed to build the OTLP span exporter")?;
+        provider = provider.with_batch_exporter(exporter);
+    }
+    let provider = provider.build();
+
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_PKG_NAME")));
+    Ok((layer, TracingGuard { provider }))
+}
+
+// Exports the spans still buffered when dropped, so keep it alive until the process exits
+#[must_use]
+pub struct TracingGuard {
+    provider: SdkTracerProvider,
+}
+
+impl TracingGuard {
+    // Exports finished spans now instead of with the next batch
+    pub fn force_flush(&self) -> Result<()> {
+        self.provider
+            .force_flush()
+            .wrap_err("Failed to export spans")
+    }
+}
+
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            eprintln!("Failed to shut down the tracer provider: {}", e);
+        }
+    }
+}
+
+// W3C `traceparent` and `tracestate` headers continuing the current span's trace,
+// for requests to other services
+pub fn trace_context_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    TraceContextPropagator::new().inject_context(
+        &Span::current().context(),
+        &mut HeaderInjector(&mut headers),
+    );
+    headers
+}
+
+tokio::task_local! {
+    // The span of the request being handled, which the handlers' own spans are nested in
+    static REQUEST_SPAN: Span;
+}
+
+// Lets handlers record `user_id` and `outcome` on the request span from within their own spans,
+// so they end up on the `[REQUEST END]` line
+pub async fn scope_request_span(request: Request, next: Next) -> Response {
+    REQUEST_SPAN.scope(Span::current(), next.run(request)).await
+}
+
+// Identifies the user in the logs without their email address. The email is keyed with the JWT
+// secret, so the id can't be matched against the hashes of candidate emails.
+pub fn record_user(email: &str, jwt_secret: &SecretString) {
+    let mut mac = Hmac::<Sha256>::new_from_slice(jwt_secret.expose_secret().as_bytes())
+        .expect("HMAC takes keys of any length");
+    mac.update(b"user_id.");
+    mac.update(email.trim().to_lowercase().as_bytes());
+    let digest = hex::encode(mac.finalize().into_bytes());
+    record_request_field("user_id", &digest[..16]);
+}
+
+// How the request went in the domain's terms, e.g. `2fa_required` for a login
+pub fn record_outcome(outcome: &'static str) {
+    record_request_field("outcome", outcome);
+}
+
+fn record_request_field(field: &str, value: impl Display) {
+    let _ = REQUEST_SPAN.try_with(|span| {
+        span.record(field, tracing::field::display(value));
+    });
+}
+
+// Makes the span a child of the caller's span when the request carries a `traceparent`
+fn set_remote_parent(span: &Span, headers: &HeaderMap) {
+    let parent = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    if parent.span().span_context().is_valid() {
+        // Only fails when no OpenTelemetry layer is installed
+        let _ = span.set_parent(parent);
+    }
 }
 
 //...
 
 // Creates a new tracing span with a unique request ID for each incoming request.
 // This helps in tracking and correlating logs for individual requests.
+// The ID comes from the `x-request-id` header when the request carries one.
 pub fn make_span_with_request_id(request: &Request<Body>) -> Span {
-    let request_id = uuid::Uuid::new_v4();
-    tracing::span!(
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(ToOwned::to_owned)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let span = tracing::span!(
         Level::INFO,
         "[REQUEST]",
         method = tracing::field::display(request.method()),
         uri = tracing::field::display(request.uri()),
         version = tracing::field::debug(request.version()),
         request_id = tracing::field::display(request_id),
-    )
+        route = tracing::field::display(route_template(request)),
+        // Recorded by the handlers, see `record_user` and `record_outcome`
+        user_id = tracing::field::Empty,
+        outcome = tracing::field::Empty,
+    );
+    set_remote_parent(&span, request.headers());
+    span
 }
 
 // Logs an event indicating the start of a request.
@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use auth_service::settings::{CookieSameSite, Settings};
+use reqwest::{header::SET_COOKIE, Response};
+
+use crate::helpers::{get_random_email, TestApp};
+
+// Signs up a user without 2FA and logs them in
+async fn log_in(app: &TestApp) -> Response {
+    let email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    let response = app
+        .post_login(&serde_json::json!({ "email": email, "password": "password123" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+}
+
+// The auth cookie and the CSRF cookie are set together
+fn set_cookies(response: &Response) -> (&str, &str) {
+    let set_cookie: Vec<_> = response
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .map(|header| header.to_str().unwrap())
+        .collect();
+    assert_eq!(set_cookie.len(), 2, "{:?}", set_cookie);
+    // In no particular order
+    match set_cookie[..] {
+        [csrf_cookie, auth_cookie] | [auth_cookie, csrf_cookie]
+            if csrf_cookie.starts_with("csrf_token=") =>
+        {
+            (auth_cookie, csrf_cookie)
+        }
+        _ => panic!("Expected the auth and CSRF cookies, got {:?}", set_cookie),
+    }
+}
+
+// The token is the only part of the header that changes between logins
+fn token(set_cookie: &str) -> &str {
+    let (_, rest) = set_cookie.split_once('=').unwrap();
+    rest.split(';').next().unwrap()
+}
+
+fn shared_cookie_settings(settings: &mut Settings) {
+    settings.auth.cookie.name = "session".to_owned();
+    settings.auth.cookie.domain = Some("example.com".to_owned());
+    settings.auth.cookie.secure = true;
+    settings.auth.cookie.same_site = CookieSameSite::Strict;
+    settings.auth.cookie.max_age = Some(Duration::from_secs(300));
+}
+
+#[tokio::test]
+async fn login_sets_a_cookie_that_lasts_as_long_as_the_token() {
+    let mut app = TestApp::new().await;
+
+    let response = log_in(&app).await;
+
+    let (auth_cookie, csrf_cookie) = set_cookies(&response);
+    assert_eq!(
+        auth_cookie,
+        format!(
+            "jwt={}; HttpOnly; SameSite=Lax; Path=/; Max-Age=600",
+            token(auth_cookie)
+        )
+    );
+    // Pages read the CSRF cookie, so it isn't `HttpOnly`
+    assert_eq!(
+        csrf_cookie,
+        format!(
+            "csrf_token={}; SameSite=Lax; Path=/; Max-Age=600",
+            token(csrf_cookie)
+        )
+    );
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn login_sets_the_configured_cookie_attributes() {
+    let mut app = TestApp::builder()
+        .with_settings(shared_cookie_settings)
+        .build()
+        .await;
+
+    let response = log_in(&app).await;
+
+    let (auth_cookie, csrf_cookie) = set_cookies(&response);
+    assert_eq!(
+        auth_cookie,
+        format!(
+            "session={}; HttpOnly; SameSite=Strict; Secure; Path=/; Domain=example.com; Max-Age=300",
+            token(auth_cookie)
+        )
+    );
+    assert_eq!(
+        csrf_cookie,
+        format!(
+            "csrf_token={}; SameSite=Strict; Secure; Path=/; Domain=example.com; Max-Age=300",
+            token(csrf_cookie)
+        )
+    );
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn logout_clears_the_configured_cookie() {
+    let mut app = TestApp::builder()
+        .with_settings(shared_cookie_settings)
+        .build()
+        .await;
+    let response = log_in(&app).await;
+    let (auth_cookie, csrf_cookie) = set_cookies(&response);
+    let (token, csrf_token) = (token(auth_cookie).to_owned(), token(csrf_cookie).to_owned());
+
+    // The client's cookie jar keeps `Secure` cookies for HTTPS, so they are sent by hand
+    let response = app
+        .http_client
+        .post(format!("{}/logout", &app.address))
+        .header(
+            "Cookie",
+            format!("session={}; csrf_token={}", token, csrf_token),
+        )
+        .header("X-CSRF-Token", csrf_token)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status().as_u16(), 200);
+    // Browsers only clear a cookie set with the same name, path and domain
+    let (auth_cookie, csrf_cookie) = set_cookies(&response);
+    let expires = auth_cookie
+        .split_once("; Expires=")
+        .expect("No Expires attribute")
+        .1;
+    assert_eq!(
+        auth_cookie,
+        format!(
+            "session=; HttpOnly; SameSite=Strict; Secure; Path=/; Domain=example.com; Max-Age=0; \
+             Expires={}",
+            expires
+        )
+    );
+    assert_eq!(
+        csrf_cookie,
+        format!(
+            "csrf_token=; SameSite=Strict; Secure; Path=/; Domain=example.com; Max-Age=0; \
+             Expires={}",
+            expires
+        )
+    );
+
+    app.clean_up().await;
+}
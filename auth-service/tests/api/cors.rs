@@ -0,0 +1,129 @@
+use reqwest::{header::HeaderMap, Method};
+
+use crate::helpers::{get_random_email, TestApp};
+
+async fn app_allowing(origins: &[&str]) -> TestApp {
+    let origins: Vec<String> = origins.iter().map(|origin| origin.to_string()).collect();
+    TestApp::builder()
+        .with_settings(move |settings| settings.application.allowed_origins = origins)
+        .build()
+        .await
+}
+
+// What a browser sends before a cross-origin JSON POST
+async fn preflight(app: &TestApp, origin: &str) -> reqwest::Response {
+    app.http_client
+        .request(Method::OPTIONS, format!("{}/login", &app.address))
+        .header("Origin", origin)
+        .header("Access-Control-Request-Method", "POST")
+        .header("Access-Control-Request-Headers", "content-type")
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+fn cors_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    let mut cors_headers: Vec<_> = headers
+        .iter()
+        .filter(|(name, _)| name.as_str().starts_with("access-control-") || *name == "vary")
+        .map(|(name, value)| (name.to_string(), value.to_str().unwrap().to_owned()))
+        .collect();
+    cors_headers.sort();
+    cors_headers
+}
+
+fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+#[tokio::test]
+async fn preflights_from_allowed_origins_are_answered() {
+    let mut app = app_allowing(&["http://localhost:8000", "https://*.example.com"]).await;
+
+    for origin in ["http://localhost:8000", "https://app.example.com"] {
+        let response = preflight(&app, origin).await;
+
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(
+            cors_headers(response.headers()),
+            pairs(&[
+                ("access-control-allow-credentials", "true"),
+                (
+                    "access-control-allow-headers",
+                    "content-type,authorization,x-csrf-token"
+                ),
+                ("access-control-allow-methods", "GET,POST"),
+                ("access-control-allow-origin", origin),
+                (
+                    "vary",
+                    "origin, access-control-request-method, access-control-request-headers"
+                ),
+            ])
+        );
+    }
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn preflights_from_other_origins_are_not_allowed() {
+    let mut app = app_allowing(&["http://localhost:8000", "https://*.example.com"]).await;
+
+    for origin in [
+        "http://localhost:8001",
+        "https://example.com",
+        "http://app.example.com",
+        "https://app.example.com.evil.test",
+    ] {
+        let response = preflight(&app, origin).await;
+
+        // Without the allow headers, browsers don't send the request
+        let headers = cors_headers(response.headers());
+        assert!(
+            headers
+                .iter()
+                .all(|(name, _)| name != "access-control-allow-origin"),
+            "{}: {:?}",
+            origin,
+            headers
+        );
+    }
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn responses_to_allowed_origins_can_be_read_with_credentials() {
+    let mut app = app_allowing(&["https://*.example.com"]).await;
+
+    let response = app
+        .http_client
+        .post(format!("{}/signup", &app.address))
+        .header("Origin", "https://eu.app.example.com")
+        .json(&serde_json::json!({
+            "email": get_random_email(),
+            "password": "password123",
+            "requires2FA": false
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status().as_u16(), 201);
+    assert_eq!(
+        cors_headers(response.headers()),
+        pairs(&[
+            ("access-control-allow-credentials", "true"),
+            ("access-control-allow-origin", "https://eu.app.example.com"),
+            (
+                "vary",
+                "origin, access-control-request-method, access-control-request-headers"
+            ),
+        ])
+    );
+
+    app.clean_up().await;
+}
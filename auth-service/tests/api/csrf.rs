@@ -0,0 +1,159 @@
+use auth_service::{routes::LoginResponse, utils::constants::CSRF_COOKIE_NAME, ErrorResponse};
+use secrecy::{ExposeSecret, SecretString};
+
+use crate::helpers::{get_jti, get_random_email, TestApp};
+
+// Signs up a user and logs them in with the cookies, returning the auth token
+async fn log_in(app: &TestApp, email: &str, requires_2fa: bool) -> SecretString {
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": requires_2fa
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+    });
+    let response = app.post_login(&login_body).await;
+    let response = if requires_2fa {
+        assert_eq!(response.status().as_u16(), 206);
+        let login_attempt_id = match response.json::<LoginResponse>().await.unwrap() {
+            LoginResponse::TwoFactorAuth(response) => response.login_attempt_id,
+            other => panic!("Expected 2FA, got {:?}", other),
+        };
+        let two_fa_code = app.wait_for_2fa_code(email, 1).await;
+        app.post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": two_fa_code
+        }))
+        .await
+    } else {
+        response
+    };
+    assert_eq!(response.status().as_u16(), 200);
+
+    let token = response
+        .cookies()
+        .find(|cookie| cookie.name() != CSRF_COOKIE_NAME)
+        .expect("No auth cookie found");
+    SecretString::from(token.value().to_owned())
+}
+
+async fn post_logout_with_csrf_token(app: &TestApp, csrf_token: &str) -> reqwest::Response {
+    app.http_client
+        .post(format!("{}/logout", &app.address))
+        .header("X-CSRF-Token", csrf_token)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn is_banned(app: &TestApp, token: &SecretString) -> bool {
+    app.banned_token_store
+        .contains_token(&get_jti(token.expose_secret()))
+        .await
+        .expect("Failed to check if token is banned")
+}
+
+#[tokio::test]
+async fn login_issues_a_csrf_token_alongside_the_auth_cookie() {
+    let mut app = TestApp::new().await;
+
+    for requires_2fa in [false, true] {
+        log_in(&app, &get_random_email(), requires_2fa).await;
+
+        let csrf_token = app.csrf_token().expect("No CSRF cookie found");
+        assert_eq!(csrf_token.len(), 64);
+    }
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn cookie_authenticated_logout_requires_the_csrf_token() {
+    let mut app = TestApp::new().await;
+    let token = log_in(&app, &get_random_email(), false).await;
+
+    // What a form on another site would send: the cookies, but no header
+    let response = app
+        .http_client
+        .post(format!("{}/logout", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        "Missing or invalid CSRF token".to_owned()
+    );
+    assert!(!is_banned(&app, &token).await);
+
+    let response = post_logout_with_csrf_token(&app, "0123abcd").await;
+    assert_eq!(response.status().as_u16(), 403);
+    assert!(!is_banned(&app, &token).await);
+
+    let response = app.post_logout().await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(is_banned(&app, &token).await);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn csrf_tokens_are_bound_to_their_session() {
+    let mut app = TestApp::new().await;
+    log_in(&app, &get_random_email(), false).await;
+    let old_csrf_token = app.csrf_token().expect("No CSRF cookie found");
+
+    // Logging in again replaces both cookies
+    let token = log_in(&app, &get_random_email(), false).await;
+
+    let response = post_logout_with_csrf_token(&app, &old_csrf_token).await;
+    assert_eq!(response.status().as_u16(), 403);
+    assert!(!is_banned(&app, &token).await);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn bearer_authenticated_logout_is_exempt() {
+    let mut app = TestApp::new().await;
+    let cookie_token = log_in(&app, &get_random_email(), false).await;
+    let email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": email,
+            "password": "password123",
+            "tokenDelivery": "body"
+        }))
+        .await;
+    let bearer_token = match response.json::<LoginResponse>().await.unwrap() {
+        LoginResponse::Token(token) => SecretString::from(token.access_token),
+        other => panic!("Expected a token, got {:?}", other),
+    };
+
+    // The cookies are sent too, but the Bearer token is what the request acts with
+    let response = app
+        .post_logout_with_bearer(bearer_token.expose_secret())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(is_banned(&app, &bearer_token).await);
+    assert!(!is_banned(&app, &cookie_token).await);
+
+    app.clean_up().await;
+}
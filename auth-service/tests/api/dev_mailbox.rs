@@ -0,0 +1,75 @@
+use crate::helpers::{get_random_email, TestApp};
+
+async fn login_with_2fa(app: &TestApp, email: &str) {
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+    });
+    assert_eq!(app.post_login(&login_body).await.status().as_u16(), 206);
+}
+
+#[tokio::test]
+async fn mailbox_shows_sent_emails() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+
+    login_with_2fa(&app, &random_email).await;
+    let code = app.wait_for_2fa_code(&random_email, 1).await;
+
+    let response = app.get_dev_mailbox().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let page = response.text().await.unwrap();
+    assert!(page.contains(&random_email));
+    assert!(page.contains(&code));
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn mailbox_is_not_served_to_proxied_requests() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+
+    login_with_2fa(&app, &random_email).await;
+    app.wait_for_2fa_code(&random_email, 1).await;
+
+    for (name, value) in [
+        ("X-Forwarded-For", "203.0.113.7"),
+        ("Forwarded", "for=203.0.113.7"),
+        ("X-Real-IP", "203.0.113.7"),
+    ] {
+        let response = app
+            .http_client
+            .get(format!("{}/dev/mailbox", &app.address))
+            .header(name, value)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        let page = response.text().await.unwrap();
+        assert!(!page.contains(&random_email), "{}", name);
+    }
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn mailbox_is_not_served_without_the_capture_client() {
+    let mut app = TestApp::builder().with_postmark().build().await;
+
+    let response = app.get_dev_mailbox().await;
+
+    // Unknown paths fall back to the UI, so only the mailbox page itself is checked for
+    let page = response.text().await.unwrap();
+    assert!(!page.contains("<title>Mailbox</title>"));
+
+    app.clean_up().await;
+}
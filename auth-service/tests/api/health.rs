@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use auth_service::{
+    domain::HealthCheck,
+    services::health_checks::{DependencyStatus, ReadinessReport, ReadinessStatus},
+};
+use color_eyre::eyre::{eyre, Result};
+
+use crate::helpers::TestApp;
+
+// A dependency that refuses connections, or never answers
+enum BrokenDependency {
+    Down,
+    Hangs,
+}
+
+#[async_trait::async_trait]
+impl HealthCheck for BrokenDependency {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Down => "down",
+            Self::Hangs => "hangs",
+        }
+    }
+
+    async fn check(&self) -> Result<()> {
+        match self {
+            Self::Down => Err(eyre!("connection refused")),
+            Self::Hangs => std::future::pending().await,
+        }
+    }
+}
+
+#[tokio::test]
+async fn live_returns_200() {
+    let mut app = TestApp::new().await;
+
+    let response = app.get_health("live").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body, serde_json::json!({ "status": "live" }));
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn ready_returns_200_when_every_dependency_is_up() {
+    let mut app = TestApp::new().await;
+
+    let response = app.get_health("ready").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let report = response.json::<ReadinessReport>().await.unwrap();
+    assert_eq!(report.status, ReadinessStatus::Ready);
+    assert!(report
+        .checks
+        .values()
+        .all(|check| check.status == DependencyStatus::Up));
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "backend-tests"), ignore = "needs Postgres and Redis")]
+async fn ready_probes_postgres_and_redis() {
+    let mut app = TestApp::builder().with_postgres_and_redis().build().await;
+
+    let response = app.get_health("ready").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let report = response.json::<ReadinessReport>().await.unwrap();
+    assert_eq!(
+        report.checks.keys().collect::<Vec<_>>(),
+        vec!["postgres", "redis"]
+    );
+    assert_eq!(report.checks["postgres"].status, DependencyStatus::Up);
+    assert_eq!(report.checks["redis"].status, DependencyStatus::Up);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn ready_returns_503_when_a_dependency_is_down_or_too_slow() {
+    let mut app = TestApp::builder()
+        .with_health_check(Arc::new(BrokenDependency::Down))
+        .with_health_check(Arc::new(BrokenDependency::Hangs))
+        .build()
+        .await;
+
+    let response = app.get_health("ready").await;
+
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "not_ready");
+    assert_eq!(
+        body["checks"]["down"],
+        serde_json::json!({ "status": "down", "error": "unavailable" })
+    );
+    assert_eq!(
+        body["checks"]["hangs"],
+        serde_json::json!({ "status": "down", "error": "timed out" })
+    );
+
+    // Liveness doesn't depend on them
+    assert_eq!(app.get_health("live").await.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
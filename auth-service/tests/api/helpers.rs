@@ -1,72 +1,282 @@
-use std::{str::FromStr, sync::Arc};
+use std::{path::Path, str::FromStr, sync::Arc};
 
 use auth_service::{
-    app_state::{AppState, BannedTokenStoreType, TwoFACodeStoreType},
-    domain::Email,
-    get_postgres_pool, get_redis_client,
+    app_state::{
+        AppState, BannedTokenStoreType, EmailClientType, EmailOutboxStoreType, HealthCheckType,
+        TwoFACodeStoreType, UserStoreType, WebhookStoreType,
+    },
+    domain::{WebhookEventType, WebhookSubscription},
+    get_postgres_pool, get_redis_connection,
     services::{
-        data_stores::{PostgresUserStore, RedisBannedTokenStore, RedisTwoFACodeStore},
+        capturing_email_client::{CapturingEmailClient, SentEmail},
+        data_stores::{
+            HashMapEmailOutboxStore, HashMapTwoFACodeStore, HashMapUserStore, HashMapWebhookStore,
+            HashSetBannedTokenStore, Instrumented, PostgresEmailOutboxStore, PostgresUserStore,
+            PostgresWebhookStore, RedisBannedTokenStore, RedisConnection, RedisTwoFACodeStore,
+        },
+        email_outbox::EmailOutbox,
+        email_templates::EmailTemplates,
+        fake_clock::FakeClock,
+        fake_id_generator::FakeIdGenerator,
+        health_checks::{HealthChecks, PostgresHealthCheck, RedisHealthCheck},
         postmark_email_client::PostmarkEmailClient,
+        webhooks::{WebhookPublisher, WebhookWorker},
+    },
+    settings::{
+        DatabaseSettings, EmailSettings, RedisSettings, Settings, StorageBackend, WebhookSettings,
+        DEFAULT_CONFIG_DIR,
+    },
+    utils::{
+        constants::{test, CSRF_COOKIE_NAME, CSRF_HEADER_NAME},
+        shutdown::{BackgroundWorkers, Shutdown},
     },
-    utils::constants::{test, DATABASE_URL, DEFAULT_REDIS_HOSTNAME},
     Application,
 };
-use reqwest::{cookie::Jar, Client};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use reqwest::{
+    cookie::{CookieStore, Jar},
+    Client, Url,
+};
 use secrecy::{ExposeSecret, SecretString};
 use sqlx::{
     postgres::{PgConnectOptions, PgPoolOptions},
     Connection, Executor, PgConnection, PgPool,
 };
-use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
 use uuid::Uuid;
 use wiremock::MockServer;
 
 pub struct TestApp {
     pub address: String,
+    // Where `/metrics` is served when the app was built with its own metrics port
+    pub metrics_address: Option<String>,
     pub cookie_jar: Arc<Jar>,
     pub http_client: reqwest::Client,
     pub two_fa_code_store: TwoFACodeStoreType,
     pub banned_token_store: BannedTokenStoreType,
-    pub email_server: MockServer,
-    pub db_name: String,
+    pub webhook_store: WebhookStoreType,
+    // The app's time and login attempt ids / 2FA codes, which tests can control
+    pub clock: Arc<FakeClock>,
+    pub id_generator: Arc<FakeIdGenerator>,
+    // Every email the app sent, unless it was built to send them through the Postmark mock
+    pub emails: Arc<CapturingEmailClient>,
+    pub email_server: Option<MockServer>,
+    pub webhook_server: MockServer,
+    pub settings: Settings,
+    // The database created for this test, when the app runs on Postgres
+    database: Option<(String, PgPool)>,
     pub clean_up_called: bool,
+    // Starts shutting the app down like a SIGTERM would, see `TestApp::shut_down`
+    pub shutdown: Shutdown,
+    server: Option<JoinHandle<Result<(), std::io::Error>>>,
+    workers: Option<BackgroundWorkers>,
 }
 
-impl TestApp {
-    pub async fn new() -> Self {
-        // We are creating a new database for each test case, and we need to ensure each database has a unique name!
-        let db_name = Uuid::new_v4().to_string();
-        let clean_up_called = false;
-        let pg_pool = configure_postgresql(&db_name).await;
-
-        let redis_connection = Arc::new(RwLock::new(configure_redis()));
-        let user_store = Arc::new(RwLock::new(PostgresUserStore::new(pg_pool)));
-        let banned_token_store = Arc::new(RwLock::new(RedisBannedTokenStore::new(
-            redis_connection.clone(),
-        )));
-        let two_fa_code_store = Arc::new(RwLock::new(RedisTwoFACodeStore::new(
-            redis_connection.clone(),
-        )));
-        // Set up a mock email server
-        let email_server = MockServer::start().await; // New!
-        let base_url = email_server.uri(); // New!
-        let email_client = Arc::new(configure_postmark_email_client(base_url)); // Updated!
-        let app_state = AppState::new(
-            user_store.clone(),
-            banned_token_store.clone(),
-            two_fa_code_store.clone(),
-            email_client.clone(),
+// Assembles a `TestApp`. By default every store is in memory, so no other service is needed;
+// with the `backend-tests` feature the default is Postgres and Redis instead.
+pub struct TestAppBuilder {
+    postgres_and_redis: bool,
+    postmark: bool,
+    metrics_port: bool,
+    wrap_user_store: Box<dyn FnOnce(UserStoreType) -> UserStoreType>,
+    wrap_two_fa_code_store: Box<dyn FnOnce(TwoFACodeStoreType) -> TwoFACodeStoreType>,
+    wrap_email_outbox_store: Box<dyn FnOnce(EmailOutboxStoreType) -> EmailOutboxStoreType>,
+    wrap_webhook_store: Box<dyn FnOnce(WebhookStoreType) -> WebhookStoreType>,
+    health_checks: Vec<HealthCheckType>,
+    configure: Box<dyn FnOnce(&mut Settings)>,
+}
+
+impl Default for TestAppBuilder {
+    fn default() -> Self {
+        Self {
+            postgres_and_redis: cfg!(feature = "backend-tests"),
+            postmark: false,
+            metrics_port: false,
+            wrap_user_store: Box::new(|user_store| user_store),
+            wrap_two_fa_code_store: Box::new(|two_fa_code_store| two_fa_code_store),
+            wrap_email_outbox_store: Box::new(|email_outbox_store| email_outbox_store),
+            wrap_webhook_store: Box::new(|webhook_store| webhook_store),
+            health_checks: Vec::new(),
+            configure: Box::new(|_| {}),
+        }
+    }
+}
+
+// The stores the app is built with
+struct TestStores {
+    user_store: UserStoreType,
+    banned_token_store: BannedTokenStoreType,
+    two_fa_code_store: TwoFACodeStoreType,
+    email_outbox_store: EmailOutboxStoreType,
+    webhook_store: WebhookStoreType,
+    // Probes of the external services the stores use
+    health_checks: Vec<HealthCheckType>,
+}
+
+impl TestAppBuilder {
+    // Keeps users, emails and webhooks in a new Postgres database, and tokens and 2FA codes in Redis
+    pub fn with_postgres_and_redis(mut self) -> Self {
+        self.postgres_and_redis = true;
+        self
+    }
+
+    // Sends emails to the Postmark API mocked by `TestApp::email_server` instead of capturing them
+    pub fn with_postmark(mut self) -> Self {
+        self.postmark = true;
+        self
+    }
+
+    // Serves `/metrics` on a port of its own rather than next to the API
+    pub fn with_metrics_port(mut self) -> Self {
+        self.metrics_port = true;
+        self
+    }
+
+    // Lets a test wrap the user store the app is built with, e.g. to observe how it is called
+    pub fn with_user_store(
+        mut self,
+        wrap: impl FnOnce(UserStoreType) -> UserStoreType + 'static,
+    ) -> Self {
+        self.wrap_user_store = Box::new(wrap);
+        self
+    }
+
+    // Same as `with_user_store`, for the 2FA code store
+    pub fn with_two_fa_code_store(
+        mut self,
+        wrap: impl FnOnce(TwoFACodeStoreType) -> TwoFACodeStoreType + 'static,
+    ) -> Self {
+        self.wrap_two_fa_code_store = Box::new(wrap);
+        self
+    }
+
+    // Same as `with_user_store`, for the email outbox store
+    pub fn with_email_outbox_store(
+        mut self,
+        wrap: impl FnOnce(EmailOutboxStoreType) -> EmailOutboxStoreType + 'static,
+    ) -> Self {
+        self.wrap_email_outbox_store = Box::new(wrap);
+        self
+    }
+
+    // Same as `with_user_store`, for the webhook store
+    pub fn with_webhook_store(
+        mut self,
+        wrap: impl FnOnce(WebhookStoreType) -> WebhookStoreType + 'static,
+    ) -> Self {
+        self.wrap_webhook_store = Box::new(wrap);
+        self
+    }
+
+    // Changes the test settings before the app is built, e.g. the cookie attributes
+    pub fn with_settings(mut self, configure: impl FnOnce(&mut Settings) + 'static) -> Self {
+        self.configure = Box::new(configure);
+        self
+    }
+
+    // Adds a dependency for `/health/ready` to probe
+    pub fn with_health_check(mut self, health_check: HealthCheckType) -> Self {
+        self.health_checks.push(health_check);
+        self
+    }
+
+    pub async fn build(self) -> TestApp {
+        let mut settings = test_settings();
+        if self.metrics_port {
+            settings.application.metrics_address = Some("127.0.0.1:0".to_owned());
+        }
+        (self.configure)(&mut settings);
+        let clock = Arc::new(FakeClock::default());
+        let id_generator = Arc::new(FakeIdGenerator::default());
+
+        let (stores, database) = if self.postgres_and_redis {
+            // We are creating a new database for each test case, and we need to ensure each database has a unique name!
+            let db_name = Uuid::new_v4().to_string();
+            let pg_pool = configure_postgresql(&settings.database, &db_name).await;
+            let redis_connection = configure_redis(&settings.redis).await;
+            let stores = postgres_and_redis_stores(&pg_pool, redis_connection, &clock);
+            (stores, Some((db_name, pg_pool)))
+        } else {
+            (in_memory_stores(&clock), None)
+        };
+        let user_store = (self.wrap_user_store)(stores.user_store);
+        let two_fa_code_store = (self.wrap_two_fa_code_store)(stores.two_fa_code_store);
+        let mut health_checks = stores.health_checks;
+        health_checks.extend(self.health_checks);
+        let health_checks = Arc::new(HealthChecks::new(health_checks, settings.health.timeout));
+
+        let emails = Arc::new(CapturingEmailClient::default().with_clock(clock.clone()));
+        let (email_client, email_server): (EmailClientType, _) = if self.postmark {
+            // Set up a mock email server
+            let email_server = MockServer::start().await;
+            let email_client = configure_postmark_email_client(&settings.email, email_server.uri());
+            (Arc::new(email_client), Some(email_server))
+        } else {
+            (emails.clone(), None)
+        };
+        let email_outbox_store = (self.wrap_email_outbox_store)(stores.email_outbox_store);
+        let email_outbox = Arc::new(
+            EmailOutbox::new(email_outbox_store)
+                .with_clock(clock.clone())
+                .with_id_generator(id_generator.clone()),
+        );
+        let mut workers = BackgroundWorkers::default();
+        let email_outbox_worker = email_outbox.worker(
+            email_client,
+            settings.email_outbox.retry,
+            settings.email_outbox.poll_interval,
         );
-        let app = Application::build(app_state, test::APP_ADDRESS)
+        workers.spawn("email_outbox", |shutdown| email_outbox_worker.run(shutdown));
+
+        let email_templates = Arc::new(EmailTemplates::new(settings.email.brand.clone()));
+
+        // Set up a mock webhook receiver subscribed to every event
+        let webhook_server = MockServer::start().await;
+        let webhook_subscriptions = vec![webhook_subscription(&webhook_server)];
+        let webhook_store = (self.wrap_webhook_store)(stores.webhook_store);
+        let webhook_publisher = Arc::new(
+            WebhookPublisher::new(webhook_subscriptions.clone(), webhook_store.clone())
+                .with_clock(clock.clone())
+                .with_id_generator(id_generator.clone()),
+        );
+        let webhook_worker = configure_webhook_worker(
+            &settings.webhooks,
+            webhook_subscriptions,
+            webhook_store.clone(),
+        )
+        .with_clock(clock.clone());
+        workers.spawn("webhooks", |shutdown| webhook_worker.run(shutdown));
+
+        let shutdown = Shutdown::default();
+        let mut app_state = AppState::new(
+            user_store,
+            stores.banned_token_store.clone(),
+            two_fa_code_store.clone(),
+            email_outbox,
+            email_templates,
+            webhook_publisher,
+            Arc::new(settings.auth.clone()),
+        )
+        .with_clock(clock.clone())
+        .with_id_generator(id_generator.clone())
+        .with_health_checks(health_checks)
+        .with_shutdown(shutdown.clone());
+        if email_server.is_none() {
+            app_state = app_state.with_mailbox(emails.clone());
+        }
+        let app = Application::build(app_state, &settings.application)
             .await
             .expect("Failed to build app");
 
         let address = format!("http://{}", app.address.clone());
+        let metrics_address = app
+            .metrics_address
+            .as_ref()
+            .map(|address| format!("http://{}", address));
 
         // Run the auth service in a separate async task
         // to avoid blocking the main test thread.
-        #[allow(clippy::let_underscore_future)]
-        let _ = tokio::spawn(app.run());
+        let server = tokio::spawn(app.run());
 
         let cookie_jar = Arc::new(Jar::default());
         let http_client = Client::builder()
@@ -77,19 +287,137 @@ impl TestApp {
         // Create new `TestApp` instance and return it
         TestApp {
             address,
+            metrics_address,
             cookie_jar,
             http_client,
             two_fa_code_store,
-            banned_token_store,
-            email_server, // New!
-            db_name,
-            clean_up_called,
+            banned_token_store: stores.banned_token_store,
+            webhook_store,
+            clock,
+            id_generator,
+            emails,
+            email_server,
+            webhook_server,
+            settings,
+            database,
+            clean_up_called: false,
+            shutdown,
+            server: Some(server),
+            workers: Some(workers),
         }
     }
+}
+
+// The stores are instrumented like the binary's, so their latencies show up in `/metrics`
+fn in_memory_stores(clock: &Arc<FakeClock>) -> TestStores {
+    let memory = StorageBackend::Memory;
+    let webhook_store: WebhookStoreType =
+        Arc::new(Instrumented::new(HashMapWebhookStore::default(), memory));
+    TestStores {
+        user_store: Arc::new(Instrumented::new(HashMapUserStore::default(), memory)),
+        banned_token_store: Arc::new(Instrumented::new(
+            HashSetBannedTokenStore::default().with_clock(clock.clone()),
+            memory,
+        )),
+        two_fa_code_store: Arc::new(Instrumented::new(
+            HashMapTwoFACodeStore::default().with_clock(clock.clone()),
+            memory,
+        )),
+        email_outbox_store: Arc::new(Instrumented::new(
+            HashMapEmailOutboxStore::default(),
+            memory,
+        )),
+        webhook_store,
+        health_checks: Vec::new(),
+    }
+}
+
+fn postgres_and_redis_stores(
+    pg_pool: &PgPool,
+    redis_connection: RedisConnection,
+    clock: &Arc<FakeClock>,
+) -> TestStores {
+    let (postgres, redis) = (StorageBackend::Postgres, StorageBackend::Redis);
+    TestStores {
+        user_store: Arc::new(Instrumented::new(
+            PostgresUserStore::new(pg_pool.clone()),
+            postgres,
+        )),
+        banned_token_store: Arc::new(Instrumented::new(
+            RedisBannedTokenStore::new(redis_connection.clone()),
+            redis,
+        )),
+        two_fa_code_store: Arc::new(Instrumented::new(
+            RedisTwoFACodeStore::new(redis_connection.clone()).with_clock(clock.clone()),
+            redis,
+        )),
+        email_outbox_store: Arc::new(Instrumented::new(
+            PostgresEmailOutboxStore::new(pg_pool.clone()),
+            postgres,
+        )),
+        webhook_store: Arc::new(Instrumented::new(
+            PostgresWebhookStore::new(pg_pool.clone()),
+            postgres,
+        )),
+        health_checks: vec![
+            Arc::new(PostgresHealthCheck::new(pg_pool.clone())),
+            Arc::new(RedisHealthCheck::new(redis_connection)),
+        ],
+    }
+}
+
+impl TestApp {
+    pub async fn new() -> Self {
+        Self::builder().build().await
+    }
+
+    pub fn builder() -> TestAppBuilder {
+        TestAppBuilder::default()
+    }
+
+    pub fn email_server(&self) -> &MockServer {
+        self.email_server
+            .as_ref()
+            .expect("TestApp was built without the Postmark mock, see `with_postmark`")
+    }
+
+    pub fn pg_pool(&self) -> PgPool {
+        let (_, pg_pool) = self
+            .database
+            .as_ref()
+            .expect("TestApp was built without Postgres, see `with_postgres_and_redis`");
+        pg_pool.clone()
+    }
 
     pub async fn get_root(&self) -> reqwest::Response {
         self.http_client
-            .get(&format!("{}/", &self.address))
+            .get(format!("{}/", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_dev_mailbox(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/dev/mailbox", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // Scrapes `/metrics` wherever the app serves it
+    pub async fn get_metrics(&self) -> reqwest::Response {
+        let address = self.metrics_address.as_ref().unwrap_or(&self.address);
+        self.http_client
+            .get(format!("{}/metrics", address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_health(&self, probe: &str) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/health/{}", &self.address, probe))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -100,7 +428,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.http_client
-            .post(&format!("{}/signup", &self.address))
+            .post(format!("{}/signup", &self.address))
             .json(body)
             .send()
             .await
@@ -112,7 +440,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.http_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
             .json(body)
             .send()
             .await
@@ -124,16 +452,27 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.http_client
-            .post(&format!("{}/verify-2fa", &self.address))
+            .post(format!("{}/verify-2fa", &self.address))
             .json(body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
+    // Sends the CSRF token from the cookie jar when there is one, like the pages do
     pub async fn post_logout(&self) -> reqwest::Response {
+        let mut request = self.http_client.post(format!("{}/logout", &self.address));
+        if let Some(csrf_token) = self.csrf_token() {
+            request = request.header(CSRF_HEADER_NAME, csrf_token);
+        }
+
+        request.send().await.expect("Failed to execute request.")
+    }
+
+    pub async fn post_logout_with_bearer(&self, token: &str) -> reqwest::Response {
         self.http_client
-            .post(&format!("{}/logout", &self.address))
+            .post(format!("{}/logout", &self.address))
+            .bearer_auth(token)
             .send()
             .await
             .expect("Failed to execute request.")
@@ -151,12 +490,138 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_verify_token_with_bearer(&self, token: &str) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/verify-token", &self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // The value of the CSRF cookie set by the last login
+    pub fn csrf_token(&self) -> Option<String> {
+        let url = Url::parse(&self.address).expect("Failed to parse URL");
+        let cookies = self.cookie_jar.cookies(&url)?;
+
+        cookies.to_str().ok()?.split("; ").find_map(|cookie| {
+            let (name, value) = cookie.split_once('=')?;
+            (name == CSRF_COOKIE_NAME).then(|| value.to_owned())
+        })
+    }
+
+    // Emails and webhooks are sent by background workers, so tests poll for them
+    pub async fn wait_for_emails(&self, count: usize) -> Vec<SentEmail> {
+        for _ in 0..100 {
+            let sent = self.emails.sent();
+            if sent.len() >= count {
+                return sent;
+            }
+            tokio::time::sleep(test::email_outbox::POLL_INTERVAL).await;
+        }
+        panic!("Timed out waiting for {} emails", count);
+    }
+
+    pub async fn wait_for_emails_to(&self, recipient: &str, count: usize) -> Vec<SentEmail> {
+        for _ in 0..100 {
+            let sent = self.emails.emails_to(recipient);
+            if sent.len() >= count {
+                return sent;
+            }
+            tokio::time::sleep(test::email_outbox::POLL_INTERVAL).await;
+        }
+        panic!("Timed out waiting for {} emails to {}", count, recipient);
+    }
+
+    // The 2FA code in the `nth` email sent to `recipient`, counting from 1
+    pub async fn wait_for_2fa_code(&self, recipient: &str, nth: usize) -> String {
+        self.wait_for_emails_to(recipient, nth).await[nth - 1]
+            .code()
+            .expect("No 2FA code in the email")
+    }
+
+    pub async fn wait_for_postmark_requests(&self, count: usize) -> Vec<wiremock::Request> {
+        wait_for_requests(
+            self.email_server(),
+            count,
+            test::email_outbox::POLL_INTERVAL,
+        )
+        .await
+    }
+
+    pub async fn wait_for_webhooks(&self, count: usize) -> Vec<wiremock::Request> {
+        wait_for_requests(&self.webhook_server, count, test::webhooks::POLL_INTERVAL).await
+    }
+
+    // Failed attempts are only retried once the clock says they are due, so it is moved past
+    // the longest retry delay while waiting
+    pub async fn wait_for_retried_postmark_requests(&self, count: usize) -> Vec<wiremock::Request> {
+        let retry_delay = self.settings.email_outbox.retry.max_delay;
+        self.wait_for_retried_requests(
+            self.email_server(),
+            count,
+            retry_delay,
+            test::email_outbox::POLL_INTERVAL,
+        )
+        .await
+    }
+
+    pub async fn wait_for_retried_webhooks(&self, count: usize) -> Vec<wiremock::Request> {
+        let retry_delay = self.settings.webhooks.retry.max_delay;
+        self.wait_for_retried_requests(
+            &self.webhook_server,
+            count,
+            retry_delay,
+            test::webhooks::POLL_INTERVAL,
+        )
+        .await
+    }
+
+    async fn wait_for_retried_requests(
+        &self,
+        server: &MockServer,
+        count: usize,
+        retry_delay: std::time::Duration,
+        poll_interval: std::time::Duration,
+    ) -> Vec<wiremock::Request> {
+        let retry_delay = chrono::Duration::from_std(retry_delay).unwrap();
+        for _ in 0..100 {
+            let received = server
+                .received_requests()
+                .await
+                .expect("Request recording is disabled");
+            if received.len() >= count {
+                return received;
+            }
+            self.clock.advance(retry_delay);
+            tokio::time::sleep(poll_interval).await;
+        }
+        panic!("Timed out waiting for {} requests", count);
+    }
+
+    // Shuts the app down like `main` does on SIGTERM, returning once the server has drained
+    // and the background workers have stopped
+    pub async fn shut_down(&mut self) {
+        self.shutdown.trigger();
+        if let Some(server) = self.server.take() {
+            server
+                .await
+                .expect("The server panicked")
+                .expect("The server failed");
+        }
+        if let Some(workers) = self.workers.take() {
+            workers.stop(self.settings.application.drain_timeout).await;
+        }
+    }
+
     pub async fn clean_up(&mut self) {
         if self.clean_up_called {
             return;
         }
 
-        delete_database(&self.db_name).await;
+        if let Some((db_name, _)) = &self.database {
+            delete_database(&self.settings.database, db_name).await;
+        }
 
         self.clean_up_called = true;
     }
@@ -164,16 +629,42 @@ impl TestApp {
 
 impl Drop for TestApp {
     fn drop(&mut self) {
-        if !self.clean_up_called {
+        // Only a test database would be left behind
+        if self.database.is_some() && !self.clean_up_called {
             panic!("TestApp::clean_up was not called before dropping TestApp");
         }
     }
 }
 
-async fn configure_postgresql(db_name: &str) -> PgPool {
-    let postgresql_conn_url = DATABASE_URL.expose_secret();
+async fn wait_for_requests(
+    server: &MockServer,
+    count: usize,
+    poll_interval: std::time::Duration,
+) -> Vec<wiremock::Request> {
+    for _ in 0..100 {
+        let received = server
+            .received_requests()
+            .await
+            .expect("Request recording is disabled");
+        if received.len() >= count {
+            return received;
+        }
+        tokio::time::sleep(poll_interval).await;
+    }
+    panic!("Timed out waiting for {} requests", count);
+}
+
+// Settings come from `config/test.toml`; `APP__*` variables can still point the tests
+// at another Postgres or Redis instance
+pub fn test_settings() -> Settings {
+    Settings::load_from(Path::new(DEFAULT_CONFIG_DIR), "test", std::env::vars())
+        .expect("Failed to load test settings")
+}
 
-    configure_database(postgresql_conn_url, &db_name).await;
+async fn configure_postgresql(settings: &DatabaseSettings, db_name: &str) -> PgPool {
+    let postgresql_conn_url = settings.url.expose_secret();
+
+    configure_database(postgresql_conn_url, db_name).await;
 
     let postgresql_conn_url_with_db =
         SecretString::new(format!("{}/{}", postgresql_conn_url, db_name).into_boxed_str());
@@ -212,8 +703,8 @@ async fn configure_database(db_conn_string: &str, db_name: &str) {
         .expect("Failed to migrate the database");
 }
 
-async fn delete_database(db_name: &str) {
-    let postgresql_conn_url = DATABASE_URL.expose_secret();
+async fn delete_database(settings: &DatabaseSettings, db_name: &str) {
+    let postgresql_conn_url = settings.url.expose_secret();
 
     let connection_options = PgConnectOptions::from_str(postgresql_conn_url)
         .expect("Failed to parse PostgreSQL connection string");
@@ -239,9 +730,9 @@ async fn delete_database(db_name: &str) {
         .await
         .expect("Failed to drop the database.");
 
-    // Drop the database
+    // Drop the database, forcefully since background workers may reconnect in the meantime
     connection
-        .execute(format!(r#"DROP DATABASE "{}";"#, db_name).as_str())
+        .execute(format!(r#"DROP DATABASE "{}" WITH (FORCE);"#, db_name).as_str())
         .await
         .expect("Failed to drop the database.");
 }
@@ -250,28 +741,72 @@ pub fn get_random_email() -> String {
     format!("{}@example.com", Uuid::new_v4())
 }
 
-fn configure_redis() -> redis::Connection {
-    let redis_hostname = DEFAULT_REDIS_HOSTNAME.to_owned();
+// Reads the `jti` claim tokens are banned by, without verifying the token
+pub fn get_jti(token: &str) -> String {
+    let payload = token.split('.').nth(1).expect("JWT has a payload");
+    let claims: serde_json::Value =
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).expect("payload is base64"))
+            .expect("payload is JSON");
+    claims["jti"].as_str().expect("JWT has a jti").to_owned()
+}
 
-    get_redis_client(redis_hostname)
-        .expect("Failed to get Redis client")
-        .get_connection()
-        .expect("Failed to get Redis connection")
+async fn configure_redis(settings: &RedisSettings) -> RedisConnection {
+    get_redis_connection(settings)
+        .await
+        .expect("Failed to connect to Redis")
 }
 
 // New!
-fn configure_postmark_email_client(base_url: String) -> PostmarkEmailClient {
+fn configure_postmark_email_client(
+    settings: &EmailSettings,
+    base_url: String,
+) -> PostmarkEmailClient {
     let postmark_auth_token = SecretString::new("auth_token".to_owned().into_boxed_str());
 
-    let sender = Email::parse(SecretString::new(
-        test::email_client::SENDER.to_owned().into_boxed_str(),
-    ))
-    .unwrap();
+    let sender = settings.sender().unwrap();
 
     let http_client = Client::builder()
-        .timeout(test::email_client::TIMEOUT)
+        .timeout(settings.timeout)
         .build()
         .expect("Failed to build HTTP client");
 
     PostmarkEmailClient::new(base_url, sender, postmark_auth_token, http_client)
 }
+
+// `email.brand.name` in `config/test.toml`
+pub const TEST_EMAIL_BRAND_NAME: &str = "Test Brand";
+
+pub const TEST_WEBHOOK_SECRET: &str = "webhook-secret";
+
+fn webhook_subscription(webhook_server: &MockServer) -> WebhookSubscription {
+    WebhookSubscription {
+        id: "test".to_owned(),
+        url: format!("{}/webhooks", webhook_server.uri()),
+        secret: SecretString::new(TEST_WEBHOOK_SECRET.to_owned().into_boxed_str()),
+        events: vec![
+            WebhookEventType::UserSignedUp,
+            WebhookEventType::TwoFactorEnabled,
+            WebhookEventType::PasswordChanged,
+            WebhookEventType::UserDeleted,
+        ],
+    }
+}
+
+fn configure_webhook_worker(
+    settings: &WebhookSettings,
+    subscriptions: Vec<WebhookSubscription>,
+    webhook_store: WebhookStoreType,
+) -> WebhookWorker {
+    let http_client = Client::builder()
+        .timeout(settings.timeout)
+        .build()
+        .expect("Failed to build HTTP client");
+
+    WebhookWorker::new(
+        subscriptions,
+        webhook_store,
+        http_client,
+        settings.retry,
+        settings.poll_interval,
+    )
+}
@@ -1,11 +1,21 @@
-use crate::helpers::{get_random_email, TestApp};
+use std::{sync::Arc, time::Duration};
+
+use crate::helpers::{get_random_email, TestApp, TEST_EMAIL_BRAND_NAME};
 use auth_service::{
-    domain::Email,
-    routes::{LoginResponse, TwoFactorAuthResponse},
+    app_state::{EmailOutboxStoreType, TwoFACodeStoreType, UserStoreType},
+    domain::{
+        Email, EmailOutboxStore, EmailOutboxStoreError, LoginAttemptId, OutboxEmail, TwoFACode,
+        TwoFACodeStore, TwoFACodeStoreError, User, UserStore, UserStoreError,
+    },
+    routes::{LoginResponse, TokenResponse, TwoFactorAuthResponse},
     utils::constants::JWT_COOKIE_NAME,
     ErrorResponse,
 };
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::eyre;
 use secrecy::{ExposeSecret, SecretString};
+use tokio::{sync::Barrier, task::JoinSet};
+use uuid::Uuid;
 use wiremock::{
     matchers::{method, path},
     Mock, ResponseTemplate,
@@ -56,6 +66,57 @@ async fn should_return_200_if_valid_credentials_and_2fa_disabled() {
     app.clean_up().await;
 }
 
+#[tokio::test]
+async fn should_return_token_in_body_if_requested() {
+    let mut app = TestApp::new().await;
+
+    let random_email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": false
+    });
+
+    let response = app.post_signup(&signup_body).await;
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "tokenDelivery": "body"
+    });
+
+    let response = app.post_login(&login_body).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.cookies().count(), 0);
+
+    let token = match response
+        .json::<LoginResponse>()
+        .await
+        .expect("Could not deserialize response body to LoginResponse")
+    {
+        LoginResponse::Token(token) => token,
+        other => panic!("Expected a token, got {:?}", other),
+    };
+    assert_eq!(
+        token,
+        TokenResponse {
+            access_token: token.access_token.clone(),
+            token_type: "Bearer".to_owned(),
+            expires_in: 600,
+        }
+    );
+
+    let response = app.post_verify_token_with_bearer(&token.access_token).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
+
 #[tokio::test]
 async fn should_return_422_if_malformed_credentials() {
     let app = TestApp::new().await;
@@ -70,6 +131,11 @@ async fn should_return_422_if_malformed_credentials() {
         serde_json::json!({
             "password": "password123",
         }),
+        serde_json::json!({
+            "email": random_email,
+            "password": "password123",
+            "tokenDelivery": "header",
+        }),
     ];
 
     for test_case in test_cases.iter() {
@@ -206,14 +272,6 @@ async fn should_return_206_if_valid_credentials_and_2fa_enabled() {
 
     assert_eq!(response.status().as_u16(), 201);
 
-    // Define an expectation for the mock server
-    Mock::given(path("/email")) // Expect an HTTP request to the "/email" path
-        .and(method("POST")) // Expect the HTTP method to be POST
-        .respond_with(ResponseTemplate::new(200)) // Respond with an HTTP 200 OK status
-        .expect(1) // Expect this request to be made exactly once
-        .mount(&app.email_server) // Mount this expectation on the mock email server
-        .await; // Await the asynchronous operation to ensure the mock server is set up before proceeding
-
     let login_body = serde_json::json!({
         "email": random_email,
         "password": "password123",
@@ -233,8 +291,6 @@ async fn should_return_206_if_valid_credentials_and_2fa_enabled() {
 
     let two_fa_code_store = app.two_fa_code_store.clone();
     let code = two_fa_code_store
-        .read()
-        .await
         .get_code(
             &Email::parse(SecretString::new(random_email.to_owned().into_boxed_str())).unwrap(),
         )
@@ -246,6 +302,354 @@ async fn should_return_206_if_valid_credentials_and_2fa_enabled() {
         code.0.as_ref().expose_secret().to_string()
     );
 
+    // The email is sent in the background, with the code kept in the store
+    let sent = app.wait_for_emails(1).await;
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].recipient, random_email);
+    assert_eq!(
+        sent[0].code(),
+        Some(code.1.as_ref().expose_secret().to_owned())
+    );
+
+    let mut app = app;
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_206_and_retry_email_if_email_provider_fails() {
+    let app = TestApp::builder().with_postmark().build().await;
+
+    let random_email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": true
+    });
+
+    let response = app.post_signup(&signup_body).await;
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    // The provider fails the first attempt and accepts the retry
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(app.email_server())
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(app.email_server())
+        .await;
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+    });
+
+    let response = app.post_login(&login_body).await;
+
+    assert_eq!(response.status().as_u16(), 206);
+
+    let received = app.wait_for_retried_postmark_requests(2).await;
+    assert_eq!(received[0].body, received[1].body);
+
+    let mut app = app;
+    app.clean_up().await;
+}
+
+async fn login_with_language(
+    app: &TestApp,
+    email: &str,
+    accept_language: &str,
+) -> reqwest::Response {
+    app.http_client
+        .post(format!("{}/login", &app.address))
+        .header("Accept-Language", accept_language)
+        .json(&serde_json::json!({
+            "email": email,
+            "password": "password123",
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn should_send_2fa_email_in_accept_language() {
+    let app = TestApp::new().await;
+
+    let random_email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": true
+    });
+
+    let response = app.post_signup(&signup_body).await;
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    let code = TwoFACode::default();
+    app.id_generator
+        .set(LoginAttemptId::default(), code.clone());
+    let code = code.as_ref().expose_secret().to_owned();
+
+    let response = login_with_language(&app, &random_email, "de-DE, es;q=0.9, en;q=0.8").await;
+
+    assert_eq!(response.status().as_u16(), 206);
+
+    let received = app.wait_for_emails(1).await;
+
+    assert_eq!(
+        received[0].subject,
+        format!("Tu código de verificación de {}", TEST_EMAIL_BRAND_NAME)
+    );
+    let html_body = &received[0].html_body;
+    let text_body = &received[0].text_body;
+    assert!(html_body.contains("lang=\"es\""));
+    assert!(html_body.contains(&code));
+    assert!(text_body.starts_with("Hola"));
+    assert!(text_body.contains(&code));
+    assert!(!text_body.contains('<'));
+
     let mut app = app;
     app.clean_up().await;
 }
+
+#[tokio::test]
+async fn should_prefer_user_locale_over_accept_language() {
+    let app = TestApp::new().await;
+
+    let random_email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": true,
+        "locale": "fr"
+    });
+
+    let response = app.post_signup(&signup_body).await;
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = login_with_language(&app, &random_email, "es").await;
+
+    assert_eq!(response.status().as_u16(), 206);
+
+    let received = app.wait_for_emails(1).await;
+
+    assert_eq!(
+        received[0].subject,
+        format!("Votre code de vérification {}", TEST_EMAIL_BRAND_NAME)
+    );
+
+    let mut app = app;
+    app.clean_up().await;
+}
+
+const CONCURRENT_LOGINS: usize = 5;
+
+// Holds every credential check until all of the concurrent logins have reached the store
+struct BarrierUserStore {
+    inner: UserStoreType,
+    barrier: Barrier,
+}
+
+#[async_trait::async_trait]
+impl UserStore for BarrierUserStore {
+    async fn add_user(&self, user: User) -> Result<(), UserStoreError> {
+        self.inner.add_user(user).await
+    }
+
+    async fn get_user(&self, email: &Email) -> Result<User, UserStoreError> {
+        self.inner.get_user(email).await
+    }
+
+    async fn validate_user(
+        &self,
+        email: &Email,
+        raw_password: &SecretString,
+    ) -> Result<(), UserStoreError> {
+        self.barrier.wait().await;
+        self.inner.validate_user(email, raw_password).await
+    }
+}
+
+#[tokio::test]
+async fn concurrent_logins_proceed_in_parallel() {
+    let app = TestApp::builder()
+        .with_user_store(|inner| {
+            Arc::new(BarrierUserStore {
+                inner,
+                barrier: Barrier::new(CONCURRENT_LOGINS),
+            })
+        })
+        .build()
+        .await;
+
+    let mut logins = JoinSet::new();
+    for _ in 0..CONCURRENT_LOGINS {
+        let email = get_random_email();
+        let signup_body = serde_json::json!({
+            "email": email,
+            "password": "password123",
+            "requires2FA": false
+        });
+        assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+        let request = app
+            .http_client
+            .post(format!("{}/login", &app.address))
+            .json(&serde_json::json!({
+                "email": email,
+                "password": "password123",
+            }));
+        logins.spawn(request.send());
+    }
+
+    // If logins were serialized, the first one would wait on the barrier forever
+    let responses = tokio::time::timeout(Duration::from_secs(10), logins.join_all())
+        .await
+        .expect("Concurrent logins did not proceed in parallel");
+
+    for response in responses {
+        assert_eq!(response.unwrap().status().as_u16(), 200);
+    }
+
+    let mut app = app;
+    app.clean_up().await;
+}
+
+// Refuses every email, as an outbox whose backend is down would
+struct FailingEmailOutboxStore {
+    inner: EmailOutboxStoreType,
+}
+
+#[async_trait::async_trait]
+impl EmailOutboxStore for FailingEmailOutboxStore {
+    async fn enqueue_email(&self, _email: OutboxEmail) -> Result<(), EmailOutboxStoreError> {
+        Err(EmailOutboxStoreError::UnexpectedError(eyre!(
+            "outbox is down"
+        )))
+    }
+
+    async fn claim_due_emails(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<OutboxEmail>, EmailOutboxStoreError> {
+        self.inner.claim_due_emails(now, lease_until, limit).await
+    }
+
+    async fn mark_sent(&self, id: &Uuid) -> Result<(), EmailOutboxStoreError> {
+        self.inner.mark_sent(id).await
+    }
+
+    async fn record_failure(&self, email: &OutboxEmail) -> Result<(), EmailOutboxStoreError> {
+        self.inner.record_failure(email).await
+    }
+
+    async fn get_email(&self, id: &Uuid) -> Result<OutboxEmail, EmailOutboxStoreError> {
+        self.inner.get_email(id).await
+    }
+}
+
+#[tokio::test]
+async fn should_return_500_and_store_no_2fa_code_if_its_email_cannot_be_queued() {
+    let app = TestApp::builder()
+        .with_email_outbox_store(|inner| Arc::new(FailingEmailOutboxStore { inner }))
+        .build()
+        .await;
+
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+    });
+    let response = app.post_login(&login_body).await;
+
+    assert_eq!(response.status().as_u16(), 500);
+
+    // The user never gets the code, so it mustn't be valid either
+    let email = Email::parse(SecretString::new(random_email.into_boxed_str())).unwrap();
+    assert_eq!(
+        app.two_fa_code_store.get_code(&email).await.unwrap_err(),
+        TwoFACodeStoreError::LoginAttemptIdNotFound
+    );
+
+    let mut app = app;
+    app.clean_up().await;
+}
+
+// Refuses to store any 2FA code, as a 2FA code store that is down would
+struct FailingTwoFACodeStore {
+    inner: TwoFACodeStoreType,
+}
+
+#[async_trait::async_trait]
+impl TwoFACodeStore for FailingTwoFACodeStore {
+    async fn add_code(
+        &self,
+        _email: Email,
+        _login_attempt_id: LoginAttemptId,
+        _code: TwoFACode,
+    ) -> Result<(), TwoFACodeStoreError> {
+        Err(TwoFACodeStoreError::UnexpectedError(eyre!("store is down")))
+    }
+
+    async fn remove_code(&self, email: &Email) -> Result<(), TwoFACodeStoreError> {
+        self.inner.remove_code(email).await
+    }
+
+    async fn get_code(
+        &self,
+        email: &Email,
+    ) -> Result<(LoginAttemptId, TwoFACode), TwoFACodeStoreError> {
+        self.inner.get_code(email).await
+    }
+}
+
+#[tokio::test]
+async fn should_return_500_and_queue_no_email_if_the_2fa_code_cannot_be_stored() {
+    let mut app = TestApp::builder()
+        .with_two_fa_code_store(|inner| Arc::new(FailingTwoFACodeStore { inner }))
+        .build()
+        .await;
+
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+    });
+    let response = app.post_login(&login_body).await;
+
+    assert_eq!(response.status().as_u16(), 500);
+
+    // Shutting down sends whatever is queued, and an email with an unusable code mustn't be
+    app.shut_down().await;
+    assert!(app.emails.emails_to(&random_email).is_empty());
+
+    app.clean_up().await;
+}
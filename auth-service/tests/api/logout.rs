@@ -1,8 +1,9 @@
-use auth_service::{utils::constants::JWT_COOKIE_NAME, ErrorResponse};
+use auth_service::{routes::LoginResponse, utils::constants::JWT_COOKIE_NAME, ErrorResponse};
 use reqwest::Url;
-use secrecy::SecretString;
+use secrecy::{ExposeSecret, SecretString};
+use uuid::Uuid;
 
-use crate::helpers::{get_random_email, TestApp};
+use crate::helpers::{get_jti, get_random_email, TestApp};
 
 #[tokio::test]
 async fn should_return_200_if_valid_jwt_cookie() {
@@ -51,9 +52,7 @@ async fn should_return_200_if_valid_jwt_cookie() {
 
     let banned_tokens = app.banned_token_store.clone();
     let is_banned = banned_tokens
-        .read()
-        .await
-        .contains_token(&token)
+        .contains_token(&get_jti(token.expose_secret()))
         .await
         .expect("Failed to check if token is banned");
 
@@ -63,6 +62,39 @@ async fn should_return_200_if_valid_jwt_cookie() {
     app.clean_up().await;
 }
 
+#[tokio::test]
+async fn should_ban_the_jti_issued_at_login() {
+    let app = TestApp::new().await;
+
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    let jti = Uuid::new_v4();
+    app.id_generator.set_uuid(jti);
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+    });
+    assert_eq!(app.post_login(&login_body).await.status().as_u16(), 200);
+
+    assert_eq!(app.post_logout().await.status().as_u16(), 200);
+
+    let is_banned = app
+        .banned_token_store
+        .contains_token(&jti.to_string())
+        .await
+        .expect("Failed to check if token is banned");
+    assert!(is_banned);
+
+    let mut app = app;
+    app.clean_up().await;
+}
+
 #[tokio::test]
 async fn should_return_400_if_jwt_cookie_missing() {
     let app = TestApp::new().await;
@@ -153,3 +185,96 @@ async fn should_return_401_if_invalid_token() {
     let mut app = app;
     app.clean_up().await;
 }
+
+// Logs in a new user without 2FA, asking for the token in the body
+async fn log_in_for_token(app: &TestApp) -> String {
+    let random_email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "tokenDelivery": "body"
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    match response.json::<LoginResponse>().await.unwrap() {
+        LoginResponse::Token(token) => token.access_token,
+        other => panic!("Expected a token, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn should_return_200_if_valid_bearer_token() {
+    let mut app = TestApp::new().await;
+    let token = log_in_for_token(&app).await;
+
+    let response = app.post_logout_with_bearer(&token).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    // There is no cookie to clear
+    assert!(response.headers().get("set-cookie").is_none());
+
+    let is_banned = app
+        .banned_token_store
+        .contains_token(&get_jti(&token))
+        .await
+        .expect("Failed to check if token is banned");
+    assert!(is_banned);
+
+    // The banned token is rejected like a banned cookie
+    let response = app.post_logout_with_bearer(&token).await;
+    assert_eq!(response.status().as_u16(), 401);
+    let response = app.post_verify_token_with_bearer(&token).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_prefer_bearer_token_over_cookie() {
+    let mut app = TestApp::new().await;
+    let bearer_token = log_in_for_token(&app).await;
+
+    // A cookie for another session, which stays valid
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+    });
+    let response = app.post_login(&login_body).await;
+    let cookie_token = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found")
+        .value()
+        .to_owned();
+
+    let response = app.post_logout_with_bearer(&bearer_token).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let banned_tokens = &app.banned_token_store;
+    assert!(banned_tokens
+        .contains_token(&get_jti(&bearer_token))
+        .await
+        .unwrap());
+    assert!(!banned_tokens
+        .contains_token(&get_jti(&cookie_token))
+        .await
+        .unwrap());
+
+    app.clean_up().await;
+}
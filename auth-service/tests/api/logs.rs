@@ -0,0 +1,261 @@
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use auth_service::{
+    routes::TwoFactorAuthResponse, settings::LogFormat, utils::constants::JWT_COOKIE_NAME,
+    utils::tracing::log_layer,
+};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tracing::subscriber::DefaultGuard;
+use tracing_subscriber::{fmt::MakeWriter, prelude::*};
+
+use crate::helpers::{get_random_email, TestApp};
+
+// Everything logged on the test's thread, at every level and from every crate. The app runs on
+// the test's single-threaded runtime, so that includes its handlers and background workers.
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl CapturedLogs {
+    fn start(format: LogFormat) -> (Self, DefaultGuard) {
+        let logs = Self::default();
+        let subscriber = tracing_subscriber::registry().with(log_layer(format, logs.clone()));
+        (logs, tracing::subscriber::set_default(subscriber))
+    }
+
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl<'a> MakeWriter<'a> for CapturedLogs {
+    type Writer = Self;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+impl Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Everything the flow below handles that must stay out of the logs
+struct Secrets {
+    email: String,
+    passwords: Vec<String>,
+    two_fa_codes: Vec<String>,
+    login_attempt_id: String,
+    token: String,
+}
+
+impl Secrets {
+    fn assert_absent_from(&self, logs: &str) {
+        assert!(!logs.is_empty(), "Nothing was logged");
+        let mut leaks = Vec::new();
+        // An unkeyed hash of the email can be found by hashing candidate emails
+        let mut email_hash = format!("{:x}", Sha256::digest(self.email.to_lowercase().as_bytes()));
+        email_hash.truncate(16);
+        for secret in [
+            &self.email,
+            &email_hash,
+            &self.login_attempt_id,
+            &self.token,
+        ]
+        .into_iter()
+        .chain(&self.passwords)
+        {
+            if logs.contains(secret.as_str()) {
+                leaks.push(secret.as_str());
+            }
+        }
+        for code in &self.two_fa_codes {
+            if contains_number(logs, code) {
+                leaks.push(code);
+            }
+        }
+        assert!(leaks.is_empty(), "Leaked {:?} in:\n{}", leaks, logs);
+    }
+}
+
+// Timestamps, latencies and ids are full of digits, so a 2FA code only counts
+// as leaked when it stands on its own
+fn contains_number(logs: &str, number: &str) -> bool {
+    let standalone = |c: Option<char>| c.is_none_or(|c| !c.is_ascii_alphanumeric() && c != '.');
+    logs.match_indices(number).any(|(start, _)| {
+        standalone(logs[..start].chars().next_back())
+            && standalone(logs[start + number.len()..].chars().next())
+    })
+}
+
+// Signs up with 2FA, fails and then succeeds at each step, then checks and revokes the token
+async fn log_in_with_2fa(app: &TestApp) -> Secrets {
+    let email = get_random_email();
+    let password = "correct horse battery staple";
+    let wrong_password = "incorrect horse battery staple";
+
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": password,
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    let wrong_login = serde_json::json!({ "email": email, "password": wrong_password });
+    assert_eq!(app.post_login(&wrong_login).await.status().as_u16(), 401);
+
+    let response = app
+        .post_login(&serde_json::json!({ "email": email, "password": password }))
+        .await;
+    assert_eq!(response.status().as_u16(), 206);
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .unwrap()
+        .login_attempt_id;
+    let two_fa_code = app.wait_for_2fa_code(&email, 1).await;
+    let wrong_code = if two_fa_code == "000000" {
+        "111111"
+    } else {
+        "000000"
+    };
+
+    let verify_2fa_body = |code: &str| {
+        serde_json::json!({
+            "email": email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code
+        })
+    };
+    let response = app.post_verify_2fa(&verify_2fa_body(wrong_code)).await;
+    assert_eq!(response.status().as_u16(), 401);
+    let response = app.post_verify_2fa(&verify_2fa_body(&two_fa_code)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let token = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found")
+        .value()
+        .to_owned();
+
+    let response = app
+        .post_verify_token(&serde_json::json!({ "token": token }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.post_logout().await.status().as_u16(), 200);
+
+    Secrets {
+        email,
+        passwords: vec![password.to_owned(), wrong_password.to_owned()],
+        two_fa_codes: vec![two_fa_code, wrong_code.to_owned()],
+        login_attempt_id,
+        token,
+    }
+}
+
+#[tokio::test]
+async fn text_logs_of_a_2fa_login_contain_no_secrets() {
+    let (logs, _default) = CapturedLogs::start(LogFormat::Text);
+    let mut app = TestApp::new().await;
+
+    let secrets = log_in_with_2fa(&app).await;
+
+    let logs = logs.contents();
+    secrets.assert_absent_from(&logs);
+    assert!(logs.contains("[REQUEST END]"));
+    assert!(logs.contains("2fa_required"));
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn json_logs_of_a_2fa_login_contain_no_secrets() {
+    let (logs, _default) = CapturedLogs::start(LogFormat::Json);
+    let mut app = TestApp::new().await;
+
+    let secrets = log_in_with_2fa(&app).await;
+
+    let logs = logs.contents();
+    secrets.assert_absent_from(&logs);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn json_logs_carry_the_request_fields() {
+    let (logs, _default) = CapturedLogs::start(LogFormat::Json);
+    let mut app = TestApp::new().await;
+
+    log_in_with_2fa(&app).await;
+
+    let lines: Vec<Value> = logs
+        .contents()
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("Every line is a JSON object"))
+        .collect();
+    let request_ends: Vec<_> = lines
+        .iter()
+        .filter(|line| line["message"] == "[REQUEST END]")
+        .collect();
+    // signup, 2 logins, 2 verify-2fa, verify-token and logout
+    assert_eq!(request_ends.len(), 7);
+    for line in &request_ends {
+        assert!(line["timestamp"].is_string());
+        assert_eq!(line["span"], "[REQUEST]");
+        assert!(line["request_id"].is_string());
+        let user_id = line["user_id"].as_str().expect("No user_id");
+        assert_eq!(user_id.len(), 16);
+    }
+    // Every request was made for the same user
+    assert!(request_ends
+        .iter()
+        .all(|line| line["user_id"] == request_ends[0]["user_id"]));
+
+    let outcomes: Vec<_> = request_ends
+        .iter()
+        .map(|line| {
+            (
+                line["route"].as_str().unwrap(),
+                line["status"].as_u64().unwrap(),
+                line["outcome"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        outcomes,
+        vec![
+            ("/signup", 201, "signed_up"),
+            ("/login", 401, "incorrect_credentials"),
+            ("/login", 206, "2fa_required"),
+            ("/verify-2fa", 401, "failed"),
+            ("/verify-2fa", 200, "verified"),
+            ("/verify-token", 200, "valid"),
+            ("/logout", 200, "logged_out"),
+        ]
+    );
+
+    // Lines logged while handling a request carry its fields too, not only `[REQUEST END]`
+    let error = lines
+        .iter()
+        .find(|line| {
+            line["message"]
+                .as_str()
+                .is_some_and(|message| message.contains("IncorrectCredentials"))
+        })
+        .expect("The failed login wasn't logged");
+    assert_eq!(error["level"], "ERROR");
+    assert_eq!(error["route"], "/login");
+    assert!(error["request_id"].is_string());
+    assert_eq!(error["user_id"], request_ends[0]["user_id"]);
+
+    app.clean_up().await;
+}
@@ -1,8 +1,23 @@
+mod cookies;
+mod cors;
+mod csrf;
+mod dev_mailbox;
+mod health;
 mod helpers;
 mod login;
 mod logout;
+mod logs;
+mod metrics;
+mod redis_keys;
+mod redis_outage;
 mod root;
+mod shutdown;
 mod signup;
+mod smtp_email_client;
+mod smtp_sink;
+mod store_conformance;
+mod token_stores;
+mod trace_context;
 mod verify_2fa;
 mod verify_token;
-
+mod webhooks;
@@ -0,0 +1,189 @@
+use crate::helpers::{get_random_email, TestApp};
+
+// Every test app reports to the same process-wide recorder and tests run in parallel,
+// so the tests compare scrapes taken before and after rather than absolute values
+struct Scrape(String);
+
+impl Scrape {
+    async fn take(app: &TestApp) -> Self {
+        let response = app.get_metrics().await;
+        assert_eq!(response.status().as_u16(), 200);
+        Self(response.text().await.unwrap())
+    }
+
+    // The value of the series `name` carrying every label in `labels`, 0 if it isn't there yet
+    fn value(&self, name: &str, labels: &[(&str, &str)]) -> f64 {
+        self.0
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| {
+                let (series, value) = line.rsplit_once(' ')?;
+                let (series_name, series_labels) = match series.split_once('{') {
+                    Some((series_name, series_labels)) => (series_name, series_labels),
+                    None => (series, ""),
+                };
+                let matches = series_name == name
+                    && labels.iter().all(|(label, value)| {
+                        series_labels.contains(&format!("{}=\"{}\"", label, value))
+                    });
+                matches.then(|| value.parse::<f64>().unwrap())
+            })
+            .sum()
+    }
+
+    fn increase(&self, later: &Scrape, name: &str, labels: &[(&str, &str)]) -> f64 {
+        later.value(name, labels) - self.value(name, labels)
+    }
+}
+
+async fn signup(app: &TestApp, email: &str, requires_2fa: bool) {
+    let body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": requires_2fa
+    });
+    assert_eq!(app.post_signup(&body).await.status().as_u16(), 201);
+}
+
+async fn login(app: &TestApp, email: &str, password: &str) -> reqwest::Response {
+    app.post_login(&serde_json::json!({ "email": email, "password": password }))
+        .await
+}
+
+#[tokio::test]
+async fn metrics_are_served_in_the_prometheus_text_format() {
+    let mut app = TestApp::new().await;
+    signup(&app, &get_random_email(), false).await;
+
+    let response = app.get_metrics().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers().get("content-type").unwrap(),
+        "text/plain; version=0.0.4"
+    );
+    let body = response.text().await.unwrap();
+    assert!(body.contains("# TYPE http_requests_total counter"));
+    assert!(body.contains("# TYPE http_request_duration_seconds histogram"));
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn http_requests_are_counted_by_route_and_status() {
+    let mut app = TestApp::new().await;
+    let before = Scrape::take(&app).await;
+
+    signup(&app, &get_random_email(), false).await;
+    let response = login(&app, &get_random_email(), "password123").await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let after = Scrape::take(&app).await;
+    let signup = [("method", "POST"), ("route", "/signup"), ("status", "201")];
+    assert!(before.increase(&after, "http_requests_total", &signup) >= 1.0);
+    assert!(before.increase(&after, "http_request_duration_seconds_count", &signup) >= 1.0);
+    let login = [("method", "POST"), ("route", "/login"), ("status", "401")];
+    assert!(before.increase(&after, "http_requests_total", &login) >= 1.0);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn auth_flows_are_counted_by_outcome() {
+    let mut app = TestApp::new().await;
+    let (email, email_2fa) = (get_random_email(), get_random_email());
+    let before = Scrape::take(&app).await;
+
+    signup(&app, &email, false).await;
+    signup(&app, &email_2fa, true).await;
+    assert_eq!(login(&app, &email, "wrong-password").await.status(), 401);
+    assert_eq!(login(&app, &email, "password123").await.status(), 200);
+    assert_eq!(app.post_logout().await.status(), 200);
+
+    let response = login(&app, &email_2fa, "password123").await;
+    assert_eq!(response.status(), 206);
+    let login_attempt_id = response.json::<serde_json::Value>().await.unwrap()["loginAttemptId"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let code = app.wait_for_2fa_code(&email_2fa, 1).await;
+    let wrong_code = if code == "000000" { "111111" } else { "000000" };
+    for two_fa_code in [wrong_code, code.as_str()] {
+        app.post_verify_2fa(&serde_json::json!({
+            "email": email_2fa,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": two_fa_code,
+        }))
+        .await;
+    }
+
+    let after = Scrape::take(&app).await;
+    let increase = |name: &str, labels: &[(&str, &str)]| before.increase(&after, name, labels);
+    assert!(increase("auth_signups_total", &[]) >= 2.0);
+    assert!(increase("auth_logins_total", &[("outcome", "success")]) >= 1.0);
+    assert!(increase("auth_logins_total", &[("outcome", "2fa_required")]) >= 1.0);
+    assert!(increase("auth_logins_total", &[("outcome", "incorrect_credentials")]) >= 1.0);
+    assert!(increase("auth_2fa_codes_issued_total", &[]) >= 1.0);
+    assert!(increase("auth_2fa_verifications_total", &[("outcome", "failed")]) >= 1.0);
+    assert!(increase("auth_2fa_verifications_total", &[("outcome", "verified")]) >= 1.0);
+    assert!(increase("auth_tokens_banned_total", &[]) >= 1.0);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn password_hashes_and_store_operations_are_timed() {
+    let mut app = TestApp::new().await;
+    let email = get_random_email();
+    let before = Scrape::take(&app).await;
+
+    signup(&app, &email, false).await;
+    assert_eq!(login(&app, &email, "password123").await.status(), 200);
+
+    let after = Scrape::take(&app).await;
+    let increase = |name: &str, labels: &[(&str, &str)]| before.increase(&after, name, labels);
+    let hash_duration = "auth_password_hash_duration_seconds_count";
+    assert!(increase(hash_duration, &[("operation", "hash")]) >= 1.0);
+    assert!(increase(hash_duration, &[("operation", "verify")]) >= 1.0);
+    // The users live in memory, or in Postgres with `backend-tests`
+    let backend = if cfg!(feature = "backend-tests") {
+        "postgres"
+    } else {
+        "memory"
+    };
+    let store_duration = "auth_store_operation_duration_seconds_count";
+    for operation in ["add_user", "validate_user", "get_user"] {
+        let labels = [
+            ("store", "users"),
+            ("backend", backend),
+            ("operation", operation),
+        ];
+        assert!(increase(store_duration, &labels) >= 1.0, "{}", operation);
+    }
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn metrics_can_be_served_on_their_own_port() {
+    let mut app = TestApp::builder().with_metrics_port().build().await;
+    signup(&app, &get_random_email(), false).await;
+
+    let scrape = Scrape::take(&app).await;
+    assert!(scrape.value("auth_signups_total", &[]) >= 1.0);
+
+    // The API port no longer serves them, unknown paths fall back to the UI
+    let response = app
+        .http_client
+        .get(format!("{}/metrics", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert!(!response
+        .text()
+        .await
+        .unwrap()
+        .contains("http_requests_total"));
+
+    app.clean_up().await;
+}
@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use auth_service::{
+    domain::{BannedTokenStore, Email, LoginAttemptId, TwoFACode, TwoFACodeStore},
+    get_redis_connection,
+    services::data_stores::{RedisBannedTokenStore, RedisTwoFACodeStore},
+    settings::RedisSettings,
+};
+use chrono::{TimeDelta, Utc};
+use redis::AsyncTypedCommands;
+use secrecy::SecretString;
+use uuid::Uuid;
+
+use crate::helpers::{get_random_email, test_settings};
+
+fn redis_settings(key_prefix: &str) -> RedisSettings {
+    RedisSettings {
+        key_prefix: key_prefix.to_owned(),
+        ..test_settings().redis
+    }
+}
+
+// Reads keys directly, bypassing the stores and their key layout
+async fn raw_connection(settings: &RedisSettings) -> redis::aio::MultiplexedConnection {
+    redis::Client::open(format!("redis://{}/", settings.host))
+        .unwrap()
+        .get_multiplexed_async_connection()
+        .await
+        .expect("Failed to connect to Redis")
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "backend-tests"), ignore = "needs Redis")]
+async fn keys_are_namespaced_and_hash_tagged_by_id() {
+    let settings = redis_settings("test-env:");
+    let connection = get_redis_connection(&settings).await.unwrap();
+    let two_fa_code_store = RedisTwoFACodeStore::new(connection.clone());
+    let banned_token_store = RedisBannedTokenStore::new(connection);
+
+    let email = get_random_email();
+    two_fa_code_store
+        .add_code(
+            Email::parse(SecretString::new(email.clone().into_boxed_str())).unwrap(),
+            LoginAttemptId::default(),
+            TwoFACode::default(),
+        )
+        .await
+        .unwrap();
+    let jti = Uuid::new_v4().to_string();
+    banned_token_store
+        .add_token(&jti, Utc::now() + TimeDelta::minutes(10))
+        .await
+        .unwrap();
+
+    let mut raw = raw_connection(&settings).await;
+    assert!(raw
+        .exists(format!("test-env:two_fa_code:{{{}}}", email))
+        .await
+        .unwrap());
+    assert!(raw
+        .exists(format!("test-env:banned_token:{{{}}}", jti))
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "backend-tests"), ignore = "needs Redis")]
+async fn environments_with_different_prefixes_do_not_share_keys() {
+    let staging = RedisBannedTokenStore::new(
+        get_redis_connection(&redis_settings("staging:"))
+            .await
+            .unwrap(),
+    );
+    let production = RedisBannedTokenStore::new(
+        get_redis_connection(&redis_settings("production:"))
+            .await
+            .unwrap(),
+    );
+    let jti = Uuid::new_v4().to_string();
+
+    staging
+        .add_token(&jti, Utc::now() + TimeDelta::minutes(10))
+        .await
+        .unwrap();
+
+    assert!(staging.contains_token(&jti).await.unwrap());
+    assert!(!production.contains_token(&jti).await.unwrap());
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "backend-tests"), ignore = "needs Redis")]
+async fn banned_tokens_expire_with_the_token() {
+    let settings = redis_settings("test-env:");
+    let store = RedisBannedTokenStore::new(get_redis_connection(&settings).await.unwrap());
+    let jti = Uuid::new_v4().to_string();
+
+    store
+        .add_token(&jti, Utc::now() + TimeDelta::minutes(5))
+        .await
+        .unwrap();
+
+    let ttl = raw_connection(&settings)
+        .await
+        .pttl(format!("test-env:banned_token:{{{}}}", jti))
+        .await
+        .unwrap()
+        .raw();
+    let ttl = Duration::from_millis(ttl.try_into().unwrap());
+    assert!(ttl <= Duration::from_secs(300) && ttl > Duration::from_secs(295));
+}
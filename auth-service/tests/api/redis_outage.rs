@@ -0,0 +1,137 @@
+use std::{sync::Arc, time::Duration};
+
+use auth_service::{
+    domain::{Email, LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError},
+    get_redis_connection,
+    services::data_stores::RedisTwoFACodeStore,
+    settings::RedisSettings,
+};
+use secrecy::SecretString;
+use tokio::{
+    io::copy_bidirectional,
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+    task::JoinHandle,
+};
+
+use crate::helpers::{get_random_email, test_settings};
+
+const REDIS_ADDRESS: &str = "127.0.0.1:6379";
+// Upper bound for a store call while Redis is unreachable
+const OUTAGE_DEADLINE: Duration = Duration::from_secs(3);
+
+// Forwards connections to the local Redis until it is stopped, which simulates an outage
+struct RedisProxy {
+    port: u16,
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl RedisProxy {
+    async fn start(port: u16) -> Self {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .await
+            .expect("Failed to bind Redis proxy");
+        let port = listener.local_addr().unwrap().port();
+        let tasks = Arc::new(Mutex::new(Vec::new()));
+
+        let connection_tasks = tasks.clone();
+        let accept_task = tokio::spawn(async move {
+            while let Ok((mut inbound, _)) = listener.accept().await {
+                let connection = tokio::spawn(async move {
+                    if let Ok(mut outbound) = TcpStream::connect(REDIS_ADDRESS).await {
+                        let _ = copy_bidirectional(&mut inbound, &mut outbound).await;
+                    }
+                });
+                connection_tasks.lock().await.push(connection);
+            }
+        });
+        tasks.lock().await.push(accept_task);
+
+        Self { port, tasks }
+    }
+
+    // Stops accepting connections and drops the open ones
+    async fn stop(&self) {
+        for task in self.tasks.lock().await.drain(..) {
+            task.abort();
+            let _ = task.await;
+        }
+    }
+
+    fn settings(&self) -> RedisSettings {
+        RedisSettings {
+            host: format!("127.0.0.1:{}", self.port),
+            connection_timeout: Duration::from_millis(500),
+            response_timeout: Duration::from_millis(500),
+            ..test_settings().redis
+        }
+    }
+}
+
+fn email() -> Email {
+    Email::parse(SecretString::new(get_random_email().into_boxed_str())).unwrap()
+}
+
+fn code() -> TwoFACode {
+    TwoFACode::parse(SecretString::new("123456".to_owned().into_boxed_str())).unwrap()
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "backend-tests"), ignore = "needs Redis")]
+async fn redis_outage_is_reported_as_an_error_instead_of_hanging() {
+    let proxy = RedisProxy::start(0).await;
+    let connection = get_redis_connection(&proxy.settings()).await.unwrap();
+    let store = RedisTwoFACodeStore::new(connection);
+    let email = email();
+    store
+        .add_code(email.clone(), LoginAttemptId::default(), code())
+        .await
+        .unwrap();
+
+    proxy.stop().await;
+
+    let result = tokio::time::timeout(OUTAGE_DEADLINE, store.get_code(&email))
+        .await
+        .expect("Store call hung while Redis was down");
+    assert!(matches!(
+        result,
+        Err(TwoFACodeStoreError::UnexpectedError(_))
+    ));
+
+    let result = tokio::time::timeout(
+        OUTAGE_DEADLINE,
+        store.add_code(email, LoginAttemptId::default(), code()),
+    )
+    .await
+    .expect("Store call hung while Redis was down");
+    assert!(matches!(
+        result,
+        Err(TwoFACodeStoreError::UnexpectedError(_))
+    ));
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "backend-tests"), ignore = "needs Redis")]
+async fn redis_stores_reconnect_after_an_outage() {
+    let proxy = RedisProxy::start(0).await;
+    let connection = get_redis_connection(&proxy.settings()).await.unwrap();
+    let store = RedisTwoFACodeStore::new(connection);
+    let email = email();
+    store
+        .add_code(email.clone(), LoginAttemptId::default(), code())
+        .await
+        .unwrap();
+
+    proxy.stop().await;
+    let _ = store.get_code(&email).await;
+    let _proxy = RedisProxy::start(proxy.port).await;
+
+    // The connection manager reconnects in the background after the first failed call
+    for _ in 0..50 {
+        if store.get_code(&email).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Store did not recover after Redis came back");
+}
@@ -0,0 +1,145 @@
+use std::{sync::Arc, time::Duration};
+
+use auth_service::{
+    app_state::UserStoreType,
+    domain::{Email, User, UserStore, UserStoreError},
+    services::health_checks::{ReadinessReport, ReadinessStatus},
+};
+use secrecy::SecretString;
+use tokio::time::Instant;
+
+use crate::helpers::{get_random_email, TestApp};
+
+// Takes `delay` to store a user, to keep a signup in flight
+struct SlowUserStore {
+    inner: UserStoreType,
+    delay: Duration,
+}
+
+#[async_trait::async_trait]
+impl UserStore for SlowUserStore {
+    async fn add_user(&self, user: User) -> Result<(), UserStoreError> {
+        tokio::time::sleep(self.delay).await;
+        self.inner.add_user(user).await
+    }
+
+    async fn get_user(&self, email: &Email) -> Result<User, UserStoreError> {
+        self.inner.get_user(email).await
+    }
+
+    async fn validate_user(
+        &self,
+        email: &Email,
+        raw_password: &SecretString,
+    ) -> Result<(), UserStoreError> {
+        self.inner.validate_user(email, raw_password).await
+    }
+}
+
+async fn app_with_slow_signups(delay: Duration) -> TestApp {
+    TestApp::builder()
+        .with_user_store(move |inner| Arc::new(SlowUserStore { inner, delay }))
+        .build()
+        .await
+}
+
+fn signup_body() -> serde_json::Value {
+    serde_json::json!({
+        "email": get_random_email(),
+        "password": "password123",
+        "requires2FA": false
+    })
+}
+
+#[tokio::test]
+async fn ready_fails_as_soon_as_shutdown_begins() {
+    let mut app = TestApp::new().await;
+    assert_eq!(app.get_health("ready").await.status().as_u16(), 200);
+
+    app.shutdown.trigger();
+
+    // Still served during `shutdown_delay`, for load balancers to take the instance out
+    let response = app.get_health("ready").await;
+    assert_eq!(response.status().as_u16(), 503);
+    let report = response.json::<ReadinessReport>().await.unwrap();
+    assert_eq!(report.status, ReadinessStatus::ShuttingDown);
+    assert!(report.checks.is_empty());
+    assert_eq!(app.get_health("live").await.status().as_u16(), 200);
+
+    app.shut_down().await;
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn in_flight_requests_complete_before_the_server_stops() {
+    // Outlasts `shutdown_delay` (200ms) but not `drain_timeout` (1s)
+    let mut app = app_with_slow_signups(Duration::from_millis(500)).await;
+    let signup = tokio::spawn(
+        app.http_client
+            .post(format!("{}/signup", &app.address))
+            .json(&signup_body())
+            .send(),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let started = Instant::now();
+    app.shut_down().await;
+
+    let response = signup.await.unwrap().expect("The signup was dropped");
+    assert_eq!(response.status().as_u16(), 201);
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    // New connections are refused once the server has stopped
+    let error = reqwest::Client::new()
+        .get(format!("{}/health/live", &app.address))
+        .send()
+        .await
+        .expect_err("The server still accepts connections");
+    assert!(error.is_connect(), "{:?}", error);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn shutdown_gives_up_on_requests_after_the_drain_timeout() {
+    let mut app = app_with_slow_signups(Duration::from_secs(30)).await;
+    let _signup = tokio::spawn(
+        app.http_client
+            .post(format!("{}/signup", &app.address))
+            .json(&signup_body())
+            .send(),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let started = Instant::now();
+    app.shut_down().await;
+
+    // `shutdown_delay` and `drain_timeout`, and some leeway for the workers
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(1200), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn emails_queued_before_shutdown_are_sent() {
+    let mut app = TestApp::new().await;
+    let email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    let response = app
+        .post_login(&serde_json::json!({ "email": email, "password": "password123" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 206);
+    app.shut_down().await;
+
+    // The outbox worker makes a last pass before stopping
+    assert_eq!(app.emails.emails_to(&email).len(), 1);
+
+    app.clean_up().await;
+}
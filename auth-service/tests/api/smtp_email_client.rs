@@ -0,0 +1,204 @@
+use crate::helpers::get_random_email;
+use crate::smtp_sink::{SmtpSink, SINK_CERTIFICATE};
+use auth_service::{
+    domain::{Email, EmailClient},
+    services::smtp_email_client::SmtpEmailClient,
+    settings::{SmtpCredentials, SmtpTlsMode},
+    utils::constants::test,
+};
+use secrecy::SecretString;
+
+fn email(s: &str) -> Email {
+    Email::parse(SecretString::new(s.to_owned().into_boxed_str())).unwrap()
+}
+
+// Trusts the sink's self-signed certificate
+fn smtp_email_client(
+    sink: &SmtpSink,
+    tls_mode: SmtpTlsMode,
+    credentials: Option<SmtpCredentials>,
+) -> SmtpEmailClient {
+    smtp_email_client_trusting(sink, tls_mode, Some(SINK_CERTIFICATE), credentials)
+}
+
+fn smtp_email_client_trusting(
+    sink: &SmtpSink,
+    tls_mode: SmtpTlsMode,
+    ca_certificate: Option<&[u8]>,
+    credentials: Option<SmtpCredentials>,
+) -> SmtpEmailClient {
+    SmtpEmailClient::new(
+        &sink.host,
+        sink.port,
+        tls_mode,
+        ca_certificate,
+        credentials,
+        email(test::email_client::SENDER),
+        test::email_client::TIMEOUT,
+    )
+    .unwrap()
+}
+
+fn credentials(username: &str, password: &str) -> Option<SmtpCredentials> {
+    Some(SmtpCredentials {
+        username: username.to_owned(),
+        password: SecretString::new(password.to_owned().into_boxed_str()),
+    })
+}
+
+#[tokio::test]
+async fn should_deliver_html_and_text_email_to_smtp_relay() {
+    let sink = SmtpSink::start().await;
+    let email_client = smtp_email_client(&sink, SmtpTlsMode::None, None);
+    let recipient = get_random_email();
+
+    email_client
+        .send_email(
+            &email(&recipient),
+            "Your 2FA Code",
+            "<p>Your 2FA code is: <b>123456</b></p>",
+            "Your 2FA code is: 123456",
+        )
+        .await
+        .expect("Failed to send email over SMTP");
+
+    let received = sink.received_emails().await;
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].from, test::email_client::SENDER);
+    assert_eq!(received[0].to, vec![recipient]);
+    assert!(received[0].data.contains("Subject: Your 2FA Code"));
+    // Both bodies travel as alternatives of one multipart message
+    assert!(received[0].data.contains("multipart/alternative"));
+    assert!(received[0].data.contains("Your 2FA code is: 123456"));
+    assert!(received[0]
+        .data
+        .contains("<p>Your 2FA code is: <b>123456</b></p>"));
+    assert_eq!(received[0].authenticated_as, None);
+}
+
+#[tokio::test]
+async fn should_authenticate_with_configured_credentials() {
+    let sink = SmtpSink::start_with_credentials("mailer", "password123").await;
+    let email_client = smtp_email_client(
+        &sink,
+        SmtpTlsMode::None,
+        credentials("mailer", "password123"),
+    );
+
+    email_client
+        .send_email(
+            &email(&get_random_email()),
+            "Subject",
+            "<p>Content</p>",
+            "Content",
+        )
+        .await
+        .expect("Failed to send email over SMTP");
+
+    let received = sink.received_emails().await;
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].authenticated_as, Some("mailer".to_owned()));
+}
+
+#[tokio::test]
+async fn should_fail_if_credentials_are_rejected() {
+    let sink = SmtpSink::start_with_credentials("mailer", "password123").await;
+    let email_client = smtp_email_client(
+        &sink,
+        SmtpTlsMode::None,
+        credentials("mailer", "wrong-password"),
+    );
+
+    let outcome = email_client
+        .send_email(
+            &email(&get_random_email()),
+            "Subject",
+            "<p>Content</p>",
+            "Content",
+        )
+        .await;
+
+    assert!(outcome.is_err());
+    assert!(sink.received_emails().await.is_empty());
+}
+
+#[tokio::test]
+async fn should_fail_if_starttls_is_required_but_not_offered() {
+    // The sink never advertises STARTTLS, so the client must refuse to send in plaintext
+    let sink = SmtpSink::start().await;
+    let email_client = smtp_email_client(&sink, SmtpTlsMode::StartTls, None);
+
+    let outcome = email_client
+        .send_email(
+            &email(&get_random_email()),
+            "Subject",
+            "<p>Content</p>",
+            "Content",
+        )
+        .await;
+
+    assert!(outcome.is_err());
+    assert!(sink.received_emails().await.is_empty());
+}
+
+#[tokio::test]
+async fn should_deliver_email_over_starttls() {
+    let sink = SmtpSink::start_with_tls(SmtpTlsMode::StartTls).await;
+    let email_client = smtp_email_client(&sink, SmtpTlsMode::StartTls, None);
+
+    email_client
+        .send_email(
+            &email(&get_random_email()),
+            "Your 2FA Code",
+            "<p>123456</p>",
+            "123456",
+        )
+        .await
+        .expect("Failed to send email over STARTTLS");
+
+    let received = sink.received_emails().await;
+    assert_eq!(received.len(), 1);
+    assert!(received[0].encrypted);
+    assert!(received[0].data.contains("Subject: Your 2FA Code"));
+}
+
+#[tokio::test]
+async fn should_deliver_email_over_implicit_tls() {
+    let sink = SmtpSink::start_with_tls(SmtpTlsMode::Tls).await;
+    let email_client = smtp_email_client(&sink, SmtpTlsMode::Tls, None);
+
+    email_client
+        .send_email(
+            &email(&get_random_email()),
+            "Your 2FA Code",
+            "<p>123456</p>",
+            "123456",
+        )
+        .await
+        .expect("Failed to send email over TLS");
+
+    let received = sink.received_emails().await;
+    assert_eq!(received.len(), 1);
+    assert!(received[0].encrypted);
+    assert!(received[0].data.contains("Subject: Your 2FA Code"));
+}
+
+#[tokio::test]
+async fn should_fail_if_the_relay_certificate_is_not_trusted() {
+    for tls_mode in [SmtpTlsMode::StartTls, SmtpTlsMode::Tls] {
+        let sink = SmtpSink::start_with_tls(tls_mode).await;
+        let email_client = smtp_email_client_trusting(&sink, tls_mode, None, None);
+
+        let outcome = email_client
+            .send_email(
+                &email(&get_random_email()),
+                "Subject",
+                "<p>Content</p>",
+                "Content",
+            )
+            .await;
+
+        assert!(outcome.is_err(), "{:?}", tls_mode);
+        assert!(sink.received_emails().await.is_empty());
+    }
+}
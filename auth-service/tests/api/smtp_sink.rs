@@ -0,0 +1,248 @@
+use std::sync::Arc;
+
+use auth_service::settings::SmtpTlsMode;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rustls_pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use tokio::{
+    io::{split, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, WriteHalf},
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+};
+use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+
+// Self-signed certificate for `localhost` and `127.0.0.1`, valid until 2126
+pub const SINK_CERTIFICATE: &[u8] = include_bytes!("fixtures/smtp_sink_cert.pem");
+const SINK_PRIVATE_KEY: &[u8] = include_bytes!("fixtures/smtp_sink_key.pem");
+
+// A message accepted by the sink, as seen on the wire
+#[derive(Debug, Clone, Default)]
+pub struct ReceivedEmail {
+    pub authenticated_as: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    pub data: String,
+    // Whether the message was sent over TLS, from the start or after STARTTLS
+    pub encrypted: bool,
+}
+
+#[derive(Clone)]
+struct SinkConfig {
+    credentials: Option<(String, String)>,
+    tls_mode: SmtpTlsMode,
+    tls_acceptor: TlsAcceptor,
+}
+
+// Minimal in-process SMTP server that records every message it accepts. It speaks plaintext,
+// offers STARTTLS or wraps connections in TLS, with `SINK_CERTIFICATE`.
+pub struct SmtpSink {
+    pub host: String,
+    pub port: u16,
+    received: Arc<Mutex<Vec<ReceivedEmail>>>,
+}
+
+impl SmtpSink {
+    pub async fn start() -> Self {
+        Self::start_with(None, SmtpTlsMode::None).await
+    }
+
+    // Only accepts `AUTH PLAIN` with the given username and password
+    pub async fn start_with_credentials(username: &str, password: &str) -> Self {
+        Self::start_with(
+            Some((username.to_owned(), password.to_owned())),
+            SmtpTlsMode::None,
+        )
+        .await
+    }
+
+    pub async fn start_with_tls(tls_mode: SmtpTlsMode) -> Self {
+        Self::start_with(None, tls_mode).await
+    }
+
+    async fn start_with(credentials: Option<(String, String)>, tls_mode: SmtpTlsMode) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind SMTP sink");
+        let port = listener.local_addr().unwrap().port();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let config = SinkConfig {
+            credentials,
+            tls_mode,
+            tls_acceptor: tls_acceptor(),
+        };
+
+        let sink_received = received.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(handle_connection(
+                    stream,
+                    config.clone(),
+                    sink_received.clone(),
+                ));
+            }
+        });
+
+        Self {
+            host: "127.0.0.1".to_owned(),
+            port,
+            received,
+        }
+    }
+
+    pub async fn received_emails(&self) -> Vec<ReceivedEmail> {
+        self.received.lock().await.clone()
+    }
+}
+
+fn tls_acceptor() -> TlsAcceptor {
+    let certificates = CertificateDer::pem_slice_iter(SINK_CERTIFICATE)
+        .collect::<Result<Vec<_>, _>>()
+        .expect("Failed to parse the SMTP sink certificate");
+    let key = PrivateKeyDer::from_pem_slice(SINK_PRIVATE_KEY)
+        .expect("Failed to parse the SMTP sink private key");
+    let config = ServerConfig::builder_with_provider(Arc::new(
+        tokio_rustls::rustls::crypto::aws_lc_rs::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_no_client_auth()
+    .with_single_cert(certificates, key)
+    .expect("Failed to build the SMTP sink TLS config");
+
+    TlsAcceptor::from(Arc::new(config))
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    config: SinkConfig,
+    received: Arc<Mutex<Vec<ReceivedEmail>>>,
+) -> std::io::Result<()> {
+    match config.tls_mode {
+        SmtpTlsMode::None => {
+            handle_session(stream, &config, false, received).await?;
+        }
+        SmtpTlsMode::StartTls => {
+            // The session starts over on the encrypted stream once the client asks for it
+            if let Some(stream) = handle_session(stream, &config, false, received.clone()).await? {
+                let stream = config.tls_acceptor.accept(stream).await?;
+                handle_session(stream, &config, true, received).await?;
+            }
+        }
+        SmtpTlsMode::Tls => {
+            let stream = config.tls_acceptor.accept(stream).await?;
+            handle_session(stream, &config, true, received).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn reply<S: AsyncWrite>(writer: &mut WriteHalf<S>, line: &str) -> std::io::Result<()> {
+    writer.write_all(format!("{}\r\n", line).as_bytes()).await
+}
+
+// Returns the stream to upgrade when the client sends STARTTLS, which is only offered on
+// plaintext sessions of a `StartTls` sink
+async fn handle_session<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    config: &SinkConfig,
+    encrypted: bool,
+    received: Arc<Mutex<Vec<ReceivedEmail>>>,
+) -> std::io::Result<Option<S>> {
+    let credentials = &config.credentials;
+    let offers_starttls = config.tls_mode == SmtpTlsMode::StartTls && !encrypted;
+    let (reader, mut writer) = split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    let mut current = ReceivedEmail {
+        encrypted,
+        ..Default::default()
+    };
+
+    // After STARTTLS the client starts over with EHLO, without a new greeting
+    if !encrypted || config.tls_mode == SmtpTlsMode::Tls {
+        reply(&mut writer, "220 localhost SMTP sink").await?;
+    }
+
+    while let Some(line) = lines.next_line().await? {
+        let command = line.to_ascii_uppercase();
+
+        if command.starts_with("EHLO") || command.starts_with("HELO") {
+            let auth = if credentials.is_some() {
+                "250-AUTH PLAIN"
+            } else {
+                "250-8BITMIME"
+            };
+            reply(&mut writer, "250-localhost").await?;
+            if offers_starttls {
+                reply(&mut writer, "250-STARTTLS").await?;
+            }
+            reply(&mut writer, auth).await?;
+            reply(&mut writer, "250 OK").await?;
+        } else if command == "STARTTLS" && offers_starttls {
+            reply(&mut writer, "220 Ready to start TLS").await?;
+            // The client waits for the reply before the handshake, so nothing is left buffered
+            let reader = lines.into_inner().into_inner();
+            return Ok(Some(reader.unsplit(writer)));
+        } else if command.starts_with("AUTH PLAIN") {
+            // The initial response is "\0username\0password" in base64
+            let decoded = line
+                .split_whitespace()
+                .nth(2)
+                .and_then(|b64| STANDARD.decode(b64).ok())
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .unwrap_or_default();
+            let mut parts = decoded.split('\0').skip(1);
+            let given = (parts.next(), parts.next());
+
+            match &credentials {
+                Some((user, pass)) if given == (Some(user.as_str()), Some(pass.as_str())) => {
+                    current.authenticated_as = Some(user.clone());
+                    reply(&mut writer, "235 Authentication succeeded").await?;
+                }
+                _ => reply(&mut writer, "535 Authentication failed").await?,
+            }
+        } else if command.starts_with("MAIL FROM:") {
+            if credentials.is_some() && current.authenticated_as.is_none() {
+                reply(&mut writer, "530 Authentication required").await?;
+                continue;
+            }
+            current.from = extract_address(&line);
+            reply(&mut writer, "250 OK").await?;
+        } else if command.starts_with("RCPT TO:") {
+            current.to.push(extract_address(&line));
+            reply(&mut writer, "250 OK").await?;
+        } else if command == "DATA" {
+            reply(&mut writer, "354 End data with <CR><LF>.<CR><LF>").await?;
+            let mut data = Vec::new();
+            while let Some(line) = lines.next_line().await? {
+                if line == "." {
+                    break;
+                }
+                // Undo dot-stuffing
+                data.push(line.strip_prefix('.').map(str::to_owned).unwrap_or(line));
+            }
+            current.data = data.join("\r\n");
+
+            let authenticated_as = current.authenticated_as.clone();
+            received.lock().await.push(std::mem::take(&mut current));
+            current.authenticated_as = authenticated_as;
+            current.encrypted = encrypted;
+            reply(&mut writer, "250 OK: queued").await?;
+        } else if command == "QUIT" {
+            reply(&mut writer, "221 Bye").await?;
+            break;
+        } else if command == "RSET" || command == "NOOP" {
+            reply(&mut writer, "250 OK").await?;
+        } else {
+            reply(&mut writer, "502 Command not implemented").await?;
+        }
+    }
+
+    Ok(None)
+}
+
+fn extract_address(line: &str) -> String {
+    line.split_once('<')
+        .and_then(|(_, rest)| rest.split_once('>'))
+        .map(|(address, _)| address.to_owned())
+        .unwrap_or_default()
+}
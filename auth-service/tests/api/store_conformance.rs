@@ -0,0 +1,486 @@
+// Behaviour every store must have, whatever its backend. Each suite takes the store as a trait
+// object, and the 2FA code stores also take the `FakeClock` they were built with.
+use std::{sync::Arc, time::Duration};
+
+use auth_service::{
+    app_state::{
+        BannedTokenStoreType, EmailOutboxStoreType, TwoFACodeStoreType, UserStoreType,
+        WebhookStoreType,
+    },
+    domain::{
+        Email, EmailOutboxStoreError, Locale, LoginAttemptId, OutboxEmail, OutboxEmailStatus,
+        Password, TwoFACode, TwoFACodeStoreError, User, UserStoreError, WebhookDelivery,
+        WebhookEventType, WebhookStoreError, TWO_FA_CODE_TTL,
+    },
+    get_redis_connection, get_sqlite_pool,
+    services::{
+        data_stores::{
+            HashMapEmailOutboxStore, HashMapTwoFACodeStore, HashMapUserStore, HashMapWebhookStore,
+            HashSetBannedTokenStore, PostgresBannedTokenStore, PostgresEmailOutboxStore,
+            PostgresTwoFACodeStore, PostgresUserStore, PostgresWebhookStore, RedisBannedTokenStore,
+            RedisTwoFACodeStore, SqliteUserStore,
+        },
+        fake_clock::FakeClock,
+        random_id_generator::RandomIdGenerator,
+        system_clock::SystemClock,
+    },
+};
+use chrono::{TimeDelta, Utc};
+use secrecy::SecretString;
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+use crate::helpers::{get_random_email, test_settings, TestApp};
+
+const CONCURRENT_CALLS: usize = 10;
+
+fn secret(s: &str) -> SecretString {
+    SecretString::new(s.to_owned().into_boxed_str())
+}
+
+fn jti() -> String {
+    Uuid::new_v4().to_string()
+}
+
+fn email() -> Email {
+    Email::parse(secret(&get_random_email())).unwrap()
+}
+
+async fn user(email: &Email, password: &str) -> User {
+    User::new(
+        email.clone(),
+        Password::parse(secret(password)).await.unwrap(),
+        true,
+    )
+}
+
+fn delivery(event_type: WebhookEventType) -> WebhookDelivery {
+    WebhookDelivery::new(
+        "conformance".to_owned(),
+        event_type,
+        "{}".to_owned(),
+        &SystemClock,
+        &RandomIdGenerator,
+    )
+}
+
+// Postgres keeps timestamps to the microsecond, so deliveries are compared by id and event
+async fn is_queued(webhook_store: &WebhookStoreType, delivery: &WebhookDelivery) -> bool {
+    match webhook_store.get_delivery(&delivery.id).await {
+        Ok(queued) => {
+            assert_eq!(queued.event_type, delivery.event_type);
+            true
+        }
+        Err(WebhookStoreError::DeliveryNotFound) => false,
+        Err(e) => panic!("Failed to get webhook delivery: {:?}", e),
+    }
+}
+
+// Entries are added in the past rather than by moving the clock forward, so a backend that
+// expires entries on its own, like Redis, agrees with the clock once it is set back to now
+fn added_ago(clock: &FakeClock, ago: Duration) {
+    clock.set(Utc::now() - ago);
+}
+
+// `webhook_store` is where a user store sharing its database with the webhook queue queues the
+// deliveries of its new users
+async fn user_store_conforms(store: UserStoreType, webhook_store: WebhookStoreType) {
+    let missing = email();
+    let email = email();
+
+    let signed_up = delivery(WebhookEventType::UserSignedUp);
+    let handed_back = store
+        .add_user_with_deliveries(
+            user(&email, "password123")
+                .await
+                .with_locale(Some(Locale::Fr)),
+            vec![signed_up.clone()],
+        )
+        .await
+        .unwrap();
+    let duplicate = delivery(WebhookEventType::UserSignedUp);
+    assert_eq!(
+        store
+            .add_user_with_deliveries(
+                user(&email, "other-password").await,
+                vec![duplicate.clone()]
+            )
+            .await
+            .unwrap_err(),
+        UserStoreError::UserAlreadyExists
+    );
+    // The deliveries are either queued along with the user or all handed back to the caller,
+    // and never queued for a user that wasn't added
+    match handed_back.as_slice() {
+        [] => assert!(is_queued(&webhook_store, &signed_up).await),
+        [delivery] => {
+            assert_eq!(delivery, &signed_up);
+            assert!(!is_queued(&webhook_store, &signed_up).await);
+        }
+        _ => panic!("Unexpected deliveries handed back: {:?}", handed_back),
+    }
+    assert!(!is_queued(&webhook_store, &duplicate).await);
+
+    let stored = store.get_user(&email).await.unwrap();
+    assert_eq!(stored.email(), &email);
+    assert!(stored.requires_2fa());
+    assert_eq!(stored.locale(), Some(Locale::Fr));
+    assert_eq!(
+        store.get_user(&missing).await.unwrap_err(),
+        UserStoreError::UserNotFound
+    );
+
+    // The duplicate didn't replace the first password
+    store
+        .validate_user(&email, &secret("password123"))
+        .await
+        .unwrap();
+    assert_eq!(
+        store
+            .validate_user(&email, &secret("other-password"))
+            .await
+            .unwrap_err(),
+        UserStoreError::InvalidCredentials
+    );
+    assert_eq!(
+        store
+            .validate_user(&missing, &secret("password123"))
+            .await
+            .unwrap_err(),
+        UserStoreError::UserNotFound
+    );
+
+    // Only one of several concurrent signups with the same email gets the account
+    let email = self::email();
+    let user = user(&email, "password123").await;
+    let mut signups = JoinSet::new();
+    for _ in 0..CONCURRENT_CALLS {
+        let (store, user) = (store.clone(), user.clone());
+        signups.spawn(async move { store.add_user(user).await });
+    }
+    let results = signups.join_all().await;
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+    assert!(results
+        .iter()
+        .filter_map(|result| result.as_ref().err())
+        .all(|e| *e == UserStoreError::UserAlreadyExists));
+}
+
+async fn banned_token_store_conforms(store: BannedTokenStoreType) {
+    let expires_at = Utc::now() + TimeDelta::minutes(10);
+    let banned = jti();
+    let other = jti();
+
+    assert!(!store.contains_token(&banned).await.unwrap());
+
+    store.add_token(&banned, expires_at).await.unwrap();
+    // Banning a token twice is not an error
+    store.add_token(&banned, expires_at).await.unwrap();
+
+    assert!(store.contains_token(&banned).await.unwrap());
+    assert!(!store.contains_token(&other).await.unwrap());
+
+    // A ban lasts exactly as long as the token could still be used
+    let (expired, live) = (jti(), jti());
+    store
+        .add_token(&expired, Utc::now() - TimeDelta::seconds(1))
+        .await
+        .unwrap();
+    store
+        .add_token(&live, Utc::now() + TimeDelta::minutes(1))
+        .await
+        .unwrap();
+
+    assert!(!store.contains_token(&expired).await.unwrap());
+    assert!(store.contains_token(&live).await.unwrap());
+
+    // Concurrent bans are all kept
+    let tokens: Vec<_> = (0..CONCURRENT_CALLS).map(|_| jti()).collect();
+    let mut bans = JoinSet::new();
+    for token in tokens.clone() {
+        let store = store.clone();
+        bans.spawn(async move { store.add_token(&token, expires_at).await });
+    }
+    for result in bans.join_all().await {
+        result.unwrap();
+    }
+    for token in &tokens {
+        assert!(store.contains_token(token).await.unwrap());
+    }
+}
+
+async fn two_fa_code_store_conforms(store: TwoFACodeStoreType, clock: Arc<FakeClock>) {
+    let email = email();
+
+    assert_eq!(
+        store.get_code(&email).await.unwrap_err(),
+        TwoFACodeStoreError::LoginAttemptIdNotFound
+    );
+    assert_eq!(
+        store.remove_code(&email).await.unwrap_err(),
+        TwoFACodeStoreError::LoginAttemptIdNotFound
+    );
+
+    let first_attempt = (LoginAttemptId::default(), TwoFACode::default());
+    store
+        .add_code(email.clone(), first_attempt.0, first_attempt.1)
+        .await
+        .unwrap();
+    // A new login attempt replaces the previous code
+    let second_attempt = (LoginAttemptId::default(), TwoFACode::default());
+    store
+        .add_code(
+            email.clone(),
+            second_attempt.0.clone(),
+            second_attempt.1.clone(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(store.get_code(&email).await.unwrap(), second_attempt);
+
+    store.remove_code(&email).await.unwrap();
+
+    assert_eq!(
+        store.get_code(&email).await.unwrap_err(),
+        TwoFACodeStoreError::LoginAttemptIdNotFound
+    );
+
+    // Codes can't be read nor consumed once expired
+    let (expired, live) = (self::email(), self::email());
+    added_ago(&clock, TWO_FA_CODE_TTL + Duration::from_secs(1));
+    store
+        .add_code(
+            expired.clone(),
+            LoginAttemptId::default(),
+            TwoFACode::default(),
+        )
+        .await
+        .unwrap();
+    added_ago(&clock, TWO_FA_CODE_TTL - Duration::from_secs(60));
+    store
+        .add_code(
+            live.clone(),
+            LoginAttemptId::default(),
+            TwoFACode::default(),
+        )
+        .await
+        .unwrap();
+    clock.set(Utc::now());
+
+    assert_eq!(
+        store.get_code(&expired).await.unwrap_err(),
+        TwoFACodeStoreError::LoginAttemptIdNotFound
+    );
+    assert_eq!(
+        store.remove_code(&expired).await.unwrap_err(),
+        TwoFACodeStoreError::LoginAttemptIdNotFound
+    );
+    assert!(store.get_code(&live).await.is_ok());
+
+    // A code is consumed once, however many requests race to remove it
+    let mut removals = JoinSet::new();
+    for _ in 0..CONCURRENT_CALLS {
+        let (store, live) = (store.clone(), live.clone());
+        removals.spawn(async move { store.remove_code(&live).await });
+    }
+    let results = removals.join_all().await;
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+    assert!(results
+        .iter()
+        .filter_map(|result| result.as_ref().err())
+        .all(|e| *e == TwoFACodeStoreError::LoginAttemptIdNotFound));
+}
+
+// Due in an hour, so the outbox worker of the app sharing the database leaves it alone
+fn outbox_email() -> OutboxEmail {
+    let mut email = OutboxEmail::new(
+        email(),
+        "Your 2FA code".to_owned(),
+        "<p>123456</p>".to_owned(),
+        "123456".to_owned(),
+        &SystemClock,
+        &RandomIdGenerator,
+    );
+    email.next_attempt_at = Utc::now() + TimeDelta::hours(1);
+    email
+}
+
+async fn email_outbox_store_conforms(store: EmailOutboxStoreType) {
+    let email = outbox_email();
+    store.enqueue_email(email.clone()).await.unwrap();
+
+    let now = email.next_attempt_at;
+    let lease_until = now + TimeDelta::seconds(60);
+    let mut claimed = store.claim_due_emails(now, lease_until, 10).await.unwrap();
+    assert_eq!(claimed.len(), 1);
+    assert_eq!(claimed[0].id, email.id);
+    assert_eq!(claimed[0].text_body, "123456");
+    // Leased until `lease_until`
+    assert!(store
+        .claim_due_emails(now, lease_until, 10)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Dead letters lose their bodies, and are never claimed again
+    let mut dead_letter = claimed.remove(0);
+    dead_letter.attempts = 5;
+    dead_letter.last_error = Some("rejected".to_owned());
+    dead_letter.dead_letter();
+    store.record_failure(&dead_letter).await.unwrap();
+
+    let stored = store.get_email(&email.id).await.unwrap();
+    assert_eq!(stored.status, OutboxEmailStatus::DeadLettered);
+    assert_eq!(stored.attempts, 5);
+    assert_eq!(stored.last_error.as_deref(), Some("rejected"));
+    assert_eq!(stored.subject, email.subject);
+    assert!(stored.html_body.is_empty());
+    assert!(stored.text_body.is_empty());
+    let later = lease_until + TimeDelta::days(1);
+    assert!(store
+        .claim_due_emails(later, later, 10)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Sent emails are removed
+    let sent = outbox_email();
+    store.enqueue_email(sent.clone()).await.unwrap();
+    store.mark_sent(&sent.id).await.unwrap();
+    assert_eq!(
+        store.get_email(&sent.id).await.unwrap_err(),
+        EmailOutboxStoreError::EmailNotFound
+    );
+    assert_eq!(
+        store.mark_sent(&sent.id).await.unwrap_err(),
+        EmailOutboxStoreError::EmailNotFound
+    );
+}
+
+#[tokio::test]
+async fn hashmap_user_store_conforms() {
+    user_store_conforms(
+        Arc::new(HashMapUserStore::default()),
+        Arc::new(HashMapWebhookStore::default()),
+    )
+    .await;
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "backend-tests"), ignore = "needs Postgres")]
+async fn postgres_user_store_conforms() {
+    let app = TestApp::builder().with_postgres_and_redis().build().await;
+
+    user_store_conforms(
+        Arc::new(PostgresUserStore::new(app.pg_pool())),
+        Arc::new(PostgresWebhookStore::new(app.pg_pool())),
+    )
+    .await;
+
+    let mut app = app;
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn sqlite_user_store_conforms() {
+    let path = std::env::temp_dir().join(format!("auth-service-{}.db", Uuid::new_v4()));
+    let pool = get_sqlite_pool(&secret(&format!("sqlite://{}", path.display())))
+        .await
+        .expect("Failed to create SQLite database");
+    sqlx::migrate!("./sqlite_migrations")
+        .run(&pool)
+        .await
+        .expect("Failed to run SQLite migrations");
+
+    user_store_conforms(
+        Arc::new(SqliteUserStore::new(pool.clone())),
+        Arc::new(HashMapWebhookStore::default()),
+    )
+    .await;
+
+    pool.close().await;
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+#[tokio::test]
+async fn hashset_banned_token_store_conforms() {
+    banned_token_store_conforms(Arc::new(HashSetBannedTokenStore::default())).await;
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "backend-tests"), ignore = "needs Redis")]
+async fn redis_banned_token_store_conforms() {
+    let connection = get_redis_connection(&test_settings().redis).await.unwrap();
+
+    banned_token_store_conforms(Arc::new(RedisBannedTokenStore::new(connection))).await;
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "backend-tests"), ignore = "needs Postgres")]
+async fn postgres_banned_token_store_conforms() {
+    let app = TestApp::builder().with_postgres_and_redis().build().await;
+
+    banned_token_store_conforms(Arc::new(PostgresBannedTokenStore::new(app.pg_pool()))).await;
+
+    let mut app = app;
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn hashmap_two_fa_code_store_conforms() {
+    let clock = Arc::new(FakeClock::default());
+
+    two_fa_code_store_conforms(
+        Arc::new(HashMapTwoFACodeStore::default().with_clock(clock.clone())),
+        clock,
+    )
+    .await;
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "backend-tests"), ignore = "needs Redis")]
+async fn redis_two_fa_code_store_conforms() {
+    let connection = get_redis_connection(&test_settings().redis).await.unwrap();
+    let clock = Arc::new(FakeClock::default());
+
+    two_fa_code_store_conforms(
+        Arc::new(RedisTwoFACodeStore::new(connection).with_clock(clock.clone())),
+        clock,
+    )
+    .await;
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "backend-tests"), ignore = "needs Postgres")]
+async fn postgres_two_fa_code_store_conforms() {
+    let app = TestApp::builder().with_postgres_and_redis().build().await;
+    let clock = Arc::new(FakeClock::default());
+
+    two_fa_code_store_conforms(
+        Arc::new(PostgresTwoFACodeStore::new(app.pg_pool()).with_clock(clock.clone())),
+        clock,
+    )
+    .await;
+
+    let mut app = app;
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn hashmap_email_outbox_store_conforms() {
+    email_outbox_store_conforms(Arc::new(HashMapEmailOutboxStore::default())).await;
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "backend-tests"), ignore = "needs Postgres")]
+async fn postgres_email_outbox_store_conforms() {
+    let app = TestApp::builder().with_postgres_and_redis().build().await;
+
+    email_outbox_store_conforms(Arc::new(PostgresEmailOutboxStore::new(app.pg_pool()))).await;
+
+    let mut app = app;
+    app.clean_up().await;
+}
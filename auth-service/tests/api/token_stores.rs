@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use auth_service::{
+    domain::{
+        BannedTokenStore, Clock, Email, ExpiringStore, LoginAttemptId, TwoFACode, TwoFACodeStore,
+        TwoFACodeStoreError,
+    },
+    services::{
+        data_stores::{PostgresBannedTokenStore, PostgresTwoFACodeStore},
+        fake_clock::FakeClock,
+    },
+};
+use chrono::TimeDelta;
+use secrecy::SecretString;
+use uuid::Uuid;
+
+use crate::helpers::{get_random_email, TestApp};
+
+fn jti() -> String {
+    Uuid::new_v4().to_string()
+}
+
+fn email() -> Email {
+    Email::parse(SecretString::new(get_random_email().into_boxed_str())).unwrap()
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "backend-tests"), ignore = "needs Postgres")]
+async fn postgres_banned_tokens_expire_and_are_purged() {
+    let app = TestApp::builder().with_postgres_and_redis().build().await;
+    let clock = Arc::new(FakeClock::default());
+    let store = PostgresBannedTokenStore::new(app.pg_pool()).with_clock(clock.clone());
+    let expired = jti();
+    let banned = jti();
+
+    store
+        .add_token(&expired, clock.now() + TimeDelta::minutes(5))
+        .await
+        .unwrap();
+    store
+        .add_token(&banned, clock.now() + TimeDelta::minutes(10))
+        .await
+        .unwrap();
+    clock.advance(TimeDelta::minutes(5));
+
+    assert!(!store.contains_token(&expired).await.unwrap());
+    assert_eq!(store.purge_expired(clock.now()).await.unwrap(), 1);
+    assert!(store.contains_token(&banned).await.unwrap());
+
+    // Once its token has expired, the other ban is purged too
+    clock.advance(TimeDelta::minutes(5));
+    assert_eq!(store.purge_expired(clock.now()).await.unwrap(), 1);
+    assert!(!store.contains_token(&banned).await.unwrap());
+
+    let mut app = app;
+    app.clean_up().await;
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "backend-tests"), ignore = "needs Postgres")]
+async fn postgres_two_fa_codes_are_purged_after_ten_minutes() {
+    let app = TestApp::builder().with_postgres_and_redis().build().await;
+    let clock = Arc::new(FakeClock::default());
+    let store = PostgresTwoFACodeStore::new(app.pg_pool()).with_clock(clock.clone());
+    let email = email();
+    store
+        .add_code(
+            email.clone(),
+            LoginAttemptId::default(),
+            TwoFACode::default(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(store.purge_expired(clock.now()).await.unwrap(), 0);
+    assert!(store.get_code(&email).await.is_ok());
+
+    clock.advance(chrono::Duration::minutes(10));
+    assert_eq!(store.purge_expired(clock.now()).await.unwrap(), 1);
+    assert_eq!(
+        store.get_code(&email).await.unwrap_err(),
+        TwoFACodeStoreError::LoginAttemptIdNotFound
+    );
+
+    let mut app = app;
+    app.clean_up().await;
+}
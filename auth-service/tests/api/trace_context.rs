@@ -0,0 +1,205 @@
+use std::time::Duration;
+
+use auth_service::{
+    settings::{LogFormat, OtlpProtocol, TracingSettings},
+    utils::tracing::{opentelemetry_layer, TracingGuard},
+};
+use serde_json::Value;
+use tracing::{level_filters::LevelFilter, subscriber::DefaultGuard};
+use tracing_subscriber::prelude::*;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use crate::helpers::{get_random_email, TestApp};
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+const PARENT_SPAN_ID: &str = "00f067aa0ba902b7";
+
+// Stands in for an OpenTelemetry collector, receiving spans as OTLP/JSON
+struct Collector {
+    server: MockServer,
+    guard: TracingGuard,
+    _default: DefaultGuard,
+}
+
+impl Collector {
+    // Each test runs on its own thread with a single-threaded runtime, so a subscriber set
+    // as the thread's default sees the spans of its app and of no other test
+    async fn start() -> Self {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/traces"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let settings = TracingSettings {
+            log_format: LogFormat::Text,
+            service_name: "auth-service-test".to_owned(),
+            otlp_endpoint: Some(server.uri()),
+            otlp_protocol: OtlpProtocol::Json,
+            otlp_timeout: Duration::from_secs(1),
+        };
+        let (layer, guard) = opentelemetry_layer(&settings).unwrap();
+        let subscriber = tracing_subscriber::registry().with(layer.with_filter(LevelFilter::INFO));
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        Self {
+            server,
+            guard,
+            _default,
+        }
+    }
+
+    // Spans end slightly after the response is sent, so this flushes until a matching one shows up
+    async fn wait_for_span(&self, matches: impl Fn(&Value) -> bool) -> Value {
+        for _ in 0..100 {
+            self.guard.force_flush().unwrap();
+            if let Some(span) = self.spans().await.into_iter().find(&matches) {
+                return span;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("Timed out waiting for the span");
+    }
+
+    async fn spans(&self) -> Vec<Value> {
+        let mut spans = Vec::new();
+        for request in self.server.received_requests().await.unwrap() {
+            assert_eq!(request.headers["content-type"], "application/json");
+            let export: Value = serde_json::from_slice(&request.body).unwrap();
+            for resource_spans in export["resourceSpans"].as_array().unwrap() {
+                assert!(resource_spans["resource"]["attributes"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .any(|attribute| attribute["key"] == "service.name"
+                        && attribute["value"]["stringValue"] == "auth-service-test"));
+                for scope_spans in resource_spans["scopeSpans"].as_array().unwrap() {
+                    spans.extend(scope_spans["spans"].as_array().unwrap().iter().cloned());
+                }
+            }
+        }
+        spans
+    }
+}
+
+fn signup_body(email: &str, requires_2fa: bool) -> Value {
+    serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": requires_2fa
+    })
+}
+
+// Splits `00-<trace id>-<span id>-<flags>` into the trace and span ids
+fn parse_traceparent(value: &str) -> (String, String) {
+    let parts: Vec<_> = value.split('-').collect();
+    assert_eq!(parts.len(), 4, "malformed traceparent `{}`", value);
+    assert_eq!(parts[0], "00");
+    assert_eq!(parts[1].len(), 32);
+    assert_eq!(parts[2].len(), 16);
+    (parts[1].to_owned(), parts[2].to_owned())
+}
+
+#[tokio::test]
+async fn request_spans_continue_the_callers_trace_and_are_exported() {
+    let collector = Collector::start().await;
+    let mut app = TestApp::builder().build().await;
+
+    let response = app
+        .http_client
+        .post(format!("{}/signup", &app.address))
+        .header(
+            "traceparent",
+            format!("00-{}-{}-01", TRACE_ID, PARENT_SPAN_ID),
+        )
+        .header("x-request-id", "checkout-42")
+        .json(&signup_body(&get_random_email(), false))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status().as_u16(), 201);
+    assert_eq!(response.headers()["x-request-id"], "checkout-42");
+
+    let span = collector
+        .wait_for_span(|span| span["name"] == "[REQUEST]")
+        .await;
+    assert_eq!(span["traceId"], TRACE_ID);
+    assert_eq!(span["parentSpanId"], PARENT_SPAN_ID);
+    let request_id = span["attributes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|attribute| attribute["key"] == "request_id")
+        .expect("No request_id attribute");
+    assert_eq!(request_id["value"]["stringValue"], "checkout-42");
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn a_request_id_is_assigned_when_the_caller_has_none() {
+    let mut app = TestApp::new().await;
+
+    let first = app
+        .post_signup(&signup_body(&get_random_email(), false))
+        .await;
+    let second = app
+        .post_signup(&signup_body(&get_random_email(), false))
+        .await;
+
+    let first_id = first.headers()["x-request-id"].to_str().unwrap();
+    let second_id = second.headers()["x-request-id"].to_str().unwrap();
+    assert!(uuid::Uuid::parse_str(first_id).is_ok(), "{}", first_id);
+    assert_ne!(first_id, second_id);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn outgoing_requests_carry_the_trace_context() {
+    let collector = Collector::start().await;
+    let mut app = TestApp::builder().with_postmark().build().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(app.email_server())
+        .await;
+    let email = get_random_email();
+
+    assert_eq!(
+        app.post_signup(&signup_body(&email, true))
+            .await
+            .status()
+            .as_u16(),
+        201
+    );
+    let response = app
+        .post_login(&serde_json::json!({ "email": email, "password": "password123" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 206);
+
+    // The webhook and the email go out from background workers, each in a trace of its own
+    let webhook = &app.wait_for_webhooks(1).await[0];
+    let (trace_id, parent_span_id) =
+        parse_traceparent(webhook.headers["traceparent"].to_str().unwrap());
+    let span = collector
+        .wait_for_span(|span| span["spanId"] == parent_span_id.as_str())
+        .await;
+    assert_eq!(span["name"], "Delivering webhook");
+    assert_eq!(span["traceId"], trace_id);
+
+    let email = &app.wait_for_postmark_requests(1).await[0];
+    let (trace_id, parent_span_id) =
+        parse_traceparent(email.headers["traceparent"].to_str().unwrap());
+    let span = collector
+        .wait_for_span(|span| span["spanId"] == parent_span_id.as_str())
+        .await;
+    assert_eq!(span["name"], "Sending email");
+    assert_eq!(span["traceId"], trace_id);
+
+    app.clean_up().await;
+}
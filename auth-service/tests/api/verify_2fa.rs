@@ -1,25 +1,12 @@
 use crate::helpers::{get_random_email, TestApp};
 use auth_service::{
-    domain::{Email, LoginAttemptId, TwoFACode},
-    routes::TwoFactorAuthResponse,
+    domain::{LoginAttemptId, TwoFACode},
+    routes::{TokenResponse, TwoFactorAuthResponse},
     utils::constants::JWT_COOKIE_NAME,
     ErrorResponse,
 };
-use secrecy::{ExposeSecret, SecretString};
-use wiremock::{
-    matchers::{method, path},
-    Mock, ResponseTemplate,
-};
-
-fn create_email(s: &str) -> Email {
-    // updated
-    Email::parse(secret_str(s)).expect("valid email") // updated
-}
-
-fn secret_str(s: &str) -> SecretString {
-    // new
-    SecretString::new(s.to_owned().into_boxed_str()) // new
-}
+use chrono::Utc;
+use secrecy::ExposeSecret;
 
 #[tokio::test]
 async fn should_return_200_if_correct_code() {
@@ -37,13 +24,6 @@ async fn should_return_200_if_correct_code() {
 
     assert_eq!(response.status().as_u16(), 201);
 
-    Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .expect(1)
-        .mount(&app.email_server)
-        .await;
-
     let login_body = serde_json::json!({
         "email": random_email,
         "password": "password123",
@@ -61,26 +41,13 @@ async fn should_return_200_if_correct_code() {
     let expected_response_message = "2FA required".to_owned();
     assert_eq!(response_body.message, expected_response_message);
 
-    let two_fa_code_store = app.two_fa_code_store.read().await;
-    let code = two_fa_code_store
-        .get_code(&create_email(&random_email))
-        .await
-        .expect("Could not get 2FA code from store");
-
-    assert_eq!(
-        response_body.login_attempt_id,
-        code.0.as_ref().expose_secret().to_owned()
-    );
-
-    let login_attempt_id = code.0.as_ref().to_owned();
-    let two_fa_code = code.1.as_ref().to_owned();
-
-    drop(two_fa_code_store);
+    let login_attempt_id = response_body.login_attempt_id;
+    let two_fa_code = app.wait_for_2fa_code(&random_email, 1).await;
 
     let verify_2fa_body = serde_json::json!({
         "email": random_email.as_str(),
-        "loginAttemptId": login_attempt_id.expose_secret(),
-        "2FACode": two_fa_code.expose_secret()
+        "loginAttemptId": login_attempt_id.as_str(),
+        "2FACode": two_fa_code.as_str()
     });
 
     let response = app.post_verify_2fa(&verify_2fa_body).await;
@@ -99,10 +66,70 @@ async fn should_return_200_if_correct_code() {
         verify_2fa_body
     );
 
+    app.wait_for_emails(1).await;
+
     let mut app = app;
     app.clean_up().await;
 }
 
+#[tokio::test]
+async fn should_return_token_in_body_if_requested() {
+    let mut app = TestApp::new().await;
+
+    let random_email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": true
+    });
+
+    let response = app.post_signup(&signup_body).await;
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+    });
+
+    let response = app.post_login(&login_body).await;
+
+    assert_eq!(response.status().as_u16(), 206);
+
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+    let two_fa_code = app.wait_for_2fa_code(&random_email, 1).await;
+
+    let verify_2fa_body = serde_json::json!({
+        "email": random_email.as_str(),
+        "loginAttemptId": login_attempt_id.as_str(),
+        "2FACode": two_fa_code.as_str(),
+        "tokenDelivery": "body"
+    });
+
+    let response = app.post_verify_2fa(&verify_2fa_body).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.cookies().count(), 0);
+
+    let token = response
+        .json::<TokenResponse>()
+        .await
+        .expect("Could not deserialize response body to TokenResponse");
+    assert_eq!(token.token_type, "Bearer");
+    assert_eq!(token.expires_in, 600);
+
+    let response = app.post_verify_token_with_bearer(&token.access_token).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
+
 #[tokio::test]
 async fn should_return_422_if_malformed_input() {
     let app = TestApp::new().await;
@@ -221,13 +248,6 @@ async fn should_return_401_if_incorrect_credentials() {
 
     assert_eq!(response.status().as_u16(), 201);
 
-    Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .expect(1)
-        .mount(&app.email_server)
-        .await;
-
     let login_body = serde_json::json!({
         "email": random_email,
         "password": "password123",
@@ -245,21 +265,8 @@ async fn should_return_401_if_incorrect_credentials() {
     let expected_response_message = "2FA required".to_owned();
     assert_eq!(response_body.message, expected_response_message);
 
-    let two_fa_code_store = app.two_fa_code_store.read().await;
-    let code = two_fa_code_store
-        .get_code(&create_email(&random_email))
-        .await
-        .expect("Could not get 2FA code from store");
-
-    assert_eq!(
-        response_body.login_attempt_id,
-        code.0.as_ref().expose_secret().to_owned()
-    );
-
-    let login_attempt_id = code.0.as_ref().expose_secret().to_owned();
-    let two_fa_code = code.1.as_ref().expose_secret().to_owned();
-
-    drop(two_fa_code_store);
+    let login_attempt_id = response_body.login_attempt_id;
+    let two_fa_code = app.wait_for_2fa_code(&random_email, 1).await;
 
     let incorrect_email = get_random_email();
     let incorrect_login_attempt_id = LoginAttemptId::default()
@@ -306,6 +313,8 @@ async fn should_return_401_if_incorrect_credentials() {
         );
     }
 
+    app.wait_for_emails(1).await;
+
     let mut app = app;
     app.clean_up().await;
 }
@@ -326,13 +335,6 @@ async fn should_return_401_if_old_code() {
 
     assert_eq!(response.status().as_u16(), 201);
 
-    Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .expect(2)
-        .mount(&app.email_server)
-        .await;
-
     let login_body = serde_json::json!({
         "email": random_email,
         "password": "password123",
@@ -350,19 +352,7 @@ async fn should_return_401_if_old_code() {
     let expected_response_message = "2FA required".to_owned();
     assert_eq!(response_body.message, expected_response_message);
 
-    let two_fa_code_store = app.two_fa_code_store.read().await;
-    let code = two_fa_code_store
-        .get_code(&create_email(&random_email))
-        .await
-        .expect("Could not get 2FA code from store");
-
-    assert_eq!(
-        response_body.login_attempt_id,
-        code.0.as_ref().expose_secret().to_owned()
-    );
-
-    let old_two_fa_code = code.1.as_ref().expose_secret().to_owned();
-    drop(two_fa_code_store);
+    let old_two_fa_code = app.wait_for_2fa_code(&random_email, 1).await;
 
     let login_body = serde_json::json!({
         "email": random_email,
@@ -373,14 +363,11 @@ async fn should_return_401_if_old_code() {
 
     assert_eq!(response.status().as_u16(), 206);
 
-    let two_fa_code_store = app.two_fa_code_store.read().await;
-    let code = two_fa_code_store
-        .get_code(&create_email(&random_email))
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
         .await
-        .expect("Could not get 2FA code from store");
-
-    let login_attempt_id = code.0.as_ref().expose_secret().to_owned();
-    drop(two_fa_code_store);
+        .expect("Could not deserialize response body to LoginResponse")
+        .login_attempt_id;
 
     let verify_2fa_body = serde_json::json!({
         "email": random_email.as_str(),
@@ -406,6 +393,8 @@ async fn should_return_401_if_old_code() {
         "Incorrect credentials".to_owned()
     );
 
+    app.wait_for_emails(2).await;
+
     let mut app = app;
     app.clean_up().await;
 }
@@ -426,13 +415,6 @@ async fn should_return_401_if_same_code_twice() {
 
     assert_eq!(response.status().as_u16(), 201);
 
-    Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .expect(1)
-        .mount(&app.email_server)
-        .await;
-
     let login_body = serde_json::json!({
         "email": random_email,
         "password": "password123",
@@ -450,21 +432,8 @@ async fn should_return_401_if_same_code_twice() {
     let expected_response_message = "2FA required".to_owned();
     assert_eq!(response_body.message, expected_response_message);
 
-    let two_fa_code_store = app.two_fa_code_store.read().await;
-    let code = two_fa_code_store
-        .get_code(&create_email(&random_email))
-        .await
-        .expect("Could not get 2FA code from store");
-
-    assert_eq!(
-        response_body.login_attempt_id,
-        code.0.as_ref().expose_secret().to_owned()
-    );
-
-    let login_attempt_id = code.0.as_ref().expose_secret().to_owned();
-    let two_fa_code = code.1.as_ref().expose_secret().to_owned();
-
-    drop(two_fa_code_store);
+    let login_attempt_id = response_body.login_attempt_id;
+    let two_fa_code = app.wait_for_2fa_code(&random_email, 1).await;
 
     let verify_2fa_body = serde_json::json!({
         "email": random_email.as_str(),
@@ -506,6 +475,79 @@ async fn should_return_401_if_same_code_twice() {
         "Incorrect credentials".to_owned()
     );
 
+    app.wait_for_emails(1).await;
+
+    let mut app = app;
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_401_if_expired_code() {
+    let app = TestApp::new().await;
+
+    let random_email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": true
+    });
+
+    let response = app.post_signup(&signup_body).await;
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    let login_attempt_id = LoginAttemptId::default();
+    let two_fa_code = TwoFACode::default();
+    app.id_generator
+        .set(login_attempt_id.clone(), two_fa_code.clone());
+
+    // Log in as if it happened just over ten minutes ago
+    app.clock
+        .set(Utc::now() - chrono::Duration::minutes(10) - chrono::Duration::seconds(1));
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+    });
+
+    let response = app.post_login(&login_body).await;
+
+    assert_eq!(response.status().as_u16(), 206);
+
+    let response_body = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to LoginResponse");
+
+    assert_eq!(
+        response_body.login_attempt_id,
+        login_attempt_id.as_ref().expose_secret().to_owned()
+    );
+
+    app.clock.set(Utc::now());
+
+    let verify_2fa_body = serde_json::json!({
+        "email": random_email.as_str(),
+        "loginAttemptId": login_attempt_id.as_ref().expose_secret(),
+        "2FACode": two_fa_code.as_ref().expose_secret()
+    });
+
+    let response = app.post_verify_2fa(&verify_2fa_body).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        "Incorrect credentials".to_owned()
+    );
+
+    app.wait_for_emails(1).await;
+
     let mut app = app;
     app.clean_up().await;
 }
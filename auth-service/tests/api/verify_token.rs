@@ -1,7 +1,7 @@
 use auth_service::{utils::constants::JWT_COOKIE_NAME, ErrorResponse};
 use secrecy::{ExposeSecret, SecretString};
 
-use crate::helpers::{get_random_email, TestApp};
+use crate::helpers::{get_jti, get_random_email, TestApp};
 
 #[tokio::test]
 async fn should_return_422_if_malformed_input() {
@@ -110,9 +110,7 @@ async fn should_return_401_if_banned_token() {
 
     let banned_tokens = app.banned_token_store.clone();
     let is_banned = banned_tokens
-        .read()
-        .await
-        .contains_token(&token)
+        .contains_token(&get_jti(token.expose_secret()))
         .await
         .expect("Failed to check if token is banned");
 
@@ -182,3 +180,93 @@ async fn should_return_200_valid_token() {
     let mut app = app;
     app.clean_up().await;
 }
+
+#[tokio::test]
+async fn should_return_401_if_expired_token() {
+    let app = TestApp::new().await;
+
+    let random_email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": false
+    });
+
+    let response = app.post_signup(&signup_body).await;
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+    });
+
+    let response = app.post_login(&login_body).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    let auth_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found");
+
+    let verify_token_body = serde_json::json!({
+        "token": auth_cookie.value(),
+    });
+
+    // A token is rejected as soon as its lifetime is over
+    app.clock
+        .advance(chrono::Duration::from_std(app.settings.auth.token_ttl).unwrap());
+
+    let response = app.post_verify_token(&verify_token_body).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        "Invalid auth token".to_owned()
+    );
+
+    let mut app = app;
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_accept_bearer_token_without_body() {
+    let mut app = TestApp::new().await;
+
+    let random_email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let token = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found")
+        .value()
+        .to_owned();
+
+    let response = app.post_verify_token_with_bearer(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.post_verify_token_with_bearer("invalid").await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
@@ -0,0 +1,249 @@
+use std::sync::Arc;
+
+use auth_service::{
+    app_state::WebhookStoreType,
+    domain::{
+        WebhookDelivery, WebhookDeliveryAttempt, WebhookDeliveryStatus, WebhookEvent,
+        WebhookEventType, WebhookStore, WebhookStoreError,
+    },
+    services::webhooks::{
+        sign_payload, WEBHOOK_EVENT_HEADER, WEBHOOK_ID_HEADER, WEBHOOK_SIGNATURE_HEADER,
+        WEBHOOK_TIMESTAMP_HEADER,
+    },
+};
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::eyre;
+use secrecy::SecretString;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, Request, ResponseTemplate,
+};
+
+use crate::helpers::{get_random_email, TestApp, TEST_WEBHOOK_SECRET};
+
+fn header<'a>(request: &'a Request, name: &str) -> &'a str {
+    request
+        .headers
+        .get(name)
+        .unwrap_or_else(|| panic!("Missing {} header", name))
+        .to_str()
+        .expect("Header is not valid UTF-8")
+}
+
+fn assert_valid_signature(request: &Request) {
+    let timestamp = header(request, WEBHOOK_TIMESTAMP_HEADER)
+        .parse::<i64>()
+        .expect("Timestamp is not a number");
+    let body = std::str::from_utf8(&request.body).expect("Body is not valid UTF-8");
+    let secret = SecretString::new(TEST_WEBHOOK_SECRET.to_owned().into_boxed_str());
+
+    assert_eq!(
+        header(request, WEBHOOK_SIGNATURE_HEADER),
+        sign_payload(&secret, timestamp, body).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn signup_delivers_signed_user_signed_up_webhook() {
+    let app = TestApp::new().await;
+
+    Mock::given(path("/webhooks"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.webhook_server)
+        .await;
+
+    let random_email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": false
+    });
+
+    let response = app.post_signup(&signup_body).await;
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    let received = app.wait_for_webhooks(1).await;
+    let request = &received[0];
+
+    assert_valid_signature(request);
+    assert_eq!(header(request, WEBHOOK_EVENT_HEADER), "user.signed_up");
+
+    let event: WebhookEvent =
+        serde_json::from_slice(&request.body).expect("Could not deserialize webhook event");
+    assert_eq!(event.event_type, WebhookEventType::UserSignedUp);
+    assert_eq!(event.data.email, random_email);
+
+    let mut app = app;
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn signup_with_2fa_delivers_2fa_enabled_webhook() {
+    let app = TestApp::new().await;
+
+    Mock::given(path("/webhooks"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.webhook_server)
+        .await;
+
+    let signup_body = serde_json::json!({
+        "email": get_random_email(),
+        "password": "password123",
+        "requires2FA": true
+    });
+
+    let response = app.post_signup(&signup_body).await;
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    let received = app.wait_for_webhooks(2).await;
+    let mut events: Vec<&str> = received
+        .iter()
+        .map(|r| header(r, WEBHOOK_EVENT_HEADER))
+        .collect();
+    events.sort();
+
+    assert_eq!(events, vec!["user.2fa_enabled", "user.signed_up"]);
+
+    let mut app = app;
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn failed_webhook_is_retried_and_logged() {
+    let app = TestApp::new().await;
+
+    Mock::given(path("/webhooks"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&app.webhook_server)
+        .await;
+    Mock::given(path("/webhooks"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.webhook_server)
+        .await;
+
+    let signup_body = serde_json::json!({
+        "email": get_random_email(),
+        "password": "password123",
+        "requires2FA": false
+    });
+
+    let response = app.post_signup(&signup_body).await;
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    let received = app.wait_for_retried_webhooks(2).await;
+    let delivery_id = Uuid::parse_str(header(&received[0], WEBHOOK_ID_HEADER))
+        .expect("Delivery id is not a UUID");
+    assert_eq!(
+        header(&received[1], WEBHOOK_ID_HEADER),
+        delivery_id.to_string()
+    );
+
+    // The log entry for the successful attempt is written right after the response
+    let mut delivery = None;
+    for _ in 0..20 {
+        let current = app
+            .webhook_store
+            .get_delivery(&delivery_id)
+            .await
+            .expect("Could not get webhook delivery");
+        if current.status == WebhookDeliveryStatus::Delivered {
+            delivery = Some(current);
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    let delivery = delivery.expect("Webhook was not marked as delivered");
+    assert_eq!(delivery.attempts, 2);
+
+    let log = app
+        .webhook_store
+        .get_delivery_log(&delivery_id)
+        .await
+        .expect("Could not get webhook delivery log");
+
+    assert_eq!(log.len(), 2);
+    assert_eq!(log[0].response_status, Some(500));
+    assert_eq!(log[1].response_status, Some(200));
+
+    let mut app = app;
+    app.clean_up().await;
+}
+
+// Refuses every delivery, as a webhook queue that is down would
+struct FailingWebhookStore {
+    inner: WebhookStoreType,
+}
+
+#[async_trait::async_trait]
+impl WebhookStore for FailingWebhookStore {
+    async fn enqueue_delivery(&self, _delivery: WebhookDelivery) -> Result<(), WebhookStoreError> {
+        Err(WebhookStoreError::UnexpectedError(eyre!("queue is down")))
+    }
+
+    async fn claim_due_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<WebhookDelivery>, WebhookStoreError> {
+        self.inner
+            .claim_due_deliveries(now, lease_until, limit)
+            .await
+    }
+
+    async fn record_attempt(
+        &self,
+        delivery: &WebhookDelivery,
+        attempt: WebhookDeliveryAttempt,
+    ) -> Result<(), WebhookStoreError> {
+        self.inner.record_attempt(delivery, attempt).await
+    }
+
+    async fn get_delivery(&self, id: &Uuid) -> Result<WebhookDelivery, WebhookStoreError> {
+        self.inner.get_delivery(id).await
+    }
+
+    async fn get_delivery_log(
+        &self,
+        id: &Uuid,
+    ) -> Result<Vec<WebhookDeliveryAttempt>, WebhookStoreError> {
+        self.inner.get_delivery_log(id).await
+    }
+}
+
+#[tokio::test]
+async fn signup_succeeds_when_its_webhooks_cannot_be_queued() {
+    let app = TestApp::builder()
+        .with_webhook_store(|inner| Arc::new(FailingWebhookStore { inner }))
+        .build()
+        .await;
+
+    let signup_body = serde_json::json!({
+        "email": get_random_email(),
+        "password": "password123",
+        "requires2FA": false
+    });
+
+    // The user is already added when the deliveries are queued, so failing the signup would only
+    // turn its retry into a 409
+    let response = app.post_signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = app.post_signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 409);
+
+    let mut app = app;
+    app.clean_up().await;
+}